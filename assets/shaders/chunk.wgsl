@@ -9,18 +9,62 @@
     forward_io::FragmentOutput,
 }
 
+#ifdef SCREEN_SPACE_AMBIENT_OCCLUSION
+#import bevy_pbr::mesh_view_bindings::screen_space_ambient_occlusion_texture
+#endif
+
 struct ChunkMaterial {
     reflectance: f32,
     perceptual_roughness: f32,
     metallic: f32,
+    ao_strength: f32,
+    ao_enabled: u32,
+    face_shading_enabled: u32,
+    skylight_enabled: u32,
+    skylight_min: f32,
+    sun_shadow_strength: f32,
+    biome_tint_low: vec4<f32>,
+    biome_tint_high: vec4<f32>,
+    detail_fade_enabled: u32,
+    detail_fade_start: f32,
+    detail_fade_end: f32,
+    detail_fade_ao: f32,
 }
 
 @group(2) @binding(0) var<uniform> chunk_material: ChunkMaterial;
 
+#ifdef TRIPLANAR
+@group(2) @binding(1) var block_textures: texture_2d_array<f32>;
+@group(2) @binding(2) var block_sampler: sampler;
+// Side, top and bottom texture layers and texture repeats per voxel, by voxel type
+@group(2) @binding(3) var<storage, read> block_layers: array<vec4<f32>>;
+// Average side, top and bottom texture colours by voxel type, the alpha is 0 until they are known
+@group(2) @binding(4) var<storage, read> block_colours: array<vec4<f32>>;
+#endif
+
+#ifdef SMOOTH_VERTICES
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) biome_tint: f32,
+    @location(3) voxel_type: u32,
+};
+#else
+#ifdef FAR_FACES
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
-    @location(0) vert_data: u32
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) face_data: u32,
 };
+#else
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) vert_data: vec2<u32>,
+    @location(1) biome_tint: f32,
+};
+#endif
+#endif
 
 struct VertexOut {
     @builtin(position) clip_pos: vec4<f32>,
@@ -29,6 +73,10 @@ struct VertexOut {
     @location(2) world_pos: vec4<f32>,
     @location(3) blend_colour: vec3<f32>,
     @location(4) instance_index: u32,
+    @location(5) biome_colour: vec3<f32>,
+    @location(6) @interpolate(flat) block_index: u32,
+    // Texture layer of the face, unused by smooth terrain which is textured along every axis
+    @location(7) @interpolate(flat) texture_layer: u32,
 }
 
 var<private> normals: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
@@ -40,11 +88,50 @@ var<private> normals: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
 	vec3<f32>(0.0, -1.0, 0.0) // Down
 );
 
+// Axes the width and height of a far face run along for each normal, matches FACE_AXES
+var<private> face_width_axes: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+	vec3<f32>(0.0, 0.0, 1.0), // Left
+	vec3<f32>(0.0, 1.0, 0.0), // Right
+	vec3<f32>(1.0, 0.0, 0.0), // Back
+	vec3<f32>(0.0, 1.0, 0.0), // Front
+	vec3<f32>(0.0, 0.0, 1.0), // Up
+	vec3<f32>(1.0, 0.0, 0.0) // Down
+);
+
+var<private> face_height_axes: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+	vec3<f32>(0.0, 1.0, 0.0), // Left
+	vec3<f32>(0.0, 0.0, 1.0), // Right
+	vec3<f32>(0.0, 1.0, 0.0), // Back
+	vec3<f32>(1.0, 0.0, 0.0), // Front
+	vec3<f32>(1.0, 0.0, 0.0), // Up
+	vec3<f32>(0.0, 0.0, 1.0) // Down
+);
+
+// Corners of the two triangles of a far face, as multiples of its width and height
+var<private> face_corners: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+	vec2<f32>(0.0, 0.0),
+	vec2<f32>(1.0, 0.0),
+	vec2<f32>(1.0, 1.0),
+	vec2<f32>(0.0, 0.0),
+	vec2<f32>(1.0, 1.0),
+	vec2<f32>(0.0, 1.0)
+);
+
 var<private> ambient_lerps: vec4<f32> = vec4<f32>(1.0,0.7,0.5,0.15);
 
-var<private> block_colour: array<vec3<f32>,2> = array<vec3<f32>,2>(
+var<private> face_shades: array<f32, 6> = array<f32, 6>(
+	0.8, // Left
+	0.8, // Right
+	0.9, // Back
+	0.9, // Front
+	1.0, // Up
+	0.6 // Down
+);
+
+var<private> block_colour: array<vec3<f32>,3> = array<vec3<f32>,3>(
 	vec3<f32>(0.0, 0.0, 0.0), // air
 	vec3<f32>(5.0, 1.0, 3.0), // block
+	vec3<f32>(0.2, 0.5, 2.0), // water
 );
 
 // var<private> regions: array<f32, 4> = array<f32, 4>(
@@ -63,27 +150,167 @@ fn x_bits(bit_num: u32) -> u32 {
     return (1u << bit_num) - 1u;
 }
 
+// 0 up close, rising to 1 at the end of the detail fade
+fn detail_fade(world_pos: vec3<f32>) -> f32 {
+    if chunk_material.detail_fade_enabled == 0u {
+        return 0.0;
+    }
+
+    let distance = length(world_pos - mesh_view_bindings::view.world_position);
+    let end = max(chunk_material.detail_fade_end, chunk_material.detail_fade_start + 0.001);
+    return smoothstep(chunk_material.detail_fade_start, end, distance);
+}
+
+// Face shades blended by how much a smooth normal faces along each axis
+fn smooth_face_shade(normal: vec3<f32>) -> f32 {
+    let weights = normal * normal;
+    let vertical = select(face_shades[5], face_shades[4], normal.y > 0.0);
+    return weights.x * face_shades[0] + weights.z * face_shades[2] + weights.y * vertical;
+}
+
+#ifdef TRIPLANAR
+// Voxel types past the end of the lookup use the first entry
+fn block_layers_of(block_index: u32) -> vec4<f32> {
+    return block_layers[select(0u, block_index, block_index < arrayLength(&block_layers))];
+}
+
+// Layer of a block's face, for far faces which have no room for it
+fn face_layer(block_index: u32, normal_index: u32) -> u32 {
+    let layers = block_layers_of(block_index);
+    if normal_index == 4u {
+        return u32(layers.y);
+    }
+    if normal_index == 5u {
+        return u32(layers.z);
+    }
+    return u32(layers.x);
+}
+
+// A face's texture layer projected along the axis the face points down
+// Positions are in world space, so quads of any size tile without UVs
+fn face_colour(world_pos: vec3<f32>, world_normal: vec3<f32>, block_index: u32, layer: u32) -> vec3<f32> {
+    // Texture v runs down, so it is flipped to keep textures upright on the sides
+    let pos = world_pos * block_layers_of(block_index).w * vec3<f32>(1.0, -1.0, 1.0);
+    let normal = abs(world_normal);
+    let uv = select(select(pos.xy, pos.xz, normal.y > 0.5), pos.zy, normal.x > 0.5);
+
+    return textureSample(block_textures, block_sampler, uv, i32(layer)).rgb;
+}
+
+// The block's textures projected along each axis, blended by how much the surface faces that axis
+fn triplanar_colour(world_pos: vec3<f32>, world_normal: vec3<f32>, block_index: u32) -> vec3<f32> {
+    let layers = block_layers_of(block_index);
+    // Texture v runs down, so it is flipped to keep textures upright on the sides
+    let pos = world_pos * layers.w * vec3<f32>(1.0, -1.0, 1.0);
+
+    var weights = pow(abs(world_normal), vec3<f32>(4.0));
+    weights /= weights.x + weights.y + weights.z;
+    let vertical_layer = select(layers.z, layers.y, world_normal.y > 0.0);
+
+    let x = textureSample(block_textures, block_sampler, pos.zy, i32(layers.x)).rgb;
+    let y = textureSample(block_textures, block_sampler, pos.xz, i32(vertical_layer)).rgb;
+    let z = textureSample(block_textures, block_sampler, pos.xy, i32(layers.x)).rgb;
+
+    return x * weights.x + y * weights.y + z * weights.z;
+}
+
+// The block's average texture colours blended like triplanar_colour, so a face gets the average of
+// its own layer
+fn average_colour(world_normal: vec3<f32>, block_index: u32) -> vec4<f32> {
+    // Voxel types past the end of the lookup use the first entry, like block_layers_of
+    let index = 3u * select(0u, block_index, 3u * block_index + 2u < arrayLength(&block_colours));
+
+    var weights = pow(abs(world_normal), vec3<f32>(4.0));
+    weights /= weights.x + weights.y + weights.z;
+    let vertical = block_colours[index + select(2u, 1u, world_normal.y > 0.0)];
+
+    return block_colours[index] * (weights.x + weights.z) + vertical * weights.y;
+}
+#endif
+
 @vertex 
 fn vertex(vertex: Vertex) -> VertexOut {
     var out: VertexOut;
 
-    // Unpack Vertex data into component parts
-    let x = f32(vertex.vert_data & x_bits(6u));
-    let y = f32((vertex.vert_data >> 6u) & x_bits(6u));
-    let z = f32((vertex.vert_data >> 12u) & x_bits(6u));
-    let ao = (vertex.vert_data >> 18u) & x_bits(3u);
-    let normal_index = (vertex.vert_data >> 21u) & x_bits(3u);
-    let block_index = (vertex.vert_data >> 24u) & x_bits(11u);
+#ifdef SMOOTH_VERTICES
+    // Smooth vertices have no AO or skylight
+    let local_normal = vertex.normal;
+    let face_shade = smooth_face_shade(vertex.normal);
+    let block_index = vertex.voxel_type;
+    let texture_layer = 0u;
+    let ao = 0u;
+    let sky_darkness = 0u;
+    let sun_shadow = 0u;
+    let biome_tint = vertex.biome_tint;
+
+    let local_pos = vec4<f32>(vertex.position, 1.0);
+#else
+#ifdef FAR_FACES
+    // Every vertex of a far face holds the whole face, the corner comes from its place in the face
+    let origin = vec3<f32>(
+        f32(vertex.face_data & x_bits(6u)),
+        f32((vertex.face_data >> 6u) & x_bits(6u)),
+        f32((vertex.face_data >> 12u) & x_bits(6u))
+    );
+    let width = f32(((vertex.face_data >> 18u) & x_bits(5u)) + 1u);
+    let height = f32(((vertex.face_data >> 23u) & x_bits(5u)) + 1u);
+    let normal_index = (vertex.face_data >> 28u) & x_bits(3u);
+    let block_index = 1u + (vertex.face_data >> 31u);
+#ifdef TRIPLANAR
+    let texture_layer = face_layer(block_index, normal_index);
+#else
+    let texture_layer = 0u;
+#endif
+    let ao = 0u;
+    let sky_darkness = 0u;
+    let sun_shadow = 0u;
+    let biome_tint = 0.5;
+
+    let corner = face_corners[vertex.vertex_index % 6u];
+    let local_pos = vec4<f32>(
+        origin + face_width_axes[normal_index] * corner.x * width + face_height_axes[normal_index] * corner.y * height,
+        1.0
+    );
+#else
+    // Unpack Vertex data into component parts, matches PackedVertex
+    let x = f32(vertex.vert_data.x & x_bits(9u));
+    let y = f32((vertex.vert_data.x >> 9u) & x_bits(9u));
+    let z = f32((vertex.vert_data.x >> 18u) & x_bits(9u));
+    let normal_index = (vertex.vert_data.x >> 27u) & x_bits(3u);
+    let ao = vertex.vert_data.x >> 30u;
+    let block_index = vertex.vert_data.y & x_bits(12u);
+    let sky_darkness = (vertex.vert_data.y >> 12u) & x_bits(4u);
+    let sun_shadow = (vertex.vert_data.y >> 16u) & x_bits(4u);
+    let texture_layer = vertex.vert_data.y >> 20u;
+    let biome_tint = vertex.biome_tint;
 
     let local_pos = vec4<f32>(x, y, z, 1.0); 
+#endif
+    let local_normal = normals[normal_index];
+    let face_shade = face_shades[normal_index];
+#endif
     let world_pos = get_world_from_local(vertex.instance_index) * local_pos;
 
     out.clip_pos = mesh_position_local_to_clip(
         get_world_from_local(vertex.instance_index),
         local_pos
     );
-    out.world_normal = mesh_normal_local_to_world(normals[normal_index], vertex.instance_index);
-    out.ambient = ambient_lerps[ao];
+    out.world_normal = mesh_normal_local_to_world(local_normal, vertex.instance_index);
+    out.ambient = 1.0;
+    if chunk_material.ao_enabled != 0u {
+        // Distant AO is flattened, its steps alias into shimmer
+        let ao_strength = chunk_material.ao_strength * mix(1.0, chunk_material.detail_fade_ao, detail_fade(world_pos.xyz));
+        out.ambient = mix(1.0, ambient_lerps[ao], ao_strength);
+    }
+    if chunk_material.face_shading_enabled != 0u {
+        out.ambient *= face_shade;
+    }
+    // Faces below the column's sky were darkened by their depth when meshed
+    if chunk_material.skylight_enabled != 0u {
+        out.ambient *= mix(1.0, chunk_material.skylight_min, f32(sky_darkness) / 15.0);
+    }
+    // Hills between the face and the sun were found when meshed, a day/night cycle fades them out with the sun
+    out.ambient *= 1.0 - chunk_material.sun_shadow_strength * f32(sun_shadow) / 15.0;
     out.world_pos = world_pos;
 
     let high = vec3<f32>(5.00, 0.2, 5.0);
@@ -91,6 +318,15 @@ fn vertex(vertex: Vertex) -> VertexOut {
     let noise = (out.world_pos.y) / 32.;
     out.blend_colour = ((low * noise) + (high * (1.0-noise)));
 
+    // Blend between the two ends of the biome ramp
+    out.biome_colour = mix(chunk_material.biome_tint_low.rgb, chunk_material.biome_tint_high.rgb, biome_tint);
+
+    // Water isn't coloured by height or biome
+    if block_index == 2u {
+        out.blend_colour = block_colour[block_index];
+        out.biome_colour = vec3<f32>(1.0);
+    }
+
     // if world_pos.y < regions[0] {
     //     out.blend_colour = region_colours[0];
     // } else if world_pos.y < regions[1] {
@@ -105,6 +341,8 @@ fn vertex(vertex: Vertex) -> VertexOut {
     
     // out.blend_colour = block_colour[block_index];
     out.instance_index = vertex.instance_index;
+    out.block_index = block_index;
+    out.texture_layer = texture_layer;
 
     return out;
 }
@@ -119,12 +357,31 @@ fn fragment(input: VertexOut) -> FragmentOutput {
     pbr_input.world_position = input.world_pos;
     pbr_input.world_normal = prepare_world_normal(input.world_normal, false, false);
 
-    pbr_input.material.base_color = vec4<f32>(input.blend_colour * input.ambient, 1.0);
+#ifdef TRIPLANAR
+#ifdef SMOOTH_VERTICES
+    let albedo = triplanar_colour(input.world_pos.xyz, input.world_normal, input.block_index);
+#else
+    let albedo = face_colour(input.world_pos.xyz, input.world_normal, input.block_index, input.texture_layer);
+#endif
+    // Texels smaller than a pixel shimmer as the camera moves, so distant textures fade to their average
+    let average = average_colour(input.world_normal, input.block_index);
+    let faded_albedo = mix(albedo, average.rgb, detail_fade(input.world_pos.xyz) * average.a);
+    pbr_input.material.base_color = vec4<f32>(faded_albedo * input.biome_colour * input.ambient, 1.0);
+#else
+    pbr_input.material.base_color = vec4<f32>(input.blend_colour * input.biome_colour * input.ambient, 1.0);
+#endif
 
     pbr_input.material.reflectance = chunk_material.reflectance;
     pbr_input.material.perceptual_roughness = chunk_material.perceptual_roughness;
     pbr_input.material.metallic = chunk_material.metallic;
 
+#ifdef SCREEN_SPACE_AMBIENT_OCCLUSION
+    // Screen space AO darkens the lighting, on top of the per-vertex AO in the base colour
+    let ssao = textureLoad(screen_space_ambient_occlusion_texture, vec2<i32>(input.clip_pos.xy), 0i).r;
+    pbr_input.diffuse_occlusion = vec3<f32>(ssao);
+    pbr_input.specular_occlusion = ssao;
+#endif
+
     var out: FragmentOutput;
     out.color = apply_pbr_lighting(pbr_input);
     out.color = main_pass_post_lighting_processing(pbr_input, out.color);
@@ -0,0 +1,165 @@
+#import bevy_pbr::{
+    mesh_functions::{get_world_from_local, get_previous_world_from_local, mesh_position_local_to_clip, mesh_normal_local_to_world},
+    mesh_view_bindings::view,
+    prepass_bindings,
+    prepass_io::FragmentOutput,
+}
+
+// Prepass for the packed chunk vertices, writes depth and (when requested) normals and motion vectors
+// Also used for the shadow passes, which only write depth
+
+#ifdef SMOOTH_VERTICES
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+#else
+#ifdef FAR_FACES
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) face_data: u32,
+};
+#else
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) vert_data: vec2<u32>,
+};
+#endif
+#endif
+
+struct VertexOut {
+    @builtin(position) clip_pos: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) world_position: vec4<f32>,
+#ifdef MOTION_VECTOR_PREPASS
+    @location(2) previous_world_position: vec4<f32>,
+#endif
+#ifdef DEPTH_CLAMP_ORTHO
+    @location(3) clip_position_unclamped: vec4<f32>,
+#endif
+}
+
+var<private> normals: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+	vec3<f32>(-1.0, 0.0, 0.0), // Left
+	vec3<f32>(1.0, 0.0, 0.0), // Right
+	vec3<f32>(0.0, 0.0, 1.0), // Back
+	vec3<f32>(0.0, 0.0, -1.0), // Front
+	vec3<f32>(0.0, 1.0, 0.0), // Up
+	vec3<f32>(0.0, -1.0, 0.0) // Down
+);
+
+// Far face tables, must match chunk.wgsl
+var<private> face_width_axes: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+	vec3<f32>(0.0, 0.0, 1.0), // Left
+	vec3<f32>(0.0, 1.0, 0.0), // Right
+	vec3<f32>(1.0, 0.0, 0.0), // Back
+	vec3<f32>(0.0, 1.0, 0.0), // Front
+	vec3<f32>(0.0, 0.0, 1.0), // Up
+	vec3<f32>(1.0, 0.0, 0.0) // Down
+);
+
+var<private> face_height_axes: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+	vec3<f32>(0.0, 1.0, 0.0), // Left
+	vec3<f32>(0.0, 0.0, 1.0), // Right
+	vec3<f32>(0.0, 1.0, 0.0), // Back
+	vec3<f32>(1.0, 0.0, 0.0), // Front
+	vec3<f32>(1.0, 0.0, 0.0), // Up
+	vec3<f32>(0.0, 0.0, 1.0) // Down
+);
+
+var<private> face_corners: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+	vec2<f32>(0.0, 0.0),
+	vec2<f32>(1.0, 0.0),
+	vec2<f32>(1.0, 1.0),
+	vec2<f32>(0.0, 0.0),
+	vec2<f32>(1.0, 1.0),
+	vec2<f32>(0.0, 1.0)
+);
+
+fn x_bits(bit_num: u32) -> u32 {
+    return (1u << bit_num) - 1u;
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOut {
+    var out: VertexOut;
+
+#ifdef SMOOTH_VERTICES
+    let local_pos = vec4<f32>(vertex.position, 1.0);
+    let local_normal = vertex.normal;
+#else
+#ifdef FAR_FACES
+    let origin = vec3<f32>(
+        f32(vertex.face_data & x_bits(6u)),
+        f32((vertex.face_data >> 6u) & x_bits(6u)),
+        f32((vertex.face_data >> 12u) & x_bits(6u))
+    );
+    let width = f32(((vertex.face_data >> 18u) & x_bits(5u)) + 1u);
+    let height = f32(((vertex.face_data >> 23u) & x_bits(5u)) + 1u);
+    let normal_index = (vertex.face_data >> 28u) & x_bits(3u);
+
+    let corner = face_corners[vertex.vertex_index % 6u];
+    let local_pos = vec4<f32>(
+        origin + face_width_axes[normal_index] * corner.x * width + face_height_axes[normal_index] * corner.y * height,
+        1.0
+    );
+    let local_normal = normals[normal_index];
+#else
+    let x = f32(vertex.vert_data.x & x_bits(9u));
+    let y = f32((vertex.vert_data.x >> 9u) & x_bits(9u));
+    let z = f32((vertex.vert_data.x >> 18u) & x_bits(9u));
+    let normal_index = (vertex.vert_data.x >> 27u) & x_bits(3u);
+
+    let local_pos = vec4<f32>(x, y, z, 1.0);
+    let local_normal = normals[normal_index];
+#endif
+#endif
+
+    out.clip_pos = mesh_position_local_to_clip(
+        get_world_from_local(vertex.instance_index),
+        local_pos
+    );
+    out.world_normal = mesh_normal_local_to_world(local_normal, vertex.instance_index);
+    out.world_position = get_world_from_local(vertex.instance_index) * local_pos;
+
+#ifdef MOTION_VECTOR_PREPASS
+    out.previous_world_position = get_previous_world_from_local(vertex.instance_index) * local_pos;
+#endif
+
+#ifdef DEPTH_CLAMP_ORTHO
+    // Directional shadow maps clamp depth so casters behind the near plane still cast shadows
+    out.clip_position_unclamped = out.clip_pos;
+    out.clip_pos.z = min(out.clip_pos.z, 1.0);
+#endif
+
+    return out;
+}
+
+#ifdef PREPASS_FRAGMENT
+@fragment
+fn fragment(input: VertexOut) -> FragmentOutput {
+    var out: FragmentOutput;
+
+#ifdef NORMAL_PREPASS
+    out.normal = vec4<f32>(normalize(input.world_normal) * 0.5 + vec3<f32>(0.5), 1.0);
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    // The camera still moves past the terrain, so TAA needs where each fragment was last frame.
+    // Scaled from clip space to UV offsets with V pointing down, like Bevy's prepass
+    let clip_position_t = view.unjittered_clip_from_world * input.world_position;
+    let clip_position = clip_position_t.xy / clip_position_t.w;
+    let previous_clip_position_t = prepass_bindings::previous_view_uniforms.clip_from_world * input.previous_world_position;
+    let previous_clip_position = previous_clip_position_t.xy / previous_clip_position_t.w;
+    out.motion_vector = (clip_position - previous_clip_position) * vec2<f32>(0.5, -0.5);
+#endif
+
+#ifdef DEPTH_CLAMP_ORTHO
+    out.frag_depth = input.clip_position_unclamped.z;
+#endif
+
+    return out;
+}
+#endif
@@ -0,0 +1,67 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct VoxelOutline {
+    colour: vec4<f32>,
+    thickness: f32,
+    depth_threshold: f32,
+    normal_threshold: f32,
+}
+
+@group(0) @binding(0) var screen_texture: texture_2d<f32>;
+@group(0) @binding(1) var texture_sampler: sampler;
+// With MSAA the prepass textures are multisampled, textureLoad then reads the first sample
+#ifdef MULTISAMPLED
+@group(0) @binding(2) var depth_texture: texture_depth_multisampled_2d;
+@group(0) @binding(3) var normal_texture: texture_multisampled_2d<f32>;
+#else
+@group(0) @binding(2) var depth_texture: texture_depth_2d;
+@group(0) @binding(3) var normal_texture: texture_2d<f32>;
+#endif
+@group(0) @binding(4) var<uniform> settings: VoxelOutline;
+
+var<private> sample_offsets: array<vec2<i32>, 4> = array<vec2<i32>, 4>(
+	vec2<i32>(1, 0),
+	vec2<i32>(-1, 0),
+	vec2<i32>(0, 1),
+	vec2<i32>(0, -1),
+);
+
+// Depth is reversed and infinite, so the distance to the camera is proportional to 1 / depth
+fn view_distance(coord: vec2<i32>) -> f32 {
+    return 1.0 / max(textureLoad(depth_texture, coord, 0), 0.000001);
+}
+
+fn world_normal(coord: vec2<i32>) -> vec3<f32> {
+    return textureLoad(normal_texture, coord, 0).xyz * 2.0 - vec3<f32>(1.0);
+}
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let colour = textureSample(screen_texture, texture_sampler, in.uv);
+
+    let coord = vec2<i32>(in.position.xy);
+    let max_coord = vec2<i32>(textureDimensions(depth_texture)) - vec2<i32>(1);
+
+    // Don't outline the sky
+    if textureLoad(depth_texture, coord, 0) == 0.0 {
+        return colour;
+    }
+
+    let centre_distance = view_distance(coord);
+    let centre_normal = world_normal(coord);
+
+    var edge = 0.0;
+    for (var i = 0; i < 4; i++) {
+        let sample_coord = clamp(coord + sample_offsets[i] * i32(settings.thickness), vec2<i32>(0), max_coord);
+
+        // Relative to the distance, so far away terrain isn't covered in lines
+        let depth_change = abs(view_distance(sample_coord) - centre_distance) / centre_distance;
+        let normal_change = 1.0 - dot(world_normal(sample_coord), centre_normal);
+
+        if depth_change > settings.depth_threshold || normal_change > settings.normal_threshold {
+            edge = 1.0;
+        }
+    }
+
+    return vec4<f32>(mix(colour.rgb, settings.colour.rgb, edge * settings.colour.a), colour.a);
+}
@@ -0,0 +1,18 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use cube_world::{chunk::Chunk, positions::ChunkPos};
+
+// Encoding and decoding of a generated chunk, as saving and loading it do
+fn chunk_bytes(c: &mut Criterion) {
+    let chunk = Chunk::new_from_noise(ChunkPos::new(0, 0, 0));
+    let bytes = chunk.to_bytes();
+
+    c.bench_function("chunk_to_bytes", |b| {
+        b.iter(|| black_box(&chunk).to_bytes())
+    });
+    c.bench_function("chunk_from_bytes", |b| {
+        b.iter(|| Chunk::from_bytes(black_box(&bytes)))
+    });
+}
+
+criterion_group!(benches, chunk_bytes);
+criterion_main!(benches);
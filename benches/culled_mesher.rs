@@ -0,0 +1,84 @@
+use bevy::math::{IVec3, UVec3};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use cube_world::{
+    constants::CHUNK_SIZE,
+    lod::Lod,
+    meshing::{mesh_chunk_sync, MesherKind},
+    voxel::VoxelType,
+    voxel_grid::VoxelGrid,
+};
+
+// A chunk's voxels with a layer of padding on each side, filled ahead of time so the bench only
+// times the mesher
+struct Grid {
+    voxels: Vec<VoxelType>,
+}
+
+impl Grid {
+    const PADDED: i32 = CHUNK_SIZE as i32 + 2;
+
+    fn from_fn(voxel_at: impl Fn(IVec3) -> VoxelType) -> Self {
+        let mut voxels = Vec::new();
+        for z in -1..Self::PADDED - 1 {
+            for y in -1..Self::PADDED - 1 {
+                for x in -1..Self::PADDED - 1 {
+                    voxels.push(voxel_at(IVec3::new(x, y, z)));
+                }
+            }
+        }
+
+        Self { voxels }
+    }
+}
+
+impl VoxelGrid for Grid {
+    fn size(&self) -> UVec3 {
+        UVec3::splat(CHUNK_SIZE as u32)
+    }
+
+    fn voxel_at(&self, pos: IVec3) -> VoxelType {
+        let pos = pos + 1;
+        self.voxels[((pos.z * Self::PADDED + pos.y) * Self::PADDED + pos.x) as usize]
+    }
+}
+
+// Rolling hills through the middle of the chunk, the usual case
+fn hills(pos: IVec3) -> VoxelType {
+    let height = 16. + (pos.x as f32 * 0.3).sin() * 6. + (pos.z as f32 * 0.2).cos() * 6.;
+
+    if (pos.y as f32) < height {
+        VoxelType::BLOCK
+    } else {
+        VoxelType::AIR
+    }
+}
+
+// Solid in about a quarter of the voxels, from a hash of the position, the worst case
+fn scattered(pos: IVec3) -> VoxelType {
+    let hash = (pos.x.wrapping_mul(73_856_093)
+        ^ pos.y.wrapping_mul(19_349_663)
+        ^ pos.z.wrapping_mul(83_492_791)) as u32;
+
+    if hash.wrapping_mul(2_654_435_761) >> 30 == 0 {
+        VoxelType::BLOCK
+    } else {
+        VoxelType::AIR
+    }
+}
+
+// Culled meshes of a whole chunk in full detail
+fn culled_mesher(c: &mut Criterion) {
+    for (name, voxel_at) in [
+        ("culled_mesh_hills", hills as fn(IVec3) -> VoxelType),
+        ("culled_mesh_scattered", scattered),
+    ] {
+        let grid = Grid::from_fn(voxel_at);
+
+        c.bench_function(name, |b| {
+            b.iter(|| mesh_chunk_sync(black_box(&grid), Lod::L32, MesherKind::Culled))
+        });
+    }
+}
+
+criterion_group!(benches, culled_mesher);
+criterion_main!(benches);
@@ -0,0 +1,411 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use crate::{
+    byte_codec::ByteReader,
+    chunk::Chunk,
+    constants::{ANVIL_COLUMN_CACHE_CAPACITY, CHUNK_HEIGHT, CHUNK_SIZE},
+    positions::{ChunkPos, VoxelPos, WorldPos},
+    voxel::VoxelType,
+    world_generator::WorldGenerator,
+};
+
+// Read-only import of Minecraft Anvil (.mca) region files, one Minecraft block per voxel
+// Sections are read from the 1.16+ block state layout (1.18+ "sections", or "Level"/"Sections"
+// before that), older worlds whose block indices span longs come out as air
+
+// Minecraft chunks are 16x16 columns of 16x16x16 sections, regions are 32x32 chunks
+const SECTION_SIZE: i32 = 16;
+const SECTION_VOLUME: usize = 16 * 16 * 16;
+const REGION_CHUNKS: i32 = 32;
+const REGION_SECTOR_SIZE: u64 = 4096;
+
+// Block names (without the "minecraft:" namespace) which don't fill their voxel
+const AIR_BLOCKS: [&str; 4] = ["air", "cave_air", "void_air", "structure_void"];
+const WATER_BLOCKS: [&str; 6] = [
+    "water",
+    "bubble_column",
+    "seagrass",
+    "tall_seagrass",
+    "kelp",
+    "kelp_plant",
+];
+
+// Map a block state to the engine's voxel types, anything which isn't air or water is a block
+pub fn voxel_type_from_block_state(name: &str) -> VoxelType {
+    let name = name.strip_prefix("minecraft:").unwrap_or(name);
+
+    if AIR_BLOCKS.contains(&name) {
+        VoxelType::AIR
+    } else if WATER_BLOCKS.contains(&name) {
+        VoxelType::WATER
+    } else {
+        VoxelType::BLOCK
+    }
+}
+
+// Streams chunks out of a Minecraft world's region directory, so it can be explored like a
+// generated world. Saved edits still take priority, as with any other generator
+pub struct AnvilGenerator {
+    region_directory: PathBuf,
+    // Added to Minecraft's y to get the engine's
+    y_offset: i32,
+    // Recently read chunk columns, as every engine chunk in a column needs the same ones
+    columns: Mutex<ColumnCache>,
+}
+
+#[derive(Default)]
+struct ColumnCache {
+    entries: HashMap<(i32, i32), Arc<AnvilColumn>>,
+    // Insertion order, the oldest column is evicted first
+    order: VecDeque<(i32, i32)>,
+}
+
+impl AnvilGenerator {
+    pub fn new(region_directory: impl Into<PathBuf>, y_offset: i32) -> Self {
+        Self {
+            region_directory: region_directory.into(),
+            y_offset,
+            columns: Mutex::default(),
+        }
+    }
+
+    fn column(&self, column_x: i32, column_z: i32) -> Arc<AnvilColumn> {
+        let key = (column_x, column_z);
+        if let Some(column) = self.columns.lock().unwrap().entries.get(&key) {
+            return column.clone();
+        }
+
+        // Read outside the lock, two tasks reading the same column just do the work twice
+        let column = Arc::new(self.read_column(column_x, column_z).unwrap_or_default());
+
+        let mut cache = self.columns.lock().unwrap();
+        if cache.entries.insert(key, column.clone()).is_none() {
+            cache.order.push_back(key);
+        }
+        while cache.entries.len() > ANVIL_COLUMN_CACHE_CAPACITY {
+            let Some(oldest) = cache.order.pop_front() else {
+                break;
+            };
+            cache.entries.remove(&oldest);
+        }
+
+        column
+    }
+
+    // None if the column was never generated in Minecraft or can't be read
+    fn read_column(&self, column_x: i32, column_z: i32) -> Option<AnvilColumn> {
+        let (region_x, region_z) = (
+            column_x.div_euclid(REGION_CHUNKS),
+            column_z.div_euclid(REGION_CHUNKS),
+        );
+        let path = self
+            .region_directory
+            .join(format!("r.{region_x}.{region_z}.mca"));
+        let mut file = File::open(path).ok()?;
+
+        // The header's first sector holds a location for each chunk: a 3 byte sector offset and a
+        // 1 byte sector count, big endian
+        let index = (column_x.rem_euclid(REGION_CHUNKS)
+            + column_z.rem_euclid(REGION_CHUNKS) * REGION_CHUNKS) as u64;
+        let mut location = [0; 4];
+        file.seek(SeekFrom::Start(index * 4)).ok()?;
+        file.read_exact(&mut location).ok()?;
+
+        let sector = u32::from_be_bytes([0, location[0], location[1], location[2]]) as u64;
+        if sector == 0 {
+            return None;
+        }
+
+        // The chunk's length (including the compression byte), the compression, then the data
+        let mut header = [0; 5];
+        file.seek(SeekFrom::Start(sector * REGION_SECTOR_SIZE))
+            .ok()?;
+        file.read_exact(&mut header).ok()?;
+
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let mut compressed = Vec::new();
+        file.take(length.saturating_sub(1))
+            .read_to_end(&mut compressed)
+            .ok()?;
+
+        let mut bytes = Vec::new();
+        match header[4] {
+            1 => GzDecoder::new(compressed.as_slice())
+                .read_to_end(&mut bytes)
+                .ok()?,
+            2 => ZlibDecoder::new(compressed.as_slice())
+                .read_to_end(&mut bytes)
+                .ok()?,
+            3 => {
+                bytes = compressed;
+                bytes.len()
+            }
+            // LZ4, custom and external (oversized) chunks
+            compression => {
+                warn!("Anvil chunk ({column_x}, {column_z}) uses unsupported compression {compression}");
+                return None;
+            }
+        };
+
+        let column = AnvilColumn::from_nbt(&Nbt::read_root(&bytes)?);
+        if column.is_none() {
+            warn!("Anvil chunk ({column_x}, {column_z}) couldn't be read, importing it as air");
+        }
+
+        column
+    }
+}
+
+impl WorldGenerator for AnvilGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        // Fetch the columns the chunk covers once, rather than locking the cache for every voxel
+        let min = WorldPos::from_voxel_pos(VoxelPos::new(0, 0, 0), chunk_pos);
+        let max = WorldPos::from_voxel_pos(
+            VoxelPos::new(CHUNK_SIZE - 1, CHUNK_HEIGHT - 1, CHUNK_SIZE - 1),
+            chunk_pos,
+        );
+        let (min_column, max_column) = (
+            IVec2::new(min.x, min.z).div_euclid(IVec2::splat(SECTION_SIZE)),
+            IVec2::new(max.x, max.z).div_euclid(IVec2::splat(SECTION_SIZE)),
+        );
+
+        let columns = (min_column.x..=max_column.x)
+            .flat_map(|x| (min_column.y..=max_column.y).map(move |z| (x, z)))
+            .map(|(x, z)| ((x, z), self.column(x, z)))
+            .collect::<HashMap<_, _>>();
+
+        Chunk::from_fn(chunk_pos, |world_pos| {
+            let WorldPos { x, y, z } = world_pos;
+
+            columns[&(x.div_euclid(SECTION_SIZE), z.div_euclid(SECTION_SIZE))].voxel_type(
+                x.rem_euclid(SECTION_SIZE),
+                y - self.y_offset,
+                z.rem_euclid(SECTION_SIZE),
+            )
+        })
+    }
+}
+
+enum AnvilSection {
+    Uniform(VoxelType),
+    // In Minecraft's order, x + (z + y * 16) * 16
+    Voxels(Vec<VoxelType>),
+}
+
+// The sections of a Minecraft chunk by their y, missing sections are air
+#[derive(Default)]
+struct AnvilColumn {
+    sections: HashMap<i32, AnvilSection>,
+}
+
+impl AnvilColumn {
+    fn voxel_type(&self, x: i32, y: i32, z: i32) -> VoxelType {
+        let section_y = y.div_euclid(SECTION_SIZE);
+        let y = y.rem_euclid(SECTION_SIZE);
+
+        match self.sections.get(&section_y) {
+            Some(AnvilSection::Uniform(voxel_type)) => *voxel_type,
+            Some(AnvilSection::Voxels(voxels)) => {
+                voxels[(x + (z + y * SECTION_SIZE) * SECTION_SIZE) as usize]
+            }
+            None => VoxelType::AIR,
+        }
+    }
+
+    fn from_nbt(root: &Nbt) -> Option<Self> {
+        // 1.18 moved the sections out of "Level" and the palette into "block_states"
+        let (sections, palette_key, data_key) = match root.get("sections") {
+            Some(sections) => (sections, "palette", "data"),
+            None => (
+                root.get("Level")?.get("Sections")?,
+                "Palette",
+                "BlockStates",
+            ),
+        };
+
+        let Nbt::List(sections) = sections else {
+            return None;
+        };
+
+        let mut column = Self::default();
+        for section in sections {
+            let Some(Nbt::Byte(section_y)) = section.get("Y") else {
+                continue;
+            };
+            let states = section.get("block_states").unwrap_or(section);
+
+            // Sections without blocks (lighting only) have no palette
+            let Some(Nbt::List(palette)) = states.get(palette_key) else {
+                continue;
+            };
+            let palette = palette
+                .iter()
+                .map(|state| match state.get("Name") {
+                    Some(Nbt::String(name)) => Some(voxel_type_from_block_state(name)),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            let section = match (palette.as_slice(), states.get(data_key)) {
+                ([voxel_type], _) => AnvilSection::Uniform(*voxel_type),
+                (_, Some(Nbt::LongArray(data))) => match unpack_block_states(data, palette.len()) {
+                    Some(indices) => AnvilSection::Voxels(
+                        indices
+                            .into_iter()
+                            .map(|index| palette.get(index).copied().unwrap_or(VoxelType::AIR))
+                            .collect(),
+                    ),
+                    None => continue,
+                },
+                _ => continue,
+            };
+
+            column.sections.insert(*section_y as i32, section);
+        }
+
+        Some(column)
+    }
+}
+
+// Palette indices packed into longs, at least 4 bits each and never spanning two longs
+// None for the spanning layout from before 1.16
+fn unpack_block_states(data: &[i64], palette_len: usize) -> Option<Vec<usize>> {
+    let bits = (usize::BITS - (palette_len - 1).leading_zeros()).max(4) as usize;
+    let per_long = 64 / bits;
+
+    if data.len() != SECTION_VOLUME.div_ceil(per_long) {
+        return None;
+    }
+
+    let mask = (1 << bits) - 1;
+    Some(
+        (0..SECTION_VOLUME)
+            .map(|i| ((data[i / per_long] as u64 >> ((i % per_long) * bits)) & mask) as usize)
+            .collect(),
+    )
+}
+
+// Minecraft's named binary tag format, big endian
+#[derive(Debug, Clone, PartialEq)]
+enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(HashMap<String, Nbt>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+// Deeper tags than this are treated as corrupt rather than risking the stack
+const NBT_MAX_DEPTH: usize = 512;
+
+impl Nbt {
+    fn get(&self, key: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(tags) => tags.get(key),
+            _ => None,
+        }
+    }
+
+    // The root is a named compound, the name is always empty in chunks
+    fn read_root(bytes: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader::new(bytes);
+
+        let tag_type = reader.read_u8()?;
+        read_string(&mut reader)?;
+
+        Self::read(&mut reader, tag_type, 0).filter(|root| matches!(root, Nbt::Compound(_)))
+    }
+
+    fn read(reader: &mut ByteReader, tag_type: u8, depth: usize) -> Option<Self> {
+        if depth > NBT_MAX_DEPTH {
+            return None;
+        }
+
+        Some(match tag_type {
+            1 => Nbt::Byte(reader.read_u8()? as i8),
+            2 => Nbt::Short(i16::from_be_bytes(read_array(reader)?)),
+            3 => Nbt::Int(i32::from_be_bytes(read_array(reader)?)),
+            4 => Nbt::Long(i64::from_be_bytes(read_array(reader)?)),
+            5 => Nbt::Float(f32::from_be_bytes(read_array(reader)?)),
+            6 => Nbt::Double(f64::from_be_bytes(read_array(reader)?)),
+            7 => {
+                let len = read_len(reader)?;
+                Nbt::ByteArray(reader.read_bytes(len)?.to_vec())
+            }
+            8 => Nbt::String(read_string(reader)?),
+            9 => {
+                let element_type = reader.read_u8()?;
+                let len = read_len(reader)?;
+
+                Nbt::List(
+                    (0..len)
+                        .map(|_| Self::read(reader, element_type, depth + 1))
+                        .collect::<Option<_>>()?,
+                )
+            }
+            10 => {
+                let mut tags = HashMap::new();
+                loop {
+                    let tag_type = reader.read_u8()?;
+                    if tag_type == 0 {
+                        break;
+                    }
+
+                    let name = read_string(reader)?;
+                    tags.insert(name, Self::read(reader, tag_type, depth + 1)?);
+                }
+
+                Nbt::Compound(tags)
+            }
+            11 => {
+                let len = read_len(reader)?;
+                Nbt::IntArray(
+                    (0..len)
+                        .map(|_| read_array(reader).map(i32::from_be_bytes))
+                        .collect::<Option<_>>()?,
+                )
+            }
+            12 => {
+                let len = read_len(reader)?;
+                Nbt::LongArray(
+                    (0..len)
+                        .map(|_| read_array(reader).map(i64::from_be_bytes))
+                        .collect::<Option<_>>()?,
+                )
+            }
+            _ => return None,
+        })
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut ByteReader) -> Option<[u8; N]> {
+    reader.read_bytes(N)?.try_into().ok()
+}
+
+// Negative lengths are corrupt, lengths past the end of the data fail when the elements are read
+fn read_len(reader: &mut ByteReader) -> Option<usize> {
+    usize::try_from(i32::from_be_bytes(read_array(reader)?)).ok()
+}
+
+// Modified UTF-8, which only differs from UTF-8 for nulls and characters outside the BMP
+fn read_string(reader: &mut ByteReader) -> Option<String> {
+    let len = u16::from_be_bytes(read_array(reader)?) as usize;
+
+    Some(String::from_utf8_lossy(reader.read_bytes(len)?).into_owned())
+}
@@ -0,0 +1,136 @@
+use bevy::{
+    prelude::*,
+    window::{PrimaryWindow, WindowOccluded},
+};
+
+use crate::constants::{
+    BACKGROUND_MESH_TASKS, MAX_MESH_TASKS, RESUME_BURST_FRAMES, RESUME_BURST_MESH_TASKS,
+};
+
+// Slows down chunk meshing while the window is in the background, to save CPU and GPU time
+// Meshing catches up with a burst once the window is back in the foreground
+pub struct BackgroundThrottlePlugin;
+
+impl Plugin for BackgroundThrottlePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BackgroundPolicy>()
+            .init_resource::<MeshingPace>()
+            .register_type::<BackgroundPolicy>()
+            .register_type::<MeshingPace>()
+            .add_systems(PreUpdate, MeshingPace::update);
+    }
+}
+
+#[derive(Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BackgroundMode {
+    Full,
+    // Limit the number of mesh tasks in flight
+    Throttled(usize),
+    // Start no mesh tasks and spawn no mesh entities
+    Paused,
+}
+
+// What meshing does while the window is in the background
+#[derive(Resource, Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct BackgroundPolicy {
+    pub unfocused: BackgroundMode,
+    // Also used when the window is fully covered by other windows
+    pub minimized: BackgroundMode,
+    // Frames of extra mesh tasks after returning to the foreground
+    pub resume_burst_frames: u32,
+}
+
+impl Default for BackgroundPolicy {
+    fn default() -> Self {
+        Self {
+            unfocused: BackgroundMode::Throttled(BACKGROUND_MESH_TASKS),
+            minimized: BackgroundMode::Paused,
+            resume_burst_frames: RESUME_BURST_FRAMES,
+        }
+    }
+}
+
+// How fast the world is allowed to mesh this frame, decided from the window state and the policy
+#[derive(Resource, Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct MeshingPace {
+    pub max_mesh_tasks: usize,
+    pub paused: bool,
+    pub burst_frames_left: u32,
+    // Set by WindowOccluded, which is the only report of minimization on some platforms
+    pub occluded: bool,
+    pub in_background: bool,
+}
+
+impl Default for MeshingPace {
+    fn default() -> Self {
+        Self {
+            max_mesh_tasks: MAX_MESH_TASKS,
+            paused: false,
+            burst_frames_left: 0,
+            occluded: false,
+            in_background: false,
+        }
+    }
+}
+
+impl MeshingPace {
+    // Run condition for the systems which spawn mesh tasks or mesh entities
+    pub fn is_running(pace: Res<MeshingPace>) -> bool {
+        !pace.paused
+    }
+
+    fn update(
+        mut pace: ResMut<MeshingPace>,
+        policy: Res<BackgroundPolicy>,
+        windows: Query<(Entity, &Window), With<PrimaryWindow>>,
+        mut occluded_events: EventReader<WindowOccluded>,
+    ) {
+        let Ok((window_entity, window)) = windows.get_single() else {
+            return;
+        };
+
+        for event in occluded_events.read() {
+            if event.window == window_entity {
+                pace.occluded = event.occluded;
+            }
+        }
+
+        // Minimized windows report a zero size on some platforms
+        let minimized = pace.occluded
+            || window.resolution.physical_width() == 0
+            || window.resolution.physical_height() == 0;
+
+        let mode = if minimized {
+            policy.minimized
+        } else if !window.focused {
+            policy.unfocused
+        } else {
+            BackgroundMode::Full
+        };
+
+        let in_background = mode != BackgroundMode::Full;
+        if pace.in_background && !in_background {
+            pace.burst_frames_left = policy.resume_burst_frames;
+        }
+        pace.in_background = in_background;
+
+        let (max_mesh_tasks, paused) = match mode {
+            BackgroundMode::Full if pace.burst_frames_left > 0 => {
+                pace.burst_frames_left -= 1;
+                (RESUME_BURST_MESH_TASKS, false)
+            }
+            BackgroundMode::Full => (MAX_MESH_TASKS, false),
+            BackgroundMode::Throttled(max_mesh_tasks) => (max_mesh_tasks, false),
+            BackgroundMode::Paused => (0, true),
+        };
+
+        // Only write when something changed, so change detection stays useful
+        pace.set_if_neq(MeshingPace {
+            max_mesh_tasks,
+            paused,
+            ..*pace
+        });
+    }
+}
@@ -0,0 +1,63 @@
+use bracket_noise::prelude::*;
+
+use crate::{
+    chunk_mesh::ChunkMesh,
+    constants::{BIOME_COUNT, BIOME_FREQUENCY, CHUNK_EXTENT},
+    positions::{ChunkPos, WorldPos},
+    vertex::Vertex,
+    world_generator,
+};
+
+// Low frequency noise deciding which biome a column of voxels belongs to
+pub struct BiomeMap {
+    noise: FastNoise,
+}
+
+impl Default for BiomeMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BiomeMap {
+    pub fn new() -> Self {
+        let mut noise = FastNoise::seeded(world_generator::seed() + 1);
+        noise.set_noise_type(NoiseType::Simplex);
+        noise.set_frequency(BIOME_FREQUENCY);
+
+        Self { noise }
+    }
+
+    // Biome tint in the range 0..=1, used to index into the material's tint ramp
+    pub fn tint_at(&self, x: i32, z: i32) -> f32 {
+        (self.noise.get_noise(x as f32, z as f32) * 0.5 + 0.5).clamp(0., 1.)
+    }
+
+    // Id of the biome a column belongs to, in the range 0..BIOME_COUNT
+    pub fn biome_at(&self, x: i32, z: i32) -> usize {
+        ((self.tint_at(x, z) * BIOME_COUNT as f32) as usize).min(BIOME_COUNT - 1)
+    }
+
+    // Sample a tint for every vertex of the mesh, so that tints blend smoothly across quads
+    pub fn apply_tints(&self, mesh: &mut ChunkMesh, chunk_pos: ChunkPos) {
+        if mesh.is_smooth() {
+            // Smooth vertices lie between voxels, so they're tinted by the column they're over
+            let chunk_min = chunk_pos.to_ivec3() * CHUNK_EXTENT;
+            mesh.biome_tints.clear();
+            mesh.biome_tints
+                .extend(mesh.smooth_vertices.iter().map(|vertex| {
+                    let column = chunk_min + vertex.pos.floor().as_ivec3();
+                    self.tint_at(column.x, column.z)
+                }));
+
+            return;
+        }
+
+        // Pooled meshes keep the tint buffer of the last mesh they held
+        mesh.biome_tints.clear();
+        mesh.biome_tints.extend(mesh.vertices.iter().map(|&vertex| {
+            let world_pos = WorldPos::from_voxel_pos(Vertex::from(vertex).pos, chunk_pos);
+            self.tint_at(world_pos.x, world_pos.z)
+        }));
+    }
+}
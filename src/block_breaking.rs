@@ -0,0 +1,333 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    constants::{BREAKING_BUTTON, BREAKING_STAGES, CRACK_TEXTURE_SIZE},
+    positions::{VoxelScale, WorldPos},
+    voxel::VoxelType,
+    voxel_picking::{VoxelHit, VoxelPicking},
+    world::World,
+};
+
+// Minecraft-style mining: holding the button on a voxel breaks it after its hardness (in seconds)
+// Breaking restarts when the target changes, and a crack decal on the targeted face shows the progress
+// Games can follow BlockBreakingEvent to drop items or play sounds
+pub struct BlockBreakingPlugin;
+
+impl Plugin for BlockBreakingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BlockBreaking>()
+            .add_event::<BlockBreakingEvent>()
+            .add_systems(Startup, CrackDecal::create_materials)
+            .add_systems(
+                Update,
+                (BlockBreaking::update, CrackDecal::update)
+                    .chain()
+                    .after(VoxelPicking::update_hovered),
+            );
+    }
+}
+
+#[derive(Event, Debug, Copy, Clone, PartialEq)]
+pub enum BlockBreakingEvent {
+    Started {
+        world_pos: WorldPos,
+        voxel_type: VoxelType,
+    },
+    // Sent every frame while breaking, progress runs from 0 to 1
+    Progress {
+        world_pos: WorldPos,
+        voxel_type: VoxelType,
+        progress: f32,
+    },
+    // The button was released, or the target changed, before the voxel broke
+    Cancelled {
+        world_pos: WorldPos,
+    },
+    Broken {
+        world_pos: WorldPos,
+        voxel_type: VoxelType,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BreakingState {
+    Idle,
+    Breaking {
+        hit: VoxelHit,
+        voxel_type: VoxelType,
+        // Seconds spent on the voxel, scaled by the breaking speed
+        elapsed: f32,
+    },
+}
+
+#[derive(Resource, Debug)]
+pub struct BlockBreaking {
+    pub enabled: bool,
+    pub button: MouseButton,
+    // Multiplies the time spent breaking, e.g. for faster tools
+    pub speed: f32,
+    pub state: BreakingState,
+}
+
+impl Default for BlockBreaking {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            button: BREAKING_BUTTON,
+            speed: 1.,
+            state: BreakingState::Idle,
+        }
+    }
+}
+
+impl BlockBreaking {
+    // From 0 to 1, None while nothing is being broken
+    pub fn progress(&self) -> Option<f32> {
+        match self.state {
+            BreakingState::Idle => None,
+            BreakingState::Breaking {
+                voxel_type,
+                elapsed,
+                ..
+            } => Some(breaking_progress(voxel_type, elapsed)),
+        }
+    }
+
+    fn update(
+        mut breaking: ResMut<BlockBreaking>,
+        mut world: ResMut<World>,
+        picking: Res<VoxelPicking>,
+        mouse_buttons: Res<ButtonInput<MouseButton>>,
+        time: Res<Time>,
+        mut events: EventWriter<BlockBreakingEvent>,
+    ) {
+        let held = breaking.enabled && mouse_buttons.pressed(breaking.button);
+        let target = picking.hovered.filter(|_| held).and_then(|hit| {
+            let voxel_type = world.get_voxel(hit.world_pos)?.voxel_type;
+            // Negative hardness can't be broken
+            (voxel_type.hardness() >= 0.).then_some((hit, voxel_type))
+        });
+
+        let (hit, voxel_type, elapsed) = match (breaking.state, target) {
+            (_, None) => {
+                if let BreakingState::Breaking { hit, .. } = breaking.state {
+                    events.send(BlockBreakingEvent::Cancelled {
+                        world_pos: hit.world_pos,
+                    });
+                }
+                breaking.state = BreakingState::Idle;
+                return;
+            }
+            // Edits by anything else to the voxel restart breaking, like moving to another voxel
+            (
+                BreakingState::Breaking {
+                    hit: current,
+                    voxel_type: current_type,
+                    elapsed,
+                },
+                Some((hit, voxel_type)),
+            ) if current.world_pos == hit.world_pos && current_type == voxel_type => (
+                hit,
+                voxel_type,
+                elapsed + time.delta_seconds() * breaking.speed,
+            ),
+            (state, Some((hit, voxel_type))) => {
+                if let BreakingState::Breaking { hit: current, .. } = state {
+                    events.send(BlockBreakingEvent::Cancelled {
+                        world_pos: current.world_pos,
+                    });
+                }
+                events.send(BlockBreakingEvent::Started {
+                    world_pos: hit.world_pos,
+                    voxel_type,
+                });
+
+                (hit, voxel_type, 0.)
+            }
+        };
+
+        let progress = breaking_progress(voxel_type, elapsed);
+        events.send(BlockBreakingEvent::Progress {
+            world_pos: hit.world_pos,
+            voxel_type,
+            progress,
+        });
+
+        if progress < 1. {
+            breaking.state = BreakingState::Breaking {
+                hit,
+                voxel_type,
+                elapsed,
+            };
+            return;
+        }
+
+        breaking.state = BreakingState::Idle;
+        match world.set_voxel(hit.world_pos, VoxelType::AIR) {
+            Ok(_) => {
+                events.send(BlockBreakingEvent::Broken {
+                    world_pos: hit.world_pos,
+                    voxel_type,
+                });
+            }
+            Err(err) => {
+                warn!("Failed to break {:?}: {err}", hit.world_pos);
+                events.send(BlockBreakingEvent::Cancelled {
+                    world_pos: hit.world_pos,
+                });
+            }
+        }
+    }
+}
+
+// Voxels without hardness break straight away
+fn breaking_progress(voxel_type: VoxelType, elapsed: f32) -> f32 {
+    let hardness = voxel_type.hardness();
+    if hardness <= 0. {
+        return 1.;
+    }
+
+    (elapsed / hardness).min(1.)
+}
+
+// Cracks drawn just in front of the face being broken, growing through BREAKING_STAGES textures
+#[derive(Component, Debug)]
+pub struct CrackDecal;
+
+#[derive(Resource, Debug)]
+pub struct CrackMaterials(pub Vec<Handle<StandardMaterial>>);
+
+impl CrackDecal {
+    fn create_materials(
+        mut commands: Commands,
+        mut images: ResMut<Assets<Image>>,
+        mut materials: ResMut<Assets<StandardMaterial>>,
+    ) {
+        let cracks = crack_pixels(CRACK_TEXTURE_SIZE);
+        let size = CRACK_TEXTURE_SIZE as usize;
+
+        let stages = (1..=BREAKING_STAGES)
+            .map(|stage| {
+                // Each stage shows more of the cracks than the last
+                let mut data = vec![0; size * size * 4];
+                for &(x, y) in &cracks[..cracks.len() * stage / BREAKING_STAGES] {
+                    let index = (y as usize * size + x as usize) * 4;
+                    data[index..index + 4].copy_from_slice(&[20, 20, 20, 220]);
+                }
+
+                let mut image = Image::new(
+                    Extent3d {
+                        width: CRACK_TEXTURE_SIZE,
+                        height: CRACK_TEXTURE_SIZE,
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    data,
+                    TextureFormat::Rgba8UnormSrgb,
+                    RenderAssetUsages::RENDER_WORLD,
+                );
+                image.sampler = ImageSampler::nearest();
+
+                materials.add(StandardMaterial {
+                    base_color_texture: Some(images.add(image)),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                })
+            })
+            .collect();
+
+        commands.insert_resource(CrackMaterials(stages));
+    }
+
+    fn update(
+        mut commands: Commands,
+        breaking: Res<BlockBreaking>,
+        crack_materials: Res<CrackMaterials>,
+        voxel_scale: Res<VoxelScale>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut decals: Query<
+            (Entity, &mut Transform, &mut Handle<StandardMaterial>),
+            With<CrackDecal>,
+        >,
+    ) {
+        let target = match breaking.state {
+            // Rays starting inside a voxel have no face to draw on
+            BreakingState::Breaking { hit, .. } if hit.normal != IVec3::ZERO => Some(hit),
+            _ => None,
+        };
+        let (Some(hit), Some(progress)) = (target, breaking.progress()) else {
+            for (entity, ..) in decals.iter() {
+                commands.entity(entity).despawn();
+            }
+            return;
+        };
+
+        let stage = ((progress * BREAKING_STAGES as f32) as usize).min(BREAKING_STAGES - 1);
+        let material = crack_materials.0[stage].clone();
+
+        // The face's centre, nudged out of the face so it doesn't z-fight with the chunk mesh
+        let normal = hit.normal.as_vec3();
+        let centre = voxel_scale.to_translation(hit.world_pos)
+            + (Vec3::splat(0.5) + normal * 0.502) * voxel_scale.0;
+        let transform = Transform::from_translation(centre)
+            .with_rotation(Quat::from_rotation_arc(Vec3::Z, normal))
+            .with_scale(Vec3::splat(voxel_scale.0));
+
+        if let Ok((_, mut decal_transform, mut decal_material)) = decals.get_single_mut() {
+            decal_transform.set_if_neq(transform);
+            if *decal_material != material {
+                *decal_material = material;
+            }
+            return;
+        }
+
+        commands.spawn((
+            CrackDecal,
+            Name::new("Crack decal"),
+            PbrBundle {
+                mesh: meshes.add(Rectangle::new(1., 1.)),
+                material,
+                transform,
+                ..default()
+            },
+        ));
+    }
+}
+
+// Pixels of a few cracks branching out from the centre of the texture, in the order they appear
+fn crack_pixels(size: u32) -> Vec<(u32, u32)> {
+    const BRANCHES: u64 = 6;
+
+    let centre = IVec2::splat(size as i32 / 2);
+    let mut pixels = vec![(centre.x as u32, centre.y as u32)];
+
+    // The branches grow a step each in turn, so every stage spreads the cracks further out
+    let mut tips = vec![centre; BRANCHES as usize];
+    for step in 0..size as u64 {
+        for (branch, tip) in tips.iter_mut().enumerate() {
+            // Each branch heads away from the centre in its own direction, wandering a little
+            let angle = branch as f32 / BRANCHES as f32 * std::f32::consts::TAU;
+            let wander = (xxh3_64(&[branch as u8, step as u8]) % 3) as i32 - 1;
+            let direction = Vec2::from_angle(angle).round().as_ivec2();
+            let side = IVec2::new(-direction.y, direction.x) * wander;
+
+            *tip = (*tip + direction + side).clamp(IVec2::ZERO, IVec2::splat(size as i32 - 1));
+
+            let pixel = (tip.x as u32, tip.y as u32);
+            if !pixels.contains(&pixel) {
+                pixels.push(pixel);
+            }
+        }
+    }
+
+    pixels
+}
@@ -0,0 +1,326 @@
+use std::{collections::HashMap, fs, path::Path, sync::OnceLock};
+
+use bevy::{app::AppExit, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::{BLOCK_REGISTRY_PATH, SAVED_BLOCK_IDS_FILE},
+    persistence,
+    vertex::{MAX_TEXTURE_LAYER, MAX_VERTEX_VOXEL_TYPE},
+    voxel::VoxelType,
+};
+
+// Block definitions shipped with the game, used when the registry file is missing or invalid
+const BUILTIN_BLOCKS: &str = include_str!("../assets/blocks.ron");
+
+static BLOCK_REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+// Loads the block definitions, and checks the ids saved chunks were written with still match them
+// The registry is read once, as the generation and meshing tasks need it before the asset server
+// has loaded anything, so changes to the definitions need a restart
+pub struct BlockRegistryPlugin;
+
+impl Plugin for BlockRegistryPlugin {
+    fn build(&self, app: &mut App) {
+        let registry = BlockRegistry::global();
+        info!("Loaded {} block definitions", registry.len());
+
+        app.add_systems(Startup, BlockRegistry::check_save_ids);
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BlockTextures {
+    pub side: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+// A block as defined in the registry file, everything but the id and name has a default
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockDefinition {
+    // Written to chunk saves, so it must be stable across versions of the definitions
+    pub id: u16,
+    pub name: String,
+    #[serde(default)]
+    pub solid: bool,
+    // Light and transparent passes see through it
+    #[serde(default)]
+    pub transparent: bool,
+    // Texture array indices of the block's faces
+    #[serde(default)]
+    pub textures: BlockTextures,
+    // How long the block takes to break, in seconds, negative hardness can't be broken
+    #[serde(default)]
+    pub hardness: f32,
+    // Key of the behaviour which runs when the block is ticked, e.g. "flow"
+    #[serde(default)]
+    pub tick: Option<String>,
+    // sRGB colour of the block's particles
+    #[serde(default)]
+    pub colour: Option<[f32; 3]>,
+}
+
+impl BlockDefinition {
+    // Blocks without a colour get a hue picked from their id, so neighbouring ids stand apart
+    pub fn particle_colour(&self) -> Color {
+        match self.colour {
+            Some([red, green, blue]) => Color::srgb(red, green, blue),
+            None => Color::hsl(self.id as f32 * 137.5 % 360., 0.5, 0.5),
+        }
+    }
+}
+
+pub struct BlockRegistry {
+    // Indexed by id, ids without a definition are None
+    definitions: Vec<Option<BlockDefinition>>,
+    ids_by_name: HashMap<String, VoxelType>,
+}
+
+impl BlockRegistry {
+    // Loads the registry the first time it is used
+    pub fn global() -> &'static BlockRegistry {
+        BLOCK_REGISTRY.get_or_init(Self::load)
+    }
+
+    fn load() -> Self {
+        match fs::read_to_string(BLOCK_REGISTRY_PATH)
+            .map_err(|err| err.to_string())
+            .and_then(|definitions| Self::from_ron(&definitions))
+        {
+            Ok(registry) => registry,
+            Err(err) => {
+                error!("Failed to load blocks from {BLOCK_REGISTRY_PATH}: {err}, using the built in blocks");
+
+                Self::from_ron(BUILTIN_BLOCKS).expect("Built in block definitions are invalid")
+            }
+        }
+    }
+
+    pub fn from_ron(definitions: &str) -> Result<Self, String> {
+        let definitions: Vec<BlockDefinition> =
+            ron::from_str(definitions).map_err(|err| err.to_string())?;
+
+        Self::from_definitions(definitions)
+    }
+
+    pub fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<Self, String> {
+        let mut registry = Self {
+            definitions: Vec::new(),
+            ids_by_name: HashMap::new(),
+        };
+
+        for definition in definitions {
+            // Packed vertices only have room for ids up to MAX_VERTEX_VOXEL_TYPE
+            if u32::from(definition.id) > MAX_VERTEX_VOXEL_TYPE {
+                return Err(format!(
+                    "Block {} has id {}, past {MAX_VERTEX_VOXEL_TYPE}",
+                    definition.name, definition.id
+                ));
+            }
+
+            let id = definition.id as usize;
+            if registry.definitions.len() <= id {
+                registry.definitions.resize(id + 1, None);
+            }
+
+            if let Some(existing) = &registry.definitions[id] {
+                return Err(format!(
+                    "Blocks {} and {} both have id {id}",
+                    existing.name, definition.name
+                ));
+            }
+            if registry
+                .ids_by_name
+                .insert(definition.name.clone(), VoxelType::from_id(definition.id))
+                .is_some()
+            {
+                return Err(format!("Block {} is defined twice", definition.name));
+            }
+
+            // Packed vertices only have room for layers up to MAX_TEXTURE_LAYER
+            let textures = definition.textures;
+            if textures.side.max(textures.top).max(textures.bottom) > MAX_TEXTURE_LAYER {
+                return Err(format!(
+                    "Block {} has a texture layer past {MAX_TEXTURE_LAYER}",
+                    definition.name
+                ));
+            }
+
+            registry.definitions[id] = Some(definition);
+        }
+
+        // The engine refers to these blocks directly
+        for (voxel_type, name, solid) in [
+            (VoxelType::AIR, "air", false),
+            (VoxelType::BLOCK, "block", true),
+            (VoxelType::WATER, "water", true),
+        ] {
+            if registry
+                .get(voxel_type)
+                .is_none_or(|definition| definition.name != name || definition.solid != solid)
+            {
+                return Err(format!(
+                    "Block {name} must have id {} and solid set to {solid}",
+                    voxel_type.id()
+                ));
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn get(&self, voxel_type: VoxelType) -> Option<&BlockDefinition> {
+        self.definitions
+            .get(voxel_type.id() as usize)
+            .and_then(Option::as_ref)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<VoxelType> {
+        self.ids_by_name.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids_by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids_by_name.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockDefinition> {
+        self.definitions.iter().flatten()
+    }
+
+    // Chunk saves hold block ids, so the names they were saved with are kept next to them
+    // A saved id which now belongs to another block would load as that block, so the world isn't
+    // loaded, and the ids are only written back when the saves agree with the registry
+    fn check_save_ids(mut exit_events: EventWriter<AppExit>) {
+        let path = persistence::save_directory().join(SAVED_BLOCK_IDS_FILE);
+        let registry = Self::global();
+
+        match registry.compare_save_ids(&path) {
+            SaveIds::Agree => {}
+            // Keep the ids the saves were written with, rather than the registry's
+            SaveIds::Differ => return,
+            SaveIds::Conflict => {
+                error!(
+                    "Saved chunks in {} use block ids which have changed, refusing to load the world",
+                    persistence::save_directory().display()
+                );
+                exit_events.send(AppExit::error());
+                return;
+            }
+        }
+
+        let saved = registry
+            .iter()
+            .map(|definition| SavedBlockId {
+                id: definition.id,
+                name: definition.name.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let result = ron::to_string(&saved)
+            .map_err(|err| err.to_string())
+            .and_then(|ids| {
+                fs::create_dir_all(persistence::save_directory())
+                    .and_then(|_| fs::write(&path, ids))
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            error!("Failed to save block ids to {}: {err}", path.display());
+        }
+    }
+
+    // How the ids in the saved ids file compare to the registry's, a missing file agrees
+    fn compare_save_ids(&self, path: &Path) -> SaveIds {
+        let Some(saved) = fs::read_to_string(path)
+            .ok()
+            .and_then(|saved| ron::from_str::<Vec<SavedBlockId>>(&saved).ok())
+        else {
+            return SaveIds::Agree;
+        };
+
+        let mut save_ids = SaveIds::Agree;
+        for SavedBlockId { id, name } in saved {
+            match self.by_name(&name) {
+                Some(voxel_type) if voxel_type.id() == id => continue,
+                Some(voxel_type) => error!(
+                    "Block {name} was saved with id {id} but is now {}",
+                    voxel_type.id()
+                ),
+                None => warn!("Block {name} (id {id}) in saved chunks is no longer defined"),
+            }
+
+            // The saved voxels with this id would become another block
+            if let Some(definition) = self.get(VoxelType::from_id(id)) {
+                error!(
+                    "Saved block {name} (id {id}) would load as {}",
+                    definition.name
+                );
+                save_ids = SaveIds::Conflict;
+            } else if save_ids == SaveIds::Agree {
+                save_ids = SaveIds::Differ;
+            }
+        }
+
+        save_ids
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SaveIds {
+    Agree,
+    // Blocks were removed or renamed, but no saved id belongs to another block
+    Differ,
+    Conflict,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedBlockId {
+    id: u16,
+    name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    // The saved ids file, written to a temporary file of its own
+    fn compare(registry: &BlockRegistry, name: &str, saved: &str) -> SaveIds {
+        let path = env::temp_dir().join(format!("cube_world_{}_{name}.ron", process::id()));
+        fs::write(&path, saved).unwrap();
+        let save_ids = registry.compare_save_ids(&path);
+        fs::remove_file(path).unwrap();
+
+        save_ids
+    }
+
+    #[test]
+    fn saved_ids_are_compared_with_the_registry() {
+        let registry = BlockRegistry::from_ron(BUILTIN_BLOCKS).unwrap();
+        let air = r#"(id: 0, name: "air")"#;
+
+        assert_eq!(
+            compare(&registry, "same", &format!("[{air}]")),
+            SaveIds::Agree
+        );
+        assert_eq!(
+            registry.compare_save_ids(Path::new("missing/blocks.ron")),
+            SaveIds::Agree
+        );
+
+        // An id the registry doesn't define loads as an unknown block rather than another one
+        let removed = format!(r#"[{air}, (id: 60000, name: "removed")]"#);
+        assert_eq!(compare(&registry, "removed", &removed), SaveIds::Differ);
+
+        let conflicting = format!(r#"[{air}, (id: 1, name: "removed")]"#);
+        assert_eq!(
+            compare(&registry, "conflicting", &conflicting),
+            SaveIds::Conflict
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use bevy::prelude::*;
+use bracket_noise::prelude::*;
+
+use crate::{
+    positions::WorldPos,
+    voxel::VoxelType,
+    world::World,
+    world_edit::{EditError, EditTransaction},
+    world_generator,
+};
+
+// Procedural brushes, a function of the world position evaluated over a box of voxels and written as
+// one transaction, for tools, spells and terrain sculpting. SdfShape has brushes for common shapes
+
+// Box of world positions a brush is evaluated over, both corners are included
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BrushRegion {
+    pub min: WorldPos,
+    pub max: WorldPos,
+}
+
+impl BrushRegion {
+    // The corners may be given in any order
+    pub fn new(a: WorldPos, b: WorldPos) -> Self {
+        let (a, b) = (IVec3::from(a.to_tuple()), IVec3::from(b.to_tuple()));
+
+        Self::from_corners(a.min(b), a.max(b))
+    }
+
+    // The smallest region holding every voxel within the distance of the point
+    pub fn around(center: Vec3, distance: f32) -> Self {
+        let min = (center - distance).floor().as_ivec3();
+        let max = (center + distance).ceil().as_ivec3();
+
+        Self::from_corners(min, max)
+    }
+
+    fn from_corners(min: IVec3, max: IVec3) -> Self {
+        Self {
+            min: WorldPos::new(min.x, min.y, min.z),
+            max: WorldPos::new(max.x, max.y, max.z),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = WorldPos> {
+        let (min, max) = (self.min, self.max);
+
+        (min.z..=max.z).flat_map(move |z| {
+            (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| WorldPos::new(x, y, z)))
+        })
+    }
+}
+
+// Shapes as signed distance functions, negative inside, in voxels
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SdfShape {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    // A ring around the Y axis, the tube is minor_radius thick
+    Torus {
+        center: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    // A sphere whose surface is pushed in and out by Perlin noise, by up to roughness times the radius
+    Blob {
+        center: Vec3,
+        radius: f32,
+        roughness: f32,
+    },
+}
+
+impl SdfShape {
+    pub fn region(&self) -> BrushRegion {
+        match *self {
+            Self::Sphere { center, radius } => BrushRegion::around(center, radius),
+            Self::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => BrushRegion::around(center, major_radius + minor_radius),
+            Self::Blob {
+                center,
+                radius,
+                roughness,
+            } => BrushRegion::around(center, radius * (1. + roughness.abs())),
+        }
+    }
+
+    // Blobs read the noise, the other shapes ignore it
+    fn distance(&self, pos: Vec3, noise: &FastNoise) -> f32 {
+        match *self {
+            Self::Sphere { center, radius } => pos.distance(center) - radius,
+            Self::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let offset = pos - center;
+                Vec2::new(offset.xz().length() - major_radius, offset.y).length() - minor_radius
+            }
+            Self::Blob {
+                center,
+                radius,
+                roughness,
+            } => {
+                let perturbed_radius =
+                    radius * (1. + roughness * noise.get_noise3d(pos.x, pos.y, pos.z));
+                pos.distance(center) - perturbed_radius
+            }
+        }
+    }
+
+    // Brush writing the voxel type into every voxel whose centre is inside the shape
+    pub fn fill(self, voxel_type: VoxelType) -> impl FnMut(WorldPos) -> Option<VoxelType> {
+        let mut noise = FastNoise::seeded(world_generator::seed() + 4);
+        noise.set_noise_type(NoiseType::Perlin);
+        noise.set_frequency(0.15);
+
+        move |world_pos| {
+            let pos = IVec3::from(world_pos.to_tuple()).as_vec3() + 0.5;
+
+            (self.distance(pos, &noise) <= 0.).then_some(voxel_type)
+        }
+    }
+}
+
+impl World {
+    // Write the brush's voxel into every position of the region it returns one for, as one transaction
+    // which is remeshed in one frame and can be undone. Returns the number of voxels changed
+    // Voxels in chunks which aren't loaded are left alone, the brush isn't called for them
+    pub fn apply_sdf(
+        &mut self,
+        region: BrushRegion,
+        mut brush: impl FnMut(WorldPos) -> Option<VoxelType>,
+    ) -> Result<usize, EditError> {
+        let mut transaction = EditTransaction::new();
+
+        for world_pos in region.iter() {
+            let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+            if !self.chunks.contains_key(&chunk_pos) {
+                continue;
+            }
+
+            if let Some(voxel_type) = brush(world_pos) {
+                transaction.set_voxel(world_pos, voxel_type);
+            }
+        }
+
+        self.commit(transaction)
+    }
+
+    // Fill the shape with the voxel type, see World::apply_sdf
+    pub fn apply_shape(
+        &mut self,
+        shape: SdfShape,
+        voxel_type: VoxelType,
+    ) -> Result<usize, EditError> {
+        self.apply_sdf(shape.region(), shape.fill(voxel_type))
+    }
+}
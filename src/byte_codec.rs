@@ -0,0 +1,66 @@
+// Helpers shared by the chunk and chunk metadata byte formats
+
+// LEB128: seven bits per byte, the high bit is set on every byte but the last
+pub fn write_varint(bytes: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+pub struct ByteReader<'a> {
+    pub bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+
+        Some(byte)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Some(bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        let (value, rest) = self.bytes.split_first_chunk::<4>()?;
+        self.bytes = rest;
+
+        Some(u32::from_le_bytes(*value))
+    }
+
+    // Fails on varints which are truncated or don't fit in a u32
+    pub fn read_varint(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+
+        for shift in (0..32).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+
+            if byte & 0x80 == 0 {
+                // The fifth byte only has four bits to spare
+                return (shift < 28 || byte < 0x10).then_some(value);
+            }
+        }
+
+        None
+    }
+}
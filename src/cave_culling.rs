@@ -0,0 +1,217 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    transform::TransformSystem,
+};
+
+use crate::{
+    chunk::Chunk,
+    constants::{CAVE_CULLING_ENABLED, CHUNK_EXTENT},
+    occlusion_culling::OcclusionCulling,
+    positions::{ChunkPos, VoxelPos, VoxelScale},
+    world::World,
+};
+
+// Chunk entities hidden by cave culling this frame
+pub const CAVE_CULLED_CHUNKS: DiagnosticPath =
+    DiagnosticPath::const_new("cave_culling/hidden_chunks");
+
+// Offsets to the neighbouring chunk through each face, opposite faces are adjacent (face ^ 1)
+const CHUNK_FACES: [IVec3; 6] = [
+    IVec3::NEG_X,
+    IVec3::X,
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+];
+
+// Cave culling: each chunk records which of its faces are connected through non-opaque voxels when
+// it is meshed, then chunks are flood filled from the camera's chunk through connected faces
+// Chunks the flood fill can't reach can't be seen, which hides most caves from the surface and
+// most of the surface from inside caves
+pub struct CaveCullingPlugin;
+
+impl Plugin for CaveCullingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CaveCulling>()
+            .register_type::<CaveCulling>()
+            .register_diagnostic(Diagnostic::new(CAVE_CULLED_CHUNKS))
+            .add_systems(
+                PostUpdate,
+                CaveCulling::find_visible_chunks
+                    .after(TransformSystem::TransformPropagate)
+                    .before(OcclusionCulling::hide_occluded_chunks),
+            );
+    }
+}
+
+// Which pairs of chunk faces can see each other through the chunk, bit (a * 6 + b) for faces a and b
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FaceConnectivity(u64);
+
+impl FaceConnectivity {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self((1 << 36) - 1);
+
+    pub fn connects(&self, a: usize, b: usize) -> bool {
+        (self.0 >> (a * 6 + b)) & 1 == 1
+    }
+
+    // Connect every pair of the faces in the bitmask to each other
+    fn connect_faces(&mut self, faces: u8) {
+        for a in (0..6).filter(|a| faces & (1 << a) != 0) {
+            for b in (0..6).filter(|b| faces & (1 << b) != 0) {
+                self.0 |= 1 << (a * 6 + b);
+            }
+        }
+    }
+
+    // Flood fill each region of non-opaque voxels and connect the faces it touches
+    pub fn from_chunk(chunk: &Chunk) -> Self {
+        if chunk.is_all_opaque() {
+            return Self::NONE;
+        }
+        if chunk.is_all_air() {
+            return Self::ALL;
+        }
+
+        let mut connectivity = Self::NONE;
+        let mut visited = vec![false; chunk.len()];
+        let mut stack = Vec::new();
+
+        for start in 0..chunk.len() {
+            if visited[start] || chunk[start].voxel_type.is_opaque() {
+                continue;
+            }
+
+            visited[start] = true;
+            stack.push(start);
+
+            let mut faces = 0u8;
+            while let Some(index) = stack.pop() {
+                let voxel_pos = VoxelPos::from_index(index).to_ivec3();
+
+                for (face, offset) in CHUNK_FACES.iter().enumerate() {
+                    let neighbour = voxel_pos + *offset;
+
+                    if neighbour.cmplt(IVec3::ZERO).any() || neighbour.cmpge(CHUNK_EXTENT).any() {
+                        faces |= 1 << face;
+                        continue;
+                    }
+
+                    let neighbour_index = VoxelPos::from_ivec3(neighbour).to_index();
+                    if !visited[neighbour_index] && !chunk[neighbour_index].voxel_type.is_opaque() {
+                        visited[neighbour_index] = true;
+                        stack.push(neighbour_index);
+                    }
+                }
+            }
+
+            connectivity.connect_faces(faces);
+        }
+
+        connectivity
+    }
+}
+
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct CaveCulling {
+    pub enabled: bool,
+    // Counters from the last frame
+    pub reachable: usize,
+    pub hidden: usize,
+    // Chunks reached by the flood fill, None when it didn't run (so nothing is hidden)
+    #[reflect(ignore)]
+    visible: Option<HashSet<ChunkPos>>,
+}
+
+impl Default for CaveCulling {
+    fn default() -> Self {
+        Self {
+            enabled: CAVE_CULLING_ENABLED,
+            reachable: 0,
+            hidden: 0,
+            visible: None,
+        }
+    }
+}
+
+impl CaveCulling {
+    pub fn is_hidden(&self, chunk_pos: ChunkPos) -> bool {
+        self.visible
+            .as_ref()
+            .is_some_and(|visible| !visible.contains(&chunk_pos))
+    }
+
+    fn find_visible_chunks(
+        mut culling: ResMut<CaveCulling>,
+        world: Res<World>,
+        voxel_scale: Res<VoxelScale>,
+        cameras: Query<&GlobalTransform, With<Camera3d>>,
+        mut diagnostics: Diagnostics,
+    ) {
+        culling.visible = None;
+        culling.reachable = 0;
+        culling.hidden = 0;
+
+        let Ok(camera_transform) = cameras.get_single() else {
+            return;
+        };
+
+        let chunk_size = CHUNK_EXTENT.as_vec3() * voxel_scale.0;
+        let camera_chunk =
+            ChunkPos::from_vec3((camera_transform.translation() / chunk_size).floor());
+
+        // Nothing is known about the surroundings of a camera outside the loaded chunks
+        if !culling.enabled || !world.chunks.contains_key(&camera_chunk) {
+            return;
+        }
+
+        let _span = info_span!("find_visible_chunks").entered();
+
+        let mut visible = HashSet::from([camera_chunk]);
+        // Chunk, the face it was entered through, and every direction travelled to reach it
+        let mut queue = VecDeque::from([(camera_chunk, None::<usize>, 0u8)]);
+
+        while let Some((chunk_pos, entered_through, directions)) = queue.pop_front() {
+            // Chunks without a mesh yet might connect anything
+            let connectivity = world
+                .chunk_connectivity
+                .get(&chunk_pos)
+                .copied()
+                .unwrap_or(FaceConnectivity::ALL);
+
+            for (face, offset) in CHUNK_FACES.iter().enumerate() {
+                // Only ever move away from the camera
+                if directions & (1 << (face ^ 1)) != 0 {
+                    continue;
+                }
+                if entered_through.is_some_and(|entered| !connectivity.connects(entered, face)) {
+                    continue;
+                }
+
+                let neighbour = chunk_pos + ChunkPos::new(offset.x, offset.y, offset.z);
+                if visible.contains(&neighbour) || !world.chunks.contains_key(&neighbour) {
+                    continue;
+                }
+
+                visible.insert(neighbour);
+                queue.push_back((neighbour, Some(face ^ 1), directions | (1 << face)));
+            }
+        }
+
+        culling.reachable = visible.len();
+        culling.hidden = world
+            .chunk_entities
+            .keys()
+            .filter(|chunk_pos| !visible.contains(chunk_pos))
+            .count();
+        culling.visible = Some(visible);
+
+        diagnostics.add_measurement(&CAVE_CULLED_CHUNKS, || culling.hidden as f64);
+    }
+}
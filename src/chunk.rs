@@ -1,20 +1,44 @@
+use bevy::{log::info_span, math::BVec3};
 use bracket_noise::prelude::*;
+use xxhash_rust::xxh3::Xxh3;
 
 use crate::{
-    constants::{CHUNK_SIZE, NOISE_FREQUENCY, NOISE_HEIGHT_SCALE, NOISE_SEED},
+    byte_codec::{write_varint, ByteReader},
+    constants::{
+        CHUNK_DENSITY_MAGIC, CHUNK_FORMAT_MAGIC, CHUNK_HEIGHT, CHUNK_SIZE, CHUNK_VOLUME,
+        DENSITY_SCALE, NOISE_FREQUENCY, NOISE_HEIGHT_SCALE,
+    },
     positions::{ChunkPos, VoxelPos, WorldPos},
+    rivers::RiverMap,
     voxel::{Voxel, VoxelType},
+    world_edit::EditError,
+    world_generator,
 };
 
 #[derive(Clone, Debug)]
 pub struct Chunk {
-    voxels: [Voxel; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE],
+    // CHUNK_VOLUME voxels in index order, boxed as tall chunks are too large for the stack
+    voxels: Box<[Voxel]>,
+    // Cached so that queries can skip chunks which are entirely air
+    solid_count: usize,
+    // Cached so that occlusion culling can find chunks which block everything behind them
+    opaque_count: usize,
+    // One above the highest opaque voxel of each column (x + z * CHUNK_SIZE), 0 if the column has none
+    heightmap: [u16; CHUNK_SIZE * CHUNK_SIZE],
+    // Density of each voxel from generation, positive inside the terrain, in 1/DENSITY_SCALE voxels
+    // Smooth meshing places the surface between voxels with it, chunks without densities (built
+    // voxel by voxel, or without the densities feature) are meshed from their voxels' solidity
+    densities: Option<Box<[i8]>>,
 }
 
 impl Default for Chunk {
     fn default() -> Self {
         Self {
-            voxels: [Voxel::default(); CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE],
+            voxels: empty_voxels(),
+            solid_count: 0,
+            opaque_count: 0,
+            heightmap: [0; CHUNK_SIZE * CHUNK_SIZE],
+            densities: None,
         }
     }
 }
@@ -25,54 +49,196 @@ impl Chunk {
     }
 
     pub fn new_from_noise(chunk_pos: ChunkPos) -> Self {
-        let mut noise = FastNoise::seeded(NOISE_SEED);
-        noise.set_noise_type(NoiseType::PerlinFractal);
-        noise.set_frequency(NOISE_FREQUENCY * 1.5);
-        noise.set_fractal_octaves(8);
-        noise.set_fractal_lacunarity(2.);
-        noise.set_fractal_gain(0.25);
-
-        let mut voxels = [Voxel::default(); CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
-        (0..voxels.len()).for_each(|index| {
-            let voxel_pos = VoxelPos::from_index(index);
-            let world_pos = WorldPos::from_voxel_pos(voxel_pos, chunk_pos);
+        let _span = info_span!("chunk_new_from_noise", ?chunk_pos).entered();
 
-            // let overhang =
-            //     noise.get_noise3d(voxel_pos.x as f32, voxel_pos.y as f32, voxel_pos.z as f32)
-            //         * 55.0;
+        let noise = terrain_noise();
+        let river_map = RiverMap::new();
 
+        // The density is the height of the surface above the voxel
+        Self::from_density_fn(chunk_pos, |world_pos| {
             let noise_val =
                 noise.get_noise3d(world_pos.x as f32, world_pos.y as f32, world_pos.z as f32);
-            let height = noise_val * NOISE_HEIGHT_SCALE;
+            let height =
+                noise_val * NOISE_HEIGHT_SCALE - river_map.carve_depth(world_pos.x, world_pos.z);
+
+            height - world_pos.y as f32
+        })
+    }
+
+    // Build a chunk by deciding the type of each voxel from its world position
+    pub fn from_fn(chunk_pos: ChunkPos, voxel_at: impl Fn(WorldPos) -> VoxelType) -> Self {
+        let mut voxels = empty_voxels();
+        (0..voxels.len()).for_each(|index| {
+            let world_pos = WorldPos::from_voxel_pos(VoxelPos::from_index(index), chunk_pos);
+
+            voxels[index] = Voxel::new(voxel_at(world_pos));
+        });
+
+        Self::from_voxels(voxels)
+    }
+
+    // Build a chunk from a density at each world position, roughly the distance in voxels to the
+    // surface, positive inside. Voxels with a positive density are blocks, the densities are kept
+    // with the densities feature. Water is filled in by the generator's surface pass
+    pub fn from_density_fn(chunk_pos: ChunkPos, density_at: impl Fn(WorldPos) -> f32) -> Self {
+        let mut voxels = empty_voxels();
+        let mut densities =
+            cfg!(feature = "densities").then(|| vec![0; voxels.len()].into_boxed_slice());
 
-            let solid = height > world_pos.y as f32;
-            // let solid = height > NOISE_HEIGHT_SCALE * 0.25;
+        (0..voxels.len()).for_each(|index| {
+            let world_pos = WorldPos::from_voxel_pos(VoxelPos::from_index(index), chunk_pos);
+            let density = density_at(world_pos);
+
+            if density > 0. {
+                voxels[index] = Voxel::new(VoxelType::BLOCK);
+            }
+            // Solid voxels keep a positive density when it is rounded
+            if let Some(densities) = densities.as_mut() {
+                densities[index] = if density > 0. {
+                    (density * DENSITY_SCALE).clamp(1., i8::MAX as f32) as i8
+                } else {
+                    (density * DENSITY_SCALE).clamp(i8::MIN as f32, 0.) as i8
+                };
+            }
+        });
 
-            // let solid = world_pos.y < 10;
+        Self {
+            densities,
+            ..Self::from_voxels(voxels)
+        }
+    }
 
-            let voxel_type = if solid {
-                VoxelType::Block
-            } else {
-                VoxelType::Air
+    // Copy of the chunk flipped along the axes, see BoundaryPolicy::Mirror
+    pub fn mirrored(&self, axes: BVec3) -> Self {
+        let source_index = |index: usize| {
+            let VoxelPos { x, y, z } = VoxelPos::from_index(index);
+            let flip = |value: usize, size: usize, flipped: bool| {
+                if flipped {
+                    size - 1 - value
+                } else {
+                    value
+                }
             };
 
-            voxels[index] = Voxel::new(voxel_type);
+            VoxelPos::new(
+                flip(x, CHUNK_SIZE, axes.x),
+                flip(y, CHUNK_HEIGHT, axes.y),
+                flip(z, CHUNK_SIZE, axes.z),
+            )
+            .to_index()
+        };
+
+        let mut voxels = empty_voxels();
+        (0..voxels.len()).for_each(|index| voxels[index] = self.voxels[source_index(index)]);
+        let densities = self.densities.as_ref().map(|densities| {
+            (0..densities.len())
+                .map(|index| densities[source_index(index)])
+                .collect()
         });
 
-        Chunk { voxels }
+        Self {
+            densities,
+            ..Self::from_voxels(voxels)
+        }
     }
 
+    fn from_voxels(voxels: Box<[Voxel]>) -> Self {
+        let solid_count = voxels
+            .iter()
+            .filter(|voxel| voxel.voxel_type.is_solid())
+            .count();
+        let opaque_count = voxels
+            .iter()
+            .filter(|voxel| voxel.voxel_type.is_opaque())
+            .count();
+
+        let mut chunk = Self {
+            voxels,
+            solid_count,
+            opaque_count,
+            heightmap: [0; CHUNK_SIZE * CHUNK_SIZE],
+            densities: None,
+        };
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.update_column_height(x, z);
+            }
+        }
+
+        chunk
+    }
+
+    // Panics if the position is outside the chunk, see try_set_voxel
     pub fn set_voxel(&mut self, voxel_pos: VoxelPos, voxel_type: VoxelType) {
-        // Check that the position is within the chunk
-        assert!(
-            voxel_pos.x < CHUNK_SIZE && voxel_pos.y < CHUNK_SIZE && voxel_pos.z < CHUNK_SIZE,
-            "x: {}, y: {}, z: {}",
-            voxel_pos.x,
-            voxel_pos.y,
-            voxel_pos.z
-        );
+        if let Err(err) = self.try_set_voxel(voxel_pos, voxel_type) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_set_voxel(
+        &mut self,
+        voxel_pos: VoxelPos,
+        voxel_type: VoxelType,
+    ) -> Result<(), EditError> {
+        if !voxel_pos.is_in_chunk() {
+            return Err(EditError::OutOfChunk(voxel_pos.to_ivec3()));
+        }
+
+        let index = voxel_pos.to_index();
+        let voxel = &mut self.voxels[index];
+        match (voxel.voxel_type.is_solid(), voxel_type.is_solid()) {
+            (false, true) => self.solid_count += 1,
+            (true, false) => self.solid_count -= 1,
+            _ => {}
+        }
+
+        // A density which disagrees with the voxel is replaced, edited voxels mesh like blocks
+        if let Some(densities) = self.densities.as_mut() {
+            if (densities[index] > 0) != voxel_type.is_solid() {
+                densities[index] = solidity_density(voxel_type);
+            }
+        }
+
+        let was_opaque = voxel.voxel_type.is_opaque();
+        match (was_opaque, voxel_type.is_opaque()) {
+            (false, true) => self.opaque_count += 1,
+            (true, false) => self.opaque_count -= 1,
+            _ => {}
+        }
+        voxel.voxel_type = voxel_type;
+
+        // Only rescan the column when its top voxel might have changed
+        let column_top = self.heightmap[voxel_pos.x + voxel_pos.z * CHUNK_SIZE] as usize;
+        if (voxel_type.is_opaque() && voxel_pos.y >= column_top)
+            || (was_opaque && voxel_pos.y + 1 == column_top)
+        {
+            self.update_column_height(voxel_pos.x, voxel_pos.z);
+        }
+
+        Ok(())
+    }
 
-        self[voxel_pos].voxel_type = voxel_type;
+    fn update_column_height(&mut self, x: usize, z: usize) {
+        self.heightmap[x + z * CHUNK_SIZE] = (0..CHUNK_HEIGHT)
+            .rev()
+            .find(|&y| self[VoxelPos::new(x, y, z)].voxel_type.is_opaque())
+            .map_or(0, |y| y as u16 + 1);
+    }
+
+    // Local y of the highest opaque voxel in a column
+    pub fn column_height(&self, x: usize, z: usize) -> Option<usize> {
+        (self.heightmap[x + z * CHUNK_SIZE] as usize).checked_sub(1)
+    }
+
+    // Density of a voxel in voxels, falling back to half a voxel either side of the surface, which
+    // puts smooth surfaces on the faces of blocks
+    pub fn density(&self, voxel_pos: VoxelPos) -> f32 {
+        let density = match &self.densities {
+            Some(densities) => densities[voxel_pos.to_index()],
+            None => solidity_density(self[voxel_pos].voxel_type),
+        };
+
+        density as f32 / DENSITY_SCALE
     }
 
     pub fn set_voxels(&mut self, voxels: Vec<(VoxelPos, VoxelType)>) {
@@ -83,10 +249,7 @@ impl Chunk {
 
     pub fn with_voxels(voxels: Vec<(VoxelPos, VoxelType)>) -> Self {
         let mut chunk = Self::default();
-
-        for (voxel_pos, voxel_type) in voxels {
-            chunk.voxels[voxel_pos.to_index()].voxel_type = voxel_type;
-        }
+        chunk.set_voxels(voxels);
 
         chunk
     }
@@ -98,6 +261,185 @@ impl Chunk {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    // Compact format for save files and the network, the voxels in index order as runs
+    // The magic byte, a varint palette of voxel types, then (varint run length, varint palette index) pairs
+    // Chunks with densities follow the runs with the density magic byte and (varint run length,
+    // density byte) pairs
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let _span = info_span!("chunk_to_bytes").entered();
+
+        let mut palette: Vec<VoxelType> = Vec::new();
+        let mut runs: Vec<(u32, usize)> = Vec::new();
+
+        for voxel in self.voxels.iter() {
+            let palette_index = match palette.iter().position(|&t| t == voxel.voxel_type) {
+                Some(palette_index) => palette_index,
+                None => {
+                    palette.push(voxel.voxel_type);
+                    palette.len() - 1
+                }
+            };
+
+            match runs.last_mut() {
+                Some((length, last_index)) if *last_index == palette_index => *length += 1,
+                _ => runs.push((1, palette_index)),
+            }
+        }
+
+        let mut bytes = vec![CHUNK_FORMAT_MAGIC];
+
+        write_varint(&mut bytes, palette.len() as u32);
+        for &voxel_type in &palette {
+            write_varint(&mut bytes, u32::from(voxel_type));
+        }
+
+        for (length, palette_index) in runs {
+            write_varint(&mut bytes, length);
+            write_varint(&mut bytes, palette_index as u32);
+        }
+
+        if let Some(densities) = &self.densities {
+            bytes.push(CHUNK_DENSITY_MAGIC);
+
+            // Densities are clamped away from the surface, so most of a chunk is in a few long runs
+            let mut runs: Vec<(u32, i8)> = Vec::new();
+            for &density in densities.iter() {
+                match runs.last_mut() {
+                    Some((length, last)) if *last == density => *length += 1,
+                    _ => runs.push((1, density)),
+                }
+            }
+
+            for (length, density) in runs {
+                write_varint(&mut bytes, length);
+                bytes.push(density as u8);
+            }
+        }
+
+        bytes
+    }
+
+    // Returns None for bytes which don't start with the magic byte or don't decode to a whole chunk
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let _span = info_span!("chunk_from_bytes").entered();
+
+        let mut voxels = empty_voxels();
+
+        let (&CHUNK_FORMAT_MAGIC, rest) = bytes.split_first()? else {
+            return None;
+        };
+
+        let mut reader = ByteReader::new(rest);
+
+        // Every palette entry is at least one byte, so this bounds the allocation by the input size
+        let palette_len = reader.read_varint()? as usize;
+        if palette_len > reader.bytes.len() {
+            return None;
+        }
+        let palette = (0..palette_len)
+            .map(|_| VoxelType::try_from_u32(reader.read_varint()?))
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut index = 0;
+        while index < voxels.len() {
+            let length = reader.read_varint()? as usize;
+            let voxel_type = *palette.get(reader.read_varint()? as usize)?;
+
+            // Runs must be non-empty and stay within the chunk
+            if length == 0 || length > voxels.len() - index {
+                return None;
+            }
+
+            voxels[index..index + length].fill(Voxel::new(voxel_type));
+            index += length;
+        }
+
+        if reader.is_empty() {
+            return Some(Self::from_voxels(voxels));
+        }
+
+        if reader.read_u8()? != CHUNK_DENSITY_MAGIC {
+            return None;
+        }
+
+        let mut densities = vec![0; voxels.len()].into_boxed_slice();
+        let mut index = 0;
+        while index < densities.len() {
+            let length = reader.read_varint()? as usize;
+            let density = reader.read_u8()? as i8;
+
+            if length == 0 || length > densities.len() - index {
+                return None;
+            }
+
+            densities[index..index + length].fill(density);
+            index += length;
+        }
+
+        // Builds without the densities feature check the densities but don't keep them
+        reader.is_empty().then(|| Self {
+            densities: cfg!(feature = "densities").then_some(densities),
+            ..Self::from_voxels(voxels)
+        })
+    }
+
+    // Fingerprint of the voxels which is the same on every platform, for detecting desyncs
+    // Only the voxel types are hashed, the cached counts and heightmap are derived from them
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Xxh3::new();
+        let mut row = [0; CHUNK_SIZE * 4];
+
+        for voxels in self.voxels.chunks_exact(CHUNK_SIZE) {
+            for (bytes, voxel) in row.chunks_exact_mut(4).zip(voxels) {
+                bytes.copy_from_slice(&u32::from(voxel.voxel_type).to_le_bytes());
+            }
+            hasher.update(&row);
+        }
+
+        hasher.digest()
+    }
+
+    pub fn solid_count(&self) -> usize {
+        self.solid_count
+    }
+
+    pub fn is_all_air(&self) -> bool {
+        self.solid_count == 0
+    }
+
+    // Whether every solid voxel is opaque, so nothing above a column's height is solid
+    pub fn solids_are_opaque(&self) -> bool {
+        self.solid_count == self.opaque_count
+    }
+
+    pub fn is_all_opaque(&self) -> bool {
+        self.opaque_count == self.voxels.len()
+    }
+}
+
+fn empty_voxels() -> Box<[Voxel]> {
+    vec![Voxel::default(); CHUNK_VOLUME].into_boxed_slice()
+}
+
+fn solidity_density(voxel_type: VoxelType) -> i8 {
+    if voxel_type.is_solid() {
+        (DENSITY_SCALE / 2.) as i8
+    } else {
+        -(DENSITY_SCALE / 2.) as i8
+    }
+}
+
+// The noise which NoiseGenerator's terrain is shaped by, scaled by NOISE_HEIGHT_SCALE
+pub(crate) fn terrain_noise() -> FastNoise {
+    let mut noise = FastNoise::seeded(world_generator::seed());
+    noise.set_noise_type(NoiseType::PerlinFractal);
+    noise.set_frequency(NOISE_FREQUENCY * 1.5);
+    noise.set_fractal_octaves(8);
+    noise.set_fractal_lacunarity(2.);
+    noise.set_fractal_gain(0.25);
+
+    noise
 }
 
 impl std::ops::Index<usize> for Chunk {
@@ -108,12 +450,6 @@ impl std::ops::Index<usize> for Chunk {
     }
 }
 
-impl std::ops::IndexMut<usize> for Chunk {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.voxels[index]
-    }
-}
-
 impl std::ops::Index<VoxelPos> for Chunk {
     type Output = Voxel;
 
@@ -122,8 +458,162 @@ impl std::ops::Index<VoxelPos> for Chunk {
     }
 }
 
-impl std::ops::IndexMut<VoxelPos> for Chunk {
-    fn index_mut(&mut self, index: VoxelPos) -> &mut Self::Output {
-        &mut self.voxels[index.to_index()]
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // Voxel types every block registry defines
+    const TYPES: [VoxelType; 3] = [VoxelType::AIR, VoxelType::BLOCK, VoxelType::WATER];
+
+    // Chunk filled from runs of voxel types in index order, the last run fills the rest of it
+    fn chunk_from_runs(runs: &[(usize, usize)]) -> Chunk {
+        let mut chunk = Chunk::new();
+        let mut index = 0;
+        let mut voxels = Vec::new();
+
+        for &(length, type_index) in runs {
+            for _ in 0..length.min(CHUNK_VOLUME - index) {
+                voxels.push((VoxelPos::from_index(index), TYPES[type_index]));
+                index += 1;
+            }
+        }
+        while index < CHUNK_VOLUME {
+            voxels.push((VoxelPos::from_index(index), VoxelType::AIR));
+            index += 1;
+        }
+
+        chunk.set_voxels(voxels);
+        chunk
+    }
+
+    fn assert_round_trips(chunk: &Chunk) {
+        let bytes = chunk.to_bytes();
+        let decoded = Chunk::from_bytes(&bytes).expect("Failed to decode an encoded chunk");
+
+        assert_eq!(decoded.content_hash(), chunk.content_hash());
+        assert_eq!(decoded.solid_count(), chunk.solid_count());
+        assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    proptest! {
+        #[test]
+        fn runs_of_voxels_round_trip(
+            runs in prop::collection::vec((1..CHUNK_VOLUME / 4, 0..TYPES.len()), 1..64),
+        ) {
+            assert_round_trips(&chunk_from_runs(&runs));
+        }
+
+        #[test]
+        fn scattered_voxels_round_trip(types in prop::collection::vec(0..TYPES.len(), 1..4096)) {
+            let runs = types.into_iter().map(|type_index| (1, type_index)).collect::<Vec<_>>();
+
+            assert_round_trips(&chunk_from_runs(&runs));
+        }
+
+        // Densities are only kept by builds with the densities feature, the voxels round trip either way
+        #[test]
+        fn generated_terrain_round_trips(height in -8_f32..CHUNK_HEIGHT as f32 + 8., slope in -1_f32..1.) {
+            let chunk = Chunk::from_density_fn(ChunkPos::splat(0), |world_pos| {
+                height + slope * world_pos.x as f32 - world_pos.y as f32
+            });
+
+            assert_round_trips(&chunk);
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = Chunk::from_bytes(&bytes);
+        }
+
+        #[test]
+        fn truncated_chunks_are_rejected(
+            runs in prop::collection::vec((1..CHUNK_VOLUME / 4, 0..TYPES.len()), 1..64),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let bytes = chunk_from_runs(&runs).to_bytes();
+            let len = cut.index(bytes.len());
+
+            prop_assert!(Chunk::from_bytes(&bytes[..len]).is_none());
+        }
+    }
+
+    // Palette entries and runs below 128 are single byte varints, so they are written out by hand
+    fn encoded(palette: &[u8], runs: &[(u8, u8)]) -> Vec<u8> {
+        let mut bytes = vec![CHUNK_FORMAT_MAGIC, palette.len() as u8];
+        bytes.extend(palette);
+        for &(length, palette_index) in runs {
+            bytes.extend([length, palette_index]);
+        }
+
+        bytes
+    }
+
+    // A single run covering the whole chunk
+    fn whole_chunk_run(palette_index: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, CHUNK_VOLUME as u32);
+        bytes.push(palette_index);
+
+        bytes
+    }
+
+    #[test]
+    fn whole_chunk_run_decodes() {
+        let mut bytes = encoded(&[1], &[]);
+        bytes.extend(whole_chunk_run(0));
+
+        let chunk = Chunk::from_bytes(&bytes).unwrap();
+        assert_eq!(chunk.solid_count(), CHUNK_VOLUME);
+    }
+
+    #[test]
+    fn corrupt_chunks_are_rejected() {
+        let valid = |palette: &[u8], palette_index| {
+            let mut bytes = encoded(palette, &[]);
+            bytes.extend(whole_chunk_run(palette_index));
+            bytes
+        };
+
+        // Not the magic byte
+        let mut wrong_magic = valid(&[1], 0);
+        wrong_magic[0] = CHUNK_FORMAT_MAGIC.wrapping_add(1);
+        assert!(Chunk::from_bytes(&wrong_magic).is_none());
+
+        // Palette index past the palette
+        assert!(Chunk::from_bytes(&valid(&[1], 1)).is_none());
+
+        // Voxel type the block registry doesn't define
+        assert!(Chunk::from_bytes(&valid(&[0x7f], 0)).is_none());
+
+        // Palette longer than the input
+        assert!(Chunk::from_bytes(&[CHUNK_FORMAT_MAGIC, 100, 1]).is_none());
+
+        // Empty run
+        let mut empty_run = encoded(&[1], &[(0, 0)]);
+        empty_run.extend(whole_chunk_run(0));
+        assert!(Chunk::from_bytes(&empty_run).is_none());
+
+        // Run past the end of the chunk
+        let mut overflowing = encoded(&[1], &[(1, 0)]);
+        overflowing.extend(whole_chunk_run(0));
+        assert!(Chunk::from_bytes(&overflowing).is_none());
+
+        // Trailing bytes which aren't densities
+        let mut trailing = valid(&[1], 0);
+        trailing.push(CHUNK_DENSITY_MAGIC.wrapping_add(1));
+        assert!(Chunk::from_bytes(&trailing).is_none());
+
+        // Densities which stop short of the chunk
+        let mut short_densities = valid(&[1], 0);
+        short_densities.extend([CHUNK_DENSITY_MAGIC, 1, 0]);
+        assert!(Chunk::from_bytes(&short_densities).is_none());
+
+        // No bytes at all
+        assert!(Chunk::from_bytes(&[]).is_none());
+
+        // One byte per voxel, without the magic byte
+        assert!(Chunk::from_bytes(&vec![1; CHUNK_VOLUME]).is_none());
     }
 }
@@ -0,0 +1,115 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use bevy::{
+    diagnostic::{DiagnosticPath, Diagnostics},
+    prelude::*,
+    utils::HashMap,
+};
+
+use crate::{
+    chunk::Chunk, chunk_meta::ChunkMeta, constants::CHUNK_CACHE_CAPACITY, positions::ChunkPos,
+    world::World,
+};
+
+// Total cache hits and misses since startup
+pub const CHUNK_CACHE_HITS: DiagnosticPath = DiagnosticPath::const_new("chunk_cache/hits");
+pub const CHUNK_CACHE_MISSES: DiagnosticPath = DiagnosticPath::const_new("chunk_cache/misses");
+
+// Recently unloaded chunk data, so chunks which are reloaded soon after (the loader doubling back)
+// skip loading from disk or generating
+// Entries are removed when taken, so evicting the oldest insertion evicts the least recently used
+#[derive(Debug)]
+pub struct ChunkCache {
+    entries: HashMap<ChunkPos, (u64, Arc<Chunk>, Arc<ChunkMeta>)>,
+    // Insertion order, entries which have since been taken are skipped when evicting
+    order: VecDeque<(ChunkPos, u64)>,
+    next_id: u64,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Default for ChunkCache {
+    fn default() -> Self {
+        Self::new(CHUNK_CACHE_CAPACITY)
+    }
+}
+
+impl ChunkCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            next_id: 0,
+            capacity,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Keep an unloaded chunk, evicting the oldest chunks when over capacity
+    pub fn insert(&mut self, chunk_pos: ChunkPos, chunk: Arc<Chunk>, meta: Arc<ChunkMeta>) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.entries.insert(chunk_pos, (id, chunk, meta));
+        self.order.push_back((chunk_pos, id));
+
+        while self.entries.len() > self.capacity {
+            let Some((oldest_pos, oldest_id)) = self.order.pop_front() else {
+                break;
+            };
+
+            if self
+                .entries
+                .get(&oldest_pos)
+                .is_some_and(|(id, ..)| *id == oldest_id)
+            {
+                self.entries.remove(&oldest_pos);
+            }
+        }
+
+        // Drop the stale order entries left by taken chunks
+        if self.order.len() > self.capacity * 2 {
+            let entries = &self.entries;
+            self.order.retain(|(chunk_pos, id)| {
+                entries.get(chunk_pos).is_some_and(|entry| entry.0 == *id)
+            });
+        }
+    }
+
+    // Drop every cached chunk, keeping the hit and miss counts
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    // Remove a chunk from the cache to load it again, counting the hit or miss
+    pub fn take(&mut self, chunk_pos: ChunkPos) -> Option<(Arc<Chunk>, Arc<ChunkMeta>)> {
+        let entry = self.entries.remove(&chunk_pos);
+
+        match entry {
+            Some((_, chunk, meta)) => {
+                self.hits += 1;
+                Some((chunk, meta))
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn measure(mut diagnostics: Diagnostics, world: Res<World>) {
+        diagnostics.add_measurement(&CHUNK_CACHE_HITS, || world.chunk_cache.hits as f64);
+        diagnostics.add_measurement(&CHUNK_CACHE_MISSES, || world.chunk_cache.misses as f64);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Weak},
+};
+
+use bevy::prelude::*;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    chunk::Chunk,
+    constants::{DIGEST_KEY, DIGEST_REGION_SIZE},
+    positions::{ChunkPos, VoxelScale, WorldPos},
+    world::World,
+};
+
+// Digests of the loaded voxels, so multiplayer or replay systems can compare worlds cheaply
+// The digest is a two level Merkle tree: each region of chunks is hashed from its chunks' content
+// hashes, and the root from the region digests, so peers whose roots differ can narrow the
+// divergence down to regions and then chunks without sending every chunk
+pub struct ChunkDigestPlugin;
+
+impl Plugin for ChunkDigestPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChunkDigests>()
+            .add_systems(Update, ChunkDigests::print_camera_digest);
+    }
+}
+
+// Content hashes of the loaded chunks, a chunk is only hashed again once it has been replaced
+// Edits copy chunks on write, so an unchanged Arc means unchanged voxels
+#[derive(Resource, Default)]
+pub struct ChunkDigests {
+    hashes: HashMap<ChunkPos, (Weak<Chunk>, u64)>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WorldDigest {
+    pub root: u64,
+    // Keyed by the region position, in chunks divided by DIGEST_REGION_SIZE
+    pub regions: BTreeMap<(i32, i32, i32), u64>,
+}
+
+impl WorldDigest {
+    // Regions which are in either digest with a different hash, or in only one of them
+    pub fn differing_regions(&self, other: &WorldDigest) -> Vec<(i32, i32, i32)> {
+        let mut regions = self
+            .regions
+            .iter()
+            .filter(|(region, hash)| other.regions.get(region) != Some(hash))
+            .map(|(&region, _)| region)
+            .collect::<Vec<_>>();
+        regions.extend(
+            other
+                .regions
+                .keys()
+                .filter(|region| !self.regions.contains_key(region)),
+        );
+
+        regions
+    }
+}
+
+impl ChunkDigests {
+    // Hash the chunks which changed since the last update, and forget the unloaded ones
+    pub fn update(&mut self, world: &World) {
+        self.hashes
+            .retain(|chunk_pos, _| world.chunks.contains_key(chunk_pos));
+
+        for (&chunk_pos, chunk) in &world.chunks {
+            let is_current = self
+                .hashes
+                .get(&chunk_pos)
+                .is_some_and(|(source, _)| std::ptr::eq(source.as_ptr(), Arc::as_ptr(chunk)));
+
+            if !is_current {
+                self.hashes
+                    .insert(chunk_pos, (Arc::downgrade(chunk), chunk.content_hash()));
+            }
+        }
+    }
+
+    pub fn chunk_hash(&self, chunk_pos: ChunkPos) -> Option<u64> {
+        self.hashes.get(&chunk_pos).map(|&(_, hash)| hash)
+    }
+
+    // Merkle digest of the chunks as of the last update
+    pub fn digest(&self) -> WorldDigest {
+        let mut region_leaves = BTreeMap::<_, Vec<_>>::new();
+        for (&chunk_pos, &(_, hash)) in &self.hashes {
+            region_leaves
+                .entry(region_of(chunk_pos))
+                .or_default()
+                .push((chunk_pos.to_tuple(), hash));
+        }
+
+        let regions = region_leaves
+            .into_iter()
+            .map(|(region, mut leaves)| {
+                // Hash maps iterate in a different order on every peer
+                leaves.sort_unstable();
+                let leaves = leaves
+                    .into_iter()
+                    .map(|(chunk_pos, hash)| leaf_hash(chunk_pos, hash))
+                    .collect();
+
+                (region, merkle_root(leaves))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let root = merkle_root(
+            regions
+                .iter()
+                .map(|(&region, &hash)| leaf_hash(region, hash))
+                .collect(),
+        );
+
+        WorldDigest { root, regions }
+    }
+
+    // Log the hashes leading from the camera's chunk up to the root
+    fn print_camera_digest(
+        mut digests: ResMut<ChunkDigests>,
+        world: Res<World>,
+        keys: Res<ButtonInput<KeyCode>>,
+        cameras: Query<&GlobalTransform, With<Camera3d>>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        if !keys.just_pressed(DIGEST_KEY) {
+            return;
+        }
+        let Ok(camera_transform) = cameras.get_single() else {
+            return;
+        };
+
+        let world_pos = voxel_scale.to_world_pos(camera_transform.translation());
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        digests.update(&world);
+        let digest = digests.digest();
+        let region = region_of(chunk_pos);
+
+        match digests.chunk_hash(chunk_pos) {
+            Some(hash) => info!(
+                "Chunk {chunk_pos:?}: {hash:016x}, region {region:?}: {:016x}, world ({} chunks): {:016x}",
+                digest.regions[&region],
+                world.chunks.len(),
+                digest.root
+            ),
+            None => info!(
+                "Chunk {chunk_pos:?} isn't loaded, world ({} chunks): {:016x}",
+                world.chunks.len(),
+                digest.root
+            ),
+        }
+    }
+}
+
+fn region_of(chunk_pos: ChunkPos) -> (i32, i32, i32) {
+    let size = DIGEST_REGION_SIZE as i32;
+
+    (
+        chunk_pos.x.div_euclid(size),
+        chunk_pos.y.div_euclid(size),
+        chunk_pos.z.div_euclid(size),
+    )
+}
+
+// Positions are part of the leaves, so moving a chunk changes the digest
+fn leaf_hash((x, y, z): (i32, i32, i32), hash: u64) -> u64 {
+    let mut bytes = [0; 20];
+    bytes[..4].copy_from_slice(&x.to_le_bytes());
+    bytes[4..8].copy_from_slice(&y.to_le_bytes());
+    bytes[8..12].copy_from_slice(&z.to_le_bytes());
+    bytes[12..].copy_from_slice(&hash.to_le_bytes());
+
+    xxh3_64(&bytes)
+}
+
+// Hash pairs of nodes until one is left, an odd node out is carried up to the next level
+fn merkle_root(mut nodes: Vec<u64>) -> u64 {
+    if nodes.is_empty() {
+        return 0;
+    }
+
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| match *pair {
+                [left, right] => {
+                    let mut bytes = [0; 16];
+                    bytes[..8].copy_from_slice(&left.to_le_bytes());
+                    bytes[8..].copy_from_slice(&right.to_le_bytes());
+
+                    xxh3_64(&bytes)
+                }
+                [node] => node,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+
+    nodes[0]
+}
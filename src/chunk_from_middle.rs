@@ -1,14 +1,25 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
 
-use bevy::math::IVec3;
+use bevy::{
+    log::info_span,
+    math::{IVec3, UVec3, Vec3},
+};
 
 use crate::{
     chunk::Chunk,
-    constants::{CHUNKS_FROM_MIDDLE_SIZE, CHUNK_SIZE},
+    constants::{CHUNKS_FROM_MIDDLE_SIZE, CHUNK_EXTENT, CHUNK_HEIGHT},
     positions::{chunk_pos_to_index_bounds, index_to_chunk_pos_bounds, ChunkPos, VoxelPos},
-    voxel::Voxel,
+    voxel::{Voxel, VoxelType},
+    voxel_grid::VoxelGrid,
+    world_border::{BoundaryPolicy, WorldBorder},
 };
 
+// Shared by every neighbour past a border with the solid boundary policy
+static SOLID_CHUNK: OnceLock<Arc<Chunk>> = OnceLock::new();
+
 // pointers to chunk data, a middle one with all their neighbours
 #[derive(Clone)]
 pub struct ChunksFromMiddle {
@@ -21,6 +32,8 @@ impl ChunksFromMiddle {
         chunk_hashmap: &HashMap<ChunkPos, Arc<Chunk>>,
         middle_chunk: ChunkPos,
     ) -> Option<Self> {
+        let _span = info_span!("chunks_from_middle_try_new", ?middle_chunk).entered();
+
         let mut chunks = Vec::new();
 
         for index in 0..CHUNKS_FROM_MIDDLE_SIZE * CHUNKS_FROM_MIDDLE_SIZE * CHUNKS_FROM_MIDDLE_SIZE
@@ -35,15 +48,127 @@ impl ChunksFromMiddle {
         Some(Self { chunks })
     }
 
+    // Like try_new, with the neighbours past the world border replaced following its boundary policy
+    pub fn try_new_bounded(
+        chunk_hashmap: &HashMap<ChunkPos, Arc<Chunk>>,
+        middle_chunk: ChunkPos,
+        border: &WorldBorder,
+    ) -> Option<Self> {
+        let mut chunks_from_middle = Self::try_new(chunk_hashmap, middle_chunk)?;
+        if border.boundary == BoundaryPolicy::Air || !border.contains_chunk(middle_chunk) {
+            return Some(chunks_from_middle);
+        }
+
+        for index in 0..chunks_from_middle.chunks.len() {
+            let offset = index_to_chunk_pos_bounds(index, CHUNKS_FROM_MIDDLE_SIZE as u32)
+                + ChunkPos::splat(-1);
+            let outside = border.outside_axes(middle_chunk + offset, 0);
+            if !outside.any() {
+                continue;
+            }
+
+            chunks_from_middle.chunks[index] = match border.boundary {
+                BoundaryPolicy::Air => continue,
+                BoundaryPolicy::Solid => Arc::clone(SOLID_CHUNK.get_or_init(|| {
+                    Arc::new(Chunk::from_fn(ChunkPos::splat(0), |_| VoxelType::BLOCK))
+                })),
+                // Reflected from the chunk on the inside of each axis the neighbour is past the border
+                // on, the border is separable so that chunk is always inside
+                BoundaryPolicy::Mirror => {
+                    let source = ChunkPos::new(
+                        if outside.x { 0 } else { offset.x },
+                        if outside.y { 0 } else { offset.y },
+                        if outside.z { 0 } else { offset.z },
+                    ) + ChunkPos::splat(1);
+                    let source_index =
+                        chunk_pos_to_index_bounds(source, CHUNKS_FROM_MIDDLE_SIZE as u32);
+
+                    Arc::new(chunks_from_middle.chunks[source_index].mirrored(outside))
+                }
+            };
+        }
+
+        Some(chunks_from_middle)
+    }
+
+    // Construct a neighbourhood directly from chunk data, given each chunk's offset from the middle
+    // Useful for building known solid patterns across chunk borders without a World
+    pub fn from_fn(mut chunk_at: impl FnMut(ChunkPos) -> Chunk) -> Self {
+        let chunks =
+            (0..CHUNKS_FROM_MIDDLE_SIZE * CHUNKS_FROM_MIDDLE_SIZE * CHUNKS_FROM_MIDDLE_SIZE)
+                .map(|index| {
+                    let offset = index_to_chunk_pos_bounds(index, CHUNKS_FROM_MIDDLE_SIZE as u32)
+                        + ChunkPos::splat(-1);
+
+                    Arc::new(chunk_at(offset))
+                })
+                .collect();
+
+        Self { chunks }
+    }
+
     pub fn get_voxel(&self, voxel_pos_ivec3: IVec3) -> &Voxel {
-        let voxel_pos = VoxelPos::from_ivec3(voxel_pos_ivec3 + IVec3::splat(CHUNK_SIZE as i32));
-        let chunk_pos = (voxel_pos / CHUNK_SIZE).to_i32().into();
+        let (chunk, voxel_pos) = self.locate(voxel_pos_ivec3);
+
+        &chunk[voxel_pos]
+    }
 
-        // Take modulus of x, y, and z with respect to CHUNK_SIZE, adding CHUNK_SIZE so that negative values don't appear
-        let voxel_pos = voxel_pos % CHUNK_SIZE;
+    // Density of the voxel for smooth meshing, see Chunk::density
+    pub fn get_density(&self, voxel_pos_ivec3: IVec3) -> f32 {
+        let (chunk, voxel_pos) = self.locate(voxel_pos_ivec3);
+
+        chunk.density(voxel_pos)
+    }
+
+    // Mean density of the cube of voxels from the position up to the jump along each axis, the
+    // density of a voxel at a lower level of detail
+    pub fn get_downsampled_density(&self, min: IVec3, jump: i32) -> f32 {
+        let mut sum = 0.;
+        for z in 0..jump {
+            for y in 0..jump {
+                for x in 0..jump {
+                    sum += self.get_density(min + IVec3::new(x, y, z));
+                }
+            }
+        }
+
+        sum / jump.pow(3) as f32
+    }
+
+    // Direction out of the terrain at a voxel, from the density gradient over its neighbours
+    // Without densities the gradient only sees which neighbours are solid, so slopes are coarser
+    pub fn surface_normal(&self, voxel_pos_ivec3: IVec3) -> Vec3 {
+        let gradient = IVec3::AXES.map(|axis| {
+            self.get_density(voxel_pos_ivec3 + axis) - self.get_density(voxel_pos_ivec3 - axis)
+        });
+
+        (-Vec3::from_array(gradient))
+            .try_normalize()
+            .unwrap_or(Vec3::Y)
+    }
+
+    // y of the highest opaque voxel in a column of the middle chunk and the one above it, from the
+    // chunks' heightmaps, None if neither has one
+    pub fn column_height(&self, x: i32, z: i32) -> Option<i32> {
+        let height = CHUNK_HEIGHT as i32;
+
+        [height, 0].into_iter().find_map(|y| {
+            let (chunk, voxel_pos) = self.locate(IVec3::new(x, y, z));
+            chunk
+                .column_height(voxel_pos.x, voxel_pos.z)
+                .map(|height| height as i32 + y)
+        })
+    }
+
+    // The chunk a position relative to the middle chunk is in, and its position in that chunk
+    fn locate(&self, voxel_pos_ivec3: IVec3) -> (&Chunk, VoxelPos) {
+        // Offset by a chunk so that negative values don't appear
+        let voxel_pos_ivec3 = voxel_pos_ivec3 + CHUNK_EXTENT;
+        let chunk_pos = ChunkPos::from_tuple((voxel_pos_ivec3 / CHUNK_EXTENT).into());
+        let voxel_pos = VoxelPos::from_ivec3(voxel_pos_ivec3 % CHUNK_EXTENT);
         let chunk_index = chunk_pos_to_index_bounds(chunk_pos, CHUNKS_FROM_MIDDLE_SIZE as u32);
 
-        &(&self.chunks[chunk_index])[voxel_pos]
+        (&self.chunks[chunk_index], voxel_pos)
     }
 
     pub fn get_voxel_no_neighbour(&self, voxel_pos: VoxelPos) -> &Voxel {
@@ -51,6 +176,10 @@ impl ChunksFromMiddle {
         &(&self.chunks[13])[voxel_pos]
     }
 
+    pub fn get_middle_chunk(&self) -> &Chunk {
+        &self.chunks[13]
+    }
+
     // Returns current, back, left, down
     pub fn get_adjacent_voxels(
         &self,
@@ -90,3 +219,19 @@ impl ChunksFromMiddle {
         true
     }
 }
+
+impl VoxelGrid for ChunksFromMiddle {
+    fn size(&self) -> UVec3 {
+        CHUNK_EXTENT.as_uvec3()
+    }
+
+    fn voxel_at(&self, pos: IVec3) -> VoxelType {
+        // Only positions outside the middle chunk need a lookup into the neighbouring chunks
+        if pos.cmpge(IVec3::ZERO).all() && pos.cmplt(CHUNK_EXTENT).all() {
+            self.get_voxel_no_neighbour(VoxelPos::from_ivec3(pos))
+                .voxel_type
+        } else {
+            self.get_voxel(pos).voxel_type
+        }
+    }
+}
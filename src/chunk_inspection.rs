@@ -0,0 +1,174 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk::Chunk,
+    chunk_from_middle::ChunksFromMiddle,
+    chunk_loading::ChunkLoader,
+    chunk_mesh::SectionMeshes,
+    constants::{ALL_SECTIONS, CHUNK_DUMP_DIRECTORY, CHUNK_DUMP_KEY},
+    crash_dump::LoaderState,
+    greedy_mesher::{self, AoMerging},
+    lod::Lod,
+    positions::{ChunkPos, WorldPos},
+    voxel::VoxelType,
+    voxel_picking::VoxelPicking,
+    world::World,
+    world_generator,
+};
+
+// Writes the chunk under the crosshair and its 26 neighbours to a RON file with the loader state,
+// so meshing and AO bugs seen in the wild can be attached to bug reports, then rebuilt from the
+// file with ChunkDump::build_meshes
+pub struct ChunkInspectionPlugin;
+
+impl Plugin for ChunkInspectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            ChunkDump::dump_hovered
+                .after(VoxelPicking::update_hovered)
+                .run_if(dump_requested),
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChunkDump {
+    pub seed: u64,
+    pub chunk_pos: [i32; 3],
+    // The voxel under the crosshair and the normal of the face it was hit on
+    pub hit: [i32; 3],
+    pub normal: [i32; 3],
+    // Debug names, for reading the report rather than replaying
+    pub meshing_mode: String,
+    pub mesh_quality: String,
+    pub ao_merging: String,
+    pub loaders: Vec<LoaderState>,
+    // Offsets from -1 to 1 around the chunk with the chunk in the Chunk::to_bytes format, the middle
+    // included. Neighbours which weren't loaded are missing
+    pub chunks: Vec<([i32; 3], Vec<u8>)>,
+}
+
+impl ChunkDump {
+    pub fn new(world: &World, hit: WorldPos, normal: IVec3, loaders: Vec<LoaderState>) -> Self {
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(hit);
+
+        let mut chunks = Vec::new();
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let offset = ChunkPos::new(x, y, z);
+                    if let Some(chunk) = world.chunks.get(&(chunk_pos + offset)) {
+                        chunks.push((offset.to_ivec3().to_array(), chunk.to_bytes()));
+                    }
+                }
+            }
+        }
+
+        Self {
+            seed: world_generator::seed(),
+            chunk_pos: chunk_pos.to_ivec3().to_array(),
+            hit: IVec3::from(hit.to_tuple()).to_array(),
+            normal: normal.to_array(),
+            meshing_mode: format!("{:?}", world.meshing_mode),
+            mesh_quality: format!("{:?}", world.mesh_quality(chunk_pos)),
+            ao_merging: format!("{:?}", world.ao_merging),
+            loaders,
+            chunks,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+        ron::from_str(&text).map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = ron::to_string(self).map_err(|err| err.to_string())?;
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+        }
+        fs::write(path, text).map_err(|err| err.to_string())
+    }
+
+    // The dumped chunks around the middle one, neighbours which weren't loaded are air
+    // None if any of the chunks can't be read
+    pub fn chunks_from_middle(&self) -> Option<ChunksFromMiddle> {
+        let mut chunks = Vec::new();
+        for (offset, bytes) in &self.chunks {
+            chunks.push((*offset, Chunk::from_bytes(bytes)?));
+        }
+
+        Some(ChunksFromMiddle::from_fn(|offset| {
+            let offset = offset.to_ivec3().to_array();
+
+            chunks
+                .iter()
+                .find(|(chunk_offset, _)| *chunk_offset == offset)
+                .map_or_else(
+                    || Chunk::from_fn(ChunkPos::splat(0), |_| VoxelType::AIR),
+                    |(_, chunk)| chunk.clone(),
+                )
+        }))
+    }
+
+    // Greedy mesh the middle chunk again, as it would be meshed at full quality
+    pub fn build_meshes(&self, ao: Option<AoMerging>) -> Option<SectionMeshes> {
+        let chunks_from_middle = self.chunks_from_middle()?;
+
+        Some(greedy_mesher::build_section_meshes(
+            &chunks_from_middle,
+            Lod::L32,
+            ao,
+            ALL_SECTIONS,
+        ))
+    }
+
+    fn dump_hovered(world: Res<World>, picking: Res<VoxelPicking>, loaders: Query<&ChunkLoader>) {
+        let Some(hit) = picking.hovered else {
+            warn!("No chunk under the crosshair to dump");
+            return;
+        };
+
+        let loaders = loaders
+            .iter()
+            .map(|loader| LoaderState {
+                chunk_pos: loader.prev_chunk_pos.to_ivec3().to_array(),
+                load_distance: loader.load_distance,
+                data_load_queue: loader.data_load_queue.len(),
+                mesh_load_queue: loader.mesh_load_queue.len(),
+            })
+            .collect();
+        let dump = ChunkDump::new(&world, hit.world_pos, hit.normal, loaders);
+
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let [x, y, z] = dump.chunk_pos;
+        let path =
+            PathBuf::from(CHUNK_DUMP_DIRECTORY).join(format!("chunk_{x}_{y}_{z}_{secs}.ron"));
+
+        match dump.save(&path) {
+            Ok(()) => info!(
+                "Wrote chunk {:?} and {} neighbours to {}",
+                dump.chunk_pos,
+                dump.chunks.len().saturating_sub(1),
+                path.display()
+            ),
+            Err(err) => error!("Failed to write the chunk dump: {err}"),
+        }
+    }
+}
+
+fn dump_requested(keys: Res<ButtonInput<KeyCode>>) -> bool {
+    keys.just_pressed(CHUNK_DUMP_KEY)
+}
@@ -1,106 +1,270 @@
-use std::collections::VecDeque;
-
-use bevy::{prelude::*, utils::HashSet};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 
 use crate::{
-    constants::{ADJACENT_CHUNK_DIRECTIONS, CHUNK_SIZE, MAX_CHUNK_LOADS, MAX_DATA_TASKS},
-    positions::{index_to_chunk_pos_bounds, ChunkPos},
+    chunk_queue::ChunkQueue,
+    constants::{
+        ADJACENT_CHUNK_DIRECTIONS, BURST_CHUNK_LOADS_PER_FRAME, BURST_FRAMES, CHUNK_HEIGHT,
+        CHUNK_LOADS_PER_FRAME, CHUNK_SIZE, CHUNK_UNLOAD_DELAY, COLUMN_MAX_CHUNK_Y,
+        COLUMN_MIN_CHUNK_Y, MIN_CHUNK_LOADS_PER_FRAME, TARGET_FRAME_TIME, TELEPORT_DISTANCE,
+    },
+    mesh_quality::MeshQualityPolicy,
+    pipeline_stepping::PipelineStepping,
+    positions::{ChunkPos, VoxelScale},
     world::World,
+    world_border::WorldBorder,
 };
 
 pub struct ChunkLoaderPlugin;
 
 impl Plugin for ChunkLoaderPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_systems(
-            PreUpdate,
-            (
-                ChunkLoader::detect_move,
-                ChunkLoader::load_chunks,
-                ChunkLoader::unload_chunks,
-                ChunkLoader::load_mesh,
-                ChunkLoader::unload_mesh,
+        app.register_type::<ChunkLoader>()
+            .register_type::<ChunkLoadAnchor>()
+            .init_resource::<ChunkLoadPacing>()
+            .init_resource::<AnchoredChunks>()
+            .register_type::<ChunkLoadPacing>()
+            .add_systems(
+                PreUpdate,
+                (
+                    ChunkLoader::apply_edited.before(ChunkLoader::detect_move),
+                    ChunkLoader::detect_move,
+                    ChunkLoader::load_chunks,
+                    ChunkLoader::unload_chunks,
+                    ChunkLoader::load_mesh,
+                    ChunkLoader::unload_mesh,
+                    ChunkLoadPacing::tick_burst
+                        .after(ChunkLoader::load_chunks)
+                        .after(ChunkLoader::load_mesh),
+                    AnchoredChunks::update
+                        .after(ChunkLoader::unload_chunks)
+                        .after(ChunkLoader::unload_mesh),
+                )
+                    .run_if(PipelineStepping::is_running),
+            );
+    }
+}
+
+// Limits how many chunks are moved from the loaders' queues to the World's queues each frame
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct ChunkLoadPacing {
+    // Loads per frame when running at the target frame time, slower frames load fewer chunks
+    pub loads_per_frame: usize,
+    pub min_loads_per_frame: usize,
+    pub target_frame_time: f32,
+
+    // After a teleport, load this many chunks per frame for a number of frames
+    pub burst_loads_per_frame: usize,
+    pub burst_frames: u32,
+    pub burst_frames_left: u32,
+
+    // Distance (in chunks) the loader has to move within a frame to count as a teleport
+    pub teleport_distance: u32,
+}
+
+impl Default for ChunkLoadPacing {
+    fn default() -> Self {
+        Self {
+            loads_per_frame: CHUNK_LOADS_PER_FRAME,
+            min_loads_per_frame: MIN_CHUNK_LOADS_PER_FRAME,
+            target_frame_time: TARGET_FRAME_TIME,
+            burst_loads_per_frame: BURST_CHUNK_LOADS_PER_FRAME,
+            burst_frames: BURST_FRAMES,
+            burst_frames_left: 0,
+            teleport_distance: TELEPORT_DISTANCE,
+        }
+    }
+}
+
+impl ChunkLoadPacing {
+    pub fn loads_this_frame(&self, frame_time: f32) -> usize {
+        if self.burst_frames_left > 0 {
+            return self.burst_loads_per_frame;
+        }
+
+        let scale = (self.target_frame_time / frame_time.max(f32::EPSILON)).min(1.);
+        ((self.loads_per_frame as f32 * scale) as usize).clamp(
+            self.min_loads_per_frame.min(self.loads_per_frame),
+            self.loads_per_frame,
+        )
+    }
+
+    pub fn start_burst(&mut self) {
+        self.burst_frames_left = self.burst_frames;
+    }
+
+    fn tick_burst(mut pacing: ResMut<ChunkLoadPacing>) {
+        pacing.burst_frames_left = pacing.burst_frames_left.saturating_sub(1);
+    }
+}
+
+#[derive(Reflect, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum LoadShape {
+    // Every chunk within the load distance along each axis
+    #[default]
+    Cube,
+    // Whole columns of chunks within the load distance along x and z, between the chunk heights
+    // Most gameplay (heightmaps, lighting, spawning) works on columns, and moving vertically within
+    // the bounds loads and unloads nothing
+    Columns {
+        min_y: i32,
+        max_y: i32,
+    },
+}
+
+impl LoadShape {
+    // Columns between the world's vertical bounds
+    pub fn columns() -> Self {
+        Self::Columns {
+            min_y: COLUMN_MIN_CHUNK_Y,
+            max_y: COLUMN_MAX_CHUNK_Y,
+        }
+    }
+
+    // Inclusive corners of the chunks around the center, margin chunks past the load distance
+    // Columns also reach margin chunks past their vertical bounds, so their ends can be meshed
+    pub fn bounds(
+        &self,
+        center: ChunkPos,
+        load_distance: u32,
+        margin: u32,
+    ) -> (ChunkPos, ChunkPos) {
+        let radius = (load_distance + margin) as i32;
+        let margin = margin as i32;
+
+        match *self {
+            Self::Cube => {
+                let vertical_radius = vertical_distance(load_distance) as i32 + margin;
+                let radius = ChunkPos::new(radius, vertical_radius, radius);
+
+                (center - radius, center + radius)
+            }
+            Self::Columns { min_y, max_y } => (
+                ChunkPos::new(center.x - radius, min_y - margin, center.z - radius),
+                ChunkPos::new(center.x + radius, max_y + margin, center.z + radius),
             ),
-        );
+        }
     }
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Reflect, Debug)]
+#[reflect(Component)]
 pub struct ChunkLoader {
     pub prev_chunk_pos: ChunkPos,
 
-    // Chunks to check in a frame
-    pub chunks_per_frame: usize,
+    // Radius (in chunks) which meshes are loaded within, data is loaded one chunk further
+    // Edits (in the inspector) are applied on the next frame, see ChunkLoader::apply_edited
+    pub load_distance: u32,
+
+    // Whether a cube or whole columns of chunks are loaded around the loader
+    pub shape: LoadShape,
+
+    // The load distance and shape the queues were last built for, so the chunks between their
+    // boxes and the edited ones can be queued
+    #[reflect(ignore)]
+    applied_load_distance: u32,
+    #[reflect(ignore)]
+    applied_shape: LoadShape,
 
-    // Offset grid sampling across frames
-    pub data_offset: usize,
-    pub mesh_offset: usize,
+    // How the chunks around the loader are meshed by distance
+    pub mesh_quality: MeshQualityPolicy,
 
     // Loading queues for chunk data and meshes
-    pub data_load_queue: Vec<ChunkPos>,
-    pub mesh_load_queue: Vec<ChunkPos>,
+    pub data_load_queue: ChunkQueue,
+    pub mesh_load_queue: ChunkQueue,
 
     // Unloading queues for chunk data and meshes
-    pub data_unload_queue: VecDeque<ChunkPos>,
-    pub mesh_unload_queue: VecDeque<ChunkPos>,
-
-    // When the loader is moved, these offsets identify which chunks need to be checked
-    pub data_sampling_offsets: Vec<ChunkPos>,
-    pub mesh_sampling_offsets: Vec<ChunkPos>,
+    pub data_unload_queue: ChunkQueue,
+    pub mesh_unload_queue: ChunkQueue,
+
+    // Seconds a chunk which left range waits before it is unloaded, chunks which come back into
+    // range in time are kept as they are, rather than unloaded and generated or meshed again
+    pub unload_delay: f32,
+    // Chunks waiting out the unload delay, by the elapsed seconds they are unloaded at
+    pub pending_data_unloads: HashMap<ChunkPos, f32>,
+    pub pending_mesh_unloads: HashMap<ChunkPos, f32>,
 }
 
 impl ChunkLoader {
     pub fn new(load_distance: u32) -> Self {
-        let data_distance = load_distance + 1;
-        let mesh_distance = load_distance;
-
-        let data_sampling_offsets = Self::make_spherical_offsets(data_distance);
-        let mesh_sampling_offsets = Self::make_spherical_offsets(mesh_distance);
-
         Self {
-            chunks_per_frame: CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE,
             prev_chunk_pos: ChunkPos::new(999, 999, 999),
-            data_offset: 0,
-            mesh_offset: 0,
-            data_load_queue: Vec::new(),
-            mesh_load_queue: Vec::new(),
-            data_unload_queue: VecDeque::new(),
-            mesh_unload_queue: VecDeque::new(),
-            data_sampling_offsets,
-            mesh_sampling_offsets,
+            load_distance,
+            shape: LoadShape::default(),
+            applied_load_distance: load_distance,
+            applied_shape: LoadShape::default(),
+            mesh_quality: MeshQualityPolicy::default(),
+            data_load_queue: ChunkQueue::new(),
+            mesh_load_queue: ChunkQueue::new(),
+            data_unload_queue: ChunkQueue::new(),
+            mesh_unload_queue: ChunkQueue::new(),
+            unload_delay: CHUNK_UNLOAD_DELAY,
+            pending_data_unloads: HashMap::new(),
+            pending_mesh_unloads: HashMap::new(),
         }
     }
 
-    fn make_spherical_offsets(radius: u32) -> Vec<ChunkPos> {
-        let r = (radius * 2) + 1;
+    // Chunks in the box between the inclusive corners which aren't in the other box
+    // Rows outside the other box are taken whole and the rest only visit the part which sticks out,
+    // so a step of one chunk doesn't touch every chunk in the box
+    fn box_difference(
+        (min, max): (ChunkPos, ChunkPos),
+        (other_min, other_max): (ChunkPos, ChunkPos),
+    ) -> Vec<ChunkPos> {
+        let outside = |value: i32, min: i32, max: i32| value < min || value > max;
+
+        let mut chunks = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                if outside(x, other_min.x, other_max.x) || outside(y, other_min.y, other_max.y) {
+                    chunks.extend((min.z..=max.z).map(|z| ChunkPos::new(x, y, z)));
+                } else {
+                    // Either side of the other box's z range
+                    let below = min.z..=max.z.min(other_min.z - 1);
+                    let above = min.z.max(other_max.z + 1)..=max.z;
+                    chunks.extend(below.chain(above).map(|z| ChunkPos::new(x, y, z)));
+                }
+            }
+        }
 
-        let mut sampling_offsets = Vec::new();
-        for i in 0..r * r * r {
-            let mut chunk_pos = index_to_chunk_pos_bounds(i as usize, r);
-            chunk_pos -= ChunkPos::splat(r as i32 / 2);
+        chunks
+    }
 
-            sampling_offsets.push(chunk_pos);
-        }
+    // Corners of the chunks whose data is kept loaded around the center, one chunk past the meshes
+    fn data_bounds(&self, center: ChunkPos, load_distance: u32) -> (ChunkPos, ChunkPos) {
+        self.shape.bounds(center, load_distance, 1)
+    }
 
-        // Sort offsets by the distance from origin
-        let origin = (0, 0, 0).into();
-        sampling_offsets.sort_by(|lhs, rhs| {
-            lhs.distance_squared(origin)
-                .cmp(&rhs.distance_squared(origin))
-        });
+    fn mesh_bounds(&self, center: ChunkPos, load_distance: u32) -> (ChunkPos, ChunkPos) {
+        self.shape.bounds(center, load_distance, 0)
+    }
+
+    // Whether the chunk's data is in range of where the loader last was
+    pub fn keeps_data(&self, chunk_pos: ChunkPos) -> bool {
+        bounds_contain(
+            self.data_bounds(self.prev_chunk_pos, self.load_distance),
+            chunk_pos,
+        )
+    }
 
-        sampling_offsets
+    pub fn keeps_mesh(&self, chunk_pos: ChunkPos) -> bool {
+        bounds_contain(
+            self.mesh_bounds(self.prev_chunk_pos, self.load_distance),
+            chunk_pos,
+        )
     }
 
     fn detect_move(
         mut loaders: Query<(&mut ChunkLoader, &GlobalTransform)>,
         mut world: ResMut<World>,
+        mut pacing: ResMut<ChunkLoadPacing>,
+        voxel_scale: Res<VoxelScale>,
     ) {
         for (mut loader, g_transform) in loaders.iter_mut() {
-            let chunk_pos = ChunkPos::from_vec3(
-                (g_transform.translation() - Vec3::splat(CHUNK_SIZE as f32 / 2.))
-                    / CHUNK_SIZE as f32,
-            );
+            let chunk_pos = voxel_scale.loader_chunk_pos(g_transform.translation());
 
             let prev_chunk_pos = loader.prev_chunk_pos;
             let chunk_pos_has_changed = chunk_pos != prev_chunk_pos;
@@ -109,120 +273,193 @@ impl ChunkLoader {
             }
             loader.prev_chunk_pos = chunk_pos;
 
-            let load_data_area = loader
-                .data_sampling_offsets
-                .iter()
-                .map(|offset| chunk_pos + *offset)
-                .collect::<HashSet<ChunkPos>>();
-
-            let unload_data_area = loader
-                .data_sampling_offsets
-                .iter()
-                .map(|offset| prev_chunk_pos + *offset)
-                .collect::<HashSet<ChunkPos>>();
-
-            let load_mesh_area = loader
-                .mesh_sampling_offsets
-                .iter()
-                .map(|offset| chunk_pos + *offset)
-                .collect::<HashSet<ChunkPos>>();
+            // Load the new surroundings quickly after a teleport
+            if chunk_pos.distance_squared(prev_chunk_pos) > pacing.teleport_distance.pow(2) {
+                pacing.start_burst();
+            }
 
-            let unload_mesh_area = loader
-                .mesh_sampling_offsets
-                .iter()
-                .map(|offset| prev_chunk_pos + *offset)
-                .collect::<HashSet<ChunkPos>>();
+            // Only the slabs of each box which moved in or out are visited, columns don't change
+            // when the loader only moves vertically
+            let load_distance = loader.load_distance;
+            let (data, prev_data) = (
+                loader.data_bounds(chunk_pos, load_distance),
+                loader.data_bounds(prev_chunk_pos, load_distance),
+            );
+            let (mesh, prev_mesh) = (
+                loader.mesh_bounds(chunk_pos, load_distance),
+                loader.mesh_bounds(prev_chunk_pos, load_distance),
+            );
 
-            let data_load = load_data_area.difference(&unload_data_area);
-            let data_unload = unload_data_area.difference(&load_data_area);
-            let mesh_load = load_mesh_area.difference(&unload_mesh_area);
-            let mesh_unload = unload_mesh_area.difference(&load_mesh_area);
+            let data_load = Self::box_difference(data, prev_data);
+            let data_unload = Self::box_difference(prev_data, data);
+            let mesh_load = Self::box_difference(mesh, prev_mesh);
+            let mesh_unload = Self::box_difference(prev_mesh, mesh);
 
             loader.data_load_queue.extend(data_load);
             loader.data_unload_queue.extend(data_unload);
             loader.mesh_load_queue.extend(mesh_load);
             loader.mesh_unload_queue.extend(mesh_unload);
 
-            let ChunkLoader {
-                data_load_queue,
-                mesh_load_queue,
-                data_unload_queue,
-                mesh_unload_queue,
-                ..
-            } = loader.as_mut();
-
-            // Remove resolved chunk data from queue
-            for pos in data_unload_queue.iter() {
-                if let Some((i, _)) = world
-                    .load_data_queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_i, world_chunk_pos)| *world_chunk_pos == pos)
-                {
-                    world.load_data_queue.remove(i);
-                }
-            }
+            loader.resolve_queues(&mut world);
+        }
+    }
 
-            // Remove resolved meshes from queue
-            for pos in mesh_unload_queue.iter() {
-                if let Some((i, _)) = world
-                    .load_mesh_queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_i, world_chunk_pos)| *world_chunk_pos == pos)
-                {
-                    world.load_mesh_queue.remove(i);
-                }
+    // Drop the World's queued loads of chunks the loader is unloading, and sort its load queues
+    fn resolve_queues(&mut self, world: &mut World) {
+        let ChunkLoader {
+            prev_chunk_pos,
+            shape,
+            data_load_queue,
+            mesh_load_queue,
+            data_unload_queue,
+            mesh_unload_queue,
+            pending_data_unloads,
+            pending_mesh_unloads,
+            ..
+        } = self;
+
+        // Remove resolved chunk data from queue
+        for pos in data_unload_queue.iter() {
+            world.load_data_queue.remove(pos);
+            world.cancel_generation(*pos);
+        }
+
+        // Remove resolved meshes from queue
+        for pos in mesh_unload_queue.iter() {
+            world.load_mesh_queue.remove(pos);
+            world.remesh_sections.remove(pos);
+        }
+
+        // Remove the unloads from load
+        data_load_queue.retain(|pos| !data_unload_queue.contains(pos));
+        mesh_load_queue.retain(|pos| !mesh_unload_queue.contains(pos));
+
+        // Chunks back in range before their unload delay is up are kept, unless their load was
+        // cancelled before it finished
+        data_load_queue.retain(|pos| {
+            pending_data_unloads.remove(pos).is_none() || !world.chunks.contains_key(pos)
+        });
+        mesh_load_queue.retain(|pos| {
+            pending_mesh_unloads.remove(pos).is_none() || !world.chunk_entities.contains_key(pos)
+        });
+
+        // Sort data and mesh load queues by distance to the loader, columns are loaded whole
+        match shape {
+            LoadShape::Cube => {
+                data_load_queue.sort_by_distance(*prev_chunk_pos);
+                mesh_load_queue.sort_by_distance(*prev_chunk_pos);
             }
+            LoadShape::Columns { .. } => {
+                data_load_queue.sort_by_column_distance(*prev_chunk_pos);
+                mesh_load_queue.sort_by_column_distance(*prev_chunk_pos);
+            }
+        }
+    }
 
-            // Remove the unloads from load
-            data_load_queue.retain(|pos| !data_unload_queue.contains(pos));
-            mesh_load_queue.retain(|pos| !mesh_unload_queue.contains(pos));
+    // Change the radius at runtime, loading or unloading the shell between the old and new boxes
+    pub fn set_load_distance(&mut self, load_distance: u32, world: &mut World) {
+        self.load_distance = load_distance;
+        self.apply_edits(world);
+    }
 
-            // Sort data and mesh load queues by distance to chunk_pos
-            loader.data_load_queue.sort_by(|lhs, rhs| {
-                lhs.distance_squared(chunk_pos)
-                    .cmp(&rhs.distance_squared(chunk_pos))
-            });
-            loader.mesh_load_queue.sort_by(|lhs, rhs| {
-                lhs.distance_squared(chunk_pos)
-                    .cmp(&rhs.distance_squared(chunk_pos))
-            });
+    // Switch between loading a cube and whole columns around the loader at runtime
+    pub fn set_shape(&mut self, shape: LoadShape, world: &mut World) {
+        self.shape = shape;
+        self.apply_edits(world);
+    }
+
+    // Loaders whose load distance or shape was edited directly, such as in the inspector
+    fn apply_edited(
+        mut loaders: Query<&mut ChunkLoader, Changed<ChunkLoader>>,
+        mut world: ResMut<World>,
+    ) {
+        for mut loader in loaders.iter_mut() {
+            loader.bypass_change_detection().apply_edits(&mut world);
         }
     }
 
+    // Queue the chunks between the boxes the queues were built for and the edited ones
+    fn apply_edits(&mut self, world: &mut World) {
+        let (load_distance, shape) = (self.load_distance, self.shape);
+        let (previous, prev_shape) = (self.applied_load_distance, self.applied_shape);
+        if load_distance == previous && shape == prev_shape {
+            return;
+        }
+        self.applied_load_distance = load_distance;
+        self.applied_shape = shape;
+
+        // One of each pair is empty when only the distance changed, depending on whether the
+        // boxes grew or shrank
+        let center = self.prev_chunk_pos;
+        let (data, prev_data) = (
+            shape.bounds(center, load_distance, 1),
+            prev_shape.bounds(center, previous, 1),
+        );
+        let (mesh, prev_mesh) = (
+            shape.bounds(center, load_distance, 0),
+            prev_shape.bounds(center, previous, 0),
+        );
+
+        self.queue_changes(data, prev_data, mesh, prev_mesh, world);
+    }
+
+    // Load the chunks which are only in the new boxes and unload those only in the previous ones
+    fn queue_changes(
+        &mut self,
+        data: (ChunkPos, ChunkPos),
+        prev_data: (ChunkPos, ChunkPos),
+        mesh: (ChunkPos, ChunkPos),
+        prev_mesh: (ChunkPos, ChunkPos),
+        world: &mut World,
+    ) {
+        self.data_load_queue
+            .extend(Self::box_difference(data, prev_data));
+        self.data_unload_queue
+            .extend(Self::box_difference(prev_data, data));
+        self.mesh_load_queue
+            .extend(Self::box_difference(mesh, prev_mesh));
+        self.mesh_unload_queue
+            .extend(Self::box_difference(prev_mesh, mesh));
+
+        self.resolve_queues(world);
+    }
+
+    // Forget what has been loaded, so everything around the loader is queued again on the next frame
+    pub fn reload(&mut self) {
+        self.prev_chunk_pos = ChunkPos::new(999, 999, 999);
+        self.data_load_queue = ChunkQueue::new();
+        self.mesh_load_queue = ChunkQueue::new();
+        self.data_unload_queue = ChunkQueue::new();
+        self.mesh_unload_queue = ChunkQueue::new();
+        self.pending_data_unloads.clear();
+        self.pending_mesh_unloads.clear();
+    }
+
     pub fn load_chunks(
         mut loaders: Query<(&mut ChunkLoader, &GlobalTransform)>,
         mut world: ResMut<World>,
+        pacing: Res<ChunkLoadPacing>,
+        time: Res<Time>,
+        border: Res<WorldBorder>,
     ) {
-        for (mut loader, _g_transform) in loaders.iter_mut() {
-            if world.data_tasks.len() >= MAX_DATA_TASKS {
-                return;
-            }
+        let max_loads = pacing.loads_this_frame(time.delta_seconds());
 
-            let data_len = loader.data_load_queue.len();
+        for (mut loader, _g_transform) in loaders.iter_mut() {
+            for chunk_pos in loader.data_load_queue.drain_front(max_loads) {
+                if !border.should_load_chunk(chunk_pos) {
+                    continue;
+                }
 
-            for chunk_pos in loader
-                .data_load_queue
-                .drain(0..MAX_CHUNK_LOADS.min(data_len))
-            {
                 let is_busy = world.chunks.contains_key(&chunk_pos)
                     || world.load_data_queue.contains(&chunk_pos)
-                    || world.data_tasks.contains_key(&chunk_pos);
+                    || world.data_tasks.contains_key(&chunk_pos)
+                    || world.is_generating(chunk_pos);
 
                 if !is_busy {
                     world.load_data_queue.push(chunk_pos);
 
-                    // Abort load
-                    let index_of_unloading = world
-                        .unload_data_queue
-                        .iter()
-                        .enumerate()
-                        .find_map(|(i, pos)| if pos == &chunk_pos { Some(i) } else { None });
-
-                    if let Some(i) = index_of_unloading {
-                        world.unload_data_queue.remove(i);
-                    }
+                    // Abort unload
+                    world.unload_data_queue.remove(&chunk_pos);
                 }
             }
         }
@@ -231,29 +468,56 @@ impl ChunkLoader {
     pub fn unload_chunks(
         mut loaders: Query<(&mut ChunkLoader, &GlobalTransform)>,
         mut world: ResMut<World>,
+        time: Res<Time>,
     ) {
+        let now = time.elapsed_seconds();
+
         // Find all loaded and check if in range
         for (mut loader, _g_transform) in loaders.iter_mut() {
-            for chunk_pos in loader.data_unload_queue.drain(..) {
+            let unload_at = now + loader.unload_delay;
+
+            for chunk_pos in loader.data_unload_queue.drain_all() {
                 let is_busy = !world.chunks.contains_key(&chunk_pos);
 
                 if !is_busy {
-                    world.unload_data_queue.push(chunk_pos);
+                    loader
+                        .pending_data_unloads
+                        .entry(chunk_pos)
+                        .or_insert(unload_at);
                 }
             }
+
+            loader.pending_data_unloads.retain(|chunk_pos, &mut at| {
+                if at > now {
+                    return true;
+                }
+
+                if world.chunks.contains_key(chunk_pos) {
+                    world.unload_data_queue.push(*chunk_pos);
+                }
+                false
+            });
         }
     }
 
-    pub fn load_mesh(mut loaders: Query<&mut ChunkLoader>, mut world: ResMut<World>) {
+    pub fn load_mesh(
+        mut loaders: Query<&mut ChunkLoader>,
+        mut world: ResMut<World>,
+        pacing: Res<ChunkLoadPacing>,
+        time: Res<Time>,
+        border: Res<WorldBorder>,
+    ) {
+        let max_loads = pacing.loads_this_frame(time.delta_seconds());
+
         for mut loader in loaders.iter_mut() {
             let mut retries = Vec::new();
 
-            let mesh_data_len = loader.mesh_load_queue.len();
+            for chunk_pos in loader.mesh_load_queue.drain_front(max_loads) {
+                // Chunks past the border are only loaded as air for their neighbours
+                if !border.contains_chunk(chunk_pos) {
+                    continue;
+                }
 
-            for chunk_pos in loader
-                .mesh_load_queue
-                .drain(0..MAX_CHUNK_LOADS.min(mesh_data_len))
-            {
                 let mut is_busy = world.load_mesh_queue.contains(&chunk_pos);
 
                 is_busy |= !ADJACENT_CHUNK_DIRECTIONS
@@ -262,33 +526,278 @@ impl ChunkLoader {
                     .all(|pos| world.chunks.contains_key(&pos));
 
                 if !is_busy {
+                    // Loading a chunk meshes every section
                     world.load_mesh_queue.push(chunk_pos);
+                    world.remesh_sections.remove(&chunk_pos);
 
-                    // Abort load
-                    let index_of_unloading = world
-                        .unload_mesh_queue
-                        .iter()
-                        .enumerate()
-                        .find_map(|(i, pos)| if pos == &chunk_pos { Some(i) } else { None });
-
-                    if let Some(i) = index_of_unloading {
-                        world.unload_mesh_queue.remove(i);
-                    }
+                    // Abort unload
+                    world.unload_mesh_queue.remove(&chunk_pos);
                 } else {
                     retries.push(chunk_pos);
                 }
             }
 
-            loader.mesh_load_queue.append(&mut retries);
+            loader.mesh_load_queue.extend(retries);
         }
     }
 
-    pub fn unload_mesh(mut loaders: Query<&mut ChunkLoader>, mut world: ResMut<World>) {
+    pub fn unload_mesh(
+        mut loaders: Query<&mut ChunkLoader>,
+        mut world: ResMut<World>,
+        time: Res<Time>,
+    ) {
+        let now = time.elapsed_seconds();
+
         // Find all loaded and check if in range
         for mut loader in loaders.iter_mut() {
-            for chunk_pos in loader.mesh_unload_queue.drain(..) {
-                world.unload_mesh_queue.push(chunk_pos);
+            let unload_at = now + loader.unload_delay;
+
+            for chunk_pos in loader.mesh_unload_queue.drain_all() {
+                loader
+                    .pending_mesh_unloads
+                    .entry(chunk_pos)
+                    .or_insert(unload_at);
             }
+
+            loader.pending_mesh_unloads.retain(|chunk_pos, &mut at| {
+                if at > now {
+                    return true;
+                }
+
+                world.unload_mesh_queue.push(*chunk_pos);
+                false
+            });
+        }
+    }
+}
+
+// A secondary area kept loaded and meshed, such as the far side of a portal or the view of a map
+// camera. Unlike a ChunkLoader an anchor has no movement detection or load pacing, the chunks around
+// it are compared with the ones it held the frame before, so anchors should be kept small
+// Mesh qualities still follow the ChunkLoader, so anchored chunks are meshed by their distance to it
+#[derive(Component, Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct ChunkLoadAnchor {
+    // Radius (in chunks) of the cube which meshes are loaded within, data is loaded one chunk further
+    pub radius: u32,
+    // Anchors which nothing looks through, such as a cutscene's next area, only need the data
+    pub mesh: bool,
+}
+
+impl ChunkLoadAnchor {
+    pub fn new(radius: u32) -> Self {
+        Self { radius, mesh: true }
+    }
+
+    pub fn data_only(radius: u32) -> Self {
+        Self {
+            radius,
+            mesh: false,
         }
     }
 }
+
+// Chunks held by the anchors, which stay loaded when the loaders move away from them
+#[derive(Resource, Debug, Default)]
+pub struct AnchoredChunks {
+    pub data: HashSet<ChunkPos>,
+    pub meshes: HashSet<ChunkPos>,
+    // Anchored chunks waiting for their neighbours' data before they can be meshed
+    mesh_load_queue: ChunkQueue,
+}
+
+impl AnchoredChunks {
+    fn update(
+        mut anchored: ResMut<AnchoredChunks>,
+        anchors: Query<(&ChunkLoadAnchor, &GlobalTransform)>,
+        loaders: Query<&ChunkLoader>,
+        mut world: ResMut<World>,
+        voxel_scale: Res<VoxelScale>,
+        border: Res<WorldBorder>,
+    ) {
+        if anchors.is_empty() && anchored.data.is_empty() && anchored.meshes.is_empty() {
+            return;
+        }
+
+        let mut data = HashSet::new();
+        let mut meshes = HashSet::new();
+        for (anchor, g_transform) in anchors.iter() {
+            let center = voxel_scale.loader_chunk_pos(g_transform.translation());
+
+            data.extend(chunks_in(LoadShape::Cube.bounds(center, anchor.radius, 1)));
+            if anchor.mesh {
+                meshes.extend(chunks_in(LoadShape::Cube.bounds(center, anchor.radius, 0)));
+            }
+        }
+
+        let AnchoredChunks {
+            data: held_data,
+            meshes: held_meshes,
+            mesh_load_queue,
+        } = anchored.as_mut();
+
+        // Released chunks are unloaded straight away, unless a loader still wants them
+        for &chunk_pos in held_data.difference(&data) {
+            if loaders.iter().any(|loader| loader.keeps_data(chunk_pos)) {
+                continue;
+            }
+
+            world.load_data_queue.remove(&chunk_pos);
+            world.cancel_generation(chunk_pos);
+            if world.chunks.contains_key(&chunk_pos) {
+                world.unload_data_queue.push(chunk_pos);
+            }
+        }
+        for &chunk_pos in held_meshes.difference(&meshes) {
+            mesh_load_queue.remove(&chunk_pos);
+            if loaders.iter().any(|loader| loader.keeps_mesh(chunk_pos)) {
+                continue;
+            }
+
+            world.load_mesh_queue.remove(&chunk_pos);
+            world.remesh_sections.remove(&chunk_pos);
+            world.unload_mesh_queue.push(chunk_pos);
+        }
+
+        // The loaders' unloads skip anchored chunks
+        world
+            .unload_data_queue
+            .retain(|chunk_pos| !data.contains(chunk_pos));
+        world
+            .unload_mesh_queue
+            .retain(|chunk_pos| !meshes.contains(chunk_pos));
+
+        // Every anchored chunk is checked, as a loader moving away cancels loads which haven't started
+        for &chunk_pos in &data {
+            let is_busy = world.chunks.contains_key(&chunk_pos)
+                || world.load_data_queue.contains(&chunk_pos)
+                || world.data_tasks.contains_key(&chunk_pos)
+                || world.is_generating(chunk_pos);
+
+            if !is_busy && border.should_load_chunk(chunk_pos) {
+                world.load_data_queue.push(chunk_pos);
+            }
+        }
+
+        // Chunks the loader has already meshed aren't meshed again
+        for &chunk_pos in meshes.difference(held_meshes) {
+            if border.contains_chunk(chunk_pos) && !world.chunk_entities.contains_key(&chunk_pos) {
+                mesh_load_queue.push(chunk_pos);
+            }
+        }
+
+        let mut retries = Vec::new();
+        for chunk_pos in mesh_load_queue.drain_all() {
+            let neighbours_loaded = ADJACENT_CHUNK_DIRECTIONS
+                .iter()
+                .all(|&offset| world.chunks.contains_key(&(chunk_pos + offset)));
+
+            if !neighbours_loaded {
+                retries.push(chunk_pos);
+            } else if !world.load_mesh_queue.contains(&chunk_pos) {
+                // Loading a chunk meshes every section
+                world.load_mesh_queue.push(chunk_pos);
+                world.remesh_sections.remove(&chunk_pos);
+            }
+        }
+        mesh_load_queue.extend(retries);
+
+        *held_data = data;
+        *held_meshes = meshes;
+    }
+}
+
+// Chunks along y covering the same distance in voxels as the distance in chunks along x and z, which
+// is fewer when chunks are taller than they are wide
+pub fn vertical_distance(distance: u32) -> u32 {
+    (distance * CHUNK_SIZE as u32).div_ceil(CHUNK_HEIGHT as u32)
+}
+
+// Chunks between the inclusive corners
+pub(crate) fn chunks_in((min, max): (ChunkPos, ChunkPos)) -> impl Iterator<Item = ChunkPos> {
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| ChunkPos::new(x, y, z)))
+    })
+}
+
+pub(crate) fn bounds_contain((min, max): (ChunkPos, ChunkPos), chunk_pos: ChunkPos) -> bool {
+    (min.x..=max.x).contains(&chunk_pos.x)
+        && (min.y..=max.y).contains(&chunk_pos.y)
+        && (min.z..=max.z).contains(&chunk_pos.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // Inclusive corners of a box of up to 6 chunks along each axis
+    fn corners() -> impl Strategy<Value = (ChunkPos, ChunkPos)> {
+        (
+            prop::array::uniform3(-6..6_i32),
+            prop::array::uniform3(0..6_i32),
+        )
+            .prop_map(|([x, y, z], [width, height, depth])| {
+                let min = ChunkPos::new(x, y, z);
+                (min, min + ChunkPos::new(width, height, depth))
+            })
+    }
+
+    fn chunks_in((min, max): (ChunkPos, ChunkPos)) -> HashSet<ChunkPos> {
+        let mut chunks = HashSet::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    chunks.insert(ChunkPos::new(x, y, z));
+                }
+            }
+        }
+
+        chunks
+    }
+
+    proptest! {
+        #[test]
+        fn box_difference_matches_the_set_difference(a in corners(), b in corners()) {
+            let difference = ChunkLoader::box_difference(a, b);
+            let expected = chunks_in(a)
+                .difference(&chunks_in(b))
+                .copied()
+                .collect::<HashSet<_>>();
+
+            // Each chunk is visited once
+            prop_assert_eq!(difference.len(), expected.len());
+            prop_assert_eq!(difference.into_iter().collect::<HashSet<_>>(), expected);
+        }
+    }
+    #[test]
+    fn edited_load_distances_queue_the_new_shell() {
+        let mut app = App::new();
+        app.init_resource::<World>()
+            .add_systems(Update, ChunkLoader::apply_edited);
+
+        let mut loader = ChunkLoader::new(1);
+        loader.prev_chunk_pos = ChunkPos::new(0, 0, 0);
+        let entity = app.world_mut().spawn(loader).id();
+        app.update();
+
+        // As edited in the inspector
+        app.world_mut()
+            .get_mut::<ChunkLoader>(entity)
+            .unwrap()
+            .load_distance = 2;
+        app.update();
+
+        let loader = app.world().get::<ChunkLoader>(entity).unwrap();
+        let center = ChunkPos::new(0, 0, 0);
+        let shell = chunks_in(loader.data_bounds(center, 2))
+            .difference(&chunks_in(loader.data_bounds(center, 1)))
+            .copied()
+            .collect::<HashSet<_>>();
+
+        assert_eq!(loader.data_load_queue.len(), shell.len());
+        assert!(shell.iter().all(|pos| loader.data_load_queue.contains(pos)));
+        assert!(loader.data_unload_queue.is_empty());
+    }
+}
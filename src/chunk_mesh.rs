@@ -1,10 +1,17 @@
 use std::collections::VecDeque;
 
-use bevy::math::IVec3;
+use bevy::{math::IVec3, render::primitives::Aabb};
+use xxhash_rust::xxh3::Xxh3;
 
-use crate::{lod::Lod, positions::VoxelPos, vertex::VertexU32, voxel::VoxelType};
+use crate::{
+    lod::Lod,
+    mesh_pool::MeshMemory,
+    positions::VoxelPos,
+    vertex::{FaceU32, PackedVertex, SmoothVertex, Vertex},
+    voxel::VoxelType,
+};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum Direction {
     Left,
@@ -16,6 +23,44 @@ pub enum Direction {
 }
 
 impl Direction {
+    pub const ALL: [Self; 6] = [
+        Self::Left,
+        Self::Right,
+        Self::Back,
+        Self::Front,
+        Self::Up,
+        Self::Down,
+    ];
+
+    pub fn normal(&self) -> IVec3 {
+        match self {
+            Self::Left => IVec3::NEG_X,
+            Self::Right => IVec3::X,
+            Self::Back => IVec3::Z,
+            Self::Front => IVec3::NEG_Z,
+            Self::Up => IVec3::Y,
+            Self::Down => IVec3::NEG_Y,
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            Self::Back => Self::Front,
+            Self::Front => Self::Back,
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+        }
+    }
+
+    // The face a picking ray hit, None for rays starting inside a voxel
+    pub fn from_normal(normal: IVec3) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|direction| direction.normal() == normal)
+    }
+
     pub fn get_normal_index(&self) -> usize {
         match self {
             Self::Left => 0,
@@ -31,10 +76,97 @@ impl Direction {
 #[derive(Default, Clone)]
 pub struct ChunkMesh {
     // pub vertices: Vec<Vertex>,
-    pub vertices: Vec<VertexU32>,
+    pub vertices: Vec<PackedVertex>,
     pub indices: Vec<u32>,
+    // Biome tint of each vertex, sampled from the biome map once the mesh is built
+    pub biome_tints: Vec<f32>,
+    // Far meshes only store a packed face per quad, drawn without an index buffer
+    pub far_faces: Vec<FaceU32>,
+    // Smooth meshes store their vertices unpacked, indexed by the indices like packed vertices
+    pub smooth_vertices: Vec<SmoothVertex>,
+    // Bounds of the vertices relative to the chunk, set by the mesh task, see compute_aabb
+    pub aabb: Option<Aabb>,
+    // Counts the buffers towards the meshing memory diagnostics, see mesh_pool
+    pub memory: MeshMemory,
+}
+
+impl ChunkMesh {
+    // Cheap fingerprint of the mesh, so a remesh which produced the same geometry can be skipped
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Xxh3::new();
+
+        hasher.update(&(self.vertices.len() as u32).to_le_bytes());
+        for &vertex in &self.vertices {
+            for word in <[u32; 2]>::from(vertex) {
+                hasher.update(&word.to_le_bytes());
+            }
+        }
+        for tint in &self.biome_tints {
+            hasher.update(&tint.to_le_bytes());
+        }
+        for &face in &self.far_faces {
+            hasher.update(&u32::from(face).to_le_bytes());
+        }
+        for vertex in &self.smooth_vertices {
+            for component in vertex
+                .pos
+                .to_array()
+                .into_iter()
+                .chain(vertex.normal.to_array())
+            {
+                hasher.update(&component.to_le_bytes());
+            }
+            hasher.update(&u32::from(vertex.voxel_type).to_le_bytes());
+        }
+
+        hasher.digest()
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        !self.smooth_vertices.is_empty()
+    }
+
+    // Vertices of either kind, far faces aren't counted
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() + self.smooth_vertices.len()
+    }
+
+    // Bounds of the vertices, so the mesh is culled by what it covers rather than its whole section
+    // Packed vertices are only decoded when decode_packed is set, smooth vertices are always measured
+    // as they can reach past their section
+    pub fn compute_aabb(&self, decode_packed: bool) -> Option<Aabb> {
+        if self.is_smooth() {
+            return Aabb::enclosing(self.smooth_vertices.iter().map(|vertex| vertex.pos));
+        }
+        if !decode_packed {
+            return None;
+        }
+
+        Aabb::enclosing(
+            self.vertices
+                .iter()
+                .map(|&vertex| Vertex::from(vertex).pos.to_ivec3().as_vec3()),
+        )
+    }
+
+    // The far version of this mesh, vertices are grouped into quads of four
+    // Only for chunks whose faces fit, see FAR_FACES_FIT
+    pub fn to_far(&self) -> Self {
+        Self {
+            far_faces: self
+                .vertices
+                .chunks_exact(4)
+                .map(FaceU32::from_quad)
+                .collect(),
+            aabb: self.aabb,
+            ..Default::default()
+        }
+    }
 }
 
+// Meshes for the sections of a chunk, by section index, sections without faces have no mesh
+pub type SectionMeshes = Vec<(usize, Option<ChunkMesh>)>;
+
 pub struct Quad {
     pub corners: [[usize; 3]; 4],
     pub dir: Direction,
@@ -85,12 +217,12 @@ impl Quad {
     }
 }
 
-// generate a vec of indices
+// generate the indices of the vertices, appended to the buffer so pooled buffers are reused
 // assumes vertices are made of quads, and counter clockwise ordered
-pub fn generate_indices(vertex_count: usize) -> Vec<u32> {
-    let mut indices = Vec::with_capacity((vertex_count * 6) / 4);
+pub fn generate_indices(indices: &mut Vec<u32>, vertex_count: usize) {
+    indices.reserve((vertex_count * 6) / 4);
     for vert_index in (0..vertex_count as u32).step_by(4) {
-        indices.append(&mut vec![
+        indices.extend([
             vert_index,
             vert_index + 1,
             vert_index + 2,
@@ -99,7 +231,6 @@ pub fn generate_indices(vertex_count: usize) -> Vec<u32> {
             vert_index + 3,
         ]);
     }
-    indices
 }
 
 #[derive(Debug)]
@@ -115,45 +246,51 @@ impl GreedyQuad {
         Self { x, y, w, h }
     }
 
+    // AO of the corners (x, y), (x + w, y), (x + w, y + h) and (x, y + h), from the solid voxels
+    // around a face, bit i set for ADJACENT_AO_DIRS[i]
+    pub fn corner_aos(ao: u32) -> [u32; 4] {
+        [
+            (ao & 1) + ((ao >> 1) & 1) + ((ao >> 3) & 1),
+            ((ao >> 3) & 1) + ((ao >> 6) & 1) + ((ao >> 7) & 1),
+            ((ao >> 5) & 1) + ((ao >> 8) & 1) + ((ao >> 7) & 1),
+            ((ao >> 1) & 1) + ((ao >> 2) & 1) + ((ao >> 5) & 1),
+        ]
+    }
+
+    // Corner AOs are packed into the vertices, see GreedyQuad::corner_aos
     pub fn append_vertices(
         &self,
-        vertices: &mut Vec<VertexU32>,
+        vertices: &mut Vec<PackedVertex>,
         face_dir: FaceDir,
         axis: u32,
         lod: &Lod,
-        ao: u32,
+        [v1ao, v2ao, v3ao, v4ao]: [u32; 4],
         voxel_type: VoxelType,
     ) {
         let jump = lod.jump_index();
 
-        // Pack the ambient occlusion into the vertex
-        let v1ao = (ao & 1) + ((ao >> 1) & 1) + ((ao >> 3) & 1);
-        let v2ao = ((ao >> 3) & 1) + ((ao >> 6) & 1) + ((ao >> 7) & 1);
-        let v3ao = ((ao >> 5) & 1) + ((ao >> 8) & 1) + ((ao >> 7) & 1);
-        let v4ao = ((ao >> 1) & 1) + ((ao >> 2) & 1) + ((ao >> 5) & 1);
-
-        let vertex_1 = VertexU32::new(
+        let vertex_1 = PackedVertex::new(
             face_dir.world_to_sample(axis, self.x, self.y) * jump,
             v1ao,
             face_dir.get_normal_index(),
             voxel_type,
         );
 
-        let vertex_2 = VertexU32::new(
+        let vertex_2 = PackedVertex::new(
             face_dir.world_to_sample(axis, self.x + self.w, self.y) * jump,
             v2ao,
             face_dir.get_normal_index(),
             voxel_type,
         );
 
-        let vertex_3 = VertexU32::new(
+        let vertex_3 = PackedVertex::new(
             face_dir.world_to_sample(axis, self.x + self.w, self.y + self.h) * jump,
             v3ao,
             face_dir.get_normal_index(),
             voxel_type,
         );
 
-        let vertex_4 = VertexU32::new(
+        let vertex_4 = PackedVertex::new(
             face_dir.world_to_sample(axis, self.x, self.y + self.h) * jump,
             v4ao,
             face_dir.get_normal_index(),
@@ -0,0 +1,171 @@
+use crate::{
+    biome::BiomeMap,
+    byte_codec::ByteReader,
+    chunk::Chunk,
+    constants::{BIOME_COUNT, CHUNK_HEIGHT, CHUNK_SIZE},
+    positions::{ChunkPos, VoxelPos, WorldPos},
+    voxel::VoxelType,
+    world::World,
+};
+
+// Facts recorded when a chunk is generated, so gameplay code can query them without rescanning voxels
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkMeta {
+    // Number of voxel columns in each biome
+    pub biome_histogram: [u32; BIOME_COUNT],
+    // Bounds of the structures which overlap this chunk
+    pub structures: Vec<StructureBounds>,
+    // World height an entity can stand at in each column (x + z * CHUNK_SIZE), if the column has a surface
+    pub spawn_heights: Vec<Option<i32>>,
+}
+
+impl Default for ChunkMeta {
+    fn default() -> Self {
+        Self {
+            biome_histogram: [0; BIOME_COUNT],
+            structures: Vec::new(),
+            spawn_heights: vec![None; CHUNK_SIZE * CHUNK_SIZE],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StructureKind {
+    Village,
+}
+
+impl StructureKind {
+    pub fn try_from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Village),
+            _ => None,
+        }
+    }
+}
+
+// Inclusive world space bounding box of a generated structure
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StructureBounds {
+    pub kind: StructureKind,
+    pub min: WorldPos,
+    pub max: WorldPos,
+}
+
+impl StructureBounds {
+    pub fn contains(&self, world_pos: WorldPos) -> bool {
+        (self.min.x..=self.max.x).contains(&world_pos.x)
+            && (self.min.y..=self.max.y).contains(&world_pos.y)
+            && (self.min.z..=self.max.z).contains(&world_pos.z)
+    }
+}
+
+impl ChunkMeta {
+    // Metadata which can be derived from the voxels alone, without any structures
+    pub fn from_chunk(chunk_pos: ChunkPos, chunk: &Chunk) -> Self {
+        let biome_map = BiomeMap::new();
+        let mut meta = Self::default();
+
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let column_pos = WorldPos::from_voxel_pos(VoxelPos::new(x, 0, z), chunk_pos);
+                meta.biome_histogram[biome_map.biome_at(column_pos.x, column_pos.z)] += 1;
+
+                // The highest solid voxel with air above it, the top layer is skipped as the voxel above is in another chunk
+                meta.spawn_heights[x + z * CHUNK_SIZE] = (0..CHUNK_HEIGHT - 1)
+                    .rev()
+                    .find(|&y| {
+                        chunk[VoxelPos::new(x, y, z)].voxel_type.is_solid()
+                            && chunk[VoxelPos::new(x, y + 1, z)].voxel_type == VoxelType::AIR
+                    })
+                    .map(|y| column_pos.y + y as i32 + 1);
+            }
+        }
+
+        meta
+    }
+
+    // The biome covering the most columns of the chunk
+    pub fn main_biome(&self) -> usize {
+        (0..BIOME_COUNT)
+            .max_by_key(|&biome| self.biome_histogram[biome])
+            .unwrap_or_default()
+    }
+
+    pub fn spawn_height(&self, x: usize, z: usize) -> Option<i32> {
+        self.spawn_heights[x + z * CHUNK_SIZE]
+    }
+
+    pub fn structure_at(&self, world_pos: WorldPos) -> Option<&StructureBounds> {
+        self.structures
+            .iter()
+            .find(|structure| structure.contains(world_pos))
+    }
+
+    // Little endian: the biome histogram, the spawn heights (i32::MIN for none), then the structures
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        for count in self.biome_histogram {
+            bytes.extend(count.to_le_bytes());
+        }
+        for height in &self.spawn_heights {
+            bytes.extend(height.unwrap_or(i32::MIN).to_le_bytes());
+        }
+
+        bytes.extend((self.structures.len() as u32).to_le_bytes());
+        for structure in &self.structures {
+            bytes.push(structure.kind as u8);
+            for value in [structure.min.to_tuple(), structure.max.to_tuple()]
+                .into_iter()
+                .flat_map(|(x, y, z)| [x, y, z])
+            {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader::new(bytes);
+        let mut meta = Self::default();
+
+        for count in meta.biome_histogram.iter_mut() {
+            *count = reader.read_u32()?;
+        }
+        for height in meta.spawn_heights.iter_mut() {
+            *height = Some(reader.read_u32()? as i32).filter(|&height| height != i32::MIN);
+        }
+
+        let structure_count = reader.read_u32()?;
+        for _ in 0..structure_count {
+            let kind = StructureKind::try_from_u8(reader.read_u8()?)?;
+            let mut read_pos = || -> Option<WorldPos> {
+                Some(WorldPos::new(
+                    reader.read_u32()? as i32,
+                    reader.read_u32()? as i32,
+                    reader.read_u32()? as i32,
+                ))
+            };
+            let (min, max) = (read_pos()?, read_pos()?);
+
+            meta.structures.push(StructureBounds { kind, min, max });
+        }
+
+        reader.is_empty().then_some(meta)
+    }
+}
+
+impl World {
+    // Generation metadata of a loaded chunk
+    pub fn chunk_meta(&self, chunk_pos: ChunkPos) -> Option<&ChunkMeta> {
+        self.chunk_metas.get(&chunk_pos).map(AsRef::as_ref)
+    }
+
+    // Find the structure containing a world position, if its chunk is loaded
+    pub fn structure_at(&self, world_pos: WorldPos) -> Option<&StructureBounds> {
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        self.chunk_meta(chunk_pos)?.structure_at(world_pos)
+    }
+}
@@ -0,0 +1,158 @@
+use std::{cmp::Ordering, collections::VecDeque};
+
+use bevy::{
+    reflect::{std_traits::ReflectDefault, Reflect},
+    utils::HashMap,
+};
+
+use crate::positions::ChunkPos;
+
+// Ordered queue of chunk positions with a hashed membership index
+// Removal only forgets the position's entry id, stale entries are skipped when popping and are
+// dropped whenever the queue is sorted, so contains, push and remove are all constant time
+#[derive(Reflect, Default, Debug, Clone)]
+#[reflect_value(Debug, Default)]
+pub struct ChunkQueue {
+    queue: VecDeque<(ChunkPos, u64)>,
+    members: HashMap<ChunkPos, u64>,
+    next_id: u64,
+}
+
+impl ChunkQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn contains(&self, chunk_pos: &ChunkPos) -> bool {
+        self.members.contains_key(chunk_pos)
+    }
+
+    // Add a position to the back of the queue, returns false if it was already queued
+    pub fn push(&mut self, chunk_pos: ChunkPos) -> bool {
+        if self.members.contains_key(&chunk_pos) {
+            return false;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.members.insert(chunk_pos, id);
+        self.queue.push_back((chunk_pos, id));
+
+        true
+    }
+
+    // Remove a position from the queue, returns false if it wasn't queued
+    pub fn remove(&mut self, chunk_pos: &ChunkPos) -> bool {
+        let removed = self.members.remove(chunk_pos).is_some();
+
+        // Nothing left to skip over, so the stale entries can be dropped for free
+        if self.members.is_empty() {
+            self.queue.clear();
+        }
+
+        removed
+    }
+
+    pub fn pop_front(&mut self) -> Option<ChunkPos> {
+        while let Some((chunk_pos, id)) = self.queue.pop_front() {
+            if self.members.get(&chunk_pos) == Some(&id) {
+                self.members.remove(&chunk_pos);
+                return Some(chunk_pos);
+            }
+        }
+
+        None
+    }
+
+    // Remove up to count positions from the front of the queue
+    pub fn drain_front(&mut self, count: usize) -> Vec<ChunkPos> {
+        let mut drained = Vec::with_capacity(count.min(self.len()));
+
+        while drained.len() < count {
+            let Some(chunk_pos) = self.pop_front() else {
+                break;
+            };
+
+            drained.push(chunk_pos);
+        }
+
+        drained
+    }
+
+    pub fn drain_all(&mut self) -> Vec<ChunkPos> {
+        self.drain_front(self.len())
+    }
+
+    // Iterate over the queued positions in order
+    pub fn iter(&self) -> impl Iterator<Item = &ChunkPos> {
+        self.queue
+            .iter()
+            .filter(|(chunk_pos, id)| self.members.get(chunk_pos) == Some(id))
+            .map(|(chunk_pos, _id)| chunk_pos)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&ChunkPos) -> bool) {
+        self.members.retain(|chunk_pos, _id| keep(chunk_pos));
+        self.compact();
+    }
+
+    pub fn sort_by(&mut self, mut compare: impl FnMut(&ChunkPos, &ChunkPos) -> Ordering) {
+        self.compact();
+        self.queue
+            .make_contiguous()
+            .sort_by(|(lhs, _), (rhs, _)| compare(lhs, rhs));
+    }
+
+    // Sort by distance to a position, ties are broken by position so the order is deterministic
+    pub fn sort_by_distance(&mut self, origin: ChunkPos) {
+        self.sort_by(|lhs, rhs| {
+            lhs.distance_squared(origin)
+                .cmp(&rhs.distance_squared(origin))
+                .then_with(|| lhs.to_tuple().cmp(&rhs.to_tuple()))
+        });
+    }
+
+    // Sort by horizontal distance to a position, so the chunks of a column are next to each other,
+    // and then by vertical distance within the column
+    pub fn sort_by_column_distance(&mut self, origin: ChunkPos) {
+        let column_distance = |chunk_pos: &ChunkPos| {
+            let (dx, dz) = (chunk_pos.x - origin.x, chunk_pos.z - origin.z);
+            (dx * dx + dz * dz, (chunk_pos.y - origin.y).abs())
+        };
+
+        self.sort_by(|lhs, rhs| {
+            column_distance(lhs)
+                .cmp(&column_distance(rhs))
+                .then_with(|| lhs.to_tuple().cmp(&rhs.to_tuple()))
+        });
+    }
+
+    // Drop stale entries
+    fn compact(&mut self) {
+        let ChunkQueue { queue, members, .. } = self;
+        queue.retain(|(chunk_pos, id)| members.get(chunk_pos) == Some(id));
+    }
+}
+
+impl Extend<ChunkPos> for ChunkQueue {
+    fn extend<T: IntoIterator<Item = ChunkPos>>(&mut self, iter: T) {
+        for chunk_pos in iter {
+            self.push(chunk_pos);
+        }
+    }
+}
+
+impl<'a> Extend<&'a ChunkPos> for ChunkQueue {
+    fn extend<T: IntoIterator<Item = &'a ChunkPos>>(&mut self, iter: T) {
+        self.extend(iter.into_iter().copied());
+    }
+}
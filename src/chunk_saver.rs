@@ -0,0 +1,219 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    chunk::Chunk,
+    chunk_meta::ChunkMeta,
+    constants::{SAVE_RETRY_DELAY_SECS, SAVE_RETRY_MAX_DELAY_SECS},
+    persistence,
+    positions::ChunkPos,
+    region_file::{self, region_of, SyncPolicy},
+};
+
+// Write-behind cache for chunk saves, written by a thread of its own so saving never waits on the
+// disk. Saves of a chunk which hasn't been written yet replace the earlier one, and chunks are
+// written in batches by region (a cube of SAVE_REGION_SIZE chunks) into the region's file, one
+// batch per region at a time
+// Loads read the cache first, so a chunk is never loaded from an older save than the one queued
+// A batch which fails to write goes back in the cache and is retried, waiting longer after each
+// failure, so its chunks are kept until they reach the disk
+
+type Snapshot = (Arc<Chunk>, Arc<ChunkMeta>);
+
+#[derive(Default)]
+struct SaverState {
+    // Latest snapshot of each chunk waiting for its region's next batch
+    pending: HashMap<ChunkPos, Snapshot>,
+    // Regions with pending chunks, in the order they were first queued
+    regions: VecDeque<IVec3>,
+    // The batch being written, its region doesn't start another batch until it is done
+    writing: Option<(IVec3, HashMap<ChunkPos, Snapshot>)>,
+    // Failed writes in a row of each region whose last batch failed, and the chunks in that batch
+    failing: HashMap<IVec3, (u32, usize)>,
+    sync_policy: SyncPolicy,
+}
+
+impl SaverState {
+    fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.writing.is_none()
+    }
+}
+
+struct ChunkSaver {
+    state: Mutex<SaverState>,
+    // Notified when saves are queued and when a batch has been written
+    changed: Condvar,
+}
+
+static SAVER: OnceLock<ChunkSaver> = OnceLock::new();
+
+// The IO thread is started by the first save
+fn saver() -> &'static ChunkSaver {
+    SAVER.get_or_init(|| {
+        thread::Builder::new()
+            .name("Chunk saver".to_string())
+            .spawn(write_batches)
+            .expect("Failed to start the chunk saver thread");
+
+        ChunkSaver {
+            state: Mutex::new(SaverState::default()),
+            changed: Condvar::new(),
+        }
+    })
+}
+
+// A panic while holding the lock leaves the state as it was, so the saves are still usable
+fn lock() -> MutexGuard<'static, SaverState> {
+    saver()
+        .state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn set_sync_policy(sync_policy: SyncPolicy) {
+    lock().sync_policy = sync_policy;
+}
+
+// Queue the chunk to be written, replacing any save of it which hasn't started writing
+pub fn queue_save(chunk_pos: ChunkPos, chunk: Arc<Chunk>, meta: Arc<ChunkMeta>) {
+    let mut state = lock();
+
+    let region = region_of(chunk_pos);
+    if !state.regions.contains(&region) {
+        state.regions.push_back(region);
+    }
+    state.pending.insert(chunk_pos, (chunk, meta));
+
+    saver().changed.notify_all();
+}
+
+// Chunks queued or being written
+pub fn queued_saves() -> usize {
+    let state = lock();
+
+    state.pending.len() + state.writing.as_ref().map_or(0, |(_, batch)| batch.len())
+}
+
+// The newest save of the chunk which may not be on the disk yet
+pub(crate) fn unwritten_save(chunk_pos: ChunkPos) -> Option<Snapshot> {
+    let state = lock();
+
+    state
+        .pending
+        .get(&chunk_pos)
+        .or_else(|| state.writing.as_ref()?.1.get(&chunk_pos))
+        .cloned()
+}
+
+// Chunks whose last write failed and are waiting to be retried
+pub fn failing_saves() -> usize {
+    lock().failing.values().map(|&(_, chunks)| chunks).sum()
+}
+
+// Wait for every queued save to be written, returns false if the timeout was reached
+pub fn flush(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut state = lock();
+
+    while !state.is_idle() {
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            return false;
+        };
+        state = saver()
+            .changed
+            .wait_timeout(state, remaining)
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .0;
+    }
+
+    true
+}
+
+// Runs on the IO thread, writing the oldest region's pending chunks as one batch
+fn write_batches() {
+    loop {
+        let (region, batch, sync_policy) = {
+            let mut state = lock();
+            while state.regions.is_empty() {
+                state = saver()
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+
+            let Some(region) = state.regions.pop_front() else {
+                continue;
+            };
+            let chunk_positions = state
+                .pending
+                .keys()
+                .filter(|&&chunk_pos| region_of(chunk_pos) == region)
+                .copied()
+                .collect::<Vec<_>>();
+            let batch = chunk_positions
+                .into_iter()
+                .filter_map(|chunk_pos| Some((chunk_pos, state.pending.remove(&chunk_pos)?)))
+                .collect::<HashMap<_, _>>();
+
+            state.writing = Some((region, batch.clone()));
+            (region, batch, state.sync_policy)
+        };
+
+        let result = fs::create_dir_all(persistence::save_directory()).and_then(|_| {
+            let chunks = batch.iter().map(|(&chunk_pos, (chunk, meta))| {
+                (chunk_pos, (chunk.to_bytes(), meta.to_bytes()))
+            });
+            region_file::write_region(region, chunks, sync_policy)
+        });
+
+        let retry_delay = {
+            let mut state = lock();
+            state.writing = None;
+
+            match result {
+                Ok(()) => {
+                    state.failing.remove(&region);
+                    None
+                }
+                Err(err) => {
+                    let chunks = batch.len();
+                    let failures = state
+                        .failing
+                        .get(&region)
+                        .map_or(0, |&(failures, _)| failures)
+                        + 1;
+                    state.failing.insert(region, (failures, chunks));
+                    error!(
+                        "Failed to save the {chunks} chunks of region {region} ({failures} times in a row): {err}"
+                    );
+
+                    // Saves queued while writing are newer than the failed ones
+                    for (chunk_pos, snapshot) in batch {
+                        state.pending.entry(chunk_pos).or_insert(snapshot);
+                    }
+                    if !state.regions.contains(&region) {
+                        state.regions.push_back(region);
+                    }
+
+                    let delay = SAVE_RETRY_DELAY_SECS * 2_f32.powi(failures as i32 - 1);
+                    Some(Duration::from_secs_f32(
+                        delay.min(SAVE_RETRY_MAX_DELAY_SECS),
+                    ))
+                }
+            }
+        };
+        saver().changed.notify_all();
+
+        // Failures are usually the disk's (full, or gone), so every region waits out the delay
+        if let Some(retry_delay) = retry_delay {
+            thread::sleep(retry_delay);
+        }
+    }
+}
@@ -1,35 +1,358 @@
 use bevy::{
-    math::IVec2,
+    input::{keyboard::KeyCode, mouse::MouseButton},
+    math::{IVec2, IVec3, Vec3},
     render::{mesh::MeshVertexAttribute, render_resource::VertexFormat},
 };
 
-use crate::positions::ChunkPos;
+use crate::{lod::Lod, positions::ChunkPos, world_generator::GeneratorPreset};
 
 // Chunk constants
 
 pub const CHUNK_LOAD_DISTANCE: u32 = 12;
+// Seconds chunks which leave the load distance wait before they are unloaded
+pub const CHUNK_UNLOAD_DELAY: f32 = 3.;
 pub const CHUNK_SIZE: usize = 32;
+// Chunks can be taller than they are wide (e.g. 256) so surface worlds need far fewer of them, the
+// height has to be a multiple of SECTION_SIZE
+pub const CHUNK_HEIGHT: usize = CHUNK_SIZE;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE;
+// Voxels along each axis of a chunk
+pub const CHUNK_EXTENT: IVec3 =
+    IVec3::new(CHUNK_SIZE as i32, CHUNK_HEIGHT as i32, CHUNK_SIZE as i32);
 pub const CHUNK_SIZE_PADDED: usize = CHUNK_SIZE + 2;
 
+// First byte of an encoded chunk, chunks which start with anything else are rejected
+pub const CHUNK_FORMAT_MAGIC: u8 = 0xC5;
+// Marks the densities after a compressed chunk's voxels
+pub const CHUNK_DENSITY_MAGIC: u8 = 0xD5;
+
 pub const CHUNKS_FROM_MIDDLE_SIZE: usize = 3;
 
+// Steps per voxel of the densities chunks retain for smooth meshing with the densities feature, so
+// they span 4 voxels either side of the surface
+pub const DENSITY_SCALE: f32 = 32.;
+
+// Chunks from the origin to the world border along x and z, None for an endless world
+pub const WORLD_BORDER_CHUNKS: Option<u32> = None;
+// Lowest and highest chunk y inside the world, None for no floor or ceiling
+pub const WORLD_HEIGHT_BOUNDS: Option<(i32, i32)> = None;
+// Height of the border walls in chunks, centred on the loader
+pub const WORLD_BORDER_WALL_HEIGHT: usize = 8;
+
+// Vertical bounds (in chunks) of the columns loaded by column mode loaders, covering the terrain of
+// every generator preset, from 128 voxels below the origin to at least 192 above it
+pub const COLUMN_MIN_CHUNK_Y: i32 = -(128_usize.div_ceil(CHUNK_HEIGHT) as i32);
+pub const COLUMN_MAX_CHUNK_Y: i32 = (192 / CHUNK_HEIGHT) as i32;
+
+// Chunks within this distance (in chunks) of the loader are greedy meshed with AO, the rest up to
+// the far mesh distance get culled meshes without AO
+pub const FULL_QUALITY_DISTANCE: u32 = 4;
+// Chunks further than this (in chunks) from the loader are meshed with packed faces at the far LOD
+pub const FAR_MESH_DISTANCE: u32 = 8;
+pub const FAR_MESH_LOD: Lod = Lod::L16;
+
+// Size of a voxel in world units, used as the default VoxelScale
+pub const VOXEL_SCALE: f32 = 1.;
+
+// Chunk meshes are split into sections so that an edit only remeshes the sections around it
+// Set SECTION_SIZE to CHUNK_SIZE to mesh each chunk as a single section
+pub const SECTION_SIZE: usize = 16;
+pub const SECTIONS_PER_AXIS: usize = CHUNK_SIZE / SECTION_SIZE;
+// Sections stacked up a chunk, SECTIONS_PER_AXIS unless the chunks are taller than they are wide
+pub const SECTIONS_TALL: usize = CHUNK_HEIGHT / SECTION_SIZE;
+// At most 64, so that a chunk's sections fit in a u64 mask, e.g. 16 voxel sections of 32x256x32 chunks
+pub const SECTIONS_PER_CHUNK: usize = SECTIONS_PER_AXIS * SECTIONS_TALL * SECTIONS_PER_AXIS;
+// Bit mask with a bit set for every section of a chunk
+pub const ALL_SECTIONS: u64 = u64::MAX >> (64 - SECTIONS_PER_CHUNK);
+// Mesh tasks decode their packed vertices to cull each mesh by its own bounds, rather than by its
+// section's, which is much tighter for meshes covering a thin slab of terrain
+pub const TIGHT_MESH_AABBS: bool = true;
+
+// Voxels below the sky over which faces lose most (1 - 1/e) of their fake skylight
+pub const SKYLIGHT_FALLOFF_DEPTH: f32 = 6.;
+
+// Direction toward the sun, which the scene's light shines from and terrain shadows are baked for
+pub const SUN_DIRECTION: Vec3 = Vec3::new(0.5, 0.6, 0.35);
+// Voxels around a face searched for hills blocking the sun, at most CHUNK_SIZE
+pub const SUN_SHADOW_REACH: usize = 24;
+pub const SUN_SHADOW_STEPS: usize = 8;
+// Radians either side of the sun of the extra rays which soften the shadow's edges
+pub const SUN_SHADOW_SPREAD: f32 = 0.2;
+// Voxels a ray must pass below the terrain to be fully shadowed
+pub const SUN_SHADOW_SOFTNESS: f32 = 3.;
+
 pub const CHUNK_VERTEX_SHADER: &str = "shaders/chunk.wgsl";
 pub const CHUNK_FRAGMENT_SHADER: &str = "shaders/chunk.wgsl";
+pub const CHUNK_PREPASS_SHADER: &str = "shaders/chunk_prepass.wgsl";
+pub const OUTLINE_SHADER: &str = "shaders/outline.wgsl";
+
+// Square tiles stacked vertically, one per texture layer, used by textured chunk materials
+pub const BLOCK_TEXTURES_PATH: &str = "textures/blocks.png";
+// Texture repeats per voxel
+pub const BLOCK_TEXTURE_SCALE: f32 = 1.;
+// Distances from the camera over which voxel detail fades out, textures toward their average colour
+// and AO toward DETAIL_FADE_AO of its contrast, so distant terrain doesn't shimmer
+pub const DETAIL_FADE_START: f32 = 96.;
+pub const DETAIL_FADE_END: f32 = 256.;
+pub const DETAIL_FADE_AO: f32 = 0.35;
 
 // Task constants
 
 pub const MIN_THREADS: usize = 1;
 pub const MAX_THREADS: usize = 16;
 
-pub const MAX_DATA_TASKS: usize = 64;
+// Threads of the dedicated chunk pools, meshing gets fewer as generation feeds it
+pub const GENERATION_THREADS: usize = 4;
+pub const MESHING_THREADS: usize = 2;
+
 pub const MAX_MESH_TASKS: usize = 64;
-pub const MAX_CHUNK_LOADS: usize = 26000;
+
+// In-flight chunk tasks shared between the stages by the task scheduler, in proportion to the weights
+pub const CHUNK_TASK_BUDGET: usize = 128;
+pub const GENERATION_TASK_WEIGHT: f32 = 1.;
+pub const MESHING_TASK_WEIGHT: f32 = 1.;
+pub const IO_TASK_WEIGHT: f32 = 0.5;
+// Stages get a smaller share while their joins take longer than this each frame
+pub const JOIN_TIME_BUDGET_SECS: f32 = 0.004;
+
+// Chunks given mesh entities each frame, finished meshes past the limits are parked for later frames
+pub const MAX_MESH_SPAWNS_PER_FRAME: usize = 32;
+pub const MESH_JOIN_BUDGET_SECS: f32 = 0.003;
+
+// Mesh task limits while the window is unfocused, and for a short burst after it regains focus
+pub const BACKGROUND_MESH_TASKS: usize = 4;
+pub const RESUME_BURST_MESH_TASKS: usize = 256;
+pub const RESUME_BURST_FRAMES: u32 = 30;
+
+// Software occlusion culling, the depth buffer is much smaller than the screen to keep it cheap
+pub const OCCLUSION_CULLING_ENABLED: bool = true;
+pub const OCCLUSION_BUFFER_WIDTH: usize = 128;
+pub const OCCLUSION_BUFFER_HEIGHT: usize = 64;
+pub const OCCLUSION_MAX_OCCLUDERS: usize = 512;
+
+// Hide chunks which can't be seen through the connected air of the chunks between them and the camera
+pub const CAVE_CULLING_ENABLED: bool = true;
+
+// Recently unloaded chunks kept in memory for fast reloads
+pub const CHUNK_CACHE_CAPACITY: usize = 512;
+
+// Chunk load pacing defaults
+
+pub const CHUNK_LOADS_PER_FRAME: usize = 512;
+pub const MIN_CHUNK_LOADS_PER_FRAME: usize = 32;
+pub const BURST_CHUNK_LOADS_PER_FRAME: usize = 4096;
+pub const BURST_FRAMES: u32 = 30;
+pub const TARGET_FRAME_TIME: f32 = 1. / 60.;
+pub const TELEPORT_DISTANCE: u32 = 4;
+
+// Largest side of a grid the meshers accept, so a padded column still fits in a u64
+pub const VOXEL_GRID_MAX_SIZE: usize = 62;
+
+// Furthest voxel the cursor can pick, in voxels
+pub const PICK_DISTANCE: f32 = 256.;
 
 // World generation constants
 
+pub const GENERATOR_PRESET: GeneratorPreset = GeneratorPreset::Noise;
 pub const NOISE_SEED: u64 = 0;
 pub const NOISE_FREQUENCY: f32 = 0.025;
 pub const NOISE_HEIGHT_SCALE: f32 = 64.;
+pub const BIOME_FREQUENCY: f32 = 0.002;
+// Biome ids are the biome tint split into this many even bands
+pub const BIOME_COUNT: usize = 4;
+
+// Lakes are filled up to this height wherever the terrain dips below it
+pub const WATER_LEVEL: i32 = -12;
+pub const RIVER_FREQUENCY: f32 = 0.003;
+pub const RIVER_WIDTH: f32 = 0.04;
+pub const RIVER_DEPTH: f32 = 24.;
+
+// Chance of a tree in each column of the noise generator's terrain, where there is ground
+pub const TREE_CHANCE: f32 = 0.004;
+pub const TREE_TRUNK_MIN: i32 = 4;
+pub const TREE_TRUNK_MAX: i32 = 7;
+pub const TREE_CANOPY_RADIUS: i32 = 2;
+// Trees only grow on ground whose surface normal points at least this far up, so not on cliffs
+pub const TREE_MIN_GROUND_NORMAL_Y: f32 = 0.7;
+
+// Persistence constants
+
+pub const SAVE_DIRECTORY: &str = "saves/world";
+pub const AUTOSAVE_INTERVAL_SECS: f32 = 30.;
+pub const SHUTDOWN_TIMEOUT_SECS: f32 = 2.;
+// Chunk saves are written in batches to a file for each cube of this many chunks, see region_file
+pub const SAVE_REGION_SIZE: i32 = 8;
+// A batch of saves which failed to write is retried after this, doubling with each failure in a row
+pub const SAVE_RETRY_DELAY_SECS: f32 = 0.5;
+pub const SAVE_RETRY_MAX_DELAY_SECS: f32 = 30.;
+// How long flushing the saves waits for the chunk saver to write them
+pub const SAVE_FLUSH_TIMEOUT_SECS: f32 = 10.;
+// Names of the block ids in the world's chunk saves, checked against the registry on startup
+pub const SAVED_BLOCK_IDS_FILE: &str = "blocks.ron";
+// Where the chunk pipeline's state is written when the game panics
+pub const CRASH_DUMP_DIRECTORY: &str = "crash_dumps";
+
+// Schematic constants
+
+pub const SCHEMATIC_DIRECTORY: &str = "saves/schematics";
+// First byte of a schematic file
+pub const SCHEMATIC_FORMAT_MAGIC: u8 = 0x5C;
+// Longest side of a schematic which will be loaded, so corrupted sizes aren't allocated
+pub const MAX_SCHEMATIC_SIZE: u32 = 1024;
+
+// Anvil import constants
+
+// Region directory of the Minecraft world imported by the Anvil generator preset
+pub const ANVIL_REGION_DIRECTORY: &str = "saves/anvil/region";
+// Moves Minecraft's sea level (63) to just below the engine's origin
+pub const ANVIL_Y_OFFSET: i32 = -64;
+// Minecraft chunk columns kept decoded while their engine chunks are loading
+pub const ANVIL_COLUMN_CACHE_CAPACITY: usize = 1024;
+
+// Edit constants
+
+pub const MAX_UNDO_ENTRIES: usize = 64;
+
+// Explosion constants
+
+pub const MAX_DEBRIS_PER_EXPLOSION: usize = 64;
+pub const DEBRIS_LIFETIME_SECS: f32 = 3.;
+
+// Spawning constants
+
+pub const SIMULATION_DISTANCE: u32 = 4;
+pub const SPAWN_SAMPLES_PER_CHUNK: usize = 16;
+pub const MAX_SPAWNS_PER_CHUNK: usize = 4;
+pub const SPAWN_TICK_SECS: f32 = 1.;
+pub const SPAWN_CHANCE: f32 = 0.1;
+
+// Pipeline stepping constants
+
+pub const PIPELINE_PAUSE_KEY: KeyCode = KeyCode::F5;
+pub const PIPELINE_STEP_KEY: KeyCode = KeyCode::F6;
+
+// Spectator constants
+
+pub const SPECTATOR_KEY: KeyCode = KeyCode::F7;
+
+// Editor panel constants
+
+pub const EDITOR_PANEL_KEY: KeyCode = KeyCode::F8;
+// Where the editor panel and the chunk inspection write dumped chunks
+pub const CHUNK_DUMP_DIRECTORY: &str = "chunk_dumps";
+// Furthest load distance the editor panel's slider allows, in chunks
+pub const MAX_EDITOR_LOAD_DISTANCE: u32 = 32;
+pub const MAX_EDITOR_UNLOAD_DELAY: f32 = 30.;
+
+// Chunk digest constants
+
+// Logs the digest of the chunk the camera is in
+pub const DIGEST_KEY: KeyCode = KeyCode::F9;
+// Side of the regions (in chunks) which the world digest is split into
+pub const DIGEST_REGION_SIZE: usize = 8;
+
+// Pipeline metrics constants
+
+// Chunks kept in each stage's rolling window
+pub const METRICS_WINDOW: usize = 4096;
+// Writes the windows to a CSV file in the metrics directory
+pub const METRICS_DUMP_KEY: KeyCode = KeyCode::F10;
+pub const METRICS_DIRECTORY: &str = "metrics";
+
+// Chunk inspection constants
+
+// Writes the chunk under the crosshair and its neighbours to a file in the chunk dump directory
+pub const CHUNK_DUMP_KEY: KeyCode = KeyCode::F4;
+
+// Loading indicator constants
+
+// Size of the loading bar in logical pixels
+pub const LOADING_INDICATOR_WIDTH: f32 = 160.;
+pub const LOADING_INDICATOR_HEIGHT: f32 = 3.;
+
+// Debug marker constants
+
+// Shows or hides every debug marker
+pub const DEBUG_MARKERS_KEY: KeyCode = KeyCode::F11;
+// Markers added past this many are dropped, so a marker in a hot loop can't flood the screen
+pub const MAX_DEBUG_MARKERS: usize = 1024;
+pub const DEBUG_MARKER_FONT_SIZE: f32 = 14.;
+
+// Pregeneration constants
+
+// Chunks kept in the World's load queues while pregenerating, the rest wait in the region's queues
+pub const PREGENERATION_QUEUE_SIZE: usize = 256;
+// Size of the progress bar in logical pixels
+pub const PREGENERATION_BAR_WIDTH: f32 = 400.;
+pub const PREGENERATION_BAR_HEIGHT: f32 = 8.;
+
+// Mesh pool constants
+
+// Bytes of recycled mesh buffers kept for the mesh tasks, the rest are freed
+pub const MESH_POOL_MAX_BYTES: usize = 64 * 1024 * 1024;
+// Buffers of a mesh bigger than this (e.g. a large voxel object) are freed rather than pooled
+pub const MESH_POOL_MAX_MESH_BYTES: usize = 4 * 1024 * 1024;
+
+// Block breaking constants
+
+// Held on a voxel to break it
+pub const BREAKING_BUTTON: MouseButton = MouseButton::Left;
+// Crack textures drawn over the voxel as it breaks
+pub const BREAKING_STAGES: usize = 10;
+pub const CRACK_TEXTURE_SIZE: u32 = 16;
+
+// LOD refinement constants
+
+// Far chunks upgraded a step in detail each frame while the pipeline is idle
+pub const LOD_UPGRADES_PER_FRAME: usize = 2;
+// Upgrades given back each frame while chunks are loading
+pub const LOD_DOWNGRADES_PER_FRAME: usize = 2;
+
+// Decal constants
+
+// Square tiles stacked vertically, one per DecalId
+pub const DECAL_TEXTURES_PATH: &str = "textures/decals.png";
+// Size of the built in decals' tiles, drawn when the atlas can't be loaded
+pub const DECAL_FALLBACK_TILE_SIZE: u32 = 16;
+// How far decals sit in front of their faces, in voxels
+pub const DECAL_OFFSET: f32 = 0.002;
+
+// Generation preview constants
+
+pub const GENERATION_PREVIEW_KEY: KeyCode = KeyCode::F12;
+// Width and height of the preview in pixels
+pub const GENERATION_PREVIEW_SIZE: usize = 256;
+// Range of voxels per pixel the zoom slider covers
+pub const MAX_GENERATION_PREVIEW_ZOOM: i32 = 64;
+// Heights drawn from the water's colour to snow's, above the water level
+pub const GENERATION_PREVIEW_HEIGHT_RANGE: f32 = 96.;
+
+// Thumbnail constants
+
+// Width and height of thumbnails in pixels
+pub const THUMBNAIL_SIZE: u32 = 128;
+// Thumbnails being rendered at once each take a render layer, counting up from this one
+pub const THUMBNAIL_RENDER_LAYER: usize = 16;
+pub const MAX_THUMBNAILS_IN_FLIGHT: usize = 4;
+// Frames a thumbnail's camera is kept, the first frames may be skipped while its pipelines compile
+pub const THUMBNAIL_RENDER_FRAMES: u32 = 8;
+// Where thumbnail scenes are placed, far from the terrain so nothing else shadows them
+pub const THUMBNAIL_ORIGIN: Vec3 = Vec3::new(0., 10_000., 0.);
+// Direction from the middle of the grid to the orbit camera
+pub const THUMBNAIL_VIEW_DIRECTION: Vec3 = Vec3::new(1., 0.8, 1.);
+
+// Particle constants
+
+pub const PARTICLES_PER_BREAK: usize = 8;
+pub const PARTICLES_PER_PLACE: usize = 4;
+// Particles alive at once, edits past it don't emit any
+pub const MAX_PARTICLES: usize = 512;
+// Seconds, each particle lives between three quarters and one and a quarter of it
+pub const PARTICLE_LIFETIME: f32 = 0.8;
+// In voxels, per second and per second squared
+pub const PARTICLE_SIZE: f32 = 0.15;
+pub const PARTICLE_SPEED: f32 = 4.;
+pub const PARTICLE_GRAVITY: f32 = 20.;
 
 // Flycam constants
 
@@ -38,21 +361,30 @@ pub const FLYCAM_SPEED: f32 = 256.;
 
 // Voxel constants
 
+pub const BLOCK_REGISTRY_PATH: &str = "assets/blocks.ron";
+
 // A "high" random id should be used for custom attributes to ensure consistent sorting and avoid collisions with other attributes.
 // See the MeshVertexAttribute docs for more info.
 pub const ATTRIBUTE_VOXEL: MeshVertexAttribute =
-    MeshVertexAttribute::new("Voxel", 696969696, VertexFormat::Uint32);
+    MeshVertexAttribute::new("Voxel", 696969696, VertexFormat::Uint32x2);
+pub const ATTRIBUTE_BIOME_TINT: MeshVertexAttribute =
+    MeshVertexAttribute::new("BiomeTint", 696969697, VertexFormat::Float32);
+pub const ATTRIBUTE_FAR_FACE: MeshVertexAttribute =
+    MeshVertexAttribute::new("FarFace", 696969698, VertexFormat::Uint32);
+// Voxel type of a smooth mesh's vertex, the rest of a smooth vertex uses the standard attributes
+pub const ATTRIBUTE_SMOOTH_VOXEL: MeshVertexAttribute =
+    MeshVertexAttribute::new("SmoothVoxel", 696969699, VertexFormat::Uint32);
 
 // Array constants
 
-// const NORMALS_ARRAY: [[f32; 3]; 6] = [
-//     [-1.0, 0.0, 0.0], // Left
-//     [1.0, 0.0, 0.0],  // Right
-//     [0.0, 0.0, 1.0],  // Back
-//     [0.0, 0.0, -1.0], // Front
-//     [0.0, 1.0, 0.0],  // Up
-//     [0.0, -1.0, 0.0], // Down
-// ];
+pub const NORMALS_ARRAY: [[f32; 3]; 6] = [
+    [-1.0, 0.0, 0.0], // Left
+    [1.0, 0.0, 0.0],  // Right
+    [0.0, 0.0, 1.0],  // Back
+    [0.0, 0.0, -1.0], // Front
+    [0.0, 1.0, 0.0],  // Up
+    [0.0, -1.0, 0.0], // Down
+];
 
 // Adjacency array constants
 
@@ -0,0 +1,204 @@
+use std::{
+    fs,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, TryLockError,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{core::FrameCount, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk_loading::ChunkLoader, constants::CRASH_DUMP_DIRECTORY, positions::ChunkPos, world::World,
+    world_generator,
+};
+
+// The pipeline state as of the end of the last frame, kept outside of the ECS so the panic hook
+// can read it from whichever thread panicked
+static PIPELINE_STATE: Mutex<Option<PipelineState>> = Mutex::new(None);
+// Only the first panic is dumped, later ones are usually caused by it
+static DUMPED: AtomicBool = AtomicBool::new(false);
+
+// Writes the chunk pipeline's queues and tasks to a JSON file when a system or task panics, so
+// pipeline bugs reported by users can be reproduced from the loader position and seed
+pub struct CrashDumpPlugin;
+
+impl Plugin for CrashDumpPlugin {
+    fn build(&self, app: &mut App) {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            PipelineState::dump(info);
+            previous_hook(info);
+        }));
+
+        app.add_systems(Last, PipelineState::record);
+    }
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct PipelineState {
+    pub frame: u32,
+    pub seed: u64,
+    pub loaders: Vec<LoaderState>,
+    pub loaded_chunks: usize,
+    pub load_data_queue: Vec<[i32; 3]>,
+    pub load_mesh_queue: Vec<[i32; 3]>,
+    pub unload_data_queue: Vec<[i32; 3]>,
+    pub unload_mesh_queue: Vec<[i32; 3]>,
+    pub decorate_queue: Vec<[i32; 3]>,
+    pub data_tasks: Vec<[i32; 3]>,
+    pub generate_tasks: Vec<[i32; 3]>,
+    pub base_tasks: Vec<[i32; 3]>,
+    pub mesh_tasks: Vec<MeshTaskState>,
+    pub parked_meshes: usize,
+}
+
+// Also written by chunk dumps, see ChunkDump
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoaderState {
+    pub chunk_pos: [i32; 3],
+    pub load_distance: u32,
+    pub data_load_queue: usize,
+    pub mesh_load_queue: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MeshTaskState {
+    pub chunk_pos: [i32; 3],
+    pub sections: u64,
+    pub version: u64,
+    pub batch: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct CrashDump<'a> {
+    message: String,
+    location: Option<String>,
+    thread: Option<&'a str>,
+    // None if the state couldn't be read while the panic was handled
+    pipeline: Option<PipelineState>,
+}
+
+impl PipelineState {
+    fn record(world: Res<World>, loaders: Query<&ChunkLoader>, frame_count: Res<FrameCount>) {
+        // Skip the frame rather than wait on a panicking thread which is reading the state
+        let mut state = match PIPELINE_STATE.try_lock() {
+            Ok(state) => state,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return,
+        };
+        // Reuse the allocations of the previous frame's state
+        let state = state.get_or_insert_with(PipelineState::default);
+
+        state.frame = frame_count.0;
+        state.seed = world_generator::seed();
+        state.loaded_chunks = world.chunks.len();
+        state.parked_meshes = world.parked_meshes.iter().map(Vec::len).sum();
+
+        write_positions(&mut state.load_data_queue, world.load_data_queue.iter());
+        write_positions(&mut state.load_mesh_queue, world.load_mesh_queue.iter());
+        write_positions(&mut state.unload_data_queue, world.unload_data_queue.iter());
+        write_positions(&mut state.unload_mesh_queue, world.unload_mesh_queue.iter());
+        write_positions(
+            &mut state.decorate_queue,
+            world.generation_stages.decorate_queue.iter(),
+        );
+        write_positions(&mut state.data_tasks, world.data_tasks.keys());
+        write_positions(
+            &mut state.generate_tasks,
+            world.generation_stages.generate_tasks.keys(),
+        );
+        write_positions(
+            &mut state.base_tasks,
+            world.generation_stages.base_tasks.keys(),
+        );
+
+        state.mesh_tasks.clear();
+        state
+            .mesh_tasks
+            .extend(world.mesh_tasks.iter().map(|mesh_task| MeshTaskState {
+                chunk_pos: mesh_task.chunk_pos.to_ivec3().to_array(),
+                sections: mesh_task.sections,
+                version: mesh_task.version,
+                batch: mesh_task.batch,
+            }));
+
+        state.loaders.clear();
+        state
+            .loaders
+            .extend(loaders.iter().map(|loader| LoaderState {
+                chunk_pos: loader.prev_chunk_pos.to_ivec3().to_array(),
+                load_distance: loader.load_distance,
+                data_load_queue: loader.data_load_queue.len(),
+                mesh_load_queue: loader.mesh_load_queue.len(),
+            }));
+    }
+
+    fn dump(info: &PanicHookInfo) {
+        if DUMPED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // The main thread may be recording the state, give it a moment to finish
+        let mut pipeline = None;
+        for _ in 0..100 {
+            match PIPELINE_STATE.try_lock() {
+                Ok(state) => pipeline = state.clone(),
+                // Recording panicked part way through, the state is still worth having
+                Err(TryLockError::Poisoned(poisoned)) => pipeline = poisoned.into_inner().clone(),
+                Err(TryLockError::WouldBlock) => {
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+            }
+            break;
+        }
+
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+
+        let current_thread = thread::current();
+        let dump = CrashDump {
+            message,
+            location: info.location().map(ToString::to_string),
+            thread: current_thread.name(),
+            pipeline,
+        };
+
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let path = PathBuf::from(CRASH_DUMP_DIRECTORY).join(format!("pipeline_{secs}.json"));
+
+        let result = serde_json::to_string_pretty(&dump)
+            .map_err(|err| err.to_string())
+            .and_then(|json| {
+                fs::create_dir_all(CRASH_DUMP_DIRECTORY)
+                    .and_then(|_| fs::write(&path, json))
+                    .map_err(|err| err.to_string())
+            });
+
+        // The logger may be what panicked, so these go straight to stderr
+        match result {
+            Ok(()) => eprintln!("Wrote the chunk pipeline state to {}", path.display()),
+            Err(err) => eprintln!("Failed to write the chunk pipeline state: {err}"),
+        }
+    }
+}
+
+fn write_positions<'a>(
+    target: &mut Vec<[i32; 3]>,
+    chunk_positions: impl Iterator<Item = &'a ChunkPos>,
+) {
+    target.clear();
+    target.extend(chunk_positions.map(|chunk_pos| chunk_pos.to_ivec3().to_array()));
+}
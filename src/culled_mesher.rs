@@ -1,17 +1,24 @@
+use bevy::{
+    log::info_span,
+    math::{IVec3, UVec3},
+};
+
 use crate::{
     chunk_from_middle::ChunksFromMiddle,
-    chunk_mesh::{generate_indices, ChunkMesh, Direction, Quad},
-    constants::CHUNK_SIZE,
+    chunk_mesh::{generate_indices, ChunkMesh, Direction, Quad, SectionMeshes},
+    constants::{SECTIONS_PER_CHUNK, SECTION_SIZE, VOXEL_GRID_MAX_SIZE},
+    mesh_pool,
     positions::VoxelPos,
-    vertex::VertexU32,
+    vertex::{PackedVertex, Vertex},
     voxel::VoxelType,
+    voxel_grid::VoxelGrid,
 };
 
 fn push_face(mesh: &mut ChunkMesh, dir: Direction, vertex_pos: VoxelPos, voxel_type: VoxelType) {
     let quad = Quad::from_dir(vertex_pos, dir);
 
     for corner in quad.corners.iter() {
-        mesh.vertices.push(VertexU32::new(
+        mesh.vertices.push(PackedVertex::new(
             (corner[0], corner[1], corner[2]).into(),
             0,
             dir.get_normal_index(),
@@ -20,45 +27,275 @@ fn push_face(mesh: &mut ChunkMesh, dir: Direction, vertex_pos: VoxelPos, voxel_t
     }
 }
 
-pub fn build_chunk_mesh(chunks_from_middle: &ChunksFromMiddle) -> Option<ChunkMesh> {
-    let mut mesh = ChunkMesh::default();
+// Solid bits of each row of voxels along x, indexed by (z + 1) * (size.y + 1) + y + 1 with bit x + 1
+// for the voxel at x. The extra row, layer and bit hold the neighbouring voxels at -1, the only
+// neighbours a face is checked against
+fn build_solid_rows(grid: &impl VoxelGrid, size: UVec3) -> Vec<u64> {
+    let mut rows = vec![0u64; (size.z as usize + 1) * (size.y as usize + 1)];
 
-    for index in 0..(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) {
-        let voxel_pos = VoxelPos::from_index(index);
+    for z in -1..size.z as i32 {
+        for y in -1..size.y as i32 {
+            let row = &mut rows[((z + 1) * (size.y as i32 + 1) + y + 1) as usize];
 
-        let (current, back, left, down) = chunks_from_middle.get_adjacent_voxels(voxel_pos);
-
-        if current.voxel_type.is_solid() {
-            if !left.voxel_type.is_solid() {
-                push_face(&mut mesh, Direction::Left, voxel_pos, current.voxel_type)
+            for x in -1..size.x as i32 {
+                *row |= (grid.voxel_at(IVec3::new(x, y, z)).is_solid() as u64) << (x + 1);
             }
+        }
+    }
 
-            if !back.voxel_type.is_solid() {
-                push_face(&mut mesh, Direction::Back, voxel_pos, current.voxel_type)
-            }
+    rows
+}
 
-            if !down.voxel_type.is_solid() {
-                push_face(&mut mesh, Direction::Down, voxel_pos, current.voxel_type)
-            }
-        } else {
-            if left.voxel_type.is_solid() {
-                push_face(&mut mesh, Direction::Right, voxel_pos, left.voxel_type)
-            }
+// Culled mesh of the grid's voxels from the origin up to size, faces are only made on the lower side
+// of each voxel, so the faces on the upper edges are left to whatever is meshed beyond them
+fn build_mesh(grid: &impl VoxelGrid, size: UVec3) -> Option<ChunkMesh> {
+    // Rows need a bit for each voxel and one for the padding
+    assert!(size.x < u64::BITS, "Grid is too wide to mesh: {size}");
 
-            if back.voxel_type.is_solid() {
-                push_face(&mut mesh, Direction::Front, voxel_pos, back.voxel_type)
-            }
+    let mut mesh = mesh_pool::take();
+
+    let rows = build_solid_rows(grid, size);
+    let row_at = |y: usize, z: usize| rows[z * (size.y as usize + 1) + y];
+    let row_mask = (1u64 << size.x) - 1;
+
+    // A face lies between two voxels where exactly one of them is solid
+    // Rows are walked in index order so the faces come out in the same order as a per voxel walk
+    for z in 0..size.z as usize {
+        for y in 0..size.y as usize {
+            let row = row_at(y + 1, z + 1);
+
+            // Bit x is set when the voxel at x differs from its neighbour
+            let left_faces = ((row ^ (row << 1)) >> 1) & row_mask;
+            let back_faces = ((row ^ row_at(y + 1, z)) >> 1) & row_mask;
+            let down_faces = ((row ^ row_at(y, z + 1)) >> 1) & row_mask;
+
+            let mut faces = left_faces | back_faces | down_faces;
+            while faces != 0 {
+                let x = faces.trailing_zeros() as usize;
+                faces &= faces - 1;
+
+                let voxel_pos = VoxelPos::new(x, y, z);
+                let bit = 1 << x;
+
+                if (row >> (x + 1)) & 1 == 1 {
+                    let voxel_type = grid.voxel_at(voxel_pos.to_ivec3());
 
-            if down.voxel_type.is_solid() {
-                push_face(&mut mesh, Direction::Up, voxel_pos, down.voxel_type);
+                    if left_faces & bit != 0 {
+                        push_face(&mut mesh, Direction::Left, voxel_pos, voxel_type)
+                    }
+
+                    if back_faces & bit != 0 {
+                        push_face(&mut mesh, Direction::Back, voxel_pos, voxel_type)
+                    }
+
+                    if down_faces & bit != 0 {
+                        push_face(&mut mesh, Direction::Down, voxel_pos, voxel_type)
+                    }
+                } else {
+                    // The face belongs to the solid neighbour, but is placed at this voxel
+                    let neighbour_type =
+                        |offset: IVec3| grid.voxel_at(voxel_pos.to_ivec3() + offset);
+
+                    if left_faces & bit != 0 {
+                        push_face(
+                            &mut mesh,
+                            Direction::Right,
+                            voxel_pos,
+                            neighbour_type(IVec3::NEG_X),
+                        )
+                    }
+
+                    if back_faces & bit != 0 {
+                        push_face(
+                            &mut mesh,
+                            Direction::Front,
+                            voxel_pos,
+                            neighbour_type(IVec3::NEG_Z),
+                        )
+                    }
+
+                    if down_faces & bit != 0 {
+                        push_face(
+                            &mut mesh,
+                            Direction::Up,
+                            voxel_pos,
+                            neighbour_type(IVec3::NEG_Y),
+                        );
+                    }
+                }
             }
         }
     }
 
     if mesh.vertices.is_empty() {
+        mesh_pool::recycle(mesh);
         None
     } else {
-        mesh.indices = generate_indices(mesh.vertices.len());
+        generate_indices(&mut mesh.indices, mesh.vertices.len());
         Some(mesh)
     }
 }
+
+// Build the meshes of the sections set in the section mask, in the chunk's voxel positions
+pub fn build_section_meshes(chunks_from_middle: &ChunksFromMiddle, sections: u64) -> SectionMeshes {
+    let _span = info_span!("culled_build_section_meshes", sections).entered();
+
+    let section_indices = (0..SECTIONS_PER_CHUNK).filter(|section| sections & (1 << section) != 0);
+
+    if chunks_from_middle.are_all_voxels_same() {
+        return section_indices.map(|section| (section, None)).collect();
+    }
+
+    section_indices
+        .map(|section| {
+            let min = VoxelPos::from_section_index(section);
+            let section_grid = Section {
+                grid: chunks_from_middle,
+                min: min.to_ivec3(),
+            };
+
+            // Sections are meshed from their own corner, so the faces are moved back into the chunk
+            let mesh = build_mesh(&section_grid, section_grid.size()).map(|mut mesh| {
+                for vertex in mesh.vertices.iter_mut() {
+                    let mut unpacked = Vertex::from_packed(*vertex);
+                    unpacked.pos += min;
+                    *vertex = unpacked.into();
+                }
+
+                mesh
+            });
+
+            (section, mesh)
+        })
+        .collect()
+}
+
+// One section of a chunk, as a grid of its own
+struct Section<'a> {
+    grid: &'a ChunksFromMiddle,
+    min: IVec3,
+}
+
+impl VoxelGrid for Section<'_> {
+    fn size(&self) -> UVec3 {
+        UVec3::splat(SECTION_SIZE as u32)
+    }
+
+    fn voxel_at(&self, pos: IVec3) -> VoxelType {
+        self.grid.voxel_at(self.min + pos)
+    }
+}
+
+// Mesh every face of a grid, including those on its upper edges
+pub fn build_grid_mesh(grid: &impl VoxelGrid) -> Option<ChunkMesh> {
+    let _span = info_span!("culled_build_grid_mesh").entered();
+
+    assert!(
+        grid.size()
+            .cmple(UVec3::splat(VOXEL_GRID_MAX_SIZE as u32))
+            .all(),
+        "Grid is too large to mesh: {}",
+        grid.size()
+    );
+
+    // Meshing one voxel past the upper edges adds the faces which point out of the grid
+    build_mesh(grid, grid.size() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{chunk::Chunk, constants::ALL_SECTIONS, positions::WorldPos};
+
+    // The mesher before the solid rows, which looked up the neighbours of each voxel in turn
+    fn per_voxel_mesh(grid: &impl VoxelGrid, size: UVec3) -> Vec<PackedVertex> {
+        let mut mesh = ChunkMesh::default();
+
+        for z in 0..size.z as usize {
+            for y in 0..size.y as usize {
+                for x in 0..size.x as usize {
+                    let voxel_pos = VoxelPos::new(x, y, z);
+                    let voxel_at = |offset: IVec3| grid.voxel_at(voxel_pos.to_ivec3() + offset);
+                    let current = voxel_at(IVec3::ZERO);
+                    let (back, left, down) = (
+                        voxel_at(IVec3::NEG_Z),
+                        voxel_at(IVec3::NEG_X),
+                        voxel_at(IVec3::NEG_Y),
+                    );
+
+                    if current.is_solid() {
+                        if !left.is_solid() {
+                            push_face(&mut mesh, Direction::Left, voxel_pos, current)
+                        }
+                        if !back.is_solid() {
+                            push_face(&mut mesh, Direction::Back, voxel_pos, current)
+                        }
+                        if !down.is_solid() {
+                            push_face(&mut mesh, Direction::Down, voxel_pos, current)
+                        }
+                    } else {
+                        if left.is_solid() {
+                            push_face(&mut mesh, Direction::Right, voxel_pos, left)
+                        }
+                        if back.is_solid() {
+                            push_face(&mut mesh, Direction::Front, voxel_pos, back)
+                        }
+                        if down.is_solid() {
+                            push_face(&mut mesh, Direction::Up, voxel_pos, down)
+                        }
+                    }
+                }
+            }
+        }
+
+        mesh.vertices
+    }
+
+    // Neighbourhood of random voxels, air in about the given sixteenths of them and the rest split
+    // between a solid block and water
+    fn random_neighbourhood(seed: u32, air: u32) -> ChunksFromMiddle {
+        let voxel_at = move |world_pos: WorldPos| {
+            let hash = (world_pos.x.wrapping_mul(73_856_093)
+                ^ world_pos.y.wrapping_mul(19_349_663)
+                ^ world_pos.z.wrapping_mul(83_492_791)) as u32;
+
+            match (hash ^ seed).wrapping_mul(2_654_435_761) >> 27 {
+                bucket if bucket < air * 2 => VoxelType::AIR,
+                bucket if bucket % 2 == 0 => VoxelType::BLOCK,
+                _ => VoxelType::WATER,
+            }
+        };
+
+        ChunksFromMiddle::from_fn(|offset| Chunk::from_fn(offset, voxel_at))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn solid_rows_match_the_per_voxel_mesher(seed in any::<u32>(), air in 0..=16_u32) {
+            let chunks_from_middle = random_neighbourhood(seed, air);
+            for (section, mesh) in build_section_meshes(&chunks_from_middle, ALL_SECTIONS) {
+                let min = VoxelPos::from_section_index(section);
+                let section_grid = Section {
+                    grid: &chunks_from_middle,
+                    min: min.to_ivec3(),
+                };
+
+                let expected = per_voxel_mesh(&section_grid, UVec3::splat(SECTION_SIZE as u32))
+                    .into_iter()
+                    .map(|vertex| {
+                        let mut unpacked = Vertex::from_packed(vertex);
+                        unpacked.pos += min;
+                        PackedVertex::from(unpacked)
+                    })
+                    .collect::<Vec<_>>();
+
+                // Same faces, in the same order
+                prop_assert_eq!(mesh.map_or(Vec::new(), |mesh| mesh.vertices), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    sync::{Mutex, TryLockError},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    constants::{DEBUG_MARKERS_KEY, DEBUG_MARKER_FONT_SIZE, MAX_DEBUG_MARKERS},
+    positions::{ChunkPos, VoxelScale, WorldPos},
+    world::World,
+};
+
+// Markers added since the last frame, from any thread, spawned every frame
+static PENDING_MARKERS: Mutex<Vec<PendingMarker>> = Mutex::new(Vec::new());
+
+// Labels and points at voxels, for seeing generation decisions (structure anchors, biome borders)
+// and mesh bugs where they happen. A marker is hidden until its chunk has loaded, and despawned when
+// the chunk unloads or its time to live runs out
+// Add one from anywhere, including the chunk tasks, with DebugMarkers::add
+pub struct DebugMarkersPlugin;
+
+impl Plugin for DebugMarkersPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DebugMarkers>().add_systems(
+            Update,
+            (
+                DebugMarkers::toggle,
+                DebugMarkers::spawn_pending,
+                DebugMarker::update,
+                DebugMarker::draw_points.run_if(DebugMarkers::are_visible),
+            )
+                .chain(),
+        );
+    }
+}
+
+struct PendingMarker {
+    world_pos: WorldPos,
+    text: String,
+    color: Color,
+    ttl: Option<Duration>,
+}
+
+#[derive(Resource, Debug)]
+pub struct DebugMarkers {
+    pub visible: bool,
+    // Markers dropped for going over MAX_DEBUG_MARKERS
+    pub dropped: usize,
+}
+
+impl Default for DebugMarkers {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            dropped: 0,
+        }
+    }
+}
+
+impl DebugMarkers {
+    // Mark a voxel with a label, markers without a time to live stay until their chunk unloads
+    pub fn add(world_pos: WorldPos, text: impl Into<String>, color: Color, ttl: Option<Duration>) {
+        let marker = PendingMarker {
+            world_pos,
+            text: text.into(),
+            color,
+            ttl,
+        };
+
+        match PENDING_MARKERS.lock() {
+            Ok(mut markers) => markers.push(marker),
+            Err(poisoned) => poisoned.into_inner().push(marker),
+        }
+    }
+
+    pub fn are_visible(markers: Res<DebugMarkers>) -> bool {
+        markers.visible
+    }
+
+    fn toggle(mut markers: ResMut<DebugMarkers>, keys: Res<ButtonInput<KeyCode>>) {
+        if keys.just_pressed(DEBUG_MARKERS_KEY) {
+            markers.visible = !markers.visible;
+        }
+    }
+
+    fn spawn_pending(
+        mut commands: Commands,
+        mut markers: ResMut<DebugMarkers>,
+        existing: Query<(), With<DebugMarker>>,
+        time: Res<Time>,
+    ) {
+        // Skip the frame rather than wait on a task which is adding markers
+        let mut pending = match PENDING_MARKERS.try_lock() {
+            Ok(pending) => pending,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return,
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut count = existing.iter().count();
+        let dropped_before = markers.dropped;
+
+        for marker in pending.drain(..) {
+            if count >= MAX_DEBUG_MARKERS {
+                markers.dropped += 1;
+                continue;
+            }
+            count += 1;
+
+            commands.spawn((
+                DebugMarker {
+                    world_pos: marker.world_pos,
+                    color: marker.color,
+                    expires_at: marker.ttl.map(|ttl| time.elapsed() + ttl),
+                    chunk_loaded: false,
+                },
+                Name::new(format!("Debug marker: {}", marker.text)),
+                TextBundle {
+                    text: Text::from_section(
+                        marker.text,
+                        TextStyle {
+                            font_size: DEBUG_MARKER_FONT_SIZE,
+                            color: marker.color,
+                            ..default()
+                        },
+                    ),
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        ..default()
+                    },
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+            ));
+        }
+
+        if markers.dropped > dropped_before {
+            warn!(
+                "Dropped {} debug markers, at most {MAX_DEBUG_MARKERS} are shown",
+                markers.dropped - dropped_before
+            );
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct DebugMarker {
+    pub world_pos: WorldPos,
+    pub color: Color,
+    // Elapsed time at which the marker is despawned
+    pub expires_at: Option<Duration>,
+    // Markers of chunks which are still loading are kept, only those whose chunk unloads are despawned
+    chunk_loaded: bool,
+}
+
+impl DebugMarker {
+    pub fn chunk_pos(&self) -> ChunkPos {
+        WorldPos::to_voxel_pos(self.world_pos).1
+    }
+
+    // Centre of the marked voxel
+    pub fn translation(&self, voxel_scale: &VoxelScale) -> Vec3 {
+        voxel_scale.to_translation(self.world_pos) + Vec3::splat(voxel_scale.0 / 2.)
+    }
+
+    // Move the labels over their voxels, and despawn expired markers and those of unloaded chunks
+    fn update(
+        mut commands: Commands,
+        settings: Res<DebugMarkers>,
+        world: Res<World>,
+        time: Res<Time>,
+        voxel_scale: Res<VoxelScale>,
+        cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+        mut markers: Query<(Entity, &mut DebugMarker, &mut Style, &mut Visibility)>,
+    ) {
+        let camera = cameras.get_single().ok();
+
+        for (entity, mut marker, mut style, mut visibility) in markers.iter_mut() {
+            let is_loaded = world.chunks.contains_key(&marker.chunk_pos());
+            let expired = marker
+                .expires_at
+                .is_some_and(|expires_at| time.elapsed() >= expires_at);
+
+            if expired || (marker.chunk_loaded && !is_loaded) {
+                commands.entity(entity).despawn_recursive();
+                continue;
+            }
+            if is_loaded && !marker.chunk_loaded {
+                marker.chunk_loaded = true;
+            }
+
+            // Labels are drawn in screen space, so they always face the camera
+            let viewport_pos = camera.filter(|_| is_loaded && settings.visible).and_then(
+                |(camera, camera_transform)| {
+                    camera.world_to_viewport(camera_transform, marker.translation(&voxel_scale))
+                },
+            );
+
+            match viewport_pos {
+                Some(viewport_pos) => {
+                    // Beside the point rather than over it
+                    style.left = Val::Px(viewport_pos.x + DEBUG_MARKER_FONT_SIZE / 2.);
+                    style.top = Val::Px(viewport_pos.y - DEBUG_MARKER_FONT_SIZE / 2.);
+                    visibility.set_if_neq(Visibility::Inherited);
+                }
+                None => {
+                    visibility.set_if_neq(Visibility::Hidden);
+                }
+            }
+        }
+    }
+
+    fn draw_points(
+        mut gizmos: Gizmos,
+        world: Res<World>,
+        voxel_scale: Res<VoxelScale>,
+        markers: Query<&DebugMarker>,
+    ) {
+        for marker in markers.iter() {
+            if !world.chunks.contains_key(&marker.chunk_pos()) {
+                continue;
+            }
+
+            gizmos.sphere(
+                marker.translation(&voxel_scale),
+                Quat::IDENTITY,
+                voxel_scale.0 / 4.,
+                marker.color,
+            );
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    asset::LoadState,
+    prelude::*,
+    render::{
+        mesh::PrimitiveTopology,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{ImageLoaderSettings, ImageSampler},
+    },
+};
+
+use crate::{
+    chunk_mesh::Direction,
+    constants::{DECAL_FALLBACK_TILE_SIZE, DECAL_OFFSET, DECAL_TEXTURES_PATH},
+    positions::{ChunkPos, VoxelScale, WorldPos},
+    world::{self, World},
+};
+
+// Overlays drawn on voxel faces (moss, markings, scorch marks), each chunk's decals are batched into one mesh
+// Decals are removed when their voxel or the voxel in front of them changes, and when their chunk unloads
+pub struct DecalsPlugin;
+
+impl Plugin for DecalsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, DecalMeshes::setup)
+            .add_systems(Update, DecalMeshes::update);
+    }
+}
+
+// Index of a tile in the decal atlas
+pub type DecalId = u16;
+
+// The decals on each chunk's voxel faces
+#[derive(Default, Debug)]
+pub struct ChunkDecals {
+    chunks: HashMap<ChunkPos, HashMap<(WorldPos, Direction), DecalId>>,
+    // Chunks whose decal meshes need rebuilding
+    changed: HashSet<ChunkPos>,
+}
+
+impl ChunkDecals {
+    pub fn get(&self, world_pos: WorldPos, face: Direction) -> Option<DecalId> {
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        self.chunks
+            .get(&chunk_pos)?
+            .get(&(world_pos, face))
+            .copied()
+    }
+
+    pub fn insert(&mut self, world_pos: WorldPos, face: Direction, decal_id: DecalId) {
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        let previous = self
+            .chunks
+            .entry(chunk_pos)
+            .or_default()
+            .insert((world_pos, face), decal_id);
+        if previous != Some(decal_id) {
+            self.changed.insert(chunk_pos);
+        }
+    }
+
+    pub fn remove(&mut self, world_pos: WorldPos, face: Direction) -> Option<DecalId> {
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        let decals = self.chunks.get_mut(&chunk_pos)?;
+        let removed = decals.remove(&(world_pos, face))?;
+        if decals.is_empty() {
+            self.chunks.remove(&chunk_pos);
+        }
+        self.changed.insert(chunk_pos);
+
+        Some(removed)
+    }
+
+    // A voxel changed, so its decals and those on the neighbouring faces touching it no longer fit
+    pub fn remove_voxel(&mut self, world_pos: WorldPos) {
+        if self.chunks.is_empty() {
+            return;
+        }
+
+        for face in Direction::ALL {
+            self.remove(world_pos, face);
+
+            let normal = face.normal();
+            let neighbour = world_pos + WorldPos::new(normal.x, normal.y, normal.z);
+            self.remove(neighbour, face.opposite());
+        }
+    }
+
+    pub fn remove_chunk(&mut self, chunk_pos: ChunkPos) {
+        if self.chunks.remove(&chunk_pos).is_some() {
+            self.changed.insert(chunk_pos);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.changed
+            .extend(self.chunks.drain().map(|(chunk_pos, _)| chunk_pos));
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+impl World {
+    // Draw a decal from the atlas on a face of a solid voxel, replacing any decal already there
+    // Returns false if the voxel's chunk isn't loaded or the voxel isn't solid
+    pub fn add_decal(&mut self, world_pos: WorldPos, face: Direction, decal_id: DecalId) -> bool {
+        if !self
+            .get_voxel(world_pos)
+            .is_some_and(|voxel| voxel.voxel_type.is_solid())
+        {
+            return false;
+        }
+
+        self.decals.insert(world_pos, face, decal_id);
+        true
+    }
+
+    pub fn remove_decal(&mut self, world_pos: WorldPos, face: Direction) -> Option<DecalId> {
+        self.decals.remove(world_pos, face)
+    }
+}
+
+// The decal atlas has square tiles stacked vertically, one per DecalId, like the block textures
+#[derive(Resource, Debug)]
+pub struct DecalMeshes {
+    pub atlas: Handle<Image>,
+    pub material: Handle<StandardMaterial>,
+    // Tiles in the atlas the meshes were built for, 0 until it has loaded
+    tiles: u32,
+    entities: HashMap<ChunkPos, Entity>,
+}
+
+impl DecalMeshes {
+    fn setup(
+        mut commands: Commands,
+        asset_server: Res<AssetServer>,
+        mut materials: ResMut<Assets<StandardMaterial>>,
+    ) {
+        let atlas = asset_server
+            .load_with_settings(DECAL_TEXTURES_PATH, |settings: &mut ImageLoaderSettings| {
+                settings.sampler = ImageSampler::nearest()
+            });
+        let material = materials.add(StandardMaterial {
+            base_color_texture: Some(atlas.clone()),
+            alpha_mode: AlphaMode::Blend,
+            perceptual_roughness: 1.,
+            ..default()
+        });
+
+        commands.insert_resource(DecalMeshes {
+            atlas,
+            material,
+            tiles: 0,
+            entities: HashMap::new(),
+        });
+    }
+
+    fn update(
+        mut commands: Commands,
+        mut world: ResMut<World>,
+        mut decal_meshes: ResMut<DecalMeshes>,
+        (asset_server, mut images): (Res<AssetServer>, ResMut<Assets<Image>>),
+        mut materials: ResMut<Assets<StandardMaterial>>,
+        voxel_scale: Res<VoxelScale>,
+        mut meshes: ResMut<Assets<Mesh>>,
+    ) {
+        // The UVs depend on the atlas' size, so wait for it to load and rebuild everything if it changes
+        let Some(atlas) = images.get(&decal_meshes.atlas) else {
+            // The atlas isn't shipped with the game, so without one the built in decals are drawn
+            if let LoadState::Failed(err) = asset_server.load_state(&decal_meshes.atlas) {
+                warn!("Failed to load the decal atlas: {err}, using the built in decals");
+
+                decal_meshes.atlas = images.add(fallback_atlas());
+                if let Some(material) = materials.get_mut(&decal_meshes.material) {
+                    material.base_color_texture = Some(decal_meshes.atlas.clone());
+                }
+            }
+            return;
+        };
+        let tiles = (atlas.height() / atlas.width().max(1)).max(1);
+        if tiles != decal_meshes.tiles {
+            decal_meshes.tiles = tiles;
+            let ChunkDecals { chunks, changed } = &mut world.decals;
+            changed.extend(chunks.keys().copied());
+        }
+
+        // Scaling the voxels moves the chunks
+        if voxel_scale.is_changed() {
+            for (&chunk_pos, &entity) in &decal_meshes.entities {
+                commands
+                    .entity(entity)
+                    .insert(world::chunk_transform(chunk_pos, &voxel_scale));
+            }
+        }
+
+        let DecalMeshes {
+            material, entities, ..
+        } = decal_meshes.as_mut();
+
+        for chunk_pos in world.decals.changed.drain().collect::<Vec<_>>() {
+            let Some(decals) = world.decals.chunks.get(&chunk_pos) else {
+                if let Some(entity) = entities.remove(&chunk_pos) {
+                    commands.entity(entity).despawn();
+                }
+                continue;
+            };
+
+            let mesh = meshes.add(decal_mesh(decals, tiles));
+            if let Some(&entity) = entities.get(&chunk_pos) {
+                commands.entity(entity).insert(mesh);
+                continue;
+            }
+
+            let entity = commands
+                .spawn((
+                    Name::new(format!("Chunk decals {chunk_pos:?}")),
+                    PbrBundle {
+                        mesh,
+                        material: material.clone(),
+                        transform: world::chunk_transform(chunk_pos, &voxel_scale),
+                        ..default()
+                    },
+                ))
+                .id();
+            entities.insert(chunk_pos, entity);
+        }
+    }
+}
+
+// Moss, a painted cross and a scorch mark, drawn in code for when the atlas can't be loaded
+fn fallback_atlas() -> Image {
+    const TILES: usize = 3;
+    let size = DECAL_FALLBACK_TILE_SIZE as usize;
+    let centre = (size - 1) as f32 / 2.;
+
+    let mut data = vec![0; size * size * TILES * 4];
+    for tile in 0..TILES {
+        for y in 0..size {
+            for x in 0..size {
+                let pixel = match tile {
+                    // Speckles from a hash of the pixel
+                    0 if (x * 7 + y * 13 + x * y) % 5 < 2 => [70, 120, 40, 210],
+                    1 if x.abs_diff(y) <= 1 || x.abs_diff(size - 1 - y) <= 1 => {
+                        [230, 225, 210, 230]
+                    }
+                    // Darkest in the middle, fading out towards the edges
+                    2 => {
+                        let distance = Vec2::new(x as f32 - centre, y as f32 - centre).length();
+                        let alpha = (1. - distance / centre).clamp(0., 1.) * 220.;
+                        [25, 20, 18, alpha as u8]
+                    }
+                    _ => [0; 4],
+                };
+
+                let index = ((tile * size + y) * size + x) * 4;
+                data[index..index + 4].copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: DECAL_FALLBACK_TILE_SIZE,
+            height: DECAL_FALLBACK_TILE_SIZE * TILES as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::nearest();
+
+    image
+}
+
+// A quad for each decal, in the chunk's voxel space and nudged off its face so it doesn't z-fight
+fn decal_mesh(decals: &HashMap<(WorldPos, Direction), DecalId>, tiles: u32) -> Mesh {
+    let mut positions = Vec::with_capacity(decals.len() * 4);
+    let mut normals = Vec::with_capacity(decals.len() * 4);
+    let mut uvs = Vec::with_capacity(decals.len() * 4);
+    let mut indices = Vec::with_capacity(decals.len() * 6);
+
+    for (&(world_pos, face), &decal_id) in decals {
+        let (voxel_pos, _) = WorldPos::to_voxel_pos(world_pos);
+
+        let normal = face.normal().as_vec3();
+        let rotation = Quat::from_rotation_arc(Vec3::Z, normal);
+        let centre = Vec3::new(voxel_pos.x as f32, voxel_pos.y as f32, voxel_pos.z as f32)
+            + Vec3::splat(0.5)
+            + normal * (0.5 + DECAL_OFFSET);
+
+        // Tiles past the end of the atlas wrap around
+        let v_min = (decal_id as u32 % tiles) as f32 / tiles as f32;
+        let v_max = v_min + 1. / tiles as f32;
+
+        let first = positions.len() as u32;
+        for (corner, uv) in [
+            (Vec2::new(-0.5, -0.5), [0., v_max]),
+            (Vec2::new(0.5, -0.5), [1., v_max]),
+            (Vec2::new(0.5, 0.5), [1., v_min]),
+            (Vec2::new(-0.5, 0.5), [0., v_min]),
+        ] {
+            positions.push((centre + rotation * corner.extend(0.)).to_array());
+            normals.push(normal.to_array());
+            uvs.push(uv);
+        }
+        indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    let vertex_count = positions.len();
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+    .with_inserted_indices(world::compact_indices(&indices, vertex_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy::render::mesh::VertexAttributeValues;
+
+    use super::*;
+    use crate::{chunk::Chunk, voxel::VoxelType};
+
+    // The chunk at the origin, solid below y = 4
+    fn ground_world() -> World {
+        let mut world = World::default();
+        let chunk_pos = ChunkPos::splat(0);
+        let chunk = Chunk::from_fn(chunk_pos, |world_pos| {
+            if world_pos.y < 4 {
+                VoxelType::BLOCK
+            } else {
+                VoxelType::AIR
+            }
+        });
+        world.chunks.insert(chunk_pos, Arc::new(chunk));
+
+        world
+    }
+
+    #[test]
+    fn decals_are_only_added_to_solid_voxels() {
+        let mut world = ground_world();
+
+        assert!(world.add_decal(WorldPos::new(1, 3, 1), Direction::Up, 2));
+        assert!(!world.add_decal(WorldPos::new(1, 4, 1), Direction::Up, 2));
+        // Not loaded
+        assert!(!world.add_decal(WorldPos::new(-1, 3, 1), Direction::Up, 2));
+
+        assert_eq!(
+            world.decals.get(WorldPos::new(1, 3, 1), Direction::Up),
+            Some(2)
+        );
+        assert_eq!(world.decals.len(), 1);
+    }
+
+    #[test]
+    fn changed_voxels_remove_the_decals_touching_them() {
+        let mut world = ground_world();
+        let ground = WorldPos::new(1, 3, 1);
+        world.add_decal(ground, Direction::Up, 0);
+        world.add_decal(ground, Direction::Left, 1);
+        world.add_decal(WorldPos::new(2, 3, 1), Direction::Up, 1);
+        world.decals.changed.clear();
+
+        // Placing a voxel on top covers the face below it
+        world.decals.remove_voxel(ground + WorldPos::new(0, 1, 0));
+        assert_eq!(world.decals.get(ground, Direction::Up), None);
+        assert_eq!(world.decals.get(ground, Direction::Left), Some(1));
+        assert_eq!(world.decals.len(), 2);
+        assert!(world.decals.changed.contains(&ChunkPos::splat(0)));
+
+        world.decals.remove_chunk(ChunkPos::splat(0));
+        assert!(world.decals.is_empty());
+    }
+
+    #[test]
+    fn decal_quads_sample_their_tile() {
+        let decals = [
+            ((WorldPos::new(1, 3, 1), Direction::Up), 1),
+            // Wraps around to the same tile
+            ((WorldPos::new(5, 3, 1), Direction::Front), 4),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        let mesh = decal_mesh(&decals, 3);
+        assert_eq!(mesh.count_vertices(), 8);
+        assert_eq!(mesh.indices().unwrap().len(), 12);
+
+        let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+        else {
+            panic!("Decal meshes have UVs");
+        };
+        for &[u, v] in uvs {
+            assert!((0. ..=1.).contains(&u));
+            assert!((1. / 3. - 1e-6..=2. / 3. + 1e-6).contains(&v), "{v}");
+        }
+
+        // The Up quad sits just above its voxel
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("Decal meshes have positions");
+        };
+        assert!(positions
+            .iter()
+            .any(|position| (position[1] - (4. + DECAL_OFFSET)).abs() < 1e-5));
+    }
+
+    #[test]
+    fn fallback_atlas_has_a_tile_for_each_built_in_decal() {
+        let atlas = fallback_atlas();
+        let tile_len = (DECAL_FALLBACK_TILE_SIZE * DECAL_FALLBACK_TILE_SIZE * 4) as usize;
+
+        assert_eq!(atlas.width(), DECAL_FALLBACK_TILE_SIZE);
+        assert_eq!(atlas.height() % atlas.width(), 0);
+        for tile in atlas.data.chunks_exact(tile_len) {
+            assert!(tile.chunks_exact(4).any(|pixel| pixel[3] > 0));
+        }
+    }
+}
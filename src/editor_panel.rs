@@ -0,0 +1,494 @@
+use std::{fs, io, path::PathBuf};
+
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+
+use crate::{
+    chunk::Chunk,
+    chunk_loading::{ChunkLoader, LoadShape},
+    chunk_queue::ChunkQueue,
+    constants::{
+        CHUNK_DUMP_DIRECTORY, CHUNK_EXTENT, EDITOR_PANEL_KEY, GENERATOR_PRESET,
+        MAX_EDITOR_LOAD_DISTANCE, MAX_EDITOR_UNLOAD_DELAY,
+    },
+    generation_stages::GenerationStages,
+    greedy_mesher::AoMerging,
+    lod::Lod,
+    mesh_quality::MeshQuality,
+    positions::{ChunkPos, VoxelScale},
+    screen_effects::AntiAliasing,
+    world::{MeshingMode, World},
+    world_generator::{self, GeneratorPreset, WorldGen},
+};
+
+// Control surface for developing on the engine: lists the loaded chunks with per-chunk actions,
+// changes the loader and generator settings at runtime, and saves or reloads the world
+pub struct EditorPanelPlugin;
+
+impl Plugin for EditorPanelPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+
+        app.init_resource::<EditorPanel>().add_systems(
+            Update,
+            (
+                EditorPanel::toggle,
+                EditorPanel::show.run_if(EditorPanel::is_open),
+                EditorPanel::draw_highlights,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct EditorPanel {
+    pub open: bool,
+    pub filters: ChunkFilters,
+    // Chunks outlined in the world
+    pub highlighted: Vec<ChunkPos>,
+    // The preset the world was last generated with
+    pub preset: GeneratorPreset,
+}
+
+impl Default for EditorPanel {
+    fn default() -> Self {
+        Self {
+            open: false,
+            filters: ChunkFilters::default(),
+            highlighted: Vec::new(),
+            preset: GENERATOR_PRESET,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkFilters {
+    pub meshed_only: bool,
+    pub dirty_only: bool,
+    pub hide_air: bool,
+    // None shows every quality
+    pub quality: Option<MeshQuality>,
+    // In chunks from the loader
+    pub max_distance: u32,
+}
+
+impl Default for ChunkFilters {
+    fn default() -> Self {
+        Self {
+            meshed_only: false,
+            dirty_only: false,
+            hide_air: true,
+            quality: None,
+            max_distance: MAX_EDITOR_LOAD_DISTANCE,
+        }
+    }
+}
+
+impl ChunkFilters {
+    fn matches(&self, world: &World, chunk_pos: ChunkPos, chunk: &Chunk, distance: u32) -> bool {
+        let is_meshed = world.chunk_entities.contains_key(&chunk_pos);
+
+        distance <= self.max_distance
+            && (!self.meshed_only || is_meshed)
+            && (!self.dirty_only || world.dirty_chunks.contains(&chunk_pos))
+            && (!self.hide_air || !chunk.is_all_air())
+            && self
+                .quality
+                .is_none_or(|quality| is_meshed && world.mesh_quality(chunk_pos) == quality)
+    }
+}
+
+// Actions picked in the panel, applied once it has been drawn
+enum EditorAction {
+    Remesh(ChunkPos),
+    Dump(ChunkPos),
+    Save,
+    Reload,
+    Regenerate(GeneratorPreset),
+}
+
+impl EditorPanel {
+    pub fn is_open(panel: Res<EditorPanel>) -> bool {
+        panel.open
+    }
+
+    fn toggle(mut panel: ResMut<EditorPanel>, keys: Res<ButtonInput<KeyCode>>) {
+        if keys.just_pressed(EDITOR_PANEL_KEY) {
+            panel.open = !panel.open;
+        }
+    }
+
+    fn show(
+        mut contexts: EguiContexts,
+        mut panel: ResMut<EditorPanel>,
+        mut world: ResMut<World>,
+        mut world_gen: ResMut<WorldGen>,
+        mut anti_aliasing: ResMut<AntiAliasing>,
+        mut loaders: Query<&mut ChunkLoader>,
+    ) {
+        // The loader may be moving between entities, see Spectator
+        let Ok(mut loader) = loaders.get_single_mut() else {
+            return;
+        };
+
+        let mut open = panel.open;
+        let mut actions = Vec::new();
+        let mut selected_anti_aliasing = *anti_aliasing;
+
+        egui::Window::new("World editor")
+            .open(&mut open)
+            .default_width(360.)
+            .show(contexts.ctx_mut(), |ui| {
+                panel.show_settings(ui, &mut loader, &mut world, &mut actions);
+                ui.separator();
+                Self::show_rendering(ui, &mut selected_anti_aliasing);
+                ui.separator();
+                panel.show_chunks(ui, &loader, &world, &mut actions);
+            });
+
+        panel.open = open;
+        // Only marked as changed when another option is picked, the cameras are updated on change
+        anti_aliasing.set_if_neq(selected_anti_aliasing);
+
+        for action in actions {
+            match action {
+                EditorAction::Remesh(chunk_pos) => {
+                    world.invalidate_meshes(chunk_pos);
+                    if !world.queue_remesh(chunk_pos) {
+                        warn!("Chunk {chunk_pos:?} can't be remeshed until its neighbours load");
+                    }
+                }
+                EditorAction::Dump(chunk_pos) => {
+                    let Some(chunk) = world.chunks.get(&chunk_pos) else {
+                        continue;
+                    };
+
+                    match dump_chunk(chunk_pos, chunk) {
+                        Ok(path) => info!("Dumped chunk {chunk_pos:?} to {}", path.display()),
+                        Err(err) => error!("Failed to dump chunk {chunk_pos:?}: {err}"),
+                    }
+                }
+                EditorAction::Save => {
+                    let saved = world.dirty_chunks.len();
+                    world.flush_saves();
+                    info!("Saved {saved} chunks");
+                }
+                EditorAction::Reload => {
+                    world.discard_chunks();
+                    loader.reload();
+                    info!("Reloading the world from the saves");
+                }
+                EditorAction::Regenerate(preset) => {
+                    *world_gen = WorldGen::from_preset(preset);
+                    panel.preset = preset;
+                    world.discard_chunks();
+                    loader.reload();
+                    info!("Regenerating the world with the {preset:?} preset");
+                }
+            }
+        }
+    }
+
+    fn show_settings(
+        &mut self,
+        ui: &mut egui::Ui,
+        loader: &mut ChunkLoader,
+        world: &mut World,
+        actions: &mut Vec<EditorAction>,
+    ) {
+        egui::CollapsingHeader::new("World settings")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.label(format!("Seed: {}", world_generator::seed()))
+                    .on_hover_text("Set by NOISE_SEED in constants.rs, or with --seed");
+
+                let mut load_distance = loader.load_distance;
+                let slider = egui::Slider::new(&mut load_distance, 1..=MAX_EDITOR_LOAD_DISTANCE)
+                    .text("Load distance");
+                if ui.add(slider).changed() {
+                    loader.set_load_distance(load_distance, world);
+                }
+
+                ui.add(
+                    egui::Slider::new(&mut loader.unload_delay, 0.0..=MAX_EDITOR_UNLOAD_DELAY)
+                        .text("Unload delay")
+                        .suffix("s"),
+                )
+                .on_hover_text(
+                    "Chunks which come back into range within the delay aren't reloaded",
+                );
+
+                let mut columns = matches!(loader.shape, LoadShape::Columns { .. });
+                let checkbox = ui
+                    .checkbox(&mut columns, "Load whole columns")
+                    .on_hover_text("Between COLUMN_MIN_CHUNK_Y and COLUMN_MAX_CHUNK_Y");
+                if checkbox.changed() {
+                    let shape = if columns {
+                        LoadShape::columns()
+                    } else {
+                        LoadShape::Cube
+                    };
+                    loader.set_shape(shape, world);
+                }
+
+                // Changes are picked up by World::update_mesh_qualities
+                let policy = &mut loader.mesh_quality;
+                ui.add(
+                    egui::Slider::new(&mut policy.full_distance, 0..=MAX_EDITOR_LOAD_DISTANCE)
+                        .text("Greedy distance"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut policy.culled_distance, 0..=MAX_EDITOR_LOAD_DISTANCE)
+                        .text("Culled distance"),
+                );
+                policy.culled_distance = policy.culled_distance.max(policy.full_distance);
+
+                egui::ComboBox::from_label("Far LOD")
+                    .selected_text(format!("{:?}", policy.far_lod))
+                    .show_ui(ui, |ui| {
+                        for lod in [Lod::L32, Lod::L16, Lod::L8, Lod::L4, Lod::L2] {
+                            ui.selectable_value(&mut policy.far_lod, lod, format!("{lod:?}"));
+                        }
+                    });
+
+                // Remeshes every meshed chunk when it changes
+                let mut meshing_mode = world.meshing_mode;
+                egui::ComboBox::from_label("Meshing")
+                    .selected_text(format!("{meshing_mode:?}"))
+                    .show_ui(ui, |ui| {
+                        for mode in [MeshingMode::Blocky, MeshingMode::Smooth] {
+                            ui.selectable_value(&mut meshing_mode, mode, format!("{mode:?}"));
+                        }
+                    });
+                world.set_meshing_mode(meshing_mode);
+
+                // Remeshes the full quality chunks when it changes
+                let mut ao_merging = world.ao_merging;
+                egui::ComboBox::from_label("AO merging")
+                    .selected_text(format!("{ao_merging:?}"))
+                    .show_ui(ui, |ui| {
+                        for merging in [AoMerging::Exact, AoMerging::Corners] {
+                            ui.selectable_value(&mut ao_merging, merging, format!("{merging:?}"));
+                        }
+                    })
+                    .response
+                    .on_hover_text("Corners merges faces with different AO, for fewer quads");
+                world.set_ao_merging(ao_merging);
+
+                let mut preset = self.preset;
+                egui::ComboBox::from_label("Generator")
+                    .selected_text(format!("{preset:?}"))
+                    .show_ui(ui, |ui| {
+                        for &option in GeneratorPreset::ALL {
+                            ui.selectable_value(&mut preset, option, format!("{option:?}"));
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Regenerate")
+                        .on_hover_text("Drop unsaved edits and generate the loaded chunks again")
+                        .clicked()
+                    {
+                        actions.push(EditorAction::Regenerate(preset));
+                    }
+                    if ui
+                        .button(format!("Save ({})", world.dirty_chunks.len()))
+                        .on_hover_text("Write the edited chunks to disk")
+                        .clicked()
+                    {
+                        actions.push(EditorAction::Save);
+                    }
+                    if ui
+                        .button("Load")
+                        .on_hover_text("Drop unsaved edits and load the chunks from disk")
+                        .clicked()
+                    {
+                        actions.push(EditorAction::Reload);
+                    }
+                });
+            });
+    }
+
+    fn show_rendering(ui: &mut egui::Ui, anti_aliasing: &mut AntiAliasing) {
+        egui::CollapsingHeader::new("Rendering").show(ui, |ui| {
+            egui::ComboBox::from_label("Anti-aliasing")
+                .selected_text(format!("{anti_aliasing:?}"))
+                .show_ui(ui, |ui| {
+                    for &option in AntiAliasing::ALL {
+                        ui.selectable_value(anti_aliasing, option, format!("{option:?}"));
+                    }
+                })
+                .response
+                .on_hover_text("SSAO is turned off while MSAA is selected");
+        });
+    }
+
+    fn show_chunks(
+        &mut self,
+        ui: &mut egui::Ui,
+        loader: &ChunkLoader,
+        world: &World,
+        actions: &mut Vec<EditorAction>,
+    ) {
+        egui::CollapsingHeader::new("Loaded chunks")
+            .default_open(true)
+            .show(ui, |ui| {
+                let filters = &mut self.filters;
+
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut filters.meshed_only, "Meshed");
+                    ui.checkbox(&mut filters.dirty_only, "Edited");
+                    ui.checkbox(&mut filters.hide_air, "Hide air");
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("quality_filter")
+                        .selected_text(match filters.quality {
+                            Some(quality) => format!("{quality:?}"),
+                            None => "Any quality".to_string(),
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut filters.quality, None, "Any quality");
+                            for quality in [
+                                MeshQuality::Full,
+                                MeshQuality::Culled,
+                                MeshQuality::Far(loader.mesh_quality.far_lod),
+                            ] {
+                                ui.selectable_value(
+                                    &mut filters.quality,
+                                    Some(quality),
+                                    format!("{quality:?}"),
+                                );
+                            }
+                        });
+                    ui.add(
+                        egui::Slider::new(&mut filters.max_distance, 0..=MAX_EDITOR_LOAD_DISTANCE)
+                            .text("Distance"),
+                    );
+                });
+
+                let loader_pos = loader.prev_chunk_pos;
+                let mut rows = world
+                    .chunks
+                    .iter()
+                    .filter_map(|(&chunk_pos, chunk)| {
+                        let distance =
+                            (chunk_pos.distance_squared(loader_pos) as f32).sqrt() as u32;
+                        filters
+                            .matches(world, chunk_pos, chunk, distance)
+                            .then_some((chunk_pos, chunk.solid_count(), distance))
+                    })
+                    .collect::<Vec<_>>();
+                rows.sort_by_key(|&(chunk_pos, _, distance)| (distance, chunk_pos.to_tuple()));
+
+                ui.label(format!("{} of {} chunks", rows.len(), world.chunks.len()));
+
+                let row_height = ui.spacing().interact_size.y;
+                egui::ScrollArea::vertical()
+                    .max_height(row_height * 16.)
+                    .show_rows(ui, row_height, rows.len(), |ui, row_range| {
+                        for &(chunk_pos, solid_count, distance) in &rows[row_range] {
+                            ui.horizontal(|ui| {
+                                let edited = if world.dirty_chunks.contains(&chunk_pos) {
+                                    "*"
+                                } else {
+                                    ""
+                                };
+                                ui.monospace(format!(
+                                    "{:>4} {:>4} {:>4}{edited}",
+                                    chunk_pos.x, chunk_pos.y, chunk_pos.z
+                                ))
+                                .on_hover_text(format!(
+                                    "{solid_count} solid voxels, {distance} chunks away, {:?}",
+                                    world.mesh_quality(chunk_pos)
+                                ));
+
+                                if ui.small_button("Remesh").clicked() {
+                                    actions.push(EditorAction::Remesh(chunk_pos));
+                                }
+                                if ui.small_button("Dump").clicked() {
+                                    actions.push(EditorAction::Dump(chunk_pos));
+                                }
+
+                                let highlighted = self.highlighted.contains(&chunk_pos);
+                                if ui.selectable_label(highlighted, "Highlight").clicked() {
+                                    if highlighted {
+                                        self.highlighted.retain(|&pos| pos != chunk_pos);
+                                    } else {
+                                        self.highlighted.push(chunk_pos);
+                                    }
+                                }
+                            });
+                        }
+                    });
+
+                if !self.highlighted.is_empty() && ui.button("Clear highlights").clicked() {
+                    self.highlighted.clear();
+                }
+            });
+    }
+
+    fn draw_highlights(mut gizmos: Gizmos, panel: Res<EditorPanel>, voxel_scale: Res<VoxelScale>) {
+        let chunk_extent = CHUNK_EXTENT.as_vec3() * voxel_scale.0;
+
+        for &chunk_pos in &panel.highlighted {
+            let centre = voxel_scale.chunk_translation(chunk_pos) + chunk_extent / 2.;
+
+            gizmos.cuboid(
+                Transform::from_translation(centre).with_scale(chunk_extent),
+                Color::srgb(0.2, 1., 0.4),
+            );
+        }
+    }
+}
+
+// Write the chunk in the compact chunk format, which Chunk::from_bytes reads back
+pub fn dump_chunk(chunk_pos: ChunkPos, chunk: &Chunk) -> io::Result<PathBuf> {
+    fs::create_dir_all(CHUNK_DUMP_DIRECTORY)?;
+
+    let path = PathBuf::from(CHUNK_DUMP_DIRECTORY).join(format!(
+        "{}_{}_{}.chunk",
+        chunk_pos.x, chunk_pos.y, chunk_pos.z
+    ));
+    fs::write(&path, chunk.to_bytes())?;
+
+    Ok(path)
+}
+
+impl World {
+    // Drop every loaded chunk and in-flight task, so the loaders load their surroundings again
+    // Unsaved edits are lost, flush_saves first to keep them
+    pub fn discard_chunks(&mut self) {
+        self.data_tasks.clear();
+        self.generation_stages = GenerationStages::default();
+        self.load_data_queue = ChunkQueue::new();
+        self.unload_data_queue = ChunkQueue::new();
+        self.load_mesh_queue = ChunkQueue::new();
+
+        self.mesh_tasks.clear();
+        self.mesh_versions.clear();
+        self.remesh_sections.clear();
+        self.remesh_batches.clear();
+        self.finished_batches.clear();
+        self.parked_meshes.clear();
+
+        self.chunks.clear();
+        self.chunk_metas.clear();
+        self.chunk_generation += 1;
+        self.dirty_chunks.clear();
+        self.edit_history.clear();
+        self.decals.clear();
+        self.pending_edits.clear();
+        // Cached chunks would bring the old voxels back
+        self.chunk_cache.clear();
+
+        // The meshes are despawned by unload_mesh
+        let shown = self.chunk_entities.keys().copied().collect::<Vec<_>>();
+        for chunk_pos in shown {
+            self.unload_mesh_queue.push(chunk_pos);
+        }
+    }
+}
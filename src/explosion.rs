@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+
+use crate::{
+    constants::{DEBRIS_LIFETIME_SECS, MAX_DEBRIS_PER_EXPLOSION},
+    positions::{VoxelScale, WorldPos},
+    voxel::VoxelType,
+    world::World,
+};
+
+pub struct ExplosionPlugin;
+
+impl Plugin for ExplosionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Explosion>()
+            .add_systems(Update, (Explosion::apply, Debris::update));
+    }
+}
+
+// Send to blow a hole in the world
+#[derive(Event, Debug, Copy, Clone)]
+pub struct Explosion {
+    pub center: WorldPos,
+    pub radius: f32,
+    // Spawn debris cubes from a sample of the removed voxels
+    pub debris: bool,
+}
+
+impl Explosion {
+    fn apply(
+        mut commands: Commands,
+        mut world: ResMut<World>,
+        mut explosions: EventReader<Explosion>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut materials: ResMut<Assets<StandardMaterial>>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        for explosion in explosions.read() {
+            let removed = match world.explode(explosion.center, explosion.radius) {
+                Ok(removed) => removed,
+                Err(err) => {
+                    warn!("Explosion at {:?} failed: {err}", explosion.center);
+                    continue;
+                }
+            };
+
+            if !explosion.debris || removed.is_empty() {
+                continue;
+            }
+
+            let mesh = meshes.add(Cuboid::from_length(0.5 * voxel_scale.0));
+            let center = voxel_scale.to_translation(explosion.center);
+
+            // Evenly sample the removed voxels so large explosions don't spawn too many entities
+            let step = removed.len().div_ceil(MAX_DEBRIS_PER_EXPLOSION);
+            for &(world_pos, voxel_type) in removed.iter().step_by(step) {
+                let translation =
+                    voxel_scale.to_translation(world_pos) + Vec3::splat(0.5 * voxel_scale.0);
+                let outwards = (translation - center).normalize_or(Vec3::Y);
+
+                commands.spawn((
+                    Debris {
+                        velocity: (outwards + Vec3::Y) * explosion.radius * 2. * voxel_scale.0,
+                        lifetime: Timer::from_seconds(DEBRIS_LIFETIME_SECS, TimerMode::Once),
+                    },
+                    PbrBundle {
+                        mesh: mesh.clone(),
+                        material: materials.add(StandardMaterial {
+                            base_color: debris_colour(voxel_type),
+                            ..default()
+                        }),
+                        transform: Transform::from_translation(translation),
+                        ..default()
+                    },
+                ));
+            }
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Debris {
+    pub velocity: Vec3,
+    pub lifetime: Timer,
+}
+
+impl Debris {
+    fn update(
+        mut commands: Commands,
+        mut debris: Query<(Entity, &mut Debris, &mut Transform)>,
+        time: Res<Time>,
+    ) {
+        let delta = time.delta_seconds();
+
+        for (entity, mut debris, mut transform) in debris.iter_mut() {
+            if debris.lifetime.tick(time.delta()).finished() {
+                commands.entity(entity).despawn();
+                continue;
+            }
+
+            debris.velocity.y -= 9.81 * delta;
+            transform.translation += debris.velocity * delta;
+        }
+    }
+}
+
+fn debris_colour(voxel_type: VoxelType) -> Color {
+    match voxel_type {
+        VoxelType::WATER => Color::srgb(0.2, 0.4, 0.9),
+        _ => Color::srgb(0.6, 0.4, 0.5),
+    }
+}
@@ -0,0 +1,288 @@
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+
+use crate::{
+    biome::BiomeMap,
+    chunk_loading::ChunkLoader,
+    constants::{
+        BIOME_COUNT, GENERATION_PREVIEW_HEIGHT_RANGE, GENERATION_PREVIEW_KEY,
+        GENERATION_PREVIEW_SIZE, MAX_GENERATION_PREVIEW_ZOOM, WATER_LEVEL,
+    },
+    positions::VoxelScale,
+    world_generator::{PreviewLayer, WorldGen, WorldGenerator},
+};
+
+// Top-down map of the active generator's layers around the camera, for tuning generation without
+// flying around the world. Maps are rendered on a background task
+pub struct GenerationPreviewPlugin;
+
+impl Plugin for GenerationPreviewPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+
+        app.init_resource::<GenerationPreview>().add_systems(
+            Update,
+            (
+                GenerationPreview::toggle,
+                (GenerationPreview::update, GenerationPreview::show)
+                    .chain()
+                    .run_if(GenerationPreview::is_open),
+            )
+                .chain(),
+        );
+    }
+}
+
+// What a map shows, the map is rendered again when it changes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct PreviewView {
+    layer: PreviewLayer,
+    // Column in the middle of the map
+    centre: IVec2,
+    // Voxels per pixel
+    zoom: i32,
+}
+
+impl PreviewView {
+    // Maps are rendered again once the camera has moved an eighth of the way across them
+    fn is_close(&self, other: &PreviewView) -> bool {
+        self.layer == other.layer
+            && self.zoom == other.zoom
+            && (self.centre - other.centre).abs().max_element()
+                < self.zoom * GENERATION_PREVIEW_SIZE as i32 / 8
+    }
+}
+
+#[derive(Resource)]
+pub struct GenerationPreview {
+    pub open: bool,
+    pub layer: PreviewLayer,
+    // Voxels per pixel
+    pub zoom: i32,
+    // Column the camera is over
+    camera: IVec2,
+    // The view of the map on screen, and whether the generator has its layer
+    shown: Option<(PreviewView, bool)>,
+    task: Option<(PreviewView, Task<Option<egui::ColorImage>>)>,
+    texture: Option<egui::TextureHandle>,
+}
+
+impl Default for GenerationPreview {
+    fn default() -> Self {
+        Self {
+            open: false,
+            layer: PreviewLayer::Height,
+            zoom: 4,
+            camera: IVec2::ZERO,
+            shown: None,
+            task: None,
+            texture: None,
+        }
+    }
+}
+
+impl GenerationPreview {
+    pub fn is_open(preview: Res<GenerationPreview>) -> bool {
+        preview.open
+    }
+
+    fn toggle(mut preview: ResMut<GenerationPreview>, keys: Res<ButtonInput<KeyCode>>) {
+        if keys.just_pressed(GENERATION_PREVIEW_KEY) {
+            preview.open = !preview.open;
+        }
+    }
+
+    fn update(
+        mut contexts: EguiContexts,
+        mut preview: ResMut<GenerationPreview>,
+        world_gen: Res<WorldGen>,
+        loaders: Query<&GlobalTransform, With<ChunkLoader>>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        // Maps of the previous generator are out of date
+        if world_gen.is_changed() {
+            preview.shown = None;
+            preview.task = None;
+        }
+
+        if let Some((view, task)) = preview.task.as_mut() {
+            if let Some(image) = block_on(future::poll_once(task)) {
+                let view = *view;
+                preview.task = None;
+                preview.shown = Some((view, image.is_some()));
+
+                if let Some(image) = image {
+                    match preview.texture.as_mut() {
+                        Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+                        None => {
+                            preview.texture = Some(contexts.ctx_mut().load_texture(
+                                "generation_preview",
+                                image,
+                                egui::TextureOptions::NEAREST,
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+
+        // The loader may be moving between entities, see Spectator
+        let Ok(loader_transform) = loaders.get_single() else {
+            return;
+        };
+        let world_pos = voxel_scale.to_world_pos(loader_transform.translation());
+        preview.camera = IVec2::new(world_pos.x, world_pos.z);
+
+        let view = PreviewView {
+            layer: preview.layer,
+            centre: preview.camera,
+            zoom: preview.zoom,
+        };
+
+        // A map being rendered for a different view is dropped, which cancels it
+        let latest = match &preview.task {
+            Some((rendering, _)) => Some(*rendering),
+            None => preview.shown.map(|(shown, _)| shown),
+        };
+        if latest.is_some_and(|latest| latest.is_close(&view)) {
+            return;
+        }
+
+        let generator = world_gen.0.clone();
+        let task =
+            AsyncComputeTaskPool::get().spawn(async move { render_map(generator.as_ref(), view) });
+        preview.task = Some((view, task));
+    }
+
+    fn show(mut contexts: EguiContexts, mut preview: ResMut<GenerationPreview>) {
+        let preview = preview.as_mut();
+        let mut open = preview.open;
+
+        egui::Window::new("Generation preview")
+            .open(&mut open)
+            .resizable(false)
+            .show(contexts.ctx_mut(), |ui| {
+                egui::ComboBox::from_label("Layer")
+                    .selected_text(format!("{:?}", preview.layer))
+                    .show_ui(ui, |ui| {
+                        for layer in PreviewLayer::ALL {
+                            ui.selectable_value(&mut preview.layer, layer, format!("{layer:?}"));
+                        }
+                    });
+                ui.add(
+                    egui::Slider::new(&mut preview.zoom, 1..=MAX_GENERATION_PREVIEW_ZOOM)
+                        .logarithmic(true)
+                        .text("Voxels per pixel"),
+                );
+
+                match (preview.shown, &preview.texture) {
+                    (Some((view, false)), _) => {
+                        ui.label(format!("The generator has no {:?} layer", view.layer));
+                    }
+                    (Some((view, true)), Some(texture)) => {
+                        preview.show_map(ui, view, texture);
+                    }
+                    _ => {
+                        ui.spinner();
+                    }
+                }
+
+                if preview.task.is_some() {
+                    ui.label("Rendering...");
+                }
+            });
+
+        preview.open = open;
+    }
+
+    // The map with a cross on the camera, hovering it shows the column under the cursor
+    fn show_map(&self, ui: &mut egui::Ui, view: PreviewView, texture: &egui::TextureHandle) {
+        let response = ui.image((
+            texture.id(),
+            egui::Vec2::splat(GENERATION_PREVIEW_SIZE as f32),
+        ));
+        let rect = response.rect;
+
+        let offset = (self.camera - view.centre).as_vec2() / view.zoom as f32;
+        let camera = rect.center() + egui::vec2(offset.x, offset.y);
+        if rect.contains(camera) {
+            let stroke = egui::Stroke::new(1., egui::Color32::RED);
+            ui.painter().line_segment(
+                [camera - egui::vec2(4., 0.), camera + egui::vec2(4., 0.)],
+                stroke,
+            );
+            ui.painter().line_segment(
+                [camera - egui::vec2(0., 4.), camera + egui::vec2(0., 4.)],
+                stroke,
+            );
+        }
+
+        if let Some(hovered) = response.hover_pos() {
+            let pixel = ((hovered - rect.center()) * view.zoom as f32).floor();
+            let column = view.centre + IVec2::new(pixel.x as i32, pixel.y as i32);
+            response.on_hover_text(format!("x: {}, z: {}", column.x, column.y));
+        }
+    }
+}
+
+// Rows run along x, from -z at the top, None if the generator doesn't have the layer
+fn render_map(generator: &dyn WorldGenerator, view: PreviewView) -> Option<egui::ColorImage> {
+    let size = GENERATION_PREVIEW_SIZE as i32;
+    let columns = (0..size * size)
+        .map(|index| view.centre + (IVec2::new(index % size, index / size) - size / 2) * view.zoom)
+        .collect::<Vec<_>>();
+
+    let pixels = match view.layer {
+        PreviewLayer::Biomes => {
+            let biome_map = BiomeMap::new();
+            columns
+                .iter()
+                .map(|column| biome_colour(biome_map.biome_at(column.x, column.y)))
+                .collect()
+        }
+        PreviewLayer::Height => generator
+            .preview(view.layer, &columns)?
+            .into_iter()
+            .map(height_colour)
+            .collect(),
+        PreviewLayer::Rivers => generator
+            .preview(view.layer, &columns)?
+            .into_iter()
+            .map(|depth| mix([20, 20, 20], [60, 140, 255], depth))
+            .collect(),
+    };
+
+    Some(egui::ColorImage {
+        size: [GENERATION_PREVIEW_SIZE; 2],
+        pixels,
+    })
+}
+
+// Water darkens with depth, land goes from grass through rock to snow
+fn height_colour(height: f32) -> egui::Color32 {
+    let above_water = (height - WATER_LEVEL as f32) / GENERATION_PREVIEW_HEIGHT_RANGE;
+
+    if above_water < 0. {
+        mix([40, 90, 200], [10, 25, 80], -above_water)
+    } else if above_water < 0.5 {
+        mix([70, 140, 60], [120, 110, 95], above_water * 2.)
+    } else {
+        mix([120, 110, 95], [240, 240, 245], above_water * 2. - 1.)
+    }
+}
+
+fn biome_colour(biome: usize) -> egui::Color32 {
+    egui::ecolor::Hsva::new(biome as f32 / BIOME_COUNT as f32, 0.5, 0.8, 1.).into()
+}
+
+fn mix(from: [u8; 3], to: [u8; 3], t: f32) -> egui::Color32 {
+    let t = t.clamp(0., 1.);
+    let [r, g, b] = [0, 1, 2].map(|i| (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t) as u8);
+
+    egui::Color32::from_rgb(r, g, b)
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Instant,
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, Task},
+};
+
+use crate::{
+    chunk::Chunk,
+    chunk_from_middle::ChunksFromMiddle,
+    chunk_meta::ChunkMeta,
+    chunk_queue::ChunkQueue,
+    persistence,
+    pipeline_metrics::{self, PipelineStage},
+    pipeline_stepping::PipelineStepping,
+    positions::ChunkPos,
+    task_pools,
+    world::{PipelineMode, World},
+    world_border::WorldBorder,
+    world_generator::{WorldGen, WorldGenerator},
+};
+
+// Chunks are generated in stages when the generator decorates: each chunk's base (density and
+// surface) is generated on its own, and decoration waits until the bases of all of its neighbours
+// are ready, so features like trees can cross chunk borders without blocking base generation
+// Decoration tasks are data tasks, so join_data finishes chunks the same way for every generator
+pub struct GenerationStagesPlugin;
+
+impl Plugin for GenerationStagesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            World::join_bases
+                .before(World::join_data)
+                .run_if(PipelineStepping::is_running),
+        );
+    }
+}
+
+pub enum BaseResult {
+    // Saved chunks were decorated before they were saved
+    Finished(Chunk, ChunkMeta),
+    Base(Chunk),
+}
+
+#[derive(Default)]
+pub struct GenerationStages {
+    // Undecorated chunks, kept while a chunk waiting for decoration neighbours them
+    pub base_chunks: HashMap<ChunkPos, Arc<Chunk>>,
+    // Chunks which are wanted, loading their save or generating their base
+    pub generate_tasks: HashMap<ChunkPos, Task<BaseResult>>,
+    // Bases only needed for decorating their neighbours
+    pub base_tasks: HashMap<ChunkPos, Task<Chunk>>,
+    // Wanted chunks whose base is ready, waiting for the bases of their neighbours
+    pub decorate_queue: ChunkQueue,
+}
+
+impl GenerationStages {
+    pub fn len(&self) -> usize {
+        self.generate_tasks.len() + self.base_tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0 && self.decorate_queue.is_empty()
+    }
+}
+
+impl World {
+    pub fn generation_stages(&self) -> &GenerationStages {
+        &self.generation_stages
+    }
+
+    // Whether the chunk is being generated by one of the stages before decoration
+    pub fn is_generating(&self, chunk_pos: ChunkPos) -> bool {
+        self.generation_stages
+            .generate_tasks
+            .contains_key(&chunk_pos)
+            || self.generation_stages.decorate_queue.contains(&chunk_pos)
+    }
+
+    // Stop generating a chunk which is no longer wanted, its base may still be used by neighbours
+    pub fn cancel_generation(&mut self, chunk_pos: ChunkPos) {
+        self.generation_stages.generate_tasks.remove(&chunk_pos);
+        self.generation_stages.decorate_queue.remove(&chunk_pos);
+    }
+
+    // Start decorating queued chunks whose neighbours' bases are ready, requesting the missing ones
+    // Returns how many of the slots were used
+    pub(crate) fn start_decorations(
+        &mut self,
+        world_gen: &WorldGen,
+        border: &WorldBorder,
+        slots: usize,
+    ) -> usize {
+        let task_pool = task_pools::generation_pool();
+
+        let World {
+            data_tasks,
+            generation_stages,
+            ..
+        } = self;
+        let GenerationStages {
+            base_chunks,
+            generate_tasks,
+            base_tasks,
+            decorate_queue,
+        } = generation_stages;
+
+        let mut started = Vec::new();
+        for &chunk_pos in decorate_queue.iter() {
+            let missing = neighbourhood(chunk_pos)
+                .filter(|neighbour_pos| !base_chunks.contains_key(neighbour_pos))
+                .collect::<Vec<_>>();
+
+            if missing.is_empty() {
+                if started.len() < slots {
+                    started.push(chunk_pos);
+                }
+                continue;
+            }
+
+            // Bases are cheap next to decoration, so they aren't limited by the slots
+            for neighbour_pos in missing {
+                if generate_tasks.contains_key(&neighbour_pos)
+                    || base_tasks.contains_key(&neighbour_pos)
+                {
+                    continue;
+                }
+
+                let task = if border.contains_chunk(neighbour_pos) {
+                    let generator = Arc::clone(&world_gen.0);
+                    task_pool.spawn(async move { generate_base(generator.as_ref(), neighbour_pos) })
+                } else {
+                    task_pool.spawn(async { Chunk::new() })
+                };
+                base_tasks.insert(neighbour_pos, task);
+            }
+        }
+
+        for &chunk_pos in started.iter() {
+            decorate_queue.remove(&chunk_pos);
+
+            let Some(bases) = ChunksFromMiddle::try_new(base_chunks, chunk_pos) else {
+                continue;
+            };
+            let generator = Arc::clone(&world_gen.0);
+            let task = task_pool.spawn(async move {
+                let started = Instant::now();
+                let mut chunk = bases.get_middle_chunk().clone();
+                generator.decorate(chunk_pos, &bases, &mut chunk);
+                let meta = generator.generate_meta(chunk_pos, &chunk);
+                pipeline_metrics::record(
+                    PipelineStage::Decoration,
+                    chunk_pos,
+                    started.elapsed(),
+                    0,
+                );
+
+                (chunk, meta)
+            });
+
+            data_tasks.insert(chunk_pos, Some(task));
+        }
+
+        // Bases which no waiting chunk neighbours aren't needed any more
+        let needed = decorate_queue
+            .iter()
+            .chain(generate_tasks.keys())
+            .flat_map(|&chunk_pos| neighbourhood(chunk_pos))
+            .collect::<HashSet<_>>();
+        base_chunks.retain(|chunk_pos, _| needed.contains(chunk_pos));
+
+        started.len()
+    }
+
+    // Load the chunk's save, or generate its base for decoration
+    pub(crate) fn start_generation(&mut self, chunk_pos: ChunkPos, world_gen: &WorldGen) {
+        let generator = Arc::clone(&world_gen.0);
+        let task = task_pools::generation_pool().spawn(async move {
+            match persistence::load_chunk(chunk_pos) {
+                Some((chunk, meta)) => {
+                    let meta = meta.unwrap_or_else(|| generator.generate_meta(chunk_pos, &chunk));
+
+                    BaseResult::Finished(chunk, meta)
+                }
+                None => BaseResult::Base(generate_base(generator.as_ref(), chunk_pos)),
+            }
+        });
+
+        self.generation_stages
+            .generate_tasks
+            .insert(chunk_pos, task);
+    }
+
+    // Join the base tasks, queueing wanted chunks for decoration
+    pub fn join_bases(mut world: ResMut<World>, pipeline_mode: Res<PipelineMode>) {
+        let World {
+            data_tasks,
+            generation_stages,
+            ..
+        } = world.as_mut();
+        let GenerationStages {
+            base_chunks,
+            generate_tasks,
+            base_tasks,
+            decorate_queue,
+        } = generation_stages;
+
+        base_tasks.retain(|&chunk_pos, task| {
+            let Some(chunk) = poll(task, *pipeline_mode) else {
+                return true;
+            };
+
+            base_chunks.insert(chunk_pos, Arc::new(chunk));
+            false
+        });
+
+        generate_tasks.retain(|&chunk_pos, task| {
+            match poll(task, *pipeline_mode) {
+                None => return true,
+                // Finished chunks go through join_data like every other chunk
+                Some(BaseResult::Finished(chunk, meta)) => {
+                    let task = task_pools::generation_pool().spawn(async { (chunk, meta) });
+                    data_tasks.insert(chunk_pos, Some(task));
+                }
+                Some(BaseResult::Base(chunk)) => {
+                    base_chunks.insert(chunk_pos, Arc::new(chunk));
+                    decorate_queue.push(chunk_pos);
+                }
+            }
+
+            false
+        });
+    }
+}
+
+fn generate_base(generator: &dyn WorldGenerator, chunk_pos: ChunkPos) -> Chunk {
+    let started = Instant::now();
+    let chunk = generator.generate_base(chunk_pos);
+    pipeline_metrics::record(PipelineStage::Generation, chunk_pos, started.elapsed(), 0);
+
+    chunk
+}
+
+fn poll<T>(task: &mut Task<T>, pipeline_mode: PipelineMode) -> Option<T> {
+    match pipeline_mode {
+        PipelineMode::Async => block_on(future::poll_once(task)),
+        PipelineMode::Deterministic => Some(block_on(task)),
+    }
+}
+
+// The chunk and its neighbours, which decoration reads from
+fn neighbourhood(chunk_pos: ChunkPos) -> impl Iterator<Item = ChunkPos> {
+    (-1..=1).flat_map(move |z| {
+        (-1..=1).flat_map(move |y| (-1..=1).map(move |x| chunk_pos + ChunkPos::new(x, y, z)))
+    })
+}
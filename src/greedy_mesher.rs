@@ -1,20 +1,44 @@
 use std::collections::HashMap;
 
-use bevy::math::IVec3;
+use bevy::{
+    log::info_span,
+    math::{IVec3, UVec3, Vec2, Vec3},
+};
 
 use crate::{
     chunk_from_middle::ChunksFromMiddle,
-    chunk_mesh::{generate_indices, ChunkMesh, FaceDir, GreedyQuad},
-    constants::{ADJACENT_AO_DIRS, CHUNKS_FROM_MIDDLE_SIZE, CHUNK_SIZE, CHUNK_SIZE_PADDED},
+    chunk_mesh::{generate_indices, ChunkMesh, FaceDir, GreedyQuad, SectionMeshes},
+    constants::{
+        ADJACENT_AO_DIRS, CHUNK_HEIGHT, CHUNK_SIZE, SECTIONS_PER_CHUNK, SECTION_SIZE,
+        SKYLIGHT_FALLOFF_DEPTH, SUN_DIRECTION, SUN_SHADOW_REACH, SUN_SHADOW_SOFTNESS,
+        SUN_SHADOW_SPREAD, SUN_SHADOW_STEPS, VOXEL_GRID_MAX_SIZE,
+    },
     lod::Lod,
-    positions::{chunk_pos_to_index_bounds, VoxelPos},
-    voxel::Voxel,
+    mesh_pool,
+    positions::VoxelPos,
+    task_pools,
+    vertex::{PackedVertex, MAX_SKY_DARKNESS, MAX_SUN_SHADOW},
+    voxel_grid::{Downsampled, VoxelGrid},
 };
 
-pub fn greedy_mesh_binary_plane(mut data: [u32; CHUNK_SIZE], lod_size: usize) -> Vec<GreedyQuad> {
+// Rows of a binary plane, bit y of row x is set where there is a face
+type BinaryPlane = [u64; VOXEL_GRID_MAX_SIZE];
+
+// How ambient occlusion affects which faces are merged into quads
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AoMerging {
+    // Only faces with the same AO merge, exact but bumpy terrain is split into many small quads
+    #[default]
+    Exact,
+    // Faces merge regardless of AO and the AO is sampled at the quads' corners, so it is lost
+    // inside large quads, for far fewer quads
+    Corners,
+}
+
+pub fn greedy_mesh_binary_plane(mut data: BinaryPlane, lod_size: usize) -> Vec<GreedyQuad> {
     let mut greedy_quads = Vec::new();
 
-    for row in 0..data.len() {
+    for row in 0..lod_size {
         let mut y = 0;
 
         while (y as usize) < lod_size {
@@ -28,7 +52,7 @@ pub fn greedy_mesh_binary_plane(mut data: [u32; CHUNK_SIZE], lod_size: usize) ->
             let height = (data[row] >> y).trailing_ones();
 
             // Convert height into (height)-many 1 bits
-            let height_as_mask = u32::checked_shl(1, height).map_or(!0, |v| v - 1);
+            let height_as_mask = u64::checked_shl(1, height).map_or(!0, |v| v - 1);
             let mask = height_as_mask << y;
 
             // Grow horizontally
@@ -57,214 +81,758 @@ pub fn greedy_mesh_binary_plane(mut data: [u32; CHUNK_SIZE], lod_size: usize) ->
     greedy_quads
 }
 
-pub fn build_chunk_mesh(chunks_from_middle: &ChunksFromMiddle, lod: Lod) -> Option<ChunkMesh> {
-    if chunks_from_middle.are_all_voxels_same() {
-        return None;
+// Face directions in the order of the column face masks, descending then ascending along each axis
+const FACE_DIRS: [FaceDir; 6] = [
+    FaceDir::Down,
+    FaceDir::Up,
+    FaceDir::Left,
+    FaceDir::Right,
+    FaceDir::Front,
+    FaceDir::Back,
+];
+
+// Binary face masks of the columns of a window of the grid for each face direction, see FaceMasks::column
+// Windows are stacked up grids taller than VOXEL_GRID_MAX_SIZE, so that a column fits in a u64
+struct FaceMasks {
+    min: UVec3,
+    size: UVec3,
+    masks: [Vec<u64>; 6],
+}
+
+impl FaceMasks {
+    // The column at (col_x, col_z) of the face direction's plane, bit depth + 1 is the voxel at depth
+    // Positions are relative to the window, columns and depths are padded by one voxel on each side
+    fn column(&self, face_index: usize, col_x: usize, col_z: usize) -> u64 {
+        let (width, _, _) = plane_axes(self.size, FACE_DIRS[face_index]);
+
+        self.masks[face_index][(col_z + 1) * (width as usize + 2) + col_x + 1]
     }
+}
 
-    let mut mesh = ChunkMesh::default();
-    let mut axis_cols = [[[0u64; CHUNK_SIZE_PADDED]; CHUNK_SIZE_PADDED]; 3]; // Solid binary for (x, y, z) axes
-    let mut col_face_masks = [[[0u64; CHUNK_SIZE_PADDED]; CHUNK_SIZE_PADDED]; 6]; // The cull mask to perform greedy slicing
-
-    // #[inline]
-    fn add_voxel_to_axis_cols(
-        voxel: &Voxel,
-        x: usize,
-        y: usize,
-        z: usize,
-        axis_cols: &mut [[[u64; CHUNK_SIZE_PADDED]; CHUNK_SIZE_PADDED]; 3],
-    ) {
-        if voxel.voxel_type.is_solid() {
-            // x,z --- y axis
-            axis_cols[0][z][x] |= 1 << y as u64;
-
-            // y,z --- x axis
-            axis_cols[1][y][z] |= 1 << x as u64;
-
-            // x,y --- z axis
-            axis_cols[2][y][x] |= 1 << z as u64;
-        }
+// Components of a vector along the column x, column z and depth axes of a face direction's planes
+fn plane_axes(v: UVec3, face_dir: FaceDir) -> (u32, u32, u32) {
+    match face_dir {
+        FaceDir::Down | FaceDir::Up => (v.x, v.z, v.y),
+        FaceDir::Left | FaceDir::Right => (v.z, v.y, v.x),
+        FaceDir::Front | FaceDir::Back => (v.x, v.y, v.z),
     }
+}
 
-    // Inner chunk voxels
-    let chunk = &*chunks_from_middle.chunks
-        [chunk_pos_to_index_bounds((1, 1, 1).into(), CHUNKS_FROM_MIDDLE_SIZE as u32)];
-    assert!(chunk.len() == CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE || chunk.len() == 1);
-    for z in 0..CHUNK_SIZE {
-        for y in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let i = match chunk.len() {
-                    1 => 0,
-                    _ => VoxelPos::new(x, y, z).to_index(),
-                };
+// Fake skylight until there is real voxel lighting: the height of the open sky above each column,
+// from the chunk and the one above it, so faces below it are darkened by how deep they are
+// Surfaces further up than the chunk above count as the sky, and edits only update the chunks they remesh
+// Soft sun shadows are baked the same way, by marching toward the sun over the highest opaque voxel of
+// the columns around the chunk, so hills cast long shadows without a shadow map
+struct SkyHeights {
+    // Columns are padded by one voxel on each side, like the face masks
+    heights: Vec<i32>,
+    // Tops of the opaque columns, padded by SUN_SHADOW_REACH on each side
+    shadow_heights: Vec<i32>,
+}
 
-                add_voxel_to_axis_cols(&chunk[i], x + 1, y + 1, z + 1, &mut axis_cols);
+impl SkyHeights {
+    fn new(chunks_from_middle: &ChunksFromMiddle) -> Self {
+        let size = CHUNK_SIZE as i32;
+        let chunk_height = CHUNK_HEIGHT as i32;
+
+        let mut heights = Vec::with_capacity((CHUNK_SIZE + 2) * (CHUNK_SIZE + 2));
+        for z in -1..=size {
+            for x in -1..=size {
+                // Just above the highest solid voxel, or below the chunk if the column is empty
+                let height = (-1..chunk_height * 2)
+                    .rev()
+                    .find(|&y| {
+                        chunks_from_middle
+                            .get_voxel(IVec3::new(x, y, z))
+                            .voxel_type
+                            .is_solid()
+                    })
+                    .map_or(-1, |y| y + 1);
+
+                heights.push(height);
             }
         }
-    }
 
-    // Neighbour chunk voxels
-    // TODO Optimise these
-    for z in [0, CHUNK_SIZE_PADDED - 1] {
-        for y in 0..CHUNK_SIZE_PADDED {
-            for x in 0..CHUNK_SIZE_PADDED {
-                let voxel_pos = IVec3::new(x as i32, y as i32, z as i32) - IVec3::ONE;
-                add_voxel_to_axis_cols(
-                    chunks_from_middle.get_voxel(voxel_pos),
-                    x,
-                    y,
-                    z,
-                    &mut axis_cols,
-                )
+        let reach = SUN_SHADOW_REACH as i32;
+        let mut shadow_heights = Vec::with_capacity((CHUNK_SIZE + SUN_SHADOW_REACH * 2).pow(2));
+        for z in -reach..size + reach {
+            for x in -reach..size + reach {
+                // Empty columns are well below anything they could shadow
+                shadow_heights.push(
+                    chunks_from_middle
+                        .column_height(x, z)
+                        .map_or(-chunk_height, |y| y + 1),
+                );
             }
         }
+
+        Self {
+            heights,
+            shadow_heights,
+        }
     }
-    for z in 0..CHUNK_SIZE_PADDED {
-        for y in [0, CHUNK_SIZE_PADDED - 1] {
-            for x in 0..CHUNK_SIZE_PADDED {
-                let voxel_pos = IVec3::new(x as i32, y as i32, z as i32) - IVec3::ONE;
-                add_voxel_to_axis_cols(
-                    chunks_from_middle.get_voxel(voxel_pos),
-                    x,
-                    y,
-                    z,
-                    &mut axis_cols,
-                )
-            }
+
+    // Darkness of a face lit from the voxel at pos, in full detail voxels
+    fn darkness_at(&self, pos: IVec3) -> u32 {
+        let size = CHUNK_SIZE as i32;
+        let (x, z) = (pos.x.clamp(-1, size), pos.z.clamp(-1, size));
+        let depth = self.heights[((z + 1) * (size + 2) + x + 1) as usize] - pos.y;
+        if depth <= 0 {
+            return 0;
         }
+
+        // Light falls off exponentially with depth
+        let light = (-depth as f32 / SKYLIGHT_FALLOFF_DEPTH).exp();
+        (MAX_SKY_DARKNESS as f32 * (1. - light)).round() as u32
     }
-    for z in 0..CHUNK_SIZE_PADDED {
-        for x in [0, CHUNK_SIZE_PADDED - 1] {
-            for y in 0..CHUNK_SIZE_PADDED {
-                let voxel_pos = IVec3::new(x as i32, y as i32, z as i32) - IVec3::ONE;
-                add_voxel_to_axis_cols(
-                    chunks_from_middle.get_voxel(voxel_pos),
-                    x,
-                    y,
-                    z,
-                    &mut axis_cols,
-                )
+
+    // Shadow on a face lit from the voxel at pos, in full detail voxels, from rays marched toward the
+    // sun and either side of it, each fading in over SUN_SHADOW_SOFTNESS voxels below the terrain
+    fn sun_shadow_at(&self, pos: IVec3, face_dir: FaceDir) -> u32 {
+        let sun = SUN_DIRECTION.normalize();
+        let towards_sun = Vec2::new(sun.x, sun.z);
+
+        // Faces turned from the sun are already unlit, and an overhead sun casts no long shadows
+        if face_dir.sample_dir().as_vec3().dot(sun) <= 0. || towards_sun.length() < 1e-3 {
+            return 0;
+        }
+        let rise = sun.y / towards_sun.length();
+        let towards_sun = towards_sun.normalize();
+
+        let size = CHUNK_SIZE as i32;
+        let reach = SUN_SHADOW_REACH as i32;
+        let width = size + reach * 2;
+        let start = Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32) + 0.5;
+
+        let shadow = [-SUN_SHADOW_SPREAD, 0., SUN_SHADOW_SPREAD]
+            .into_iter()
+            .map(|angle| {
+                let ray = Vec2::from_angle(angle).rotate(towards_sun);
+
+                (1..=SUN_SHADOW_STEPS)
+                    .map(|step| {
+                        let distance =
+                            step as f32 * SUN_SHADOW_REACH as f32 / SUN_SHADOW_STEPS as f32;
+                        let column = (Vec2::new(start.x, start.z) + ray * distance).floor();
+                        let (x, z) = (
+                            (column.x as i32).clamp(-reach, size + reach - 1),
+                            (column.y as i32).clamp(-reach, size + reach - 1),
+                        );
+                        let height =
+                            self.shadow_heights[((z + reach) * width + x + reach) as usize];
+
+                        let below = height as f32 - (start.y + distance * rise);
+                        (below / SUN_SHADOW_SOFTNESS).clamp(0., 1.)
+                    })
+                    .fold(0., f32::max)
+            })
+            .sum::<f32>()
+            / 3.;
+
+        (MAX_SUN_SHADOW as f32 * shadow).round() as u32
+    }
+}
+
+// Build the meshes of the sections set in the section mask, without AO if ao is None
+pub fn build_section_meshes(
+    chunks_from_middle: &ChunksFromMiddle,
+    lod: Lod,
+    ao: Option<AoMerging>,
+    sections: u64,
+) -> SectionMeshes {
+    let _span = info_span!("greedy_build_section_meshes", ?lod, sections).entered();
+
+    let section_indices = (0..SECTIONS_PER_CHUNK).filter(|section| sections & (1 << section) != 0);
+
+    if chunks_from_middle.are_all_voxels_same() {
+        return section_indices.map(|section| (section, None)).collect();
+    }
+
+    let sky_heights = SkyHeights::new(chunks_from_middle);
+
+    if lod.jump_index() == 1 {
+        return section_meshes_at_lod(chunks_from_middle, lod, ao, &sky_heights, section_indices);
+    }
+
+    section_meshes_at_lod(
+        &Downsampled::new(chunks_from_middle, lod),
+        lod,
+        ao,
+        &sky_heights,
+        section_indices,
+    )
+}
+
+// The grid is in the LOD's voxels, so the section bounds are scaled down to match
+fn section_meshes_at_lod(
+    grid: &impl VoxelGrid,
+    lod: Lod,
+    ao: Option<AoMerging>,
+    sky_heights: &SkyHeights,
+    section_indices: impl Iterator<Item = usize>,
+) -> SectionMeshes {
+    let jump = lod.jump_index() as u32;
+    // Windows are whole sections tall, so no section is split between them
+    let section_size = (SECTION_SIZE as u32 / jump).max(1);
+    let col_face_masks = build_face_masks(
+        grid,
+        VOXEL_GRID_MAX_SIZE as u32 / section_size * section_size,
+    );
+
+    section_indices
+        .map(|section| {
+            let mesh = build_mesh_in_bounds(
+                grid,
+                &col_face_masks,
+                (
+                    VoxelPos::from_section_index(section).to_ivec3().as_uvec3() / jump,
+                    UVec3::splat(section_size),
+                ),
+                lod,
+                ao,
+                Some(sky_heights),
+            );
+
+            (section, mesh)
+        })
+        .collect()
+}
+
+// Mesh a whole grid, e.g. a voxel object, faces are culled against the voxels just outside it
+pub fn build_grid_mesh(grid: &impl VoxelGrid, ao_enabled: bool) -> Option<ChunkMesh> {
+    let _span = info_span!("greedy_build_grid_mesh").entered();
+
+    // Depth masks can't be made for an empty axis
+    if grid.size().min_element() == 0 {
+        return None;
+    }
+
+    // Grids aren't under the world's sky, so they are fully lit
+    build_grid_mesh_at_lod(grid, Lod::L32, ao_enabled.then_some(AoMerging::Exact), None)
+}
+
+// Mesh a whole grid downsampled to the LOD, fully lit like build_grid_mesh
+pub fn build_downsampled_grid_mesh(
+    grid: &impl VoxelGrid,
+    lod: Lod,
+    ao_enabled: bool,
+) -> Option<ChunkMesh> {
+    let _span = info_span!("greedy_build_downsampled_grid_mesh", ?lod).entered();
+
+    if grid.size().min_element() == 0 {
+        return None;
+    }
+
+    let ao = ao_enabled.then_some(AoMerging::Exact);
+    if lod.jump_index() == 1 {
+        return build_grid_mesh_at_lod(grid, lod, ao, None);
+    }
+
+    build_grid_mesh_at_lod(&Downsampled::new(grid, lod), lod, ao, None)
+}
+
+// Mesh a whole grid whose voxels are each the LOD's jump in size
+fn build_grid_mesh_at_lod(
+    grid: &impl VoxelGrid,
+    lod: Lod,
+    ao: Option<AoMerging>,
+    sky_heights: Option<&SkyHeights>,
+) -> Option<ChunkMesh> {
+    let col_face_masks = build_face_masks(grid, VOXEL_GRID_MAX_SIZE as u32);
+    build_mesh_in_bounds(
+        grid,
+        &col_face_masks,
+        (UVec3::ZERO, grid.size()),
+        lod,
+        ao,
+        sky_heights,
+    )
+}
+
+// Binary face masks for the whole grid, built once and shared by every section
+// Grids taller than VOXEL_GRID_MAX_SIZE are split into windows of the given height
+fn build_face_masks(grid: &impl VoxelGrid, window_height: u32) -> Vec<FaceMasks> {
+    let size = grid.size();
+    assert!(
+        size.x.max(size.z) <= VOXEL_GRID_MAX_SIZE as u32,
+        "Grid is too large to mesh: {size}"
+    );
+
+    if size.y <= VOXEL_GRID_MAX_SIZE as u32 {
+        return vec![build_window_face_masks(grid, UVec3::ZERO, size)];
+    }
+
+    (0..size.y)
+        .step_by(window_height as usize)
+        .map(|min_y| {
+            let window_size = UVec3::new(size.x, window_height.min(size.y - min_y), size.z);
+            build_window_face_masks(grid, UVec3::Y * min_y, window_size)
+        })
+        .collect()
+}
+
+fn build_window_face_masks(grid: &impl VoxelGrid, min: UVec3, size: UVec3) -> FaceMasks {
+    // Solid binary columns, padded by the voxels outside the grid, in the same layout as the masks
+    let mut axis_cols = [0, 2, 4].map(|face_index| {
+        let (width, height, _) = plane_axes(size, FACE_DIRS[face_index]);
+        vec![0u64; (width as usize + 2) * (height as usize + 2)]
+    });
+
+    let padded_size = size + 2;
+    for z in 0..padded_size.z {
+        for y in 0..padded_size.y {
+            for x in 0..padded_size.x {
+                let voxel_pos = (min + UVec3::new(x, y, z)).as_ivec3() - IVec3::ONE;
+                if !grid.voxel_at(voxel_pos).is_solid() {
+                    continue;
+                }
+
+                let padded_pos = UVec3::new(x, y, z);
+                for (axis, cols) in axis_cols.iter_mut().enumerate() {
+                    let (col_x, col_z, depth) = plane_axes(padded_pos, FACE_DIRS[axis * 2]);
+                    let (width, _, _) = plane_axes(padded_size, FACE_DIRS[axis * 2]);
+
+                    cols[(col_z * width + col_x) as usize] |= 1 << depth;
+                }
             }
         }
     }
 
     // Face culling
-    for axis in 0..3 {
-        for z in 0..CHUNK_SIZE_PADDED {
-            for x in 0..CHUNK_SIZE_PADDED {
-                // Set if current is solid and next is air
-                let col = axis_cols[axis][z][x];
-
-                col_face_masks[2 * axis][z][x] = col & !(col << 1); // Sample descending axis and set true when air meets solid
-                col_face_masks[2 * axis + 1][z][x] = col & !(col >> 1); // Sample ascending axis and set true when air meets solid
+    let masks = [0, 1, 2, 3, 4, 5].map(|face_index| {
+        axis_cols[face_index / 2]
+            .iter()
+            .map(|&col| match face_index % 2 {
+                // Sample descending axis and set true when air meets solid
+                0 => col & !(col << 1),
+                // Sample ascending axis and set true when air meets solid
+                _ => col & !(col >> 1),
+            })
+            .collect()
+    });
+
+    FaceMasks { min, size, masks }
+}
+
+// Mesh the box of voxels starting at min, with the given size
+fn build_mesh_in_bounds(
+    grid: &impl VoxelGrid,
+    col_face_masks: &[FaceMasks],
+    bounds: (UVec3, UVec3),
+    lod: Lod,
+    ao: Option<AoMerging>,
+    sky_heights: Option<&SkyHeights>,
+) -> Option<ChunkMesh> {
+    let mut mesh = mesh_pool::take();
+
+    // Each face direction is independent, so they are meshed in parallel and then concatenated
+    // They run on the meshing pool alongside the chunk's own task, which helps run them while it
+    // waits, rather than competing with the systems on the ComputeTaskPool
+    let vertices = task_pools::meshing_pool()
+        .scope(|scope| {
+            for face_index in 0..FACE_DIRS.len() {
+                scope.spawn(async move {
+                    col_face_masks
+                        .iter()
+                        .flat_map(|window_masks| {
+                            mesh_face_dir(
+                                grid,
+                                window_masks,
+                                face_index,
+                                bounds,
+                                ao,
+                                lod,
+                                sky_heights,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                });
             }
-        }
+        })
+        .concat();
+
+    mesh.vertices.extend(vertices);
+    if mesh.vertices.is_empty() {
+        mesh_pool::recycle(mesh);
+        None
+    } else {
+        generate_indices(&mut mesh.indices, mesh.vertices.len());
+        Some(mesh)
     }
+}
 
-    // Greedy meshing planes for all 6 axes
-    // key(voxel + ao) -> HashMap<axis(0-CHUNK_SIZE), binary_plane>
-    let mut data: [HashMap<u32, HashMap<u32, [u32; CHUNK_SIZE]>>; 6] = [
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-        HashMap::new(),
-    ];
+// Build the greedy meshed quads for a single face direction, in the part of the bounds inside the
+// face masks' window
+fn mesh_face_dir(
+    grid: &impl VoxelGrid,
+    col_face_masks: &FaceMasks,
+    face_index: usize,
+    (min, size): (UVec3, UVec3),
+    ao: Option<AoMerging>,
+    lod: Lod,
+    sky_heights: Option<&SkyHeights>,
+) -> Vec<PackedVertex> {
+    let face_dir = FACE_DIRS[face_index];
+
+    // The bounds clipped to the window, relative to it
+    let clipped_min = min.max(col_face_masks.min);
+    let clipped_max = (min + size).min(col_face_masks.min + col_face_masks.size);
+    if clipped_max.cmple(clipped_min).any() {
+        return Vec::new();
+    }
+    let (min, size) = (clipped_min - col_face_masks.min, clipped_max - clipped_min);
 
-    // Find faces and build binary planes based on the voxel+ao
-    for axis in 0..6 {
-        for z in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                // Skip using CHUNK_SIZE_PADDED by just adding 1 to x and 1 to z
-                let mut col = col_face_masks[axis][z + 1][x + 1];
-
-                // Remove right-most padding because it's invalid
-                col >>= 1;
-
-                // Remove left-most padding because it's invalid
-                col &= !(1 << CHUNK_SIZE as u64);
-
-                while col != 0 {
-                    let y = col.trailing_zeros() as usize;
-
-                    // Clear least significant, set, bit
-                    col &= col - 1;
-
-                    // Get the voxel position based on axis
-                    let voxel_pos: VoxelPos = match axis {
-                        0 | 1 => (x, y, z).into(), // Down, Up
-                        2 | 3 => (y, z, x).into(), // Left, Right
-                        _ => (x, z, y).into(),     // Front, Back
-                    };
-
-                    // Calculate ambient occlusion
-                    let mut ao_index = 0;
-                    for (ao_i, ao_offset) in ADJACENT_AO_DIRS.iter().enumerate() {
-                        // AO is sampled based on axis (ascent or descent)
-                        let ao_sample_offset = match axis {
-                            0 => IVec3::new(ao_offset.x, -1, ao_offset.y), // Down
-                            1 => IVec3::new(ao_offset.x, 1, ao_offset.y),  // Up
-                            2 => IVec3::new(-1, ao_offset.y, ao_offset.x), // Left
-                            3 => IVec3::new(1, ao_offset.y, ao_offset.x),  // Right
-                            4 => IVec3::new(ao_offset.x, ao_offset.y, -1), // Front
-                            _ => IVec3::new(ao_offset.x, ao_offset.y, 1),  // Back
-                        };
-
-                        let ao_voxel_pos = voxel_pos.to_ivec3() + ao_sample_offset;
-                        let ao_voxel = chunks_from_middle.get_voxel(ao_voxel_pos);
-
-                        if ao_voxel.voxel_type.is_solid() {
-                            ao_index |= 1 << ao_i;
-                        }
-                    }
+    let _span = info_span!("greedy_mesh_face_dir", ?face_dir).entered();
 
-                    let current_voxel = chunks_from_middle.get_voxel_no_neighbour(voxel_pos);
+    // Bounds of the columns, and the depth bits within them, which lie inside the mesh bounds
+    let (col_x_min, col_z_min, depth_min) = plane_axes(min, face_dir);
+    let (col_x_size, col_z_size, depth_size) = plane_axes(size, face_dir);
+    let (_, _, grid_depth) = plane_axes(col_face_masks.size, face_dir);
+    let (col_x_offset, col_z_offset, depth_offset) = plane_axes(col_face_masks.min, face_dir);
+    let plane_size = col_face_masks.size.max_element() as usize;
+    let depth_mask = (u64::MAX >> (64 - depth_size)) << depth_min;
 
-                    // Can only greedy mesh same voxel types with same AO
-                    let voxel_hash = ao_index | ((current_voxel.voxel_type as u32) << 9);
-                    let data = data[axis]
-                        .entry(voxel_hash)
-                        .or_default()
-                        .entry(y as u32)
-                        .or_default();
-                    data[x] |= 1 << z;
-                }
+    // Binary planes for this face direction
+    // key(voxel + ao + sky darkness) -> HashMap<depth along the face normal, binary_plane>
+    let mut planes: HashMap<u32, HashMap<u32, BinaryPlane>> = HashMap::new();
+
+    // Find faces and build binary planes based on the voxel+ao
+    for col_z in col_z_min as usize..(col_z_min + col_z_size) as usize {
+        for col_x in col_x_min as usize..(col_x_min + col_x_size) as usize {
+            let mut col = col_face_masks.column(face_index, col_x, col_z);
+
+            // Remove right-most padding because it's invalid
+            col >>= 1;
+
+            // Remove left-most padding because it's invalid
+            col &= !(1 << grid_depth as u64);
+
+            // Only keep the faces within the mesh bounds
+            col &= depth_mask;
+            while col != 0 {
+                let depth = col.trailing_zeros() as usize;
+
+                // Clear least significant, set, bit
+                col &= col - 1;
+
+                let voxel_pos = plane_voxel_pos(
+                    face_dir,
+                    col_x + col_x_offset as usize,
+                    col_z + col_z_offset as usize,
+                    depth + depth_offset as usize,
+                );
+
+                // With corner AO the AO is sampled once the quads are known
+                let ao_index = match ao {
+                    Some(AoMerging::Exact) => ao_mask(grid, voxel_pos, face_dir),
+                    _ => 0,
+                };
+
+                let voxel_type = grid.voxel_at(voxel_pos.to_ivec3());
+
+                // The face is lit from the voxel in front of it, in full detail voxels
+                let (sky_darkness, sun_shadow) = sky_heights.map_or((0, 0), |sky_heights| {
+                    let jump = lod.jump_index() as i32;
+                    let lit_pos = (voxel_pos.to_ivec3() + face_dir.sample_dir()) * jump;
+                    (
+                        sky_heights.darkness_at(lit_pos),
+                        sky_heights.sun_shadow_at(lit_pos, face_dir),
+                    )
+                });
+
+                // Can only greedy mesh same voxel types with same AO, sky darkness and sun shadow
+                let voxel_hash = ao_index
+                    | (u32::from(voxel_type) << 9)
+                    | (sky_darkness << 21)
+                    | (sun_shadow << 25);
+                let plane = planes
+                    .entry(voxel_hash)
+                    .or_default()
+                    .entry(depth as u32 + depth_offset)
+                    .or_insert([0; VOXEL_GRID_MAX_SIZE]);
+                plane[col_x] |= 1 << col_z;
             }
         }
     }
 
     // Time for greedy meshing
     let mut vertices = Vec::new();
-    for (axis, voxel_ao_data) in data.into_iter().enumerate() {
-        let face_dir = match axis {
-            0 => FaceDir::Down,
-            1 => FaceDir::Up,
-            2 => FaceDir::Left,
-            3 => FaceDir::Right,
-            4 => FaceDir::Front,
-            _ => FaceDir::Back,
+    for (voxel_ao, depth_planes) in planes.into_iter() {
+        let ao_index = voxel_ao & 0b111111111; // 9 1s
+        let voxel_type = ((voxel_ao >> 9) & 0xfff).into();
+        let sky_darkness = (voxel_ao >> 21) & 0b1111;
+        let sun_shadow = voxel_ao >> 25;
+
+        for (depth, plane) in depth_planes.into_iter() {
+            let quads_from_plane = greedy_mesh_binary_plane(plane, plane_size);
+
+            quads_from_plane.into_iter().for_each(|q| {
+                // Back out of the window's planes into the grid's
+                let q = GreedyQuad::new(
+                    q.x + col_x_offset as usize,
+                    q.y + col_z_offset as usize,
+                    q.w,
+                    q.h,
+                );
+
+                // Each corner takes its AO from the face in that corner of the quad
+                let corner_aos = match ao {
+                    Some(AoMerging::Corners) => {
+                        let (right, top) = (q.x + q.w - 1, q.y + q.h - 1);
+                        let corners = [(q.x, q.y), (right, q.y), (right, top), (q.x, top)];
+
+                        std::array::from_fn(|corner| {
+                            let (col_x, col_z) = corners[corner];
+                            let voxel_pos = plane_voxel_pos(face_dir, col_x, col_z, depth as usize);
+                            GreedyQuad::corner_aos(ao_mask(grid, voxel_pos, face_dir))[corner]
+                        })
+                    }
+                    _ => GreedyQuad::corner_aos(ao_index),
+                };
+
+                let first_vertex = vertices.len();
+                q.append_vertices(&mut vertices, face_dir, depth, &lod, corner_aos, voxel_type);
+
+                for vertex in &mut vertices[first_vertex..] {
+                    *vertex = vertex
+                        .with_sky_darkness(sky_darkness)
+                        .with_sun_shadow(sun_shadow);
+                }
+            })
+        }
+    }
+
+    vertices
+}
+
+// Position of a face in a binary plane, based on which axis the plane's columns run along
+fn plane_voxel_pos(face_dir: FaceDir, col_x: usize, col_z: usize, depth: usize) -> VoxelPos {
+    match face_dir {
+        FaceDir::Down | FaceDir::Up => (col_x, depth, col_z).into(),
+        FaceDir::Left | FaceDir::Right => (depth, col_z, col_x).into(),
+        FaceDir::Front | FaceDir::Back => (col_x, col_z, depth).into(),
+    }
+}
+
+// Which of the voxels around a face are solid, bit i set for ADJACENT_AO_DIRS[i]
+fn ao_mask(grid: &impl VoxelGrid, voxel_pos: VoxelPos, face_dir: FaceDir) -> u32 {
+    let mut ao_index = 0;
+    for (ao_i, ao_offset) in ADJACENT_AO_DIRS.iter().enumerate() {
+        // AO is sampled in the plane one voxel in front of the face
+        let ao_sample_offset = match face_dir {
+            FaceDir::Down => IVec3::new(ao_offset.x, -1, ao_offset.y),
+            FaceDir::Up => IVec3::new(ao_offset.x, 1, ao_offset.y),
+            FaceDir::Left => IVec3::new(-1, ao_offset.y, ao_offset.x),
+            FaceDir::Right => IVec3::new(1, ao_offset.y, ao_offset.x),
+            FaceDir::Front => IVec3::new(ao_offset.x, ao_offset.y, -1),
+            FaceDir::Back => IVec3::new(ao_offset.x, ao_offset.y, 1),
         };
 
-        for (voxel_ao, axis_plane) in voxel_ao_data.into_iter() {
-            let ao = voxel_ao & 0b111111111; // 9 1s
-            let voxel_type = (voxel_ao >> 9).into();
+        if grid
+            .voxel_at(voxel_pos.to_ivec3() + ao_sample_offset)
+            .is_solid()
+        {
+            ao_index |= 1 << ao_i;
+        }
+    }
+
+    ao_index
+}
 
-            for (axis_pos, plane) in axis_plane.into_iter() {
-                let quads_from_axis = greedy_mesh_binary_plane(plane, lod.size());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        constants::CHUNK_EXTENT,
+        positions::{ChunkPos, WorldPos},
+        vertex::Vertex,
+        voxel::VoxelType,
+    };
+
+    // Neighbourhood around the chunk at the origin, every chunk filled from the same world pattern
+    fn neighbourhood(voxel_at: impl Fn(WorldPos) -> VoxelType) -> ChunksFromMiddle {
+        ChunksFromMiddle::from_fn(|offset| Chunk::from_fn(offset, &voxel_at))
+    }
 
-                quads_from_axis.into_iter().for_each(|q| {
-                    q.append_vertices(&mut vertices, face_dir, axis_pos, &Lod::L32, ao, voxel_type);
-                })
+    // Mesh of the whole middle chunk, which the world builds section by section
+    fn build_chunk_mesh(
+        chunks_from_middle: &ChunksFromMiddle,
+        lod: Lod,
+        ao_enabled: bool,
+    ) -> Option<ChunkMesh> {
+        if chunks_from_middle.are_all_voxels_same() {
+            return None;
+        }
+
+        let sky_heights = SkyHeights::new(chunks_from_middle);
+        let ao = ao_enabled.then_some(AoMerging::Exact);
+
+        if lod.jump_index() == 1 {
+            return build_grid_mesh_at_lod(chunks_from_middle, lod, ao, Some(&sky_heights));
+        }
+
+        build_grid_mesh_at_lod(
+            &Downsampled::new(chunks_from_middle, lod),
+            lod,
+            ao,
+            Some(&sky_heights),
+        )
+    }
+
+    // Solid in about a quarter of the voxels, from a hash of the world position, so faces with every
+    // AO value lie along each border of the middle chunk
+    fn scattered(world_pos: WorldPos) -> VoxelType {
+        let hash = (world_pos.x.wrapping_mul(73_856_093)
+            ^ world_pos.y.wrapping_mul(19_349_663)
+            ^ world_pos.z.wrapping_mul(83_492_791)) as u32;
+
+        if hash.wrapping_mul(2_654_435_761) >> 30 == 0 {
+            VoxelType::BLOCK
+        } else {
+            VoxelType::AIR
+        }
+    }
+
+    // The voxels in the layer in front of a vertex's face which touch its corner, one of them is the
+    // air in front of the face itself and the other three decide the vertex's AO
+    fn front_voxels(vertex: &Vertex) -> Vec<IVec3> {
+        let normal = FACE_DIRS
+            .iter()
+            .find(|face_dir| face_dir.get_normal_index() == vertex.normal)
+            .unwrap()
+            .sample_dir();
+        let front = if normal.max_element() > 0 { 0 } else { -1 };
+        let corner = vertex.pos.to_ivec3();
+
+        let mut voxels = Vec::new();
+        for z in -1..=0 {
+            for y in -1..=0 {
+                for x in -1..=0 {
+                    let offset = IVec3::new(x, y, z);
+                    if offset.dot(normal.abs()) == front {
+                        voxels.push(corner + offset);
+                    }
+                }
             }
         }
+
+        voxels
     }
 
-    mesh.vertices.extend(vertices);
-    if mesh.vertices.is_empty() {
-        None
-    } else {
-        mesh.indices = generate_indices(mesh.vertices.len());
-        Some(mesh)
+    // Mesh the middle chunk, checking the AO of every vertex whose corner touches a neighbour
+    // against the voxels around it, returns how many were checked on each side of the chunk
+    fn check_border_ao(chunks_from_middle: &ChunksFromMiddle) -> [usize; 6] {
+        let mesh = build_chunk_mesh(chunks_from_middle, Lod::L32, true).unwrap();
+
+        let mut checked = [0; 6];
+        for &packed in &mesh.vertices {
+            let vertex = Vertex::from_packed(packed);
+            let voxels = front_voxels(&vertex);
+
+            // Sides are in the order of the normal indices, past that face of the chunk
+            let sides = [
+                voxels.iter().any(|voxel| voxel.x < 0),
+                voxels.iter().any(|voxel| voxel.x >= CHUNK_EXTENT.x),
+                voxels.iter().any(|voxel| voxel.z >= CHUNK_EXTENT.z),
+                voxels.iter().any(|voxel| voxel.z < 0),
+                voxels.iter().any(|voxel| voxel.y >= CHUNK_EXTENT.y),
+                voxels.iter().any(|voxel| voxel.y < 0),
+            ];
+            if !sides.contains(&true) {
+                continue;
+            }
+
+            let solid = voxels
+                .iter()
+                .filter(|&&voxel| chunks_from_middle.get_voxel(voxel).voxel_type.is_solid())
+                .count() as u32;
+            assert_eq!(vertex.ao, solid, "AO of {vertex:?} on the chunk border");
+
+            for (side, touches) in sides.into_iter().enumerate() {
+                checked[side] += touches as usize;
+            }
+        }
+
+        checked
+    }
+
+    #[test]
+    fn scattered_voxels_have_border_ao_from_their_neighbours() {
+        let checked = check_border_ao(&neighbourhood(scattered));
+
+        assert!(checked.iter().all(|&count| count > 0), "{checked:?}");
+    }
+
+    #[test]
+    fn floor_has_border_ao_from_the_neighbouring_walls() {
+        // A floor along the bottom of the middle chunk, walled in by solid neighbours
+        let chunks_from_middle = ChunksFromMiddle::from_fn(|offset| {
+            if offset == ChunkPos::splat(0) {
+                Chunk::from_fn(offset, |world_pos| match world_pos.y {
+                    0 => VoxelType::BLOCK,
+                    _ => VoxelType::AIR,
+                })
+            } else {
+                Chunk::from_fn(offset, |_| VoxelType::BLOCK)
+            }
+        });
+
+        // Only the floor's top faces are meshed, and they meet the walls on the four sides
+        let checked = check_border_ao(&chunks_from_middle);
+        assert!(checked[..4].iter().all(|&count| count > 0), "{checked:?}");
+    }
+
+    // Bounds of the middle chunk's mesh in voxels, as the mesh task computes them
+    fn mesh_extent(chunks_from_middle: &ChunksFromMiddle, lod: Lod) -> (Vec3, Vec3) {
+        let aabb = build_chunk_mesh(chunks_from_middle, lod, true)
+            .and_then(|mesh| mesh.compute_aabb(true))
+            .unwrap();
+
+        (aabb.min().into(), aabb.max().into())
+    }
+
+    #[test]
+    fn coarse_mesh_covers_the_same_extent_as_full_detail() {
+        // A box whose bounds are even, so it is the same at half resolution
+        let even_box = neighbourhood(|world_pos| {
+            let inside = (4..20).contains(&world_pos.x)
+                && (2..12).contains(&world_pos.y)
+                && (6..26).contains(&world_pos.z);
+
+            if inside {
+                VoxelType::BLOCK
+            } else {
+                VoxelType::AIR
+            }
+        });
+        assert_eq!(
+            mesh_extent(&even_box, Lod::L16),
+            mesh_extent(&even_box, Lod::L32)
+        );
+
+        // Ground across the whole middle chunk, so the coarse mesh reaches its far sides too
+        let ground = ChunksFromMiddle::from_fn(|offset| {
+            Chunk::from_fn(offset, |world_pos| {
+                if offset == ChunkPos::splat(0) && world_pos.y < CHUNK_EXTENT.y / 2 {
+                    VoxelType::BLOCK
+                } else {
+                    VoxelType::AIR
+                }
+            })
+        });
+        let extent = mesh_extent(&ground, Lod::L32);
+        assert_eq!(
+            extent,
+            (
+                Vec3::ZERO,
+                Vec3::new(
+                    CHUNK_EXTENT.x as f32,
+                    (CHUNK_EXTENT.y / 2) as f32,
+                    CHUNK_EXTENT.z as f32
+                )
+            )
+        );
+        assert_eq!(mesh_extent(&ground, Lod::L16), extent);
     }
 }
@@ -0,0 +1,243 @@
+use std::{fs, path::PathBuf};
+
+use bevy::{app::AppExit, prelude::*};
+use clap::Parser;
+
+use crate::{
+    chunk_loading::ChunkLoader,
+    constants::SAVE_DIRECTORY,
+    loading_progress::LoadingProgress,
+    persistence,
+    pipeline_metrics::PipelineMetrics,
+    screen_effects::AntiAliasing,
+    world::{MeshingMode, World},
+    world_generator,
+};
+
+// Options given on the command line, parsed before the app is built because the seed and the save
+// directory have to be set before anything reads them
+pub struct LaunchOptionsPlugin;
+
+impl Plugin for LaunchOptionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LaunchOptions>()
+            // The loaders are spawned in Startup
+            .add_systems(PostStartup, LaunchOptions::apply)
+            .add_systems(
+                Update,
+                LaunchOptions::finish_benchmark.run_if(LaunchOptions::is_benchmarking),
+            );
+    }
+}
+
+// Flags which aren't known are rejected, `--help` lists them all
+#[derive(Parser, Resource, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LaunchOptions {
+    #[arg(long, help = "Generate the world from another seed, saved separately")]
+    pub seed: Option<u64>,
+    #[arg(long, value_name = "CHUNKS", help = "Load distance of the camera")]
+    pub render_distance: Option<u32>,
+    #[arg(
+        long = "mesher",
+        value_name = "blocky|smooth",
+        value_parser = parse_meshing_mode,
+        help = "How chunks are meshed"
+    )]
+    pub meshing_mode: Option<MeshingMode>,
+    #[arg(
+        long,
+        value_name = "off|fxaa|msaa|taa",
+        value_parser = parse_anti_aliasing,
+        help = "How the camera's edges are smoothed"
+    )]
+    pub anti_aliasing: Option<AntiAliasing>,
+    #[arg(long, help = "Run without a window or renderer")]
+    pub headless: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the pipeline metrics to the path once the first load finishes, then exit"
+    )]
+    pub benchmark: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "DIRECTORY",
+        help = "Load and save the world in another directory"
+    )]
+    pub load_world: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "RADIUS",
+        help = "Generate and mesh every chunk within the radius before starting"
+    )]
+    pub pregenerate: Option<u32>,
+    #[arg(
+        long,
+        value_name = "RADIUS",
+        conflicts_with = "pregenerate",
+        help = "Generate every chunk within the radius before starting, without meshing them"
+    )]
+    pub pregenerate_data: Option<u32>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Record the camera, the loaders and the edits to the file, written on exit"
+    )]
+    pub record: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "record",
+        help = "Play back a recording made with --record"
+    )]
+    pub replay: Option<PathBuf>,
+}
+
+impl LaunchOptions {
+    // Set the seed and the save directory, must be called before the app is built
+    pub fn apply_globals(&self) {
+        if let Some(seed) = self.seed {
+            world_generator::set_seed(seed);
+        }
+
+        // Chunks saved with another seed wouldn't line up with newly generated ones
+        let save_directory = match (&self.load_world, self.seed) {
+            (Some(load_world), _) => Some(load_world.clone()),
+            (None, Some(seed)) => Some(PathBuf::from(format!("{SAVE_DIRECTORY}_{seed}"))),
+            (None, None) => None,
+        };
+        if let Some(save_directory) = save_directory {
+            persistence::set_save_directory(save_directory);
+        }
+    }
+
+    pub fn is_benchmarking(options: Res<LaunchOptions>) -> bool {
+        options.benchmark.is_some()
+    }
+
+    fn apply(
+        options: Res<LaunchOptions>,
+        mut world: ResMut<World>,
+        mut anti_aliasing: ResMut<AntiAliasing>,
+        mut loaders: Query<&mut ChunkLoader>,
+    ) {
+        if let Some(meshing_mode) = options.meshing_mode {
+            world.set_meshing_mode(meshing_mode);
+        }
+
+        if let Some(option) = options.anti_aliasing {
+            *anti_aliasing = option;
+        }
+
+        if let Some(render_distance) = options.render_distance {
+            for mut loader in loaders.iter_mut() {
+                loader.set_load_distance(render_distance, &mut world);
+            }
+        }
+    }
+
+    // Once the first load around the loaders has finished, write out how long each chunk took and exit
+    fn finish_benchmark(
+        options: Res<LaunchOptions>,
+        progress: Res<LoadingProgress>,
+        metrics: Res<PipelineMetrics>,
+        time: Res<Time<Real>>,
+        mut exit_events: EventWriter<AppExit>,
+        mut started: Local<bool>,
+    ) {
+        if progress.load_size > 0 {
+            *started = true;
+        }
+        if !*started || progress.is_loading() {
+            return;
+        }
+
+        let Some(path) = options.benchmark.as_ref() else {
+            return;
+        };
+        info!(
+            "Benchmark loaded the world in {:.2}s",
+            time.elapsed_seconds()
+        );
+
+        let result = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, metrics.to_csv()));
+        match result {
+            Ok(()) => {
+                info!("Wrote the pipeline metrics to {}", path.display());
+                exit_events.send(AppExit::Success);
+            }
+            Err(err) => {
+                error!("Failed to write the pipeline metrics: {err}");
+                exit_events.send(AppExit::error());
+            }
+        }
+    }
+}
+
+fn parse_meshing_mode(value: &str) -> Result<MeshingMode, String> {
+    match value {
+        "blocky" => Ok(MeshingMode::Blocky),
+        "smooth" => Ok(MeshingMode::Smooth),
+        _ => Err("expected blocky or smooth".to_string()),
+    }
+}
+
+fn parse_anti_aliasing(value: &str) -> Result<AntiAliasing, String> {
+    match value {
+        "off" => Ok(AntiAliasing::Off),
+        "fxaa" => Ok(AntiAliasing::Fxaa),
+        "msaa" => Ok(AntiAliasing::Msaa4),
+        "taa" => Ok(AntiAliasing::Taa),
+        _ => Err("expected off, fxaa, msaa or taa".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_are_parsed() {
+        let options = LaunchOptions::try_parse_from([
+            "cube_world",
+            "--seed",
+            "7",
+            "--mesher",
+            "smooth",
+            "--pregenerate-data",
+            "4",
+            "--replay",
+            "run.ron",
+        ])
+        .unwrap();
+
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.meshing_mode, Some(MeshingMode::Smooth));
+        assert_eq!(options.pregenerate_data, Some(4));
+        assert_eq!(options.replay, Some(PathBuf::from("run.ron")));
+    }
+
+    #[test]
+    fn bad_flags_are_rejected() {
+        for args in [
+            &["cube_world", "--sead", "7"][..],
+            &["cube_world", "--seed", "seven"],
+            &["cube_world", "--seed"],
+            &["cube_world", "--mesher", "marching"],
+            &["cube_world", "--record", "a.ron", "--replay", "b.ron"],
+            &[
+                "cube_world",
+                "--pregenerate",
+                "4",
+                "--pregenerate-data",
+                "4",
+            ],
+        ] {
+            assert!(LaunchOptions::try_parse_from(args).is_err(), "{args:?}");
+        }
+    }
+}
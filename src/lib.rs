@@ -0,0 +1,72 @@
+#[cfg(feature = "anvil")]
+pub mod anvil;
+pub mod background_throttle;
+pub mod biome;
+pub mod block_breaking;
+pub mod block_registry;
+pub mod brushes;
+pub(crate) mod byte_codec;
+pub mod cave_culling;
+pub mod chunk;
+pub mod chunk_cache;
+pub mod chunk_digest;
+pub(crate) mod chunk_from_middle;
+pub mod chunk_inspection;
+pub mod chunk_loading;
+pub mod chunk_mesh;
+pub mod chunk_meta;
+pub mod chunk_queue;
+pub mod chunk_saver;
+pub mod constants;
+pub mod crash_dump;
+pub(crate) mod culled_mesher;
+pub mod debug_markers;
+pub mod decals;
+pub mod editor_panel;
+pub mod explosion;
+pub mod generation_preview;
+pub mod generation_stages;
+pub(crate) mod greedy_mesher;
+pub mod launch_options;
+pub mod loading_progress;
+pub mod lod;
+pub mod lod_refinement;
+pub mod mesh_override;
+pub mod mesh_pool;
+pub mod mesh_quality;
+pub mod meshing;
+pub mod occlusion_culling;
+pub mod particles;
+pub mod pathfinding;
+pub mod persistence;
+pub mod pipeline_metrics;
+pub mod pipeline_stepping;
+pub mod positions;
+pub mod pregeneration;
+pub mod prelude;
+pub mod region_file;
+pub mod rendering;
+pub mod replay;
+pub mod rivers;
+pub mod schematic;
+pub mod screen_effects;
+pub mod spatial_queries;
+pub mod spawning;
+pub mod spectator;
+#[cfg(feature = "profiling")]
+pub mod stress_generators;
+pub(crate) mod surface_nets;
+pub mod task_pools;
+pub mod task_scheduler;
+pub mod thumbnails;
+pub(crate) mod vertex;
+pub mod voxel;
+pub mod voxel_grid;
+pub mod voxel_object;
+pub mod voxel_picking;
+pub mod world;
+pub mod world_border;
+pub mod world_edit;
+pub mod world_generator;
+pub mod world_reader;
+pub mod world_snapshot;
@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+
+use crate::{
+    chunk_loading::ChunkLoader,
+    constants::{LOADING_INDICATOR_HEIGHT, LOADING_INDICATOR_WIDTH},
+    world::World,
+};
+
+// Tracks how much of the world is still streaming in, and shows it as a thin bar at the bottom of
+// the screen while chunks are loading, so holes while flying quickly don't look like bugs
+// Custom UIs can read LoadingProgress and despawn the LoadingIndicator
+pub struct LoadingProgressPlugin;
+
+impl Plugin for LoadingProgressPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LoadingProgress>()
+            .register_type::<LoadingProgress>()
+            .add_systems(Startup, LoadingIndicator::spawn)
+            .add_systems(
+                Update,
+                (LoadingProgress::update, LoadingIndicator::update).chain(),
+            );
+    }
+}
+
+#[derive(Resource, Reflect, Default, Debug, Copy, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct LoadingProgress {
+    // Chunks waiting for their data, from the loaders' queues to the running tasks
+    pub outstanding_data: usize,
+    // Chunks waiting for their meshes, including finished meshes which haven't been spawned yet
+    pub outstanding_meshes: usize,
+    // Most work outstanding at once since loading started, progress is measured against it
+    pub load_size: usize,
+    // From 0 to 1, 1 when nothing is loading
+    pub fraction: f32,
+}
+
+impl LoadingProgress {
+    pub fn outstanding(&self) -> usize {
+        self.outstanding_data + self.outstanding_meshes
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.outstanding() > 0
+    }
+
+    fn update(
+        mut progress: ResMut<LoadingProgress>,
+        world: Res<World>,
+        loaders: Query<&ChunkLoader>,
+    ) {
+        let (loader_data, loader_meshes) = loaders.iter().fold((0, 0), |(data, meshes), loader| {
+            (
+                data + loader.data_load_queue.len(),
+                meshes + loader.mesh_load_queue.len(),
+            )
+        });
+
+        let outstanding_data = loader_data
+            + world.load_data_queue.len()
+            + world.data_tasks.len()
+            + world.generation_stages.generate_tasks.len()
+            + world.generation_stages.decorate_queue.len();
+        let outstanding_meshes = loader_meshes
+            + world.load_mesh_queue.len()
+            + world.mesh_tasks.len()
+            + world.parked_meshes.iter().map(Vec::len).sum::<usize>();
+
+        let outstanding = outstanding_data + outstanding_meshes;
+        // A new load starts from nothing, and grows when the loader moves on before it finishes
+        let load_size = if outstanding == 0 {
+            0
+        } else {
+            progress.load_size.max(outstanding)
+        };
+
+        let new_progress = LoadingProgress {
+            outstanding_data,
+            outstanding_meshes,
+            load_size,
+            fraction: if load_size == 0 {
+                1.
+            } else {
+                1. - outstanding as f32 / load_size as f32
+            },
+        };
+
+        // Avoid triggering change detection while nothing is loading
+        progress.set_if_neq(new_progress);
+    }
+}
+
+// The bar showing the loading progress, hidden while nothing is loading
+#[derive(Component, Debug)]
+pub struct LoadingIndicator;
+
+// The part of the bar which fills up
+#[derive(Component, Debug)]
+pub struct LoadingIndicatorFill;
+
+impl LoadingIndicator {
+    fn spawn(mut commands: Commands) {
+        commands
+            .spawn((
+                LoadingIndicator,
+                Name::new("Loading indicator"),
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        bottom: Val::Px(12.),
+                        left: Val::Percent(50.),
+                        margin: UiRect::left(Val::Px(-LOADING_INDICATOR_WIDTH / 2.)),
+                        width: Val::Px(LOADING_INDICATOR_WIDTH),
+                        height: Val::Px(LOADING_INDICATOR_HEIGHT),
+                        ..default()
+                    },
+                    background_color: Color::srgba(0., 0., 0., 0.3).into(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    LoadingIndicatorFill,
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(0.),
+                            height: Val::Percent(100.),
+                            ..default()
+                        },
+                        background_color: Color::srgba(1., 1., 1., 0.6).into(),
+                        ..default()
+                    },
+                ));
+            });
+    }
+
+    fn update(
+        progress: Res<LoadingProgress>,
+        mut indicators: Query<&mut Visibility, With<LoadingIndicator>>,
+        mut fills: Query<&mut Style, With<LoadingIndicatorFill>>,
+    ) {
+        if !progress.is_changed() {
+            return;
+        }
+
+        for mut visibility in indicators.iter_mut() {
+            visibility.set_if_neq(if progress.is_loading() {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            });
+        }
+
+        for mut style in fills.iter_mut() {
+            style.width = Val::Percent(progress.fraction * 100.);
+        }
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, Copy, Clone)]
+use bevy::reflect::Reflect;
+
+#[derive(Reflect, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Lod {
     L32,
     L16,
@@ -19,6 +21,17 @@ impl Lod {
         }
     }
 
+    // The next more detailed LOD, None at full detail
+    pub fn finer(&self) -> Option<Self> {
+        match self {
+            Lod::L32 => None,
+            Lod::L16 => Some(Lod::L32),
+            Lod::L8 => Some(Lod::L16),
+            Lod::L4 => Some(Lod::L8),
+            Lod::L2 => Some(Lod::L4),
+        }
+    }
+
     // How much to multiply to reach next voxel
     pub fn jump_index(&self) -> usize {
         match self {
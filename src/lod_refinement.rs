@@ -0,0 +1,141 @@
+use bevy::prelude::*;
+
+use crate::{
+    background_throttle::MeshingPace,
+    chunk_loading::ChunkLoader,
+    constants::{LOD_DOWNGRADES_PER_FRAME, LOD_UPGRADES_PER_FRAME},
+    positions::{ChunkPos, VoxelScale},
+    task_scheduler::TaskScheduler,
+    world::World,
+};
+
+// Upgrades the meshes of far chunks a step at a time while the pipeline has nothing else to do, nearest
+// first, so a player standing still gradually sees everything at full detail
+// Once chunks start loading again the upgrades are given back, furthest first, to free the memory
+pub struct LodRefinementPlugin;
+
+impl Plugin for LodRefinementPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LodRefinement>()
+            .register_type::<LodRefinement>()
+            .add_systems(
+                Update,
+                LodRefinement::update
+                    .after(World::update_mesh_qualities)
+                    .run_if(MeshingPace::is_running),
+            );
+    }
+}
+
+#[derive(Resource, Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct LodRefinement {
+    pub enabled: bool,
+    pub upgrades_per_frame: usize,
+    pub downgrades_per_frame: usize,
+}
+
+impl Default for LodRefinement {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            upgrades_per_frame: LOD_UPGRADES_PER_FRAME,
+            downgrades_per_frame: LOD_DOWNGRADES_PER_FRAME,
+        }
+    }
+}
+
+impl LodRefinement {
+    fn update(
+        refinement: Res<LodRefinement>,
+        mut world: ResMut<World>,
+        loaders: Query<(&GlobalTransform, &ChunkLoader)>,
+        voxel_scale: Res<VoxelScale>,
+        scheduler: Res<TaskScheduler>,
+        pace: Res<MeshingPace>,
+    ) {
+        // The loader may be moving between entities, see Spectator
+        let Ok((loader_transform, loader)) = loaders.get_single() else {
+            return;
+        };
+        let loader_pos = voxel_scale.loader_chunk_pos(loader_transform.translation());
+
+        if !refinement.enabled {
+            Self::downgrade(&mut world, loader, loader_pos, usize::MAX);
+            return;
+        }
+
+        let loading = !world.load_data_queue.is_empty()
+            || !world.data_tasks.is_empty()
+            || !world.generation_stages.is_empty()
+            || !world.generation_stages.decorate_queue.is_empty();
+        if loading {
+            Self::downgrade(
+                &mut world,
+                loader,
+                loader_pos,
+                refinement.downgrades_per_frame,
+            );
+            return;
+        }
+
+        // Only use the mesh task slots which nothing else wants
+        let parked_meshes = world.parked_meshes.iter().map(Vec::len).sum::<usize>();
+        let meshing = world.mesh_tasks.len() + parked_meshes + world.load_mesh_queue.len();
+        let spare = scheduler
+            .budget
+            .min(pace.max_mesh_tasks)
+            .saturating_sub(meshing)
+            .min(refinement.upgrades_per_frame);
+        if spare == 0 || !world.remesh_batches.is_empty() {
+            return;
+        }
+
+        let mut upgrades = world
+            .chunk_entities
+            .keys()
+            // Overridden chunks keep the quality they were pinned to
+            .filter(|&&chunk_pos| !world.is_quality_overridden(chunk_pos))
+            .filter_map(|&chunk_pos| {
+                let quality = world.mesh_quality(chunk_pos);
+                // Chunks meshed at a quality other than the policy's are still catching up with it
+                let expected = world.expected_quality(chunk_pos, &loader.mesh_quality, loader_pos);
+
+                (quality == expected)
+                    .then(|| quality.refined())
+                    .flatten()
+                    .map(|refined| (chunk_pos, refined))
+            })
+            .collect::<Vec<_>>();
+        upgrades.sort_by_key(|(chunk_pos, _)| chunk_pos.distance_squared(loader_pos));
+
+        for (chunk_pos, refined) in upgrades.into_iter().take(spare) {
+            world.refined_qualities.insert(chunk_pos, refined);
+            if !world.queue_remesh(chunk_pos) {
+                world.refined_qualities.remove(&chunk_pos);
+            }
+        }
+    }
+
+    // Give back the upgrades of the chunks furthest from the loader, they are remeshed at the policy's quality
+    fn downgrade(world: &mut World, loader: &ChunkLoader, loader_pos: ChunkPos, count: usize) {
+        if world.refined_qualities.is_empty() {
+            return;
+        }
+
+        // Upgrades which the loader has come close enough to get anyway are dropped without a remesh
+        let policy = loader.mesh_quality;
+        world.refined_qualities.retain(|&chunk_pos, &mut refined| {
+            let quality = policy.quality(chunk_pos, loader_pos);
+            quality.finest(refined) != quality
+        });
+
+        let mut refined = world.refined_qualities.keys().copied().collect::<Vec<_>>();
+        refined.sort_by_key(|chunk_pos| std::cmp::Reverse(chunk_pos.distance_squared(loader_pos)));
+
+        for chunk_pos in refined.into_iter().take(count) {
+            world.refined_qualities.remove(&chunk_pos);
+            world.queue_remesh(chunk_pos);
+        }
+    }
+}
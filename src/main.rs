@@ -1,46 +1,88 @@
-use std::f32::consts::PI;
+use std::time::Duration;
 
 use bevy::{
+    app::ScheduleRunnerPlugin,
     core::TaskPoolThreadAssignmentPolicy,
     prelude::*,
     render::{
         settings::{RenderCreation, WgpuFeatures, WgpuSettings},
         RenderPlugin,
     },
+    window::ExitCondition,
+    winit::WinitPlugin,
 };
 use bevy_flycam::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_screen_diagnostics::{
-    ScreenDiagnosticsPlugin, ScreenEntityDiagnosticsPlugin, ScreenFrameDiagnosticsPlugin,
+    Aggregate, ScreenDiagnostics, ScreenDiagnosticsPlugin, ScreenEntityDiagnosticsPlugin,
+    ScreenFrameDiagnosticsPlugin,
 };
 
-use chunk_loading::{ChunkLoader, ChunkLoaderPlugin};
-use constants::{CHUNK_LOAD_DISTANCE, FLYCAM_SENSITIVITY, FLYCAM_SPEED, MAX_THREADS, MIN_THREADS};
-use rendering::{ChunkMaterial, GlobalChunkMaterial, RenderingPlugin};
-use world::WorldPlugin;
-
-pub mod chunk;
-pub mod chunk_from_middle;
-pub mod chunk_loading;
-pub mod chunk_mesh;
-pub mod constants;
-pub mod culled_mesher;
-pub mod greedy_mesher;
-pub mod lod;
-pub mod positions;
-pub mod rendering;
-pub mod vertex;
-pub mod voxel;
-pub mod world;
+use clap::Parser;
+use cube_world::{
+    background_throttle::BackgroundThrottlePlugin,
+    block_breaking::BlockBreakingPlugin,
+    block_registry::BlockRegistryPlugin,
+    cave_culling::{self, CaveCullingPlugin},
+    chunk_cache,
+    chunk_digest::ChunkDigestPlugin,
+    chunk_inspection::ChunkInspectionPlugin,
+    chunk_loading::{ChunkLoader, ChunkLoaderPlugin},
+    constants::{
+        BLOCK_TEXTURE_SCALE, CHUNK_LOAD_DISTANCE, DETAIL_FADE_AO, DETAIL_FADE_END,
+        DETAIL_FADE_START, FLYCAM_SENSITIVITY, FLYCAM_SPEED, GENERATOR_PRESET, MAX_THREADS,
+        MIN_THREADS, SUN_DIRECTION,
+    },
+    crash_dump::CrashDumpPlugin,
+    debug_markers::DebugMarkersPlugin,
+    decals::DecalsPlugin,
+    editor_panel::EditorPanelPlugin,
+    explosion::ExplosionPlugin,
+    generation_preview::GenerationPreviewPlugin,
+    generation_stages::GenerationStagesPlugin,
+    launch_options::{LaunchOptions, LaunchOptionsPlugin},
+    loading_progress::LoadingProgressPlugin,
+    lod_refinement::LodRefinementPlugin,
+    mesh_override::MeshOverridePlugin,
+    occlusion_culling::{self, OcclusionCullingPlugin},
+    particles::ParticlesPlugin,
+    pathfinding::PathfindingPlugin,
+    persistence::PersistencePlugin,
+    pipeline_metrics::{self, PipelineMetricsPlugin},
+    pipeline_stepping::PipelineSteppingPlugin,
+    pregeneration::PregenerationPlugin,
+    rendering::{
+        ChunkMaterial, ChunkTexturing, FarChunkMaterial, FarFaces, GlobalChunkMaterial,
+        GlobalFarChunkMaterial, RenderingPlugin,
+    },
+    replay::ReplayPlugin,
+    screen_effects::{voxel_ssao_bundle, ScreenEffectsPlugin, VoxelOutline},
+    spawning::SpawningPlugin,
+    spectator::SpectatorPlugin,
+    task_pools::{self, ChunkTaskPoolsPlugin},
+    task_scheduler::TaskSchedulerPlugin,
+    thumbnails::ThumbnailsPlugin,
+    voxel_object::VoxelObjectPlugin,
+    voxel_picking::VoxelPickingPlugin,
+    world::WorldPlugin,
+    world_border::WorldBorderPlugin,
+    world_generator::WorldGen,
+    world_reader::WorldReaderPlugin,
+};
 
-fn setup(mut commands: Commands, mut chunk_materials: ResMut<Assets<ChunkMaterial>>) {
+fn setup(
+    mut commands: Commands,
+    mut chunk_materials: ResMut<Assets<ChunkMaterial>>,
+    mut far_chunk_materials: ResMut<Assets<FarChunkMaterial>>,
+) {
     // light
     commands.spawn(DirectionalLightBundle {
         directional_light: DirectionalLight {
-            shadows_enabled: false,
+            shadows_enabled: true,
             ..default()
         },
-        transform: Transform::from_rotation(Quat::from_euler(EulerRot::XYZ, -PI / 2., 0., 0.)),
+        // Terrain shadows are baked for the sun's direction
+        transform: Transform::default().looking_to(-SUN_DIRECTION, Vec3::Y),
         ..default()
     });
     // camera
@@ -51,20 +93,91 @@ fn setup(mut commands: Commands, mut chunk_materials: ResMut<Assets<ChunkMateria
             ..default()
         },
         FlyCam,
+        voxel_ssao_bundle(),
+        VoxelOutline::default(),
     ));
 
     // Chunk shader material
-    commands.insert_resource(GlobalChunkMaterial(chunk_materials.add(ChunkMaterial {
+    let chunk_material = ChunkMaterial {
         reflectance: 0.5,
         perceptual_roughness: 0.5,
         metallic: 0.5,
-    })))
+        ao_strength: 1.0,
+        ao_enabled: 1,
+        face_shading_enabled: 1,
+        skylight_enabled: 1,
+        skylight_min: 0.15,
+        sun_shadow_strength: 0.5,
+        biome_tint_low: LinearRgba::rgb(0.55, 0.75, 0.35),
+        biome_tint_high: LinearRgba::rgb(1.0, 0.85, 0.55),
+        detail_fade_enabled: 1,
+        detail_fade_start: DETAIL_FADE_START,
+        detail_fade_end: DETAIL_FADE_END,
+        detail_fade_ao: DETAIL_FADE_AO,
+        block_layers: ChunkMaterial::registry_block_layers(BLOCK_TEXTURE_SCALE),
+        block_colours: vec![Vec4::ZERO],
+        block_textures: None,
+        texturing: ChunkTexturing::Colours,
+    };
+
+    // Far chunks share the material's settings
+    commands.insert_resource(GlobalFarChunkMaterial(far_chunk_materials.add(
+        FarChunkMaterial {
+            base: chunk_material.clone(),
+            extension: FarFaces::default(),
+        },
+    )));
+    commands.insert_resource(GlobalChunkMaterial(chunk_materials.add(chunk_material)));
 }
 
+// Run with `--help` for the command line flags
 fn main() {
-    App::new()
-        .add_plugins(
-            DefaultPlugins
+    // Exits with the usage on unknown or malformed flags
+    let options = LaunchOptions::parse();
+    options.apply_globals();
+    let headless = options.headless;
+
+    let default_plugins = DefaultPlugins
+        .set(AssetPlugin {
+            // Hot-reload assets such as the chunk shader
+            watch_for_changes_override: Some(true),
+            ..default()
+        })
+        .set(TaskPoolPlugin {
+            task_pool_options: TaskPoolOptions {
+                async_compute: TaskPoolThreadAssignmentPolicy {
+                    min_threads: MIN_THREADS,
+                    max_threads: MAX_THREADS,
+                    percent: 0.75,
+                },
+                ..default()
+            },
+        });
+
+    let mut app = App::new();
+    if headless {
+        // Without a window nothing is drawn, so the renderer gets no GPU backend and the app loops
+        // as fast as it can
+        app.add_plugins((
+            default_plugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    ..default()
+                })
+                .set(RenderPlugin {
+                    render_creation: RenderCreation::Automatic(WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }),
+                    ..default()
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+        ));
+    } else {
+        app.add_plugins(
+            default_plugins
                 .set(WindowPlugin {
                     primary_window: Some(Window {
                         title: String::from("Ooga Booga Cube"),
@@ -79,35 +192,117 @@ fn main() {
                         ..default()
                     }),
                     ..default()
-                })
-                .set(TaskPoolPlugin {
-                    task_pool_options: TaskPoolOptions {
-                        async_compute: TaskPoolThreadAssignmentPolicy {
-                            min_threads: MIN_THREADS,
-                            max_threads: MAX_THREADS,
-                            percent: 0.75,
-                        },
-                        ..default()
-                    },
                 }),
-        )
-        .add_plugins((ChunkLoaderPlugin, WorldPlugin, RenderingPlugin))
-        .add_plugins(NoCameraPlayerPlugin)
-        // .add_plugins(WorldInspectorPlugin::new())
-        // .add_plugins(AssetInspectorPlugin::<Mesh>::default())
+        );
+    }
+
+    app.insert_resource(options)
+        .add_plugins(LaunchOptionsPlugin)
+        .insert_resource(WorldGen::from_preset(GENERATOR_PRESET))
+        // Block definitions are needed by everything which reads voxels
+        .add_plugins(BlockRegistryPlugin)
         .add_plugins((
-            ScreenDiagnosticsPlugin::default(),
-            ScreenFrameDiagnosticsPlugin,
-            ScreenEntityDiagnosticsPlugin,
+            ChunkLoaderPlugin,
+            ChunkTaskPoolsPlugin::default(),
+            WorldPlugin,
+            BackgroundThrottlePlugin,
+            WorldBorderPlugin,
+            RenderingPlugin,
+            PathfindingPlugin,
+            PersistencePlugin,
+            ExplosionPlugin,
+            ScreenEffectsPlugin,
+            OcclusionCullingPlugin,
+            CaveCullingPlugin,
+            VoxelObjectPlugin,
+            VoxelPickingPlugin,
         ))
-        .insert_resource(MovementSettings {
-            sensitivity: FLYCAM_SENSITIVITY,
-            speed: FLYCAM_SPEED,
-        })
-        .insert_resource(KeyBindings {
-            move_descend: KeyCode::ControlLeft,
-            ..Default::default()
-        })
-        .add_systems(Startup, setup)
-        .run();
+        .add_plugins((
+            SpawningPlugin,
+            PipelineSteppingPlugin,
+            TaskSchedulerPlugin,
+            GenerationStagesPlugin,
+            SpectatorPlugin,
+            CrashDumpPlugin,
+            LoadingProgressPlugin,
+            EditorPanelPlugin,
+            ChunkDigestPlugin,
+            ReplayPlugin,
+            PipelineMetricsPlugin,
+            DebugMarkersPlugin,
+            PregenerationPlugin,
+            WorldReaderPlugin,
+            BlockBreakingPlugin,
+        ))
+        .add_plugins((
+            DecalsPlugin,
+            LodRefinementPlugin,
+            GenerationPreviewPlugin,
+            ThumbnailsPlugin,
+            ParticlesPlugin,
+            MeshOverridePlugin,
+            ChunkInspectionPlugin,
+        ))
+        .add_systems(Startup, setup);
+
+    // The flycam, inspector and diagnostics overlay need a window
+    if !headless {
+        app.add_plugins(NoCameraPlayerPlugin)
+            .add_plugins(WorldInspectorPlugin::new())
+            // .add_plugins(AssetInspectorPlugin::<Mesh>::default())
+            .add_plugins((
+                ScreenDiagnosticsPlugin::default(),
+                ScreenFrameDiagnosticsPlugin,
+                ScreenEntityDiagnosticsPlugin,
+            ))
+            .insert_resource(MovementSettings {
+                sensitivity: FLYCAM_SENSITIVITY,
+                speed: FLYCAM_SPEED,
+            })
+            .insert_resource(KeyBindings {
+                move_descend: KeyCode::ControlLeft,
+                ..Default::default()
+            })
+            .add_systems(Startup, add_chunk_diagnostics);
+    }
+
+    app.run();
+}
+
+// Show the chunk task pool saturation, chunk cache hits and culled chunks on the diagnostics overlay
+fn add_chunk_diagnostics(mut screen_diagnostics: ResMut<ScreenDiagnostics>) {
+    for (name, path) in [
+        ("gen pool", task_pools::GENERATION_SATURATION),
+        ("mesh pool", task_pools::MESHING_SATURATION),
+    ] {
+        screen_diagnostics
+            .add(name.to_string(), path)
+            .aggregate(Aggregate::Value)
+            .format(|value| format!("{value:.0}%"));
+    }
+
+    for (name, path) in [
+        ("gen p50", pipeline_metrics::GENERATION_P50),
+        ("gen p99", pipeline_metrics::GENERATION_P99),
+        ("mesh p50", pipeline_metrics::MESHING_P50),
+        ("mesh p99", pipeline_metrics::MESHING_P99),
+    ] {
+        screen_diagnostics
+            .add(name.to_string(), path)
+            .aggregate(Aggregate::Value)
+            .format(|value| format!("{value:.0}µs"));
+    }
+
+    for (name, path) in [
+        ("mesh verts", pipeline_metrics::MESH_VERTICES),
+        ("cache hits", chunk_cache::CHUNK_CACHE_HITS),
+        ("cache misses", chunk_cache::CHUNK_CACHE_MISSES),
+        ("occluded", occlusion_culling::OCCLUDED_CHUNKS),
+        ("cave culled", cave_culling::CAVE_CULLED_CHUNKS),
+    ] {
+        screen_diagnostics
+            .add(name.to_string(), path)
+            .aggregate(Aggregate::Value)
+            .format(|value| format!("{value:.0}"));
+    }
 }
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    mesh_quality::MeshQuality,
+    positions::ChunkPos,
+    world::{MeshingMode, World},
+};
+
+// Lets tools pin a chunk to a mesh quality or mesher, whatever the loader's distance or the world's
+// meshing mode, e.g. full detail around a build site or culled meshes to debug greedy artifacts
+// Spawn an entity with a ChunkMeshOverride, the chunk is remeshed when it is added, changed or removed
+pub struct MeshOverridePlugin;
+
+impl Plugin for MeshOverridePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ChunkMeshOverride>().add_systems(
+            Update,
+            ChunkMeshOverride::sync.before(World::update_mesh_qualities),
+        );
+    }
+}
+
+#[derive(Component, Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct ChunkMeshOverride {
+    pub chunk_pos: ChunkPos,
+    // None follows the loader's mesh quality policy
+    pub quality: Option<MeshQuality>,
+    // None follows the world's meshing mode
+    pub meshing_mode: Option<MeshingMode>,
+}
+
+impl ChunkMeshOverride {
+    pub fn new(chunk_pos: ChunkPos) -> Self {
+        Self {
+            chunk_pos,
+            quality: None,
+            meshing_mode: None,
+        }
+    }
+
+    pub fn with_quality(mut self, quality: MeshQuality) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn with_meshing_mode(mut self, meshing_mode: MeshingMode) -> Self {
+        self.meshing_mode = Some(meshing_mode);
+        self
+    }
+
+    // Several overrides of a chunk are merged, the finest quality wins
+    fn merge(self, other: Self) -> Self {
+        Self {
+            chunk_pos: self.chunk_pos,
+            quality: match (self.quality, other.quality) {
+                (Some(quality), Some(other)) => Some(quality.finest(other)),
+                (quality, other) => quality.or(other),
+            },
+            meshing_mode: self.meshing_mode.or(other.meshing_mode),
+        }
+    }
+
+    fn sync(
+        mut world: ResMut<World>,
+        overrides: Query<&ChunkMeshOverride>,
+        mut removed: RemovedComponents<ChunkMeshOverride>,
+        changed: Query<(), Changed<ChunkMeshOverride>>,
+    ) {
+        // Removals don't say which chunk the override was on, so any change rebuilds the whole map
+        if removed.read().count() == 0 && changed.is_empty() {
+            return;
+        }
+
+        let mut merged = HashMap::<ChunkPos, ChunkMeshOverride>::new();
+        for &mesh_override in overrides.iter() {
+            merged
+                .entry(mesh_override.chunk_pos)
+                .and_modify(|merged| *merged = merged.merge(mesh_override))
+                .or_insert(mesh_override);
+        }
+
+        world.set_mesh_overrides(merged);
+    }
+}
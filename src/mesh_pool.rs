@@ -0,0 +1,133 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    chunk_mesh::ChunkMesh,
+    constants::{MESH_POOL_MAX_BYTES, MESH_POOL_MAX_MESH_BYTES},
+};
+
+// Meshes whose buffers have been cleared, taken by the meshers instead of allocating new buffers
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    meshes: Vec::new(),
+    bytes: 0,
+});
+
+// Bytes held by finished meshes which haven't been recycled or dropped yet, and the most ever held
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct Pool {
+    meshes: Vec<ChunkMesh>,
+    bytes: usize,
+}
+
+// Counts a mesh's buffers towards the live bytes until the mesh is dropped or recycled
+// Clones aren't counted, only the meshes the mesh tasks return are
+#[derive(Default, Debug)]
+pub struct MeshMemory(usize);
+
+impl Clone for MeshMemory {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Drop for MeshMemory {
+    fn drop(&mut self) {
+        LIVE_BYTES.fetch_sub(self.0, Ordering::Relaxed);
+    }
+}
+
+impl ChunkMesh {
+    // Bytes allocated by the mesh's buffers, including their unused capacity
+    pub fn buffer_bytes(&self) -> usize {
+        fn bytes<T>(buffer: &Vec<T>) -> usize {
+            buffer.capacity() * mem::size_of::<T>()
+        }
+
+        bytes(&self.vertices)
+            + bytes(&self.indices)
+            + bytes(&self.biome_tints)
+            + bytes(&self.far_faces)
+            + bytes(&self.smooth_vertices)
+    }
+
+    // Count the mesh towards the live bytes, called once the mesh task has finished building it
+    pub fn track_memory(&mut self) {
+        let bytes = self.buffer_bytes();
+        let live = LIVE_BYTES.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+
+        // Replacing the old token releases whatever it counted
+        self.memory = MeshMemory(bytes);
+    }
+
+    fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+        self.biome_tints.clear();
+        self.far_faces.clear();
+        self.smooth_vertices.clear();
+        self.aabb = None;
+        self.memory = MeshMemory::default();
+    }
+}
+
+// An empty mesh, with the buffers of a recycled mesh if there is one
+pub fn take() -> ChunkMesh {
+    let mut pool = match POOL.lock() {
+        Ok(pool) => pool,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    match pool.meshes.pop() {
+        Some(mesh) => {
+            pool.bytes -= mesh.buffer_bytes();
+            mesh
+        }
+        None => ChunkMesh::default(),
+    }
+}
+
+// Keep the mesh's buffers for the next mesh, once its contents have been copied out
+// Buffers over MESH_POOL_MAX_MESH_BYTES, or past the pool's MESH_POOL_MAX_BYTES, are freed instead
+pub fn recycle(mut mesh: ChunkMesh) {
+    mesh.clear();
+
+    let bytes = mesh.buffer_bytes();
+    if bytes == 0 || bytes > MESH_POOL_MAX_MESH_BYTES {
+        return;
+    }
+
+    let mut pool = match POOL.lock() {
+        Ok(pool) => pool,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if pool.bytes + bytes > MESH_POOL_MAX_BYTES {
+        return;
+    }
+
+    pool.bytes += bytes;
+    pool.meshes.push(mesh);
+}
+
+pub fn live_bytes() -> usize {
+    LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+// High watermark of the live bytes since the app started
+pub fn peak_bytes() -> usize {
+    PEAK_BYTES.load(Ordering::Relaxed)
+}
+
+pub fn pooled_bytes() -> usize {
+    match POOL.lock() {
+        Ok(pool) => pool.bytes,
+        Err(poisoned) => poisoned.into_inner().bytes,
+    }
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use crate::{
+    constants::{FAR_MESH_DISTANCE, FAR_MESH_LOD, FULL_QUALITY_DISTANCE},
+    lod::Lod,
+    positions::ChunkPos,
+};
+
+// How a chunk is meshed, the further from the loader the cheaper the mesh
+#[derive(Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MeshQuality {
+    // Greedy meshed with AO
+    #[default]
+    Full,
+    // A quad for every face and no AO, quicker to build
+    Culled,
+    // Packed faces of a greedy mesh downsampled to the LOD
+    Far(Lod),
+}
+
+impl MeshQuality {
+    pub fn is_far(&self) -> bool {
+        matches!(self, Self::Far(_))
+    }
+
+    // The next step up in detail, far LODs refine to full detail before Full, skipping Culled which is
+    // only cheaper to build
+    pub fn refined(&self) -> Option<Self> {
+        match self {
+            Self::Full => None,
+            Self::Culled | Self::Far(Lod::L32) => Some(Self::Full),
+            Self::Far(lod) => lod.finer().map(Self::Far),
+        }
+    }
+
+    // Higher is more detailed
+    fn detail(&self) -> usize {
+        match self {
+            Self::Full => 6,
+            Self::Culled => 5,
+            Self::Far(lod) => 4 - lod.jump_index().trailing_zeros() as usize,
+        }
+    }
+
+    pub fn finest(self, other: Self) -> Self {
+        if other.detail() > self.detail() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+// Distances (in chunks) from the loader where meshes switch to cheaper qualities, each chunk's
+// quality is picked when it is meshed and checked again whenever the loader moves to another chunk
+#[derive(Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MeshQualityPolicy {
+    pub full_distance: u32,
+    // Chunks past this are far
+    pub culled_distance: u32,
+    pub far_lod: Lod,
+}
+
+impl Default for MeshQualityPolicy {
+    fn default() -> Self {
+        Self {
+            full_distance: FULL_QUALITY_DISTANCE,
+            culled_distance: FAR_MESH_DISTANCE,
+            far_lod: FAR_MESH_LOD,
+        }
+    }
+}
+
+impl MeshQualityPolicy {
+    pub fn quality(&self, chunk_pos: ChunkPos, loader_pos: ChunkPos) -> MeshQuality {
+        let distance_squared = chunk_pos.distance_squared(loader_pos);
+
+        if distance_squared <= self.full_distance.pow(2) {
+            MeshQuality::Full
+        } else if distance_squared <= self.culled_distance.pow(2) {
+            MeshQuality::Culled
+        } else {
+            MeshQuality::Far(self.far_lod)
+        }
+    }
+
+    // The quality by distance, unless the chunk has been refined past it, see LodRefinement
+    pub fn refined_quality(
+        &self,
+        chunk_pos: ChunkPos,
+        loader_pos: ChunkPos,
+        refined: Option<MeshQuality>,
+    ) -> MeshQuality {
+        let quality = self.quality(chunk_pos, loader_pos);
+
+        refined.map_or(quality, |refined| refined.finest(quality))
+    }
+}
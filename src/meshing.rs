@@ -0,0 +1,48 @@
+use crate::{
+    chunk_mesh::ChunkMesh,
+    culled_mesher, greedy_mesher,
+    lod::Lod,
+    vertex::Vertex,
+    voxel_grid::{Downsampled, VoxelGrid},
+};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum MesherKind {
+    #[default]
+    Greedy,
+    // One quad per visible face, slower but simple enough to check the greedy mesher against
+    Culled,
+}
+
+// Mesh a grid on the calling thread, without any Bevy resources or tasks, for tools, tests and
+// benchmarks. The grid is in full detail voxels and lower LODs are downsampled from it, the mesh is
+// in the same units as the pipeline's, with AO sampled but without skylight as a grid has no sky
+pub fn mesh_chunk_sync(
+    chunks: &impl VoxelGrid,
+    lod: Lod,
+    mesher_kind: MesherKind,
+) -> Option<ChunkMesh> {
+    // Neither mesher can build masks for an empty axis
+    if chunks.size().min_element() == 0 {
+        return None;
+    }
+
+    let full_detail = lod.jump_index() == 1;
+    match (mesher_kind, full_detail) {
+        (MesherKind::Greedy, _) => greedy_mesher::build_downsampled_grid_mesh(chunks, lod, true),
+        (MesherKind::Culled, true) => culled_mesher::build_grid_mesh(chunks),
+        (MesherKind::Culled, false) => {
+            // The culled mesher works in the downsampled voxels, so its vertices are scaled back up
+            let mut mesh = culled_mesher::build_grid_mesh(&Downsampled::new(chunks, lod))?;
+            let jump = lod.jump_index();
+
+            for vertex in mesh.vertices.iter_mut() {
+                let mut unpacked = Vertex::from_packed(*vertex);
+                unpacked.pos *= jump;
+                *vertex = unpacked.into();
+            }
+
+            Some(mesh)
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    render::view::VisibilitySystems,
+    transform::TransformSystem,
+};
+
+use crate::{
+    cave_culling::CaveCulling,
+    constants::{
+        CHUNK_EXTENT, OCCLUSION_BUFFER_HEIGHT, OCCLUSION_BUFFER_WIDTH, OCCLUSION_CULLING_ENABLED,
+        OCCLUSION_MAX_OCCLUDERS,
+    },
+    positions::{ChunkPos, VoxelScale},
+    world::World,
+};
+
+// Chunk entities hidden by occlusion culling this frame
+pub const OCCLUDED_CHUNKS: DiagnosticPath = DiagnosticPath::const_new("occlusion/hidden_chunks");
+
+// Hides chunk entities which are completely behind nearer terrain, on top of Bevy's frustum culling
+// Chunks made entirely of opaque voxels are drawn into a small software depth buffer, then every
+// chunk entity's bounds are tested against it, which works well underground and behind mountains
+pub struct OcclusionCullingPlugin;
+
+impl Plugin for OcclusionCullingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OcclusionCulling>()
+            .register_type::<OcclusionCulling>()
+            .register_diagnostic(Diagnostic::new(OCCLUDED_CHUNKS))
+            .add_systems(
+                PostUpdate,
+                OcclusionCulling::hide_occluded_chunks
+                    .after(TransformSystem::TransformPropagate)
+                    .before(VisibilitySystems::VisibilityPropagate),
+            );
+    }
+}
+
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct OcclusionCulling {
+    pub enabled: bool,
+    // Counters from the last frame
+    pub occluders: usize,
+    pub tested: usize,
+    pub hidden: usize,
+    // Distance to the nearest occluder covering each pixel, only covered where a pixel is fully inside
+    #[reflect(ignore)]
+    depth: Vec<f32>,
+}
+
+impl Default for OcclusionCulling {
+    fn default() -> Self {
+        Self {
+            enabled: OCCLUSION_CULLING_ENABLED,
+            occluders: 0,
+            tested: 0,
+            hidden: 0,
+            depth: vec![f32::INFINITY; OCCLUSION_BUFFER_WIDTH * OCCLUSION_BUFFER_HEIGHT],
+        }
+    }
+}
+
+impl OcclusionCulling {
+    // Also applies cave culling, so that a single system decides whether chunk entities are shown
+    pub fn hide_occluded_chunks(
+        mut culling: ResMut<OcclusionCulling>,
+        cave_culling: Res<CaveCulling>,
+        world: Res<World>,
+        voxel_scale: Res<VoxelScale>,
+        cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+        mut visibilities: Query<&mut Visibility>,
+        mut diagnostics: Diagnostics,
+    ) {
+        let Ok((camera, camera_transform)) = cameras.get_single() else {
+            return;
+        };
+
+        let _span = info_span!("hide_occluded_chunks").entered();
+
+        let clip_from_world = camera.clip_from_view() * camera_transform.compute_matrix().inverse();
+        let camera_pos = camera_transform.translation();
+
+        culling.occluders = 0;
+        culling.tested = 0;
+        culling.hidden = 0;
+
+        if culling.enabled {
+            culling.depth.fill(f32::INFINITY);
+
+            // The nearest fully opaque chunks hide the most, the rest are skipped to bound the cost
+            let mut occluders = world
+                .iter_loaded_chunks()
+                .filter(|(_, chunk)| chunk.is_all_opaque())
+                .map(|(chunk_pos, _)| chunk_pos)
+                .collect::<Vec<_>>();
+            occluders.sort_by(|a, b| {
+                let distance = |chunk_pos: &ChunkPos| {
+                    chunk_center(*chunk_pos, &voxel_scale).distance_squared(camera_pos)
+                };
+                distance(a).total_cmp(&distance(b))
+            });
+            occluders.truncate(OCCLUSION_MAX_OCCLUDERS);
+
+            for chunk_pos in occluders {
+                if let Some(corners) = project_chunk(chunk_pos, &voxel_scale, clip_from_world) {
+                    culling.draw_occluder(&corners);
+                    culling.occluders += 1;
+                }
+            }
+        }
+
+        for (&chunk_pos, &entity) in world.chunk_entities.iter() {
+            let Ok(mut visibility) = visibilities.get_mut(entity) else {
+                continue;
+            };
+
+            // Chunks which cave culling hides don't need the depth test
+            let hidden = if cave_culling.is_hidden(chunk_pos) {
+                true
+            } else if culling.enabled {
+                let occluded = project_chunk(chunk_pos, &voxel_scale, clip_from_world)
+                    .is_some_and(|corners| culling.is_occluded(&corners));
+
+                culling.tested += 1;
+                culling.hidden += occluded as usize;
+                occluded
+            } else {
+                false
+            };
+
+            visibility.set_if_neq(if hidden {
+                Visibility::Hidden
+            } else {
+                Visibility::Inherited
+            });
+        }
+
+        diagnostics.add_measurement(&OCCLUDED_CHUNKS, || culling.hidden as f64);
+    }
+
+    // Cover the pixels which lie entirely inside the projected chunk with its farthest depth
+    fn draw_occluder(&mut self, corners: &[Vec3; 8]) {
+        let far = corners.iter().map(|corner| corner.z).fold(0., f32::max);
+        let hull = convex_hull(corners.map(|corner| corner.truncate()));
+
+        let Some((min, max)) = pixel_rect(corners) else {
+            return;
+        };
+
+        // Whether each pixel corner in the rect is inside the hull, a pixel is covered if all 4 are
+        let corner_width = max.x - min.x + 2;
+        let inside = (min.y..=max.y + 1)
+            .flat_map(|y| (min.x..=max.x + 1).map(move |x| UVec2::new(x, y).as_vec2()))
+            .map(|point| is_inside_hull(&hull, point))
+            .collect::<Vec<_>>();
+        let is_inside = |x: u32, y: u32| inside[((y - min.y) * corner_width + x - min.x) as usize];
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                if is_inside(x, y)
+                    && is_inside(x + 1, y)
+                    && is_inside(x, y + 1)
+                    && is_inside(x + 1, y + 1)
+                {
+                    let depth = &mut self.depth[y as usize * OCCLUSION_BUFFER_WIDTH + x as usize];
+                    *depth = depth.min(far);
+                }
+            }
+        }
+    }
+
+    // Whether every pixel the projected chunk touches is covered by an occluder in front of it
+    fn is_occluded(&self, corners: &[Vec3; 8]) -> bool {
+        let near = corners
+            .iter()
+            .map(|corner| corner.z)
+            .fold(f32::INFINITY, f32::min);
+
+        // Chunks off the screen are left to frustum culling
+        let Some((min, max)) = pixel_rect(corners) else {
+            return false;
+        };
+
+        (min.y..=max.y).all(|y| {
+            (min.x..=max.x)
+                .all(|x| self.depth[y as usize * OCCLUSION_BUFFER_WIDTH + x as usize] < near)
+        })
+    }
+}
+
+fn chunk_center(chunk_pos: ChunkPos, voxel_scale: &VoxelScale) -> Vec3 {
+    voxel_scale.chunk_translation(chunk_pos) + CHUNK_EXTENT.as_vec3() * voxel_scale.0 / 2.
+}
+
+// Corners of the chunk's bounds in buffer pixels, with the distance along the view direction as z
+// None if any corner is behind the camera, as the projection can't be bounded then
+fn project_chunk(
+    chunk_pos: ChunkPos,
+    voxel_scale: &VoxelScale,
+    clip_from_world: Mat4,
+) -> Option<[Vec3; 8]> {
+    let min = voxel_scale.chunk_translation(chunk_pos);
+    let size = CHUNK_EXTENT.as_vec3() * voxel_scale.0;
+    let buffer_size = Vec2::new(
+        OCCLUSION_BUFFER_WIDTH as f32,
+        OCCLUSION_BUFFER_HEIGHT as f32,
+    );
+
+    let mut corners = [Vec3::ZERO; 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        let offset = UVec3::new(i as u32 & 1, (i as u32 >> 1) & 1, (i as u32 >> 2) & 1);
+        let clip = clip_from_world * (min + offset.as_vec3() * size).extend(1.);
+
+        if clip.w <= f32::EPSILON {
+            return None;
+        }
+
+        let ndc = clip.truncate().truncate() / clip.w;
+        let pixel = Vec2::new(ndc.x + 1., 1. - ndc.y) * 0.5 * buffer_size;
+        *corner = pixel.extend(clip.w);
+    }
+
+    Some(corners)
+}
+
+// Inclusive pixels overlapped by the projected corners, None if they are all off the buffer
+fn pixel_rect(corners: &[Vec3; 8]) -> Option<(UVec2, UVec2)> {
+    let (min, max) = corners.iter().fold(
+        (Vec2::INFINITY, Vec2::NEG_INFINITY),
+        |(min, max), corner| (min.min(corner.truncate()), max.max(corner.truncate())),
+    );
+    let buffer_max = UVec2::new(
+        OCCLUSION_BUFFER_WIDTH as u32,
+        OCCLUSION_BUFFER_HEIGHT as u32,
+    );
+
+    if max.x < 0. || max.y < 0. || min.x >= buffer_max.x as f32 || min.y >= buffer_max.y as f32 {
+        return None;
+    }
+
+    Some((
+        min.floor().max(Vec2::ZERO).as_uvec2(),
+        max.floor().as_uvec2().min(buffer_max - UVec2::ONE),
+    ))
+}
+
+// Counter-clockwise hull of the points (monotone chain)
+fn convex_hull(mut points: [Vec2; 8]) -> Vec<Vec2> {
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+
+    let mut reversed = points;
+    reversed.reverse();
+
+    // The lower chain then the upper chain, the last point of each is the first of the other
+    let mut hull: Vec<Vec2> = Vec::with_capacity(points.len() + 1);
+    for chain in [points, reversed] {
+        let start = hull.len();
+
+        for point in chain {
+            while hull.len() >= start + 2
+                && (hull[hull.len() - 1] - hull[hull.len() - 2])
+                    .perp_dot(point - hull[hull.len() - 2])
+                    <= 0.
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+
+        hull.pop();
+    }
+
+    hull
+}
+
+fn is_inside_hull(hull: &[Vec2], point: Vec2) -> bool {
+    hull.len() >= 3
+        && (0..hull.len()).all(|i| {
+            let (a, b) = (hull[i], hull[(i + 1) % hull.len()]);
+            (b - a).perp_dot(point - a) >= 0.
+        })
+}
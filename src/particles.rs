@@ -0,0 +1,231 @@
+use bevy::{pbr::NotShadowCaster, prelude::*, utils::HashMap};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    block_registry::{BlockDefinition, BlockRegistry},
+    constants::{
+        MAX_PARTICLES, PARTICLES_PER_BREAK, PARTICLES_PER_PLACE, PARTICLE_GRAVITY,
+        PARTICLE_LIFETIME, PARTICLE_SIZE, PARTICLE_SPEED,
+    },
+    positions::{ChunkPos, VoxelScale, WorldPos},
+    voxel::VoxelType,
+    world::World,
+    world_edit::VoxelChanged,
+};
+
+// Small cubes thrown out of voxels as they are broken or placed, coloured by the block, from the
+// VoxelChanged events. Particle entities are hidden and reused rather than despawned
+pub struct ParticlesPlugin;
+
+impl Plugin for ParticlesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelParticles>()
+            .register_type::<VoxelParticles>()
+            .init_resource::<ParticlePool>()
+            .add_systems(
+                Update,
+                (
+                    ParticlePool::emit.run_if(VoxelParticles::is_enabled),
+                    ParticlePool::update,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Resource, Reflect, Debug, Copy, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct VoxelParticles {
+    pub enabled: bool,
+    pub per_break: usize,
+    pub per_place: usize,
+    pub max_particles: usize,
+    pub lifetime: f32,
+}
+
+impl Default for VoxelParticles {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            per_break: PARTICLES_PER_BREAK,
+            per_place: PARTICLES_PER_PLACE,
+            max_particles: MAX_PARTICLES,
+            lifetime: PARTICLE_LIFETIME,
+        }
+    }
+}
+
+impl VoxelParticles {
+    pub fn is_enabled(particles: Res<VoxelParticles>) -> bool {
+        particles.enabled
+    }
+}
+
+// Positions and velocities are in voxels, the transform is scaled by the VoxelScale
+#[derive(Component, Debug, Clone)]
+pub struct Particle {
+    // The chunk the particle came from, it is put away when the chunk unloads
+    pub chunk_pos: ChunkPos,
+    pub pos: Vec3,
+    pub velocity: Vec3,
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct ParticlePool {
+    mesh: Option<Handle<Mesh>>,
+    materials: HashMap<VoxelType, Handle<StandardMaterial>>,
+    // Hidden particles waiting to be reused
+    free: Vec<Entity>,
+    live: usize,
+    // Counter hashed for each random number
+    next_random: u64,
+}
+
+impl ParticlePool {
+    pub fn live(&self) -> usize {
+        self.live
+    }
+
+    // From 0 to 1
+    fn random(&mut self) -> f32 {
+        self.next_random += 1;
+
+        xxh3_64(&self.next_random.to_le_bytes()) as u32 as f32 / u32::MAX as f32
+    }
+
+    fn random_offset(&mut self) -> Vec3 {
+        Vec3::new(self.random(), self.random(), self.random()) - 0.5
+    }
+
+    fn emit(
+        mut commands: Commands,
+        mut pool: ResMut<ParticlePool>,
+        settings: Res<VoxelParticles>,
+        mut changes: EventReader<VoxelChanged>,
+        (mut meshes, mut materials): (ResMut<Assets<Mesh>>, ResMut<Assets<StandardMaterial>>),
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        let pool = pool.as_mut();
+        let registry = BlockRegistry::global();
+
+        for event in changes.read() {
+            for &(voxel_pos, old, new) in &event.changes {
+                // Breaking throws out the old voxel, placing puffs out the new one
+                let (voxel_type, count) = if new.is_solid() && new != old {
+                    (new, settings.per_place)
+                } else if old.is_solid() && !new.is_solid() {
+                    (old, settings.per_break)
+                } else {
+                    continue;
+                };
+                let count = count.min(settings.max_particles.saturating_sub(pool.live));
+                if count == 0 {
+                    continue;
+                }
+
+                let mesh = pool
+                    .mesh
+                    .get_or_insert_with(|| meshes.add(Cuboid::from_length(1.)))
+                    .clone();
+                let material = pool
+                    .materials
+                    .entry(voxel_type)
+                    .or_insert_with(|| {
+                        materials.add(StandardMaterial {
+                            base_color: registry
+                                .get(voxel_type)
+                                .map_or(Color::WHITE, BlockDefinition::particle_colour),
+                            perceptual_roughness: 1.,
+                            ..default()
+                        })
+                    })
+                    .clone();
+
+                let world_pos = WorldPos::from_voxel_pos(voxel_pos, event.chunk_pos);
+                let centre = IVec3::from(world_pos.to_tuple()).as_vec3() + 0.5;
+
+                for _ in 0..count {
+                    let offset = pool.random_offset();
+                    let particle = Particle {
+                        chunk_pos: event.chunk_pos,
+                        pos: centre + offset * 0.8,
+                        // Outwards from the middle of the voxel, with a hop upwards
+                        velocity: (offset.normalize_or_zero() + Vec3::Y * 0.5) * PARTICLE_SPEED,
+                        age: 0.,
+                        lifetime: settings.lifetime * (0.75 + pool.random() * 0.5),
+                    };
+                    let transform = Transform::from_translation(particle.pos * voxel_scale.0)
+                        .with_scale(Vec3::splat(PARTICLE_SIZE * voxel_scale.0));
+                    let bundle = (particle, transform, material.clone(), Visibility::Inherited);
+
+                    match pool.free.pop() {
+                        Some(entity) => {
+                            commands.entity(entity).insert(bundle);
+                        }
+                        None => {
+                            commands.spawn((
+                                PbrBundle {
+                                    mesh: mesh.clone(),
+                                    ..default()
+                                },
+                                NotShadowCaster,
+                                Name::new("Particle"),
+                                bundle,
+                            ));
+                        }
+                    }
+                    pool.live += 1;
+                }
+            }
+        }
+    }
+
+    fn update(
+        mut pool: ResMut<ParticlePool>,
+        mut particles: Query<(Entity, &mut Particle, &mut Transform, &mut Visibility)>,
+        world: Res<World>,
+        time: Res<Time>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        let delta = time.delta_seconds();
+        let is_solid = |pos: Vec3| {
+            let pos = pos.floor().as_ivec3();
+            world
+                .get_voxel(WorldPos::new(pos.x, pos.y, pos.z))
+                .is_some_and(|voxel| voxel.voxel_type.is_solid())
+        };
+
+        for (entity, mut particle, mut transform, mut visibility) in particles.iter_mut() {
+            if *visibility == Visibility::Hidden {
+                continue;
+            }
+
+            particle.age += delta;
+            if particle.age >= particle.lifetime || !world.chunks.contains_key(&particle.chunk_pos)
+            {
+                *visibility = Visibility::Hidden;
+                pool.free.push(entity);
+                pool.live -= 1;
+                continue;
+            }
+
+            particle.velocity.y -= PARTICLE_GRAVITY * delta;
+            let next = particle.pos + particle.velocity * delta;
+
+            // Particles land on voxels rather than falling through them, ones which started inside a
+            // voxel (placed ones) move freely until they are out
+            if is_solid(next) && !is_solid(particle.pos) {
+                particle.velocity *= Vec3::new(0.5, 0., 0.5);
+            } else {
+                particle.pos = next;
+            }
+
+            // Shrink away towards the end of their life
+            let size = PARTICLE_SIZE * (1. - particle.age / particle.lifetime).sqrt();
+            transform.translation = particle.pos * voxel_scale.0;
+            transform.scale = Vec3::splat(size * voxel_scale.0);
+        }
+    }
+}
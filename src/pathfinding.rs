@@ -0,0 +1,283 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+
+use crate::{
+    constants::CHUNK_HEIGHT, positions::WorldPos, world::World, world_reader::WorldReader,
+};
+
+pub struct PathfindingPlugin;
+
+impl Plugin for PathfindingPlugin {
+    fn build(&self, app: &mut App) {
+        // The WorldReader resource is kept by the WorldReaderPlugin
+        app.add_systems(Update, (start_path_tasks, join_path_tasks).chain());
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PathSettings {
+    // Highest ledge which can be walked up without jumping
+    pub max_step_up: i32,
+    // Furthest drop which can be walked off
+    pub max_fall: i32,
+    // Allow jumping over one voxel wide gaps
+    pub allow_jump: bool,
+    // Give up after visiting this many positions
+    pub max_nodes: usize,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        Self {
+            max_step_up: 1,
+            max_fall: 3,
+            allow_jump: false,
+            max_nodes: 16384,
+        }
+    }
+}
+
+// Add to an entity to find a path in the background, replaced with a PathResult once finished
+#[derive(Component, Debug, Copy, Clone)]
+pub struct PathRequest {
+    pub start: WorldPos,
+    pub goal: WorldPos,
+    pub settings: PathSettings,
+}
+
+#[derive(Component)]
+pub struct PathTask(Task<Option<Vec<WorldPos>>>);
+
+// Positions an agent stands in from start to goal, None if no path was found
+#[derive(Component, Debug, Clone)]
+pub struct PathResult(pub Option<Vec<WorldPos>>);
+
+// Chunks the path is searched through, shared with the path task so the World can keep changing
+#[derive(Clone)]
+pub struct PathGrid {
+    reader: WorldReader,
+}
+
+impl PathGrid {
+    pub fn from_world(world: &World) -> Self {
+        Self::from_reader(world.reader())
+    }
+
+    pub fn from_reader(reader: WorldReader) -> Self {
+        Self { reader }
+    }
+
+    // Unloaded voxels are treated as solid so paths never leave the loaded world
+    fn is_solid(&self, world_pos: WorldPos) -> bool {
+        self.reader
+            .get_voxel(world_pos)
+            .is_none_or(|voxel| voxel.voxel_type.is_solid())
+    }
+
+    // An agent (two voxels tall) can stand here
+    // Most positions are decided from the column heights of the chunk's heightmap, nothing above the
+    // height is solid unless the chunk has transparent solids
+    fn is_standable(&self, world_pos: WorldPos) -> bool {
+        let (voxel_pos, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+        let Some(chunk) = self.reader.get_chunk(chunk_pos) else {
+            return false;
+        };
+
+        if chunk.solids_are_opaque() {
+            // Local y just above the column's highest opaque voxel, 0 if the column has none
+            let surface = chunk
+                .column_height(voxel_pos.x, voxel_pos.z)
+                .map_or(0, |height| height + 1);
+
+            // Floating above the column, the voxel below is still in the chunk
+            if voxel_pos.y > surface {
+                return false;
+            }
+
+            // On top of the column, only the headroom can be in the chunk above
+            if voxel_pos.y == surface && surface > 0 {
+                return voxel_pos.y + 1 < CHUNK_HEIGHT || !self.is_solid(world_pos + up(1));
+            }
+        }
+
+        !self.is_solid(world_pos)
+            && !self.is_solid(world_pos + up(1))
+            && self.is_solid(world_pos + up(-1))
+    }
+
+    // Positions reachable from world_pos in one move, with the cost of the move
+    fn neighbours(&self, world_pos: WorldPos, settings: &PathSettings) -> Vec<(WorldPos, u32)> {
+        const HORIZONTAL_DIRS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+        let mut neighbours = Vec::new();
+
+        for (dx, dz) in HORIZONTAL_DIRS {
+            let step = WorldPos::new(world_pos.x + dx, world_pos.y, world_pos.z + dz);
+
+            if let Some(neighbour) = self.walk_to(world_pos, step, settings) {
+                neighbours.push(neighbour);
+                continue;
+            }
+
+            // Jump over a gap when walking forward would fall
+            if settings.allow_jump && !self.is_solid(step) && !self.is_solid(step + up(1)) {
+                let landing =
+                    WorldPos::new(world_pos.x + 2 * dx, world_pos.y, world_pos.z + 2 * dz);
+
+                if !self.is_solid(world_pos + up(2)) && !self.is_solid(step + up(2)) {
+                    if let Some((pos, cost)) = self.walk_to(step, landing, settings) {
+                        neighbours.push((pos, cost + 2));
+                    }
+                }
+            }
+        }
+
+        neighbours
+    }
+
+    // Walk, step up, or fall from `from` into the column of `to`
+    fn walk_to(
+        &self,
+        from: WorldPos,
+        to: WorldPos,
+        settings: &PathSettings,
+    ) -> Option<(WorldPos, u32)> {
+        if self.is_standable(to) {
+            return Some((to, 1));
+        }
+
+        // Step up, needs headroom above the agent before moving
+        for height in 1..=settings.max_step_up {
+            if self.is_solid(from + up(height + 1)) {
+                break;
+            }
+
+            let stepped = to + up(height);
+            if self.is_standable(stepped) {
+                return Some((stepped, 1 + height as u32));
+            }
+        }
+
+        // Fall, the column being fallen through has to be clear
+        if self.is_solid(to) || self.is_solid(to + up(1)) {
+            return None;
+        }
+
+        (1..=settings.max_fall)
+            .map(|depth| to + up(-depth))
+            .take_while(|&pos| !self.is_solid(pos))
+            .find(|&pos| self.is_standable(pos))
+            .map(|pos| (pos, 1 + (to.y - pos.y) as u32))
+    }
+
+    // A* search between two standable positions
+    pub fn find_path(
+        &self,
+        start: WorldPos,
+        goal: WorldPos,
+        settings: &PathSettings,
+    ) -> Option<Vec<WorldPos>> {
+        if !self.is_standable(start) || !self.is_standable(goal) {
+            return None;
+        }
+
+        let heuristic = |pos: WorldPos| {
+            pos.x.abs_diff(goal.x) + pos.y.abs_diff(goal.y) + pos.z.abs_diff(goal.z)
+        };
+
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<WorldPos, WorldPos> = HashMap::new();
+        let mut costs: HashMap<WorldPos, u32> = HashMap::from([(start, 0)]);
+
+        open.push(Reverse((heuristic(start), start.to_tuple())));
+
+        while let Some(Reverse((_, pos_tuple))) = open.pop() {
+            let pos = WorldPos::from(pos_tuple);
+
+            if pos == goal {
+                let mut path = vec![goal];
+                while let Some(&prev) = came_from.get(path.last().unwrap()) {
+                    path.push(prev);
+                }
+                path.reverse();
+
+                return Some(path);
+            }
+
+            if costs.len() > settings.max_nodes {
+                return None;
+            }
+
+            let cost = costs[&pos];
+            for (neighbour, move_cost) in self.neighbours(pos, settings) {
+                let new_cost = cost + move_cost;
+
+                if costs.get(&neighbour).is_none_or(|&old| new_cost < old) {
+                    costs.insert(neighbour, new_cost);
+                    came_from.insert(neighbour, pos);
+                    open.push(Reverse((
+                        new_cost + heuristic(neighbour),
+                        neighbour.to_tuple(),
+                    )));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn up(height: i32) -> WorldPos {
+    WorldPos::new(0, height, 0)
+}
+
+fn start_path_tasks(
+    mut commands: Commands,
+    world: Res<World>,
+    mut reader: ResMut<WorldReader>,
+    requests: Query<(Entity, &PathRequest)>,
+) {
+    if requests.is_empty() {
+        return;
+    }
+
+    // Edits earlier in the frame are seen, and requests share the reader until the chunks change
+    reader.update(&world);
+
+    let task_pool = AsyncComputeTaskPool::get();
+    let grid = PathGrid::from_reader(reader.clone());
+
+    for (entity, request) in requests.iter() {
+        let grid = grid.clone();
+        let request = *request;
+
+        let task = task_pool
+            .spawn(async move { grid.find_path(request.start, request.goal, &request.settings) });
+
+        commands
+            .entity(entity)
+            .remove::<(PathRequest, PathResult)>()
+            .insert(PathTask(task));
+    }
+}
+
+fn join_path_tasks(mut commands: Commands, mut tasks: Query<(Entity, &mut PathTask)>) {
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some(path) = block_on(future::poll_once(&mut task.0)) else {
+            // Failed to poll, keep task alive
+            continue;
+        };
+
+        commands
+            .entity(entity)
+            .remove::<PathTask>()
+            .insert(PathResult(path));
+    }
+}
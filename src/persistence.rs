@@ -0,0 +1,176 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    chunk::Chunk,
+    chunk_meta::ChunkMeta,
+    chunk_saver,
+    constants::{
+        AUTOSAVE_INTERVAL_SECS, SAVE_DIRECTORY, SAVE_FLUSH_TIMEOUT_SECS, SHUTDOWN_TIMEOUT_SECS,
+    },
+    pipeline_metrics::{self, PipelineStage},
+    positions::ChunkPos,
+    region_file::{self, SavedChunk},
+    world::World,
+    world_generator::WorldGenerator,
+};
+
+pub struct PersistencePlugin;
+
+impl Plugin for PersistencePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(AutosaveTimer(Timer::from_seconds(
+            AUTOSAVE_INTERVAL_SECS,
+            TimerMode::Repeating,
+        )))
+        .add_systems(Update, autosave)
+        .add_systems(Last, shutdown_on_exit);
+    }
+}
+
+#[derive(Resource)]
+pub struct AutosaveTimer(pub Timer);
+
+static SAVE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Where the world is saved, SAVE_DIRECTORY unless the launch options picked another
+pub fn save_directory() -> &'static Path {
+    SAVE_DIR.get_or_init(|| PathBuf::from(SAVE_DIRECTORY))
+}
+
+// Only takes effect before anything has been loaded or saved, returns false if it was too late
+pub fn set_save_directory(path: impl Into<PathBuf>) -> bool {
+    SAVE_DIR.set(path.into()).is_ok()
+}
+
+// Write the chunk and wait for it to reach the disk, the game queues its saves with
+// chunk_saver::queue_save instead
+pub fn save_chunk(chunk_pos: ChunkPos, chunk: Arc<Chunk>, meta: Arc<ChunkMeta>) -> io::Result<()> {
+    chunk_saver::queue_save(chunk_pos, chunk, meta);
+
+    if chunk_saver::flush(Duration::from_secs_f32(SAVE_FLUSH_TIMEOUT_SECS)) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "chunk saves weren't written in time",
+        ))
+    }
+}
+
+// The chunk and metadata bytes the chunk was last saved with
+fn saved_bytes(chunk_pos: ChunkPos) -> Option<SavedChunk> {
+    match region_file::read_chunk(chunk_pos) {
+        Ok(saved) => saved,
+        Err(err) => {
+            warn!("Failed to read the save of chunk {chunk_pos:?}: {err}, regenerating");
+            None
+        }
+    }
+}
+
+// Returns None if the chunk has never been saved (or the save can't be read), the chunk and its
+// metadata are read together, the metadata being None if only it is corrupted
+pub fn load_chunk(chunk_pos: ChunkPos) -> Option<(Chunk, Option<ChunkMeta>)> {
+    if let Some((chunk, meta)) = chunk_saver::unwritten_save(chunk_pos) {
+        return Some((Chunk::clone(&chunk), Some(ChunkMeta::clone(&meta))));
+    }
+
+    let (chunk_bytes, meta_bytes) = saved_bytes(chunk_pos)?;
+
+    let Some(chunk) = Chunk::from_bytes(&chunk_bytes) else {
+        warn!("Chunk save for {chunk_pos:?} is corrupted, regenerating");
+        return None;
+    };
+
+    let meta = ChunkMeta::from_bytes(&meta_bytes);
+    if meta.is_none() {
+        warn!("Chunk metadata for {chunk_pos:?} is corrupted, regenerating");
+    }
+
+    Some((chunk, meta))
+}
+
+// Load the saved chunk and its metadata, generating whatever hasn't been saved
+// Only for generators which don't decorate, decorated chunks are finished by the generation stages
+pub fn load_or_generate(chunk_pos: ChunkPos, generator: &dyn WorldGenerator) -> (Chunk, ChunkMeta) {
+    let (chunk, meta) = load_chunk(chunk_pos).unwrap_or_else(|| {
+        let started = Instant::now();
+        let chunk = generator.generate_base(chunk_pos);
+        pipeline_metrics::record(PipelineStage::Generation, chunk_pos, started.elapsed(), 0);
+
+        (chunk, None)
+    });
+    let meta = meta.unwrap_or_else(|| generator.generate_meta(chunk_pos, &chunk));
+
+    (chunk, meta)
+}
+
+impl World {
+    // Take the loaded dirty chunks and their metadata, clearing the dirty set
+    pub fn take_dirty_chunks(&mut self) -> Vec<(ChunkPos, Arc<Chunk>, Arc<ChunkMeta>)> {
+        let World {
+            chunks,
+            chunk_metas,
+            dirty_chunks,
+            ..
+        } = self;
+
+        dirty_chunks
+            .drain()
+            .filter_map(|chunk_pos| {
+                Some((
+                    chunk_pos,
+                    Arc::clone(chunks.get(&chunk_pos)?),
+                    Arc::clone(chunk_metas.get(&chunk_pos)?),
+                ))
+            })
+            .collect()
+    }
+
+    // Write every dirty chunk and wait for the saves to reach the disk, used for a clean shutdown
+    pub fn flush_saves(&mut self) {
+        for (chunk_pos, chunk, meta) in self.take_dirty_chunks() {
+            chunk_saver::queue_save(chunk_pos, chunk, meta);
+        }
+
+        if !chunk_saver::flush(Duration::from_secs_f32(SAVE_FLUSH_TIMEOUT_SECS)) {
+            warn!(
+                "{} chunk saves were still being written after {SAVE_FLUSH_TIMEOUT_SECS}s",
+                chunk_saver::queued_saves()
+            );
+        }
+
+        let failing = chunk_saver::failing_saves();
+        if failing > 0 {
+            error!("{failing} chunks couldn't be saved, their changes are lost");
+        }
+    }
+}
+
+// Periodically queue the dirty chunks to be written in the background
+fn autosave(mut world: ResMut<World>, mut timer: ResMut<AutosaveTimer>, time: Res<Time>) {
+    if !timer.0.tick(time.delta()).just_finished() || world.dirty_chunks.is_empty() {
+        return;
+    }
+
+    for (chunk_pos, chunk, meta) in world.take_dirty_chunks() {
+        chunk_saver::queue_save(chunk_pos, chunk, meta);
+    }
+}
+
+// Let in-flight tasks finish (within a time limit) then persist everything before exiting
+pub fn shutdown_on_exit(mut world: ResMut<World>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.read().next().is_none() || world.shutting_down {
+        return;
+    }
+
+    world.drain_tasks(Duration::from_secs_f32(SHUTDOWN_TIMEOUT_SECS));
+    world.flush_saves();
+}
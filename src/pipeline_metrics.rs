@@ -0,0 +1,329 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs,
+    path::PathBuf,
+    sync::{Mutex, TryLockError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+};
+
+use crate::{
+    constants::{METRICS_DIRECTORY, METRICS_DUMP_KEY, METRICS_WINDOW},
+    mesh_pool,
+    positions::ChunkPos,
+};
+
+// Medians and 99th percentiles of the per-chunk stage times over the window, in microseconds
+pub const GENERATION_P50: DiagnosticPath = DiagnosticPath::const_new("pipeline/generation_p50");
+pub const GENERATION_P99: DiagnosticPath = DiagnosticPath::const_new("pipeline/generation_p99");
+pub const MESHING_P50: DiagnosticPath = DiagnosticPath::const_new("pipeline/meshing_p50");
+pub const MESHING_P99: DiagnosticPath = DiagnosticPath::const_new("pipeline/meshing_p99");
+// Mean vertices built per meshed chunk over the window
+pub const MESH_VERTICES: DiagnosticPath = DiagnosticPath::const_new("pipeline/mesh_vertices");
+// Mesh buffers held by finished meshes waiting to be joined, the most ever held, and those pooled
+// for reuse, in MiB
+pub const MESHING_MEMORY: DiagnosticPath = DiagnosticPath::const_new("pipeline/meshing_memory");
+pub const MESHING_MEMORY_PEAK: DiagnosticPath =
+    DiagnosticPath::const_new("pipeline/meshing_memory_peak");
+pub const MESH_POOL_MEMORY: DiagnosticPath = DiagnosticPath::const_new("pipeline/mesh_pool_memory");
+
+// Timings pushed by the chunk tasks, moved into PipelineMetrics every frame
+static PENDING_TIMINGS: Mutex<Vec<ChunkTiming>> = Mutex::new(Vec::new());
+
+// Per-chunk timings of the generation and meshing tasks, measured inside the tasks on real terrain
+// Each stage keeps a rolling window of its latest chunks, which can be written out as CSV
+pub struct PipelineMetricsPlugin;
+
+impl Plugin for PipelineMetricsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PipelineMetrics>()
+            .register_diagnostic(Diagnostic::new(GENERATION_P50).with_suffix("µs"))
+            .register_diagnostic(Diagnostic::new(GENERATION_P99).with_suffix("µs"))
+            .register_diagnostic(Diagnostic::new(MESHING_P50).with_suffix("µs"))
+            .register_diagnostic(Diagnostic::new(MESHING_P99).with_suffix("µs"))
+            .register_diagnostic(Diagnostic::new(MESH_VERTICES))
+            .register_diagnostic(Diagnostic::new(MESHING_MEMORY).with_suffix("MiB"))
+            .register_diagnostic(Diagnostic::new(MESHING_MEMORY_PEAK).with_suffix("MiB"))
+            .register_diagnostic(Diagnostic::new(MESH_POOL_MEMORY).with_suffix("MiB"))
+            .add_systems(
+                Update,
+                (
+                    PipelineMetrics::collect,
+                    PipelineMetrics::measure,
+                    PipelineMetrics::measure_memory,
+                    PipelineMetrics::dump.run_if(dump_requested),
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PipelineStage {
+    // Generating a chunk, or the base of a decorated chunk
+    Generation,
+    Decoration,
+    Meshing,
+}
+
+impl PipelineStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Generation => "generation",
+            Self::Decoration => "decoration",
+            Self::Meshing => "meshing",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ChunkTiming {
+    pub stage: PipelineStage,
+    pub chunk_pos: ChunkPos,
+    pub micros: u64,
+    // Vertices built by the mesher, 0 for the other stages
+    pub vertices: usize,
+}
+
+// Called from the chunk tasks when a stage finishes
+pub fn record(stage: PipelineStage, chunk_pos: ChunkPos, duration: Duration, vertices: usize) {
+    let timing = ChunkTiming {
+        stage,
+        chunk_pos,
+        micros: duration.as_micros() as u64,
+        vertices,
+    };
+
+    match PENDING_TIMINGS.lock() {
+        Ok(mut timings) => timings.push(timing),
+        Err(poisoned) => poisoned.into_inner().push(timing),
+    }
+}
+
+// The latest timings of a stage, oldest first
+#[derive(Debug, Clone)]
+pub struct RollingHistogram {
+    timings: VecDeque<ChunkTiming>,
+    pub capacity: usize,
+}
+
+impl Default for RollingHistogram {
+    fn default() -> Self {
+        Self {
+            timings: VecDeque::new(),
+            capacity: METRICS_WINDOW,
+        }
+    }
+}
+
+impl RollingHistogram {
+    pub fn push(&mut self, timing: ChunkTiming) {
+        if self.timings.len() >= self.capacity {
+            self.timings.pop_front();
+        }
+        self.timings.push_back(timing);
+    }
+
+    pub fn len(&self) -> usize {
+        self.timings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ChunkTiming> {
+        self.timings.iter()
+    }
+
+    // Nearest-rank percentile of the times, from 0 to 100
+    pub fn percentile_micros(&self, percentile: f32) -> Option<u64> {
+        let mut micros = self
+            .timings
+            .iter()
+            .map(|timing| timing.micros)
+            .collect::<Vec<_>>();
+        micros.sort_unstable();
+
+        let rank = (percentile / 100. * micros.len() as f32).ceil() as usize;
+        micros.get(rank.clamp(1, micros.len().max(1)) - 1).copied()
+    }
+
+    pub fn mean_vertices(&self) -> Option<f64> {
+        (!self.is_empty()).then(|| {
+            self.timings
+                .iter()
+                .map(|timing| timing.vertices as f64)
+                .sum::<f64>()
+                / self.len() as f64
+        })
+    }
+
+    // Counts of the times in power of two buckets, bucket n counts times from 2^n up to 2^(n+1)µs
+    pub fn buckets(&self) -> Vec<usize> {
+        let mut buckets = Vec::new();
+        for timing in &self.timings {
+            let bucket = (u64::BITS - timing.micros.max(1).leading_zeros()) as usize - 1;
+            if buckets.len() <= bucket {
+                buckets.resize(bucket + 1, 0);
+            }
+            buckets[bucket] += 1;
+        }
+
+        buckets
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct PipelineMetrics {
+    pub generation: RollingHistogram,
+    pub decoration: RollingHistogram,
+    pub meshing: RollingHistogram,
+}
+
+impl PipelineMetrics {
+    pub fn stage(&self, stage: PipelineStage) -> &RollingHistogram {
+        match stage {
+            PipelineStage::Generation => &self.generation,
+            PipelineStage::Decoration => &self.decoration,
+            PipelineStage::Meshing => &self.meshing,
+        }
+    }
+
+    fn stage_mut(&mut self, stage: PipelineStage) -> &mut RollingHistogram {
+        match stage {
+            PipelineStage::Generation => &mut self.generation,
+            PipelineStage::Decoration => &mut self.decoration,
+            PipelineStage::Meshing => &mut self.meshing,
+        }
+    }
+
+    // Every timing in the windows, one row per chunk
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("stage,chunk_x,chunk_y,chunk_z,micros,vertices\n");
+
+        for stage in [
+            PipelineStage::Generation,
+            PipelineStage::Decoration,
+            PipelineStage::Meshing,
+        ] {
+            for timing in self.stage(stage).iter() {
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{},{}",
+                    stage.name(),
+                    timing.chunk_pos.x,
+                    timing.chunk_pos.y,
+                    timing.chunk_pos.z,
+                    timing.micros,
+                    timing.vertices
+                );
+            }
+        }
+
+        csv
+    }
+
+    fn collect(mut metrics: ResMut<PipelineMetrics>) {
+        // Skip the frame rather than wait on a task which is recording
+        let mut timings = match PENDING_TIMINGS.try_lock() {
+            Ok(timings) => timings,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return,
+        };
+        if timings.is_empty() {
+            return;
+        }
+
+        for timing in timings.drain(..) {
+            metrics.stage_mut(timing.stage).push(timing);
+        }
+    }
+
+    fn measure(mut diagnostics: Diagnostics, metrics: Res<PipelineMetrics>) {
+        if !metrics.is_changed() {
+            return;
+        }
+
+        for (path, histogram, percentile) in [
+            (&GENERATION_P50, &metrics.generation, 50.),
+            (&GENERATION_P99, &metrics.generation, 99.),
+            (&MESHING_P50, &metrics.meshing, 50.),
+            (&MESHING_P99, &metrics.meshing, 99.),
+        ] {
+            if let Some(micros) = histogram.percentile_micros(percentile) {
+                diagnostics.add_measurement(path, || micros as f64);
+            }
+        }
+
+        if let Some(vertices) = metrics.meshing.mean_vertices() {
+            diagnostics.add_measurement(&MESH_VERTICES, || vertices);
+        }
+    }
+
+    fn measure_memory(mut diagnostics: Diagnostics) {
+        for (path, bytes) in [
+            (&MESHING_MEMORY, mesh_pool::live_bytes()),
+            (&MESHING_MEMORY_PEAK, mesh_pool::peak_bytes()),
+            (&MESH_POOL_MEMORY, mesh_pool::pooled_bytes()),
+        ] {
+            diagnostics.add_measurement(path, || bytes as f64 / (1024. * 1024.));
+        }
+    }
+
+    // Write the windows to a CSV file and log each stage's histogram
+    fn dump(metrics: Res<PipelineMetrics>) {
+        for stage in [
+            PipelineStage::Generation,
+            PipelineStage::Decoration,
+            PipelineStage::Meshing,
+        ] {
+            let histogram = metrics.stage(stage);
+            if histogram.is_empty() {
+                continue;
+            }
+
+            let buckets = histogram
+                .buckets()
+                .iter()
+                .enumerate()
+                .map(|(bucket, count)| format!("<{}µs: {count}", 2u64 << bucket))
+                .collect::<Vec<_>>()
+                .join(", ");
+            info!(
+                "{} ({} chunks, p50 {}µs, p99 {}µs): {buckets}",
+                stage.name(),
+                histogram.len(),
+                histogram.percentile_micros(50.).unwrap_or_default(),
+                histogram.percentile_micros(99.).unwrap_or_default()
+            );
+        }
+        info!(
+            "Meshing memory: {:.1}MiB live, {:.1}MiB peak, {:.1}MiB pooled",
+            mesh_pool::live_bytes() as f64 / (1024. * 1024.),
+            mesh_pool::peak_bytes() as f64 / (1024. * 1024.),
+            mesh_pool::pooled_bytes() as f64 / (1024. * 1024.)
+        );
+
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let path = PathBuf::from(METRICS_DIRECTORY).join(format!("pipeline_{secs}.csv"));
+
+        let result =
+            fs::create_dir_all(METRICS_DIRECTORY).and_then(|_| fs::write(&path, metrics.to_csv()));
+        match result {
+            Ok(()) => info!("Wrote the pipeline metrics to {}", path.display()),
+            Err(err) => error!("Failed to write the pipeline metrics: {err}"),
+        }
+    }
+}
+
+fn dump_requested(keys: Res<ButtonInput<KeyCode>>) -> bool {
+    keys.just_pressed(METRICS_DUMP_KEY)
+}
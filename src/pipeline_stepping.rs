@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+
+use crate::constants::{PIPELINE_PAUSE_KEY, PIPELINE_STEP_KEY};
+
+// Debug control which freezes chunk loading, unloading and meshing while the camera keeps moving
+// The pause key toggles the freeze and the step key runs the pipeline for a single frame, so the
+// queues can be inspected and loading artifacts looked at without everything shifting
+pub struct PipelineSteppingPlugin;
+
+impl Plugin for PipelineSteppingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PipelineStepping>()
+            .register_type::<PipelineStepping>()
+            .add_systems(First, PipelineStepping::read_keys)
+            .add_systems(Last, PipelineStepping::finish_step);
+    }
+}
+
+#[derive(Resource, Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct PipelineStepping {
+    pub paused: bool,
+    // Frames the pipeline runs for while paused
+    pub steps_left: u32,
+}
+
+impl PipelineStepping {
+    // Run condition for the chunk pipeline's systems
+    pub fn is_running(stepping: Res<PipelineStepping>) -> bool {
+        !stepping.paused || stepping.steps_left > 0
+    }
+
+    fn read_keys(mut stepping: ResMut<PipelineStepping>, keys: Res<ButtonInput<KeyCode>>) {
+        if keys.just_pressed(PIPELINE_PAUSE_KEY) {
+            stepping.paused = !stepping.paused;
+            stepping.steps_left = 0;
+
+            info!(
+                "Chunk pipeline {}",
+                if stepping.paused { "paused" } else { "resumed" }
+            );
+        }
+
+        if stepping.paused && keys.just_pressed(PIPELINE_STEP_KEY) {
+            stepping.steps_left += 1;
+        }
+    }
+
+    fn finish_step(mut stepping: ResMut<PipelineStepping>) {
+        if stepping.paused && stepping.steps_left > 0 {
+            stepping.steps_left -= 1;
+        }
+    }
+}
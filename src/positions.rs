@@ -2,11 +2,18 @@
 
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Rem, RemAssign, Sub, SubAssign};
 
-use bevy::math::IVec3;
+use bevy::{
+    math::{IVec3, Vec3},
+    prelude::{ReflectResource, Resource},
+    reflect::Reflect,
+};
 
-use crate::constants::CHUNK_SIZE;
+use crate::constants::{
+    CHUNK_EXTENT, CHUNK_HEIGHT, CHUNK_SIZE, SECTIONS_PER_AXIS, SECTIONS_TALL, SECTION_SIZE,
+    VOXEL_SCALE,
+};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub struct WorldPos {
     pub x: i32,
     pub y: i32,
@@ -18,40 +25,40 @@ impl WorldPos {
         Self { x, y, z }
     }
 
-    fn splat(val: i32) -> Self {
-        Self::new(val, val, val)
-    }
-
     pub fn to_voxel_pos(pos: Self) -> (VoxelPos, ChunkPos) {
         // Subtract CHUNK_SIZE / 2 before modulus so that negative chunks are rounded down to negative values (instead of rounded up to 0,0,0)
         // Add 0.5 before division so that before rounding, a value of 1/(2 * CHUNK_SIZE) is added, this makes the even rounding work for any chunk size
+        let chunk_coord = |value: i32, size: usize| {
+            (((value - size as i32 / 2) as f32 + 0.5) / size as f32).round_ties_even() as i32
+        };
         let chunk_pos = (
-            (((pos.x - CHUNK_SIZE as i32 / 2) as f32 + 0.5) / CHUNK_SIZE as f32).round_ties_even()
-                as i32,
-            (((pos.y - CHUNK_SIZE as i32 / 2) as f32 + 0.5) / CHUNK_SIZE as f32).round_ties_even()
-                as i32,
-            (((pos.z - CHUNK_SIZE as i32 / 2) as f32 + 0.5) / CHUNK_SIZE as f32).round_ties_even()
-                as i32,
+            chunk_coord(pos.x, CHUNK_SIZE),
+            chunk_coord(pos.y, CHUNK_HEIGHT),
+            chunk_coord(pos.z, CHUNK_SIZE),
         )
             .into();
 
-        // Have to add CHUNK_SIZE after the modulus to make it a true modulus function instead of just remainder (which includes negatives)
-        let voxel_pos_i32 =
-            ((pos % CHUNK_SIZE as i32) + WorldPos::splat(CHUNK_SIZE as i32)) % CHUNK_SIZE as i32;
+        // Have to add the size after the modulus to make it a true modulus function instead of just remainder (which includes negatives)
+        let voxel_coord =
+            |value: i32, size: usize| ((value % size as i32 + size as i32) % size as i32) as usize;
         let voxel_pos = (
-            voxel_pos_i32.x as usize,
-            voxel_pos_i32.y as usize,
-            voxel_pos_i32.z as usize,
+            voxel_coord(pos.x, CHUNK_SIZE),
+            voxel_coord(pos.y, CHUNK_HEIGHT),
+            voxel_coord(pos.z, CHUNK_SIZE),
         )
             .into();
 
         (voxel_pos, chunk_pos)
     }
 
+    pub fn to_tuple(self) -> (i32, i32, i32) {
+        (self.x, self.y, self.z)
+    }
+
     pub fn from_voxel_pos(voxel_pos: VoxelPos, chunk_pos: ChunkPos) -> Self {
         (
             voxel_pos.x as i32 + chunk_pos.x * CHUNK_SIZE as i32,
-            voxel_pos.y as i32 + chunk_pos.y * CHUNK_SIZE as i32,
+            voxel_pos.y as i32 + chunk_pos.y * CHUNK_HEIGHT as i32,
             voxel_pos.z as i32 + chunk_pos.z * CHUNK_SIZE as i32,
         )
             .into()
@@ -86,7 +93,7 @@ impl Rem<i32> for WorldPos {
 
 // Chunk Position Struct (For the position of a chunk in the world)
 
-#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone, Reflect)]
 pub struct ChunkPos {
     pub x: i32,
     pub y: i32,
@@ -234,14 +241,14 @@ impl VoxelPos {
     }
 
     pub fn to_index(&self) -> usize {
-        self.x + (self.y + self.z * CHUNK_SIZE) * CHUNK_SIZE
+        self.x + (self.y + self.z * CHUNK_HEIGHT) * CHUNK_SIZE
     }
 
     pub fn from_index(index: usize) -> VoxelPos {
         VoxelPos::new(
             index % CHUNK_SIZE,
-            (index / CHUNK_SIZE) % CHUNK_SIZE,
-            (index / (CHUNK_SIZE * CHUNK_SIZE)) % CHUNK_SIZE,
+            (index / CHUNK_SIZE) % CHUNK_HEIGHT,
+            (index / (CHUNK_SIZE * CHUNK_HEIGHT)) % CHUNK_SIZE,
         )
     }
 
@@ -249,17 +256,50 @@ impl VoxelPos {
         IVec3::new(self.x as i32, self.y as i32, self.z as i32)
     }
 
+    // The position must not be negative, use try_from_ivec3 for positions which might be
     pub fn from_ivec3(voxel_pos: IVec3) -> Self {
+        debug_assert!(
+            voxel_pos.cmpge(IVec3::ZERO).all(),
+            "Voxel position {voxel_pos} is negative"
+        );
+
         Self::new(
-            voxel_pos.x.max(0) as usize,
-            voxel_pos.y.max(0) as usize,
-            voxel_pos.z.max(0) as usize,
+            voxel_pos.x as usize,
+            voxel_pos.y as usize,
+            voxel_pos.z as usize,
         )
     }
 
+    // None if any component is negative
+    pub fn try_from_ivec3(voxel_pos: IVec3) -> Option<Self> {
+        voxel_pos
+            .cmpge(IVec3::ZERO)
+            .all()
+            .then(|| Self::from_ivec3(voxel_pos))
+    }
+
+    pub fn is_in_chunk(&self) -> bool {
+        self.x < CHUNK_SIZE && self.y < CHUNK_HEIGHT && self.z < CHUNK_SIZE
+    }
+
     pub fn to_i32(&self) -> (i32, i32, i32) {
         (self.x as i32, self.y as i32, self.z as i32)
     }
+
+    // Index of the mesh section which this voxel is in
+    pub fn section_index(&self) -> usize {
+        let section = *self / SECTION_SIZE;
+        section.x + (section.y + section.z * SECTIONS_TALL) * SECTIONS_PER_AXIS
+    }
+
+    // The voxel position at the minimum corner of a mesh section
+    pub fn from_section_index(section_index: usize) -> Self {
+        VoxelPos::new(
+            section_index % SECTIONS_PER_AXIS,
+            (section_index / SECTIONS_PER_AXIS) % SECTIONS_TALL,
+            section_index / (SECTIONS_PER_AXIS * SECTIONS_TALL),
+        ) * SECTION_SIZE
+    }
 }
 
 impl From<(usize, usize, usize)> for VoxelPos {
@@ -368,3 +408,45 @@ impl RemAssign<usize> for VoxelPos {
         self.z %= rhs;
     }
 }
+
+// Voxel Scale Resource (Size of a voxel in world units)
+
+#[derive(Resource, Reflect, Debug, Copy, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct VoxelScale(pub f32);
+
+impl Default for VoxelScale {
+    fn default() -> Self {
+        Self(VOXEL_SCALE)
+    }
+}
+
+impl VoxelScale {
+    // Translation of the minimum corner of a voxel
+    pub fn to_translation(&self, world_pos: WorldPos) -> Vec3 {
+        Vec3::new(world_pos.x as f32, world_pos.y as f32, world_pos.z as f32) * self.0
+    }
+
+    // The voxel containing a translation
+    pub fn to_world_pos(&self, translation: Vec3) -> WorldPos {
+        let voxel_translation = (translation / self.0).floor();
+
+        WorldPos::new(
+            voxel_translation.x as i32,
+            voxel_translation.y as i32,
+            voxel_translation.z as i32,
+        )
+    }
+
+    // Translation of a chunk's entity
+    pub fn chunk_translation(&self, chunk_pos: ChunkPos) -> Vec3 {
+        (chunk_pos.to_ivec3() * CHUNK_EXTENT).as_vec3() * self.0
+    }
+
+    // The chunk which loaders at this translation load around
+    pub fn loader_chunk_pos(&self, translation: Vec3) -> ChunkPos {
+        ChunkPos::from_vec3(
+            (translation / self.0 - CHUNK_EXTENT.as_vec3() / 2.) / CHUNK_EXTENT.as_vec3(),
+        )
+    }
+}
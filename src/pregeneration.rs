@@ -0,0 +1,383 @@
+use std::time::Instant;
+
+use bevy::{prelude::*, transform::TransformSystem};
+
+use crate::{
+    chunk_loading::{bounds_contain, chunks_in, ChunkLoader},
+    chunk_queue::ChunkQueue,
+    constants::{
+        ADJACENT_CHUNK_DIRECTIONS, PREGENERATION_BAR_HEIGHT, PREGENERATION_BAR_WIDTH,
+        PREGENERATION_QUEUE_SIZE,
+    },
+    launch_options::LaunchOptions,
+    pipeline_stepping::PipelineStepping,
+    positions::{ChunkPos, VoxelScale},
+    world::World,
+    world_border::WorldBorder,
+};
+
+// Generates, and optionally meshes, every chunk within a radius of the loader before gameplay
+// starts, behind a progress screen, and then hands over to the loaders' streaming
+// The loaders are held in place until it finishes, and chunks past their range are unloaded after
+// Pregenerate with `cargo run -- --pregenerate <radius>`, or `--pregenerate-data <radius>` to skip meshing
+pub struct PregenerationPlugin;
+
+impl Plugin for PregenerationPlugin {
+    fn build(&self, app: &mut App) {
+        // Apps without launch options, such as tests, don't pregenerate
+        let options = app
+            .world()
+            .get_resource::<LaunchOptions>()
+            .cloned()
+            .unwrap_or_default();
+
+        app.insert_resource(Pregeneration::from_options(&options))
+            .add_systems(
+                Update,
+                (
+                    Pregeneration::start.run_if(Pregeneration::is_starting),
+                    (Pregeneration::feed, Pregeneration::finish)
+                        .chain()
+                        .run_if(Pregeneration::is_running)
+                        .run_if(PipelineStepping::is_running),
+                    PregenerationScreen::update,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                Pregeneration::hold_loaders
+                    .run_if(Pregeneration::is_running)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PregenerationPhase {
+    Off,
+    // Waiting for a loader to centre the region on
+    Starting,
+    Running,
+    Finished,
+}
+
+#[derive(Resource, Debug)]
+pub struct Pregeneration {
+    pub phase: PregenerationPhase,
+    // Radius (in chunks) of the region, in the loader's load shape
+    pub radius: u32,
+    pub mesh: bool,
+    center: ChunkPos,
+    // Where the loaders are held while the region generates
+    loader_translation: Vec3,
+    // Chunks which haven't been given to the World yet, nearest first
+    data_queue: ChunkQueue,
+    mesh_queue: ChunkQueue,
+    // Chunks whose data was given to the World but hasn't loaded yet
+    data_waiting: ChunkQueue,
+    pub total: usize,
+    started: Instant,
+}
+
+impl Pregeneration {
+    pub fn new(radius: Option<u32>, mesh: bool) -> Self {
+        Self {
+            phase: match radius {
+                Some(_) => PregenerationPhase::Starting,
+                None => PregenerationPhase::Off,
+            },
+            radius: radius.unwrap_or_default(),
+            mesh,
+            center: ChunkPos::new(0, 0, 0),
+            loader_translation: Vec3::ZERO,
+            data_queue: ChunkQueue::new(),
+            mesh_queue: ChunkQueue::new(),
+            data_waiting: ChunkQueue::new(),
+            total: 0,
+            started: Instant::now(),
+        }
+    }
+
+    // `--pregenerate <radius>` or `--pregenerate-data <radius>`
+    pub fn from_options(options: &LaunchOptions) -> Self {
+        match (options.pregenerate, options.pregenerate_data) {
+            (None, Some(radius)) => Self::new(Some(radius), false),
+            (radius, _) => Self::new(radius, true),
+        }
+    }
+
+    pub fn is_starting(pregeneration: Res<Pregeneration>) -> bool {
+        pregeneration.phase == PregenerationPhase::Starting
+    }
+
+    pub fn is_running(pregeneration: Res<Pregeneration>) -> bool {
+        pregeneration.phase == PregenerationPhase::Running
+    }
+
+    // Work left, in chunks to load and chunks to mesh
+    pub fn remaining(&self, world: &World) -> usize {
+        let meshing = world.load_mesh_queue.len()
+            + world.mesh_tasks.len()
+            + world.parked_meshes.iter().map(Vec::len).sum::<usize>();
+
+        self.data_queue.len() + self.data_waiting.len() + self.mesh_queue.len() + meshing
+    }
+
+    // From 0 to 1
+    pub fn fraction(&self, world: &World) -> f32 {
+        if self.total == 0 {
+            return 1.;
+        }
+
+        1. - (self.remaining(world) as f32 / self.total as f32).min(1.)
+    }
+
+    fn start(
+        mut commands: Commands,
+        mut pregeneration: ResMut<Pregeneration>,
+        loaders: Query<(&ChunkLoader, &GlobalTransform)>,
+        border: Res<WorldBorder>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        let Ok((loader, g_transform)) = loaders.get_single() else {
+            return;
+        };
+
+        let center = voxel_scale.loader_chunk_pos(g_transform.translation());
+        let radius = pregeneration.radius;
+
+        // Data is loaded one chunk past the meshes, like the loaders do
+        let data_chunks = chunks_in(loader.shape.bounds(center, radius, 1))
+            .filter(|&chunk_pos| border.should_load_chunk(chunk_pos));
+        let mut data_queue = ChunkQueue::new();
+        data_queue.extend(data_chunks);
+        data_queue.sort_by_distance(center);
+
+        let mut mesh_queue = ChunkQueue::new();
+        if pregeneration.mesh {
+            let mesh_chunks = chunks_in(loader.shape.bounds(center, radius, 0))
+                .filter(|&chunk_pos| border.contains_chunk(chunk_pos));
+            mesh_queue.extend(mesh_chunks);
+            mesh_queue.sort_by_distance(center);
+        }
+
+        info!(
+            "Pregenerating {} chunks and meshing {} within {radius} chunks of {center:?}",
+            data_queue.len(),
+            mesh_queue.len()
+        );
+
+        let pregeneration = pregeneration.as_mut();
+        pregeneration.total = data_queue.len() + mesh_queue.len();
+        pregeneration.data_queue = data_queue;
+        pregeneration.mesh_queue = mesh_queue;
+        pregeneration.center = center;
+        pregeneration.loader_translation = g_transform.translation();
+        pregeneration.started = Instant::now();
+        pregeneration.phase = PregenerationPhase::Running;
+
+        PregenerationScreen::spawn(&mut commands);
+    }
+
+    // Keep the World's queues topped up from the region, so they stay short enough to sort every frame
+    fn feed(mut pregeneration: ResMut<Pregeneration>, mut world: ResMut<World>) {
+        let Pregeneration {
+            data_queue,
+            mesh_queue,
+            data_waiting,
+            ..
+        } = pregeneration.as_mut();
+
+        data_waiting.retain(|chunk_pos| !world.chunks.contains_key(chunk_pos));
+
+        let data_slots = PREGENERATION_QUEUE_SIZE.saturating_sub(world.load_data_queue.len());
+        for chunk_pos in data_queue.drain_front(data_slots) {
+            let is_busy = world.chunks.contains_key(&chunk_pos)
+                || world.load_data_queue.contains(&chunk_pos)
+                || world.data_tasks.contains_key(&chunk_pos)
+                || world.is_generating(chunk_pos);
+
+            if !is_busy {
+                world.load_data_queue.push(chunk_pos);
+                world.unload_data_queue.remove(&chunk_pos);
+            }
+            if !world.chunks.contains_key(&chunk_pos) {
+                data_waiting.push(chunk_pos);
+            }
+        }
+
+        // Chunks are meshed once their neighbours have loaded, in the order they were queued
+        let mut mesh_slots = PREGENERATION_QUEUE_SIZE.saturating_sub(world.load_mesh_queue.len());
+        let mut queued = Vec::new();
+        for chunk_pos in mesh_queue.iter() {
+            if mesh_slots == 0 {
+                break;
+            }
+
+            let neighbours_loaded = ADJACENT_CHUNK_DIRECTIONS
+                .iter()
+                .all(|&offset| world.chunks.contains_key(&(*chunk_pos + offset)));
+            if neighbours_loaded {
+                queued.push(*chunk_pos);
+                mesh_slots -= 1;
+            }
+        }
+
+        for chunk_pos in queued {
+            mesh_queue.remove(&chunk_pos);
+
+            // Loading a chunk meshes every section
+            world.load_mesh_queue.push(chunk_pos);
+            world.remesh_sections.remove(&chunk_pos);
+            world.unload_mesh_queue.remove(&chunk_pos);
+        }
+    }
+
+    // Once the region is done, unload what the loaders don't keep and let them stream again
+    fn finish(
+        mut commands: Commands,
+        mut pregeneration: ResMut<Pregeneration>,
+        mut world: ResMut<World>,
+        loaders: Query<&ChunkLoader>,
+        screens: Query<Entity, With<PregenerationScreen>>,
+    ) {
+        if pregeneration.remaining(&world) > 0 {
+            return;
+        }
+
+        let (center, radius) = (pregeneration.center, pregeneration.radius);
+        if let Ok(loader) = loaders.get_single() {
+            let data = loader.shape.bounds(center, radius, 1);
+            let mesh = loader.shape.bounds(center, radius, 0);
+            let kept_data = loader.shape.bounds(center, loader.load_distance, 1);
+            let kept_mesh = loader.shape.bounds(center, loader.load_distance, 0);
+
+            for chunk_pos in chunks_in(data).filter(|&pos| !bounds_contain(kept_data, pos)) {
+                if world.chunks.contains_key(&chunk_pos) {
+                    world.unload_data_queue.push(chunk_pos);
+                }
+            }
+            for chunk_pos in chunks_in(mesh).filter(|&pos| !bounds_contain(kept_mesh, pos)) {
+                world.unload_mesh_queue.push(chunk_pos);
+            }
+        }
+
+        let elapsed = pregeneration.started.elapsed().as_secs_f32();
+        info!(
+            "Pregenerated {} chunks in {elapsed:.2}s ({:.0} chunks/s)",
+            pregeneration.total,
+            pregeneration.total as f32 / elapsed.max(f32::EPSILON)
+        );
+
+        pregeneration.phase = PregenerationPhase::Finished;
+        for entity in screens.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    // The loaders stay where the region is centred, so they don't stream anything else meanwhile
+    fn hold_loaders(
+        pregeneration: Res<Pregeneration>,
+        mut loaders: Query<&mut Transform, With<ChunkLoader>>,
+    ) {
+        for mut transform in loaders.iter_mut() {
+            // Avoid triggering change detection when the loader hasn't moved
+            if transform.translation != pregeneration.loader_translation {
+                transform.translation = pregeneration.loader_translation;
+            }
+        }
+    }
+}
+
+// Covers the screen while the region pregenerates
+#[derive(Component, Debug)]
+pub struct PregenerationScreen;
+
+#[derive(Component, Debug)]
+pub struct PregenerationBarFill;
+
+#[derive(Component, Debug)]
+pub struct PregenerationText;
+
+impl PregenerationScreen {
+    fn spawn(commands: &mut Commands) {
+        commands
+            .spawn((
+                PregenerationScreen,
+                Name::new("Pregeneration screen"),
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.),
+                        height: Val::Percent(100.),
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        row_gap: Val::Px(12.),
+                        ..default()
+                    },
+                    background_color: Color::srgb(0.05, 0.05, 0.08).into(),
+                    // Above the loading indicator and the debug overlays
+                    z_index: ZIndex::Global(100),
+                    ..default()
+                },
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    PregenerationText,
+                    TextBundle::from_section("Generating world", TextStyle::default()),
+                ));
+
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            width: Val::Px(PREGENERATION_BAR_WIDTH),
+                            height: Val::Px(PREGENERATION_BAR_HEIGHT),
+                            ..default()
+                        },
+                        background_color: Color::srgba(1., 1., 1., 0.15).into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn((
+                            PregenerationBarFill,
+                            NodeBundle {
+                                style: Style {
+                                    width: Val::Percent(0.),
+                                    height: Val::Percent(100.),
+                                    ..default()
+                                },
+                                background_color: Color::srgba(1., 1., 1., 0.8).into(),
+                                ..default()
+                            },
+                        ));
+                    });
+            });
+    }
+
+    fn update(
+        pregeneration: Res<Pregeneration>,
+        world: Res<World>,
+        mut fills: Query<&mut Style, With<PregenerationBarFill>>,
+        mut texts: Query<&mut Text, With<PregenerationText>>,
+    ) {
+        if pregeneration.phase != PregenerationPhase::Running {
+            return;
+        }
+
+        let fraction = pregeneration.fraction(&world);
+        for mut style in fills.iter_mut() {
+            style.width = Val::Percent(fraction * 100.);
+        }
+
+        let done = pregeneration.total - pregeneration.remaining(&world).min(pregeneration.total);
+        for mut text in texts.iter_mut() {
+            text.sections[0].value = format!(
+                "Generating world: {done} / {} chunks ({:.0}%)",
+                pregeneration.total,
+                fraction * 100.
+            );
+        }
+    }
+}
@@ -0,0 +1,30 @@
+// The stable API for games built on the engine: `use cube_world::prelude::*;`
+// Items reached through the other modules are engine internals, which may change between versions
+// World is also the name of Bevy's ECS world, so games importing both preludes name one by its path
+pub use crate::{
+    block_breaking::{BlockBreaking, BlockBreakingEvent, BlockBreakingPlugin},
+    block_registry::{BlockDefinition, BlockRegistry, BlockRegistryPlugin},
+    chunk::Chunk,
+    chunk_loading::{ChunkLoadAnchor, ChunkLoader, ChunkLoaderPlugin, LoadShape},
+    chunk_mesh::Direction,
+    decals::{DecalId, DecalsPlugin},
+    explosion::{Explosion, ExplosionPlugin},
+    greedy_mesher::AoMerging,
+    particles::{ParticlesPlugin, VoxelParticles},
+    pathfinding::{PathRequest, PathResult, PathfindingPlugin},
+    persistence::PersistencePlugin,
+    positions::{ChunkPos, VoxelPos, VoxelScale, WorldPos},
+    rendering::{ChunkMaterial, ChunkTexturing, RenderingPlugin},
+    schematic::{Schematic, SchematicPlacement},
+    spawning::{ChunkEnteredSimulation, ChunkLeftSimulation, SpawnTick, SpawnedIn, SpawningPlugin},
+    thumbnails::{Thumbnails, ThumbnailsPlugin},
+    voxel::{Voxel, VoxelType},
+    voxel_object::{VoxelObject, VoxelObjectPlugin},
+    voxel_picking::{VoxelHit, VoxelPicked, VoxelPicking, VoxelPickingPlugin},
+    world::{ChunkEntity, World, WorldPlugin},
+    world_border::{WorldBorder, WorldBorderPlugin},
+    world_edit::{EditError, EditTransaction, VoxelChanged},
+    world_generator::{GeneratorPreset, WorldGen, WorldGenerator},
+    world_reader::{WorldReader, WorldReaderPlugin},
+    world_snapshot::WorldSnapshot,
+};
@@ -0,0 +1,312 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+use crate::{constants::SAVE_REGION_SIZE, persistence, positions::ChunkPos};
+
+// Chunk saves are kept in one file per region (a cube of SAVE_REGION_SIZE chunks), so saving a
+// batch of chunks writes one file rather than two per chunk
+// A region file is rewritten whole: written to a temporary file next to it, then renamed over it,
+// so a crash while saving leaves either the old region or the new one, never a mix
+//
+// Layout, little endian:
+// - REGION_MAGIC, then the number of entries as a u32
+// - Each entry's chunk position (3 i32s), chunk length and metadata length (2 u32s)
+// - Each entry's chunk bytes then metadata bytes, in the order of the entries
+const REGION_MAGIC: [u8; 4] = *b"CWR1";
+const ENTRY_SIZE: usize = 20;
+const MAX_ENTRIES: usize = (SAVE_REGION_SIZE * SAVE_REGION_SIZE * SAVE_REGION_SIZE) as usize;
+
+// The chunk and metadata bytes of a saved chunk
+pub type SavedChunk = (Vec<u8>, Vec<u8>);
+
+// When a region file is flushed to the disk, later policies are safer against power loss but slower
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncPolicy {
+    // Left to the OS, a power loss may leave an empty or partly written region after the rename
+    Never,
+    // The new region is synced before it replaces the old one
+    #[default]
+    BeforeRename,
+    // The directory is synced after the rename as well, so the new region survives a power loss
+    AfterRename,
+}
+
+pub fn region_of(chunk_pos: ChunkPos) -> IVec3 {
+    chunk_pos
+        .to_ivec3()
+        .div_euclid(IVec3::splat(SAVE_REGION_SIZE))
+}
+
+pub fn region_path(region: IVec3) -> PathBuf {
+    persistence::save_directory().join(format!("{}_{}_{}.region", region.x, region.y, region.z))
+}
+
+// The chunk's save from its region, None if it has never been saved
+pub fn read_chunk(chunk_pos: ChunkPos) -> io::Result<Option<SavedChunk>> {
+    read_entry(&region_path(region_of(chunk_pos)), chunk_pos)
+}
+
+// Write the chunks into their region, keeping the region's other chunks
+pub fn write_region(
+    region: IVec3,
+    chunks: impl IntoIterator<Item = (ChunkPos, SavedChunk)>,
+    sync_policy: SyncPolicy,
+) -> io::Result<()> {
+    let path = region_path(region);
+
+    let mut entries = match read_entries(&path) {
+        Ok(entries) => entries,
+        // Nothing in it can be loaded, so it is kept aside rather than retried forever
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            let corrupt_path = path.with_extension("region.corrupt");
+            warn!(
+                "Region {region} is corrupted, moving it to {}",
+                corrupt_path.display()
+            );
+            fs::rename(&path, corrupt_path)?;
+            BTreeMap::new()
+        }
+        Err(err) => return Err(err),
+    };
+
+    for (chunk_pos, saved) in chunks {
+        entries.insert(chunk_pos.to_tuple(), saved);
+    }
+
+    let temp_path = path.with_extension("region.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(&encode(&entries))?;
+    if sync_policy != SyncPolicy::Never {
+        file.sync_all()?;
+    }
+    drop(file);
+
+    fs::rename(&temp_path, &path)?;
+    if sync_policy == SyncPolicy::AfterRename {
+        sync_directory(persistence::save_directory())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    File::open(directory)?.sync_all()
+}
+
+// Directories can't be opened as files elsewhere, the rename is left to the OS
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn encode(entries: &BTreeMap<(i32, i32, i32), SavedChunk>) -> Vec<u8> {
+    let data_len = entries
+        .values()
+        .map(|(chunk, meta)| chunk.len() + meta.len())
+        .sum::<usize>();
+    let mut bytes = Vec::with_capacity(8 + entries.len() * ENTRY_SIZE + data_len);
+
+    bytes.extend(REGION_MAGIC);
+    bytes.extend((entries.len() as u32).to_le_bytes());
+    for (&(x, y, z), (chunk, meta)) in entries {
+        for value in [x, y, z] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend((chunk.len() as u32).to_le_bytes());
+        bytes.extend((meta.len() as u32).to_le_bytes());
+    }
+    for (chunk, meta) in entries.values() {
+        bytes.extend(chunk);
+        bytes.extend(meta);
+    }
+
+    bytes
+}
+
+struct Entry {
+    chunk_pos: (i32, i32, i32),
+    chunk_len: u64,
+    meta_len: u64,
+}
+
+// The entries of a region, checked to lie within the file
+fn read_table(reader: &mut impl Read, file_len: u64) -> io::Result<Vec<Entry>> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+    let mut read_exact = |bytes: &mut [u8]| {
+        reader.read_exact(bytes).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("truncated"),
+            _ => err,
+        })
+    };
+
+    let mut header = [0; 8];
+    read_exact(&mut header)?;
+    if header[..4] != REGION_MAGIC {
+        return Err(invalid("not a region file"));
+    }
+    let count = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    if count > MAX_ENTRIES {
+        return Err(invalid("more chunks than fit in a region"));
+    }
+
+    let mut table = vec![0; count * ENTRY_SIZE];
+    read_exact(&mut table)?;
+
+    let field = |entry: &[u8], index: usize| {
+        u32::from_le_bytes(entry[index * 4..index * 4 + 4].try_into().unwrap())
+    };
+    let entries = table
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| Entry {
+            chunk_pos: (
+                field(entry, 0) as i32,
+                field(entry, 1) as i32,
+                field(entry, 2) as i32,
+            ),
+            chunk_len: field(entry, 3) as u64,
+            meta_len: field(entry, 4) as u64,
+        })
+        .collect::<Vec<_>>();
+
+    let data_len = entries
+        .iter()
+        .map(|entry| entry.chunk_len + entry.meta_len)
+        .sum::<u64>();
+    if 8 + table.len() as u64 + data_len != file_len {
+        return Err(invalid("entries don't match the file's length"));
+    }
+
+    Ok(entries)
+}
+
+// Only the table and the chunk's own bytes are read
+fn read_entry(path: &Path, chunk_pos: ChunkPos) -> io::Result<Option<SavedChunk>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let entries = read_table(&mut reader, file_len)?;
+    let mut offset = 8 + (entries.len() * ENTRY_SIZE) as u64;
+    for entry in entries {
+        if entry.chunk_pos != chunk_pos.to_tuple() {
+            offset += entry.chunk_len + entry.meta_len;
+            continue;
+        }
+
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut chunk = vec![0; entry.chunk_len as usize];
+        let mut meta = vec![0; entry.meta_len as usize];
+        reader.read_exact(&mut chunk)?;
+        reader.read_exact(&mut meta)?;
+
+        return Ok(Some((chunk, meta)));
+    }
+
+    Ok(None)
+}
+
+fn read_entries(path: &Path) -> io::Result<BTreeMap<(i32, i32, i32), SavedChunk>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut reader = bytes.as_slice();
+    let entries = read_table(&mut reader, bytes.len() as u64)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let (chunk, rest) = reader.split_at(entry.chunk_len as usize);
+            let (meta, rest) = rest.split_at(entry.meta_len as usize);
+            reader = rest;
+
+            (entry.chunk_pos, (chunk.to_vec(), meta.to_vec()))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    // Every test in the process saves to the same temporary directory
+    fn use_temp_save_directory() {
+        let directory = env::temp_dir().join(format!("cube_world_regions_{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        persistence::set_save_directory(&directory);
+        assert_eq!(persistence::save_directory(), directory);
+    }
+
+    fn saved(chunk: &[u8], meta: &[u8]) -> SavedChunk {
+        (chunk.to_vec(), meta.to_vec())
+    }
+
+    #[test]
+    fn batches_keep_the_region_s_other_chunks() {
+        use_temp_save_directory();
+
+        let region = IVec3::new(-3, 0, 5);
+        let first = ChunkPos::new(-3, 0, 5) * SAVE_REGION_SIZE;
+        let second = first + ChunkPos::new(1, 1, 1);
+        assert_eq!(region_of(second), region);
+
+        write_region(region, [(first, saved(b"first", b"a"))], SyncPolicy::Never).unwrap();
+        write_region(
+            region,
+            [(second, saved(b"second", b""))],
+            SyncPolicy::BeforeRename,
+        )
+        .unwrap();
+        assert_eq!(read_chunk(first).unwrap(), Some(saved(b"first", b"a")));
+        assert_eq!(read_chunk(second).unwrap(), Some(saved(b"second", b"")));
+
+        write_region(
+            region,
+            [(first, saved(b"newer", b"b"))],
+            SyncPolicy::AfterRename,
+        )
+        .unwrap();
+        assert_eq!(read_chunk(first).unwrap(), Some(saved(b"newer", b"b")));
+        assert_eq!(read_chunk(second).unwrap(), Some(saved(b"second", b"")));
+
+        // The temporary file was renamed over the region
+        assert!(!region_path(region).with_extension("region.tmp").exists());
+    }
+
+    #[test]
+    fn truncated_regions_are_rejected() {
+        let entries = [
+            ((0, 0, 0), saved(b"chunk", b"meta")),
+            ((1, 0, 0), saved(b"", b"m")),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+        let bytes = encode(&entries);
+        assert_eq!(
+            read_table(&mut bytes.as_slice(), bytes.len() as u64)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        for len in 0..bytes.len() {
+            let err = read_table(&mut &bytes[..len], len as u64).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{len} bytes");
+        }
+    }
+}
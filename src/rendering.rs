@@ -1,22 +1,428 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
 use bevy::{
+    asset::AssetLoadFailedEvent,
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline},
     prelude::*,
-    render::render_resource::{AsBindGroup, ShaderRef},
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        mesh::MeshVertexBufferLayoutRef,
+        render_resource::{
+            AsBindGroup, CachedPipelineState, PipelineCache, PipelineCacheError,
+            PipelineDescriptor, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+            TextureFormat,
+        },
+        texture::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
 };
 
-use crate::constants::{ATTRIBUTE_VOXEL, CHUNK_FRAGMENT_SHADER, CHUNK_VERTEX_SHADER};
+use crate::{
+    block_registry::BlockRegistry,
+    constants::{
+        ATTRIBUTE_BIOME_TINT, ATTRIBUTE_FAR_FACE, ATTRIBUTE_SMOOTH_VOXEL, ATTRIBUTE_VOXEL,
+        BLOCK_TEXTURES_PATH, CHUNK_FRAGMENT_SHADER, CHUNK_PREPASS_SHADER, CHUNK_VERTEX_SHADER,
+    },
+};
 
 pub struct RenderingPlugin;
 
 impl Plugin for RenderingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(MaterialPlugin::<ChunkMaterial>::default());
+        let shader_compile = ChunkShaderCompile::default();
+
+        app.add_plugins(MaterialPlugin::<ChunkMaterial>::default())
+            // Far faces are decoded by the prepass shader too, so TAA gets their depth and motion
+            // vectors. They are too far away to cast visible shadows
+            .add_plugins(MaterialPlugin::<FarChunkMaterial> {
+                shadows_enabled: false,
+                ..default()
+            })
+            .add_plugins(ExtractResourcePlugin::<ChunkShader>::default())
+            .init_resource::<ChunkShaderBackup>()
+            .insert_resource(shader_compile.clone())
+            .add_systems(Startup, ChunkShader::load)
+            .add_systems(
+                Update,
+                (
+                    ChunkShader::report_reloads,
+                    (ChunkTextures::load, ChunkTextures::apply).chain(),
+                ),
+            );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(shader_compile)
+            .add_systems(ExtractSchedule, ChunkShaderCompile::extract_changes)
+            .add_systems(Render, ChunkShaderCompile::check.in_set(RenderSet::Cleanup));
+    }
+}
+
+// Strong handle to the chunk shader, keeps it loaded so it is watched for hot-reloading
+#[derive(Resource, ExtractResource, Clone)]
+pub struct ChunkShader(pub Handle<Shader>);
+
+// The chunk shader's last source whose pipelines compiled, put back when a reload doesn't compile
+#[derive(Resource, Default)]
+struct ChunkShaderBackup {
+    source: Option<Shader>,
+    // The backup was put back and hasn't finished compiling
+    restoring: bool,
+}
+
+impl ChunkShader {
+    fn load(mut commands: Commands, asset_server: Res<AssetServer>) {
+        commands.insert_resource(ChunkShader(asset_server.load(CHUNK_VERTEX_SHADER)));
+    }
+
+    // A reloaded shader replaces the old one before it is compiled, so the render world reports
+    // whether the chunk pipelines compiled with it, and the backup is put back if they didn't
+    fn report_reloads(
+        chunk_shader: Res<ChunkShader>,
+        shader_compile: Res<ChunkShaderCompile>,
+        mut backup: ResMut<ChunkShaderBackup>,
+        mut shaders: ResMut<Assets<Shader>>,
+        mut failed_events: EventReader<AssetLoadFailedEvent<Shader>>,
+    ) {
+        // Files which can't be read never replace the loaded shader
+        for event in failed_events.read() {
+            if event.id == chunk_shader.0.id() {
+                error!(
+                    "Failed to reload chunk shader, keeping previous version: {}",
+                    event.error
+                );
+            }
+        }
+
+        let Some(outcome) = shader_compile.take_outcome() else {
+            return;
+        };
+
+        match outcome {
+            Ok(()) => {
+                if backup.restoring {
+                    info!("Restored previous chunk shader");
+                } else if backup.source.is_some() {
+                    info!("Reloaded chunk shader");
+                }
+
+                backup.source = shaders.get(&chunk_shader.0).cloned();
+                backup.restoring = false;
+            }
+            // The backup failing too means the error is in something it imports, so it is left
+            // to be fixed rather than restored again
+            Err(err) if backup.restoring => {
+                error!("Previous chunk shader failed to compile as well: {err}");
+                backup.restoring = false;
+            }
+            Err(err) => match backup.source.clone() {
+                Some(source) => {
+                    error!("Failed to compile chunk shader, restoring previous version: {err}");
+                    shaders.insert(&chunk_shader.0, source);
+                    backup.restoring = true;
+                }
+                None => error!("Failed to compile chunk shader: {err}"),
+            },
+        }
+    }
+}
+
+// Shared by the main and render worlds, the render world reports whether the chunk shader's
+// pipelines compiled once they have all finished after a change to the shader
+#[derive(Resource, Clone, Default)]
+struct ChunkShaderCompile(Arc<Mutex<ShaderCompileState>>);
+
+#[derive(Default)]
+struct ShaderCompileState {
+    // The shader changed and its pipelines haven't all finished compiling
+    compiling: bool,
+    // Latest outcome the main world hasn't read yet
+    outcome: Option<Result<(), String>>,
+}
+
+impl ChunkShaderCompile {
+    fn state(&self) -> MutexGuard<'_, ShaderCompileState> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn take_outcome(&self) -> Option<Result<(), String>> {
+        self.state().outcome.take()
+    }
+
+    // The pipeline cache queues the shader's pipelines again when it changes
+    fn extract_changes(
+        shader_compile: Res<ChunkShaderCompile>,
+        chunk_shader: Option<Res<ChunkShader>>,
+        mut asset_events: Extract<EventReader<AssetEvent<Shader>>>,
+    ) {
+        let Some(chunk_shader) = chunk_shader else {
+            return;
+        };
+
+        let id = chunk_shader.0.id();
+        if asset_events
+            .read()
+            .any(|event| event.is_added(id) || event.is_modified(id))
+        {
+            shader_compile.state().compiling = true;
+        }
     }
+
+    // Runs after the pipelines were processed, a compile error is final until the shader changes
+    fn check(
+        shader_compile: Res<ChunkShaderCompile>,
+        chunk_shader: Option<Res<ChunkShader>>,
+        pipeline_cache: Res<PipelineCache>,
+    ) {
+        let mut state = shader_compile.state();
+        let Some(chunk_shader) = chunk_shader.filter(|_| state.compiling) else {
+            return;
+        };
+
+        let id = chunk_shader.0.id();
+        let mut outcome = Ok(());
+        let mut used = false;
+        for pipeline in pipeline_cache.pipelines() {
+            let PipelineDescriptor::RenderPipelineDescriptor(descriptor) = &pipeline.descriptor
+            else {
+                continue;
+            };
+            let uses_shader = descriptor.vertex.shader.id() == id
+                || descriptor
+                    .fragment
+                    .as_ref()
+                    .is_some_and(|fragment| fragment.shader.id() == id);
+            if !uses_shader {
+                continue;
+            }
+
+            used = true;
+            match &pipeline.state {
+                CachedPipelineState::Ok(_) => {}
+                CachedPipelineState::Err(
+                    err @ (PipelineCacheError::ProcessShaderError(_)
+                    | PipelineCacheError::CreateShaderModule(_)),
+                ) => outcome = Err(err.to_string()),
+                // Still compiling, or waiting on the shader or its imports to load
+                _ => return,
+            }
+        }
+
+        // Nothing has been drawn with it yet, so it is checked once something is
+        if !used {
+            return;
+        }
+
+        state.compiling = false;
+        state.outcome = Some(outcome);
+    }
+}
+
+// The block textures, loaded once a chunk material textures its voxels
+// The image holds a square tile for each texture layer, stacked vertically
+#[derive(Resource, Debug)]
+pub struct ChunkTextures {
+    pub image: Handle<Image>,
+    applied: bool,
+}
+
+impl ChunkTextures {
+    fn load(
+        mut commands: Commands,
+        asset_server: Res<AssetServer>,
+        textures: Option<Res<ChunkTextures>>,
+        materials: Res<Assets<ChunkMaterial>>,
+        far_materials: Res<Assets<FarChunkMaterial>>,
+    ) {
+        let is_textured = materials
+            .iter()
+            .map(|(_, material)| material)
+            .chain(far_materials.iter().map(|(_, material)| &material.base))
+            .any(ChunkMaterial::is_triplanar);
+        if textures.is_some() || !is_textured {
+            return;
+        }
+
+        commands.insert_resource(ChunkTextures {
+            image: asset_server.load(BLOCK_TEXTURES_PATH),
+            applied: false,
+        });
+    }
+
+    // Turn the loaded image into a texture array and give it to the textured materials
+    // Without the image, the textured materials go back to colouring their voxels
+    fn apply(
+        textures: Option<ResMut<ChunkTextures>>,
+        mut images: ResMut<Assets<Image>>,
+        mut materials: ResMut<Assets<ChunkMaterial>>,
+        mut far_materials: ResMut<Assets<FarChunkMaterial>>,
+        mut failed_events: EventReader<AssetLoadFailedEvent<Image>>,
+    ) {
+        let Some(mut textures) = textures else {
+            return;
+        };
+        if textures.applied {
+            return;
+        }
+
+        let materials = materials.iter_mut().map(|(_, material)| material).chain(
+            far_materials
+                .iter_mut()
+                .map(|(_, material)| &mut material.base),
+        );
+
+        if let Some(event) = failed_events
+            .read()
+            .find(|event| event.id == textures.image.id())
+        {
+            warn!(
+                "Failed to load the block textures: {}, colouring voxels instead",
+                event.error
+            );
+            for material in materials.filter(|material| material.is_triplanar()) {
+                material.texturing = ChunkTexturing::Colours;
+            }
+            textures.applied = true;
+            return;
+        }
+
+        let Some(image) = images.get_mut(&textures.image) else {
+            return;
+        };
+
+        let layers = image.height() / image.width().max(1);
+        if image.texture_descriptor.size.depth_or_array_layers == 1 && layers > 1 {
+            image.reinterpret_stacked_2d_as_array(layers);
+        }
+        let layer_colours = layer_colours(image);
+        if layer_colours.is_none() {
+            warn!(
+                "Block textures aren't 8 bit RGBA, so distant textures won't fade to their colours"
+            );
+        }
+        // Textures tile across greedy quads, and keep their pixels sharp up close
+        image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+            address_mode_u: ImageAddressMode::Repeat,
+            address_mode_v: ImageAddressMode::Repeat,
+            mag_filter: ImageFilterMode::Nearest,
+            ..default()
+        });
+
+        for material in materials.filter(|material| material.is_triplanar()) {
+            material.block_textures = Some(textures.image.clone());
+            if let Some(layer_colours) = &layer_colours {
+                material.set_block_colours(layer_colours);
+            }
+        }
+
+        info!("Loaded {layers} block texture layers from {BLOCK_TEXTURES_PATH}");
+        textures.applied = true;
+    }
+}
+
+// Average linear colour of each layer of the texture array, which distant faces fade to
+// None unless the layers are single 8 bit RGBA images
+fn layer_colours(image: &Image) -> Option<Vec<Vec4>> {
+    let descriptor = &image.texture_descriptor;
+    let is_srgb = match descriptor.format {
+        TextureFormat::Rgba8UnormSrgb => true,
+        TextureFormat::Rgba8Unorm => false,
+        _ => return None,
+    };
+    if descriptor.mip_level_count != 1 {
+        return None;
+    }
+
+    let layers = descriptor.size.depth_or_array_layers.max(1) as usize;
+    let layer_len = image.data.len() / layers;
+    if layer_len < 4 {
+        return None;
+    }
+
+    let colours = image
+        .data
+        .chunks_exact(layer_len)
+        .map(|layer| {
+            let sum = layer.chunks_exact(4).fold(Vec3::ZERO, |sum, pixel| {
+                let colour = if is_srgb {
+                    LinearRgba::from(Srgba::rgb_u8(pixel[0], pixel[1], pixel[2]))
+                } else {
+                    LinearRgba::rgb(
+                        pixel[0] as f32 / 255.,
+                        pixel[1] as f32 / 255.,
+                        pixel[2] as f32 / 255.,
+                    )
+                };
+                sum + Vec3::new(colour.red, colour.green, colour.blue)
+            });
+
+            (sum / (layer.len() / 4) as f32).extend(1.)
+        })
+        .collect();
+
+    Some(colours)
 }
 
 #[derive(Resource, Reflect)]
 pub struct GlobalChunkMaterial(pub Handle<ChunkMaterial>);
 
+// The chunk material drawing packed far faces, see FaceU32
+pub type FarChunkMaterial = ExtendedMaterial<ChunkMaterial, FarFaces>;
+
+#[derive(Resource, Reflect)]
+pub struct GlobalFarChunkMaterial(pub Handle<FarChunkMaterial>);
+
+// Switches the chunk shader to expanding packed faces, it has no data of its own
+#[derive(Asset, Reflect, AsBindGroup, Debug, Clone, Default)]
+pub struct FarFaces {}
+
+impl MaterialExtension for FarFaces {
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.vertex.shader_defs.push("FAR_FACES".into());
+        descriptor.vertex.buffers = vec![layout
+            .0
+            .get_layout(&[ATTRIBUTE_FAR_FACE.at_shader_location(0)])?];
+
+        Ok(())
+    }
+}
+
+// How the chunk shader colours voxels
+#[derive(Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkTexturing {
+    // Colours blended by height and biome
+    #[default]
+    Colours,
+    // The block textures projected along the faces' axes in world space, so merged greedy quads
+    // tile without per-vertex UVs, and blended by the normal on smooth terrain
+    Triplanar,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkMaterialKey {
+    triplanar: bool,
+}
+
+impl From<&ChunkMaterial> for ChunkMaterialKey {
+    fn from(material: &ChunkMaterial) -> Self {
+        Self {
+            triplanar: material.is_triplanar(),
+        }
+    }
+}
+
 #[derive(Asset, Reflect, AsBindGroup, Debug, Clone)]
+#[bind_group_data(ChunkMaterialKey)]
 pub struct ChunkMaterial {
     #[uniform(0)]
     pub reflectance: f32,
@@ -24,6 +430,99 @@ pub struct ChunkMaterial {
     pub perceptual_roughness: f32,
     #[uniform(0)]
     pub metallic: f32,
+    #[uniform(0)]
+    pub ao_strength: f32,
+    // Booleans are stored as u32 since they can't be used in uniforms (0 = disabled)
+    #[uniform(0)]
+    pub ao_enabled: u32,
+    #[uniform(0)]
+    pub face_shading_enabled: u32,
+    #[uniform(0)]
+    pub skylight_enabled: u32,
+    // Light left on faces at full sky darkness, deep in caves
+    #[uniform(0)]
+    pub skylight_min: f32,
+    // Darkening of faces in the baked terrain shadows, 0 disables them
+    #[uniform(0)]
+    pub sun_shadow_strength: f32,
+    // Biome tint ramp, blended between using each vertex's biome tint
+    #[uniform(0)]
+    pub biome_tint_low: LinearRgba,
+    #[uniform(0)]
+    pub biome_tint_high: LinearRgba,
+    // Voxel detail fades out between the start and end distances from the camera, textures toward
+    // their block's average colour and AO toward detail_fade_ao of its contrast
+    #[uniform(0)]
+    pub detail_fade_enabled: u32,
+    #[uniform(0)]
+    pub detail_fade_start: f32,
+    #[uniform(0)]
+    pub detail_fade_end: f32,
+    #[uniform(0)]
+    pub detail_fade_ao: f32,
+    // Set from ChunkTextures once the block textures have loaded
+    #[texture(1, dimension = "2d_array")]
+    #[sampler(2)]
+    pub block_textures: Option<Handle<Image>>,
+    // Lookup of each voxel type by id: the side, top and bottom texture layers, and the texture
+    // repeats per voxel. Packed vertices carry their face's layer, the lookup is for far faces and
+    // smooth terrain, so new blocks only need registry entries
+    #[storage(3, read_only)]
+    pub block_layers: Vec<Vec4>,
+    // Average colours of each voxel type's side, top and bottom textures, three per type in the
+    // order of block_layers. Set from ChunkTextures, until then the alpha is 0 and textures don't fade
+    #[storage(4, read_only)]
+    pub block_colours: Vec<Vec4>,
+    // Selects the shader variant, so colour-only materials don't sample textures
+    pub texturing: ChunkTexturing,
+}
+
+impl ChunkMaterial {
+    pub fn is_ao_enabled(&self) -> bool {
+        self.ao_enabled != 0
+    }
+
+    pub fn is_triplanar(&self) -> bool {
+        self.texturing == ChunkTexturing::Triplanar
+    }
+
+    // Average the faces of each voxel type in block_layers from the average colours of the texture layers
+    pub fn set_block_colours(&mut self, layer_colours: &[Vec4]) {
+        self.block_colours = self
+            .block_layers
+            .iter()
+            .flat_map(|layers| [layers.x, layers.y, layers.z])
+            .map(|layer| {
+                layer_colours
+                    .get(layer as usize)
+                    .copied()
+                    .unwrap_or(Vec4::ZERO)
+            })
+            .collect();
+    }
+
+    // The texture layers of the block registry's voxel types, each repeating scale times per voxel
+    pub fn registry_block_layers(scale: f32) -> Vec<Vec4> {
+        let registry = BlockRegistry::global();
+        // Storage buffers can't be empty, and ids without a definition use layer 0
+        let len = registry
+            .iter()
+            .map(|definition| definition.id as usize + 1)
+            .max();
+        let mut layers = vec![Vec4::new(0., 0., 0., scale); len.unwrap_or(1)];
+
+        for definition in registry.iter() {
+            let textures = definition.textures;
+            layers[definition.id as usize] = Vec4::new(
+                textures.side as f32,
+                textures.top as f32,
+                textures.bottom as f32,
+                scale,
+            );
+        }
+
+        layers
+    }
 }
 
 impl Material for ChunkMaterial {
@@ -35,6 +534,15 @@ impl Material for ChunkMaterial {
         CHUNK_FRAGMENT_SHADER.into()
     }
 
+    // The default prepass expects positions, so the packed vertices need their own prepass
+    fn prepass_vertex_shader() -> ShaderRef {
+        CHUNK_PREPASS_SHADER.into()
+    }
+
+    fn prepass_fragment_shader() -> ShaderRef {
+        CHUNK_PREPASS_SHADER.into()
+    }
+
     fn alpha_mode(&self) -> AlphaMode {
         AlphaMode::Opaque
     }
@@ -43,11 +551,62 @@ impl Material for ChunkMaterial {
         _pipeline: &bevy::pbr::MaterialPipeline<Self>,
         descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
         layout: &bevy::render::mesh::MeshVertexBufferLayoutRef,
-        _key: bevy::pbr::MaterialPipelineKey<Self>,
+        key: bevy::pbr::MaterialPipelineKey<Self>,
     ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
-        let vertex_layout = layout
-            .0
-            .get_layout(&[ATTRIBUTE_VOXEL.at_shader_location(0)])?;
+        if key.bind_group_data.triplanar {
+            descriptor.vertex.shader_defs.push("TRIPLANAR".into());
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("TRIPLANAR".into());
+            }
+        }
+
+        // Far meshes only have packed faces, their layout is set by the FarFaces extension
+        if layout.0.contains(ATTRIBUTE_FAR_FACE) {
+            return Ok(());
+        }
+
+        let is_prepass = descriptor
+            .vertex
+            .shader_defs
+            .contains(&"PREPASS_PIPELINE".into());
+
+        // Smooth meshes have unpacked positions and normals, see surface_nets
+        if layout.0.contains(ATTRIBUTE_SMOOTH_VOXEL) {
+            descriptor.vertex.shader_defs.push("SMOOTH_VERTICES".into());
+            // Smooth terrain has no face layers, so it is textured along every axis
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("SMOOTH_VERTICES".into());
+            }
+
+            let vertex_layout = if is_prepass {
+                layout.0.get_layout(&[
+                    Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+                    Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+                ])?
+            } else {
+                layout.0.get_layout(&[
+                    Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+                    Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+                    ATTRIBUTE_BIOME_TINT.at_shader_location(2),
+                    ATTRIBUTE_SMOOTH_VOXEL.at_shader_location(3),
+                ])?
+            };
+            descriptor.vertex.buffers = vec![vertex_layout];
+
+            return Ok(());
+        }
+
+        // Prepass and shadow pipelines only need the packed positions and normals
+        let vertex_layout = if is_prepass {
+            layout
+                .0
+                .get_layout(&[ATTRIBUTE_VOXEL.at_shader_location(0)])?
+        } else {
+            layout.0.get_layout(&[
+                ATTRIBUTE_VOXEL.at_shader_location(0),
+                ATTRIBUTE_BIOME_TINT.at_shader_location(1),
+            ])?
+        };
         descriptor.vertex.buffers = vec![vertex_layout];
 
         Ok(())
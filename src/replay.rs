@@ -0,0 +1,342 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use bevy::{app::AppExit, prelude::*, time::TimeUpdateStrategy, transform::TransformSystem};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunk_digest::ChunkDigests,
+    chunk_loading::ChunkLoader,
+    launch_options::LaunchOptions,
+    persistence,
+    positions::WorldPos,
+    voxel::VoxelType,
+    world::{PipelineMode, World},
+    world_edit::EditTransaction,
+    world_generator,
+};
+
+// Records the camera and loader trajectories and the voxel edits of every frame, and plays them
+// back frame by frame with the recorded frame times, for regression benchmarks and reproducing bugs
+// Both run the pipeline in deterministic mode, so a replay loads the same chunks on the same frames
+// Record with `cargo run -- --record <file>` (written on exit), replay with `cargo run -- --replay <file>`
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        // Apps without launch options, such as tests, neither record nor replay
+        let options = app
+            .world()
+            .get_resource::<LaunchOptions>()
+            .cloned()
+            .unwrap_or_default();
+        let replay = Replay::from_options(&options);
+
+        match replay.mode {
+            ReplayMode::Off => {}
+            ReplayMode::Record(_) => {
+                app.insert_resource(PipelineMode::Deterministic)
+                    .add_systems(Startup, Replay::start_recording)
+                    .add_systems(
+                        Last,
+                        (Replay::record, Replay::save_on_exit)
+                            .chain()
+                            .before(persistence::shutdown_on_exit),
+                    );
+            }
+            ReplayMode::Play(_) => {
+                let first_delta = replay
+                    .recording
+                    .frames
+                    .first()
+                    .map_or(Duration::ZERO, RecordedFrame::delta);
+
+                app.insert_resource(PipelineMode::Deterministic)
+                    .insert_resource(TimeUpdateStrategy::ManualDuration(first_delta))
+                    .add_systems(
+                        PostUpdate,
+                        Replay::play.before(TransformSystem::TransformPropagate),
+                    )
+                    .add_systems(Last, Replay::advance);
+            }
+        }
+
+        app.insert_resource(replay);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayMode {
+    Off,
+    Record(PathBuf),
+    Play(PathBuf),
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Recording {
+    pub seed: u64,
+    pub frames: Vec<RecordedFrame>,
+    // Root of the world digest after the last frame, a replay which ends with another root diverged
+    pub digest: Option<u64>,
+}
+
+// A frame's timestamp is the sum of the deltas up to it
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct RecordedFrame {
+    pub delta_nanos: u64,
+    // Translation and rotation
+    pub camera: Option<([f32; 3], [f32; 4])>,
+    // The loader is only on its own entity while pinned, see Spectator
+    pub loader: Option<[f32; 3]>,
+    pub edits: Vec<([i32; 3], u32)>,
+}
+
+impl RecordedFrame {
+    pub fn delta(&self) -> Duration {
+        Duration::from_nanos(self.delta_nanos)
+    }
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+        ron::from_str(&text).map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = ron::to_string(self).map_err(|err| err.to_string())?;
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+        }
+        fs::write(path, text).map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct Replay {
+    pub mode: ReplayMode,
+    pub recording: Recording,
+    // Frame of the recording which is played next
+    pub next_frame: usize,
+    // Edits which couldn't be applied, because their chunks weren't loaded when they were replayed
+    pub failed_edits: usize,
+    started: Instant,
+}
+
+impl Replay {
+    pub fn new(mode: ReplayMode) -> Self {
+        let recording = match &mode {
+            ReplayMode::Play(path) => match Recording::load(path) {
+                Ok(recording) => recording,
+                Err(err) => {
+                    error!("Failed to read the replay {}: {err}", path.display());
+                    return Self::new(ReplayMode::Off);
+                }
+            },
+            _ => Recording {
+                seed: world_generator::seed(),
+                ..default()
+            },
+        };
+
+        let seed = world_generator::seed();
+        if recording.seed != seed {
+            warn!(
+                "Replay was recorded with seed {}, not {seed}, it will diverge",
+                recording.seed
+            );
+        }
+
+        Self {
+            mode,
+            recording,
+            next_frame: 0,
+            failed_edits: 0,
+            started: Instant::now(),
+        }
+    }
+
+    // `--record <file>` or `--replay <file>`, which can't both be given
+    pub fn from_options(options: &LaunchOptions) -> Self {
+        let mode = match (&options.record, &options.replay) {
+            (Some(path), _) => ReplayMode::Record(path.clone()),
+            (None, Some(path)) => ReplayMode::Play(path.clone()),
+            (None, None) => ReplayMode::Off,
+        };
+
+        Self::new(mode)
+    }
+
+    fn start_recording(mut world: ResMut<World>) {
+        world.recorded_writes = Some(Vec::new());
+    }
+
+    fn record(
+        mut replay: ResMut<Replay>,
+        mut world: ResMut<World>,
+        time: Res<Time>,
+        cameras: Query<&Transform, With<Camera3d>>,
+        loaders: Query<&Transform, With<ChunkLoader>>,
+    ) {
+        let edits = world
+            .recorded_writes
+            .replace(Vec::new())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(world_pos, voxel_type)| {
+                let (x, y, z) = world_pos.to_tuple();
+                ([x, y, z], u32::from(voxel_type))
+            })
+            .collect();
+
+        replay.recording.frames.push(RecordedFrame {
+            delta_nanos: time.delta().as_nanos() as u64,
+            camera: cameras.get_single().ok().map(|transform| {
+                (
+                    transform.translation.to_array(),
+                    transform.rotation.to_array(),
+                )
+            }),
+            loader: loaders
+                .get_single()
+                .ok()
+                .map(|transform| transform.translation.to_array()),
+            edits,
+        });
+    }
+
+    fn save_on_exit(
+        mut replay: ResMut<Replay>,
+        mut digests: ResMut<ChunkDigests>,
+        world: Res<World>,
+        mut exit_events: EventReader<AppExit>,
+    ) {
+        if exit_events.read().next().is_none() {
+            return;
+        }
+        let ReplayMode::Record(path) = replay.mode.clone() else {
+            return;
+        };
+
+        digests.update(&world);
+        replay.recording.digest = Some(digests.digest().root);
+
+        match replay.recording.save(&path) {
+            Ok(()) => info!(
+                "Recorded {} frames to {}",
+                replay.recording.frames.len(),
+                path.display()
+            ),
+            Err(err) => error!("Failed to write the recording {}: {err}", path.display()),
+        }
+    }
+
+    fn play(
+        mut replay: ResMut<Replay>,
+        mut world: ResMut<World>,
+        mut loaders: Query<(&mut Transform, Has<Camera3d>), With<ChunkLoader>>,
+        mut cameras: Query<&mut Transform, (With<Camera3d>, Without<ChunkLoader>)>,
+    ) {
+        let Some(frame) = replay.recording.frames.get(replay.next_frame) else {
+            return;
+        };
+
+        let move_camera = |transform: &mut Transform| {
+            if let Some((translation, rotation)) = frame.camera {
+                transform.translation = Vec3::from_array(translation);
+                transform.rotation = Quat::from_array(rotation);
+            }
+        };
+
+        // The camera holds the loader unless it is pinned
+        if let Ok((mut transform, is_camera)) = loaders.get_single_mut() {
+            if is_camera {
+                move_camera(&mut transform);
+            } else if let Some(translation) = frame.loader {
+                transform.translation = Vec3::from_array(translation);
+            }
+        }
+        if let Ok(mut transform) = cameras.get_single_mut() {
+            move_camera(&mut transform);
+        }
+
+        if frame.edits.is_empty() {
+            return;
+        }
+
+        let writes = frame
+            .edits
+            .iter()
+            .map(|&([x, y, z], voxel_type)| {
+                Some((WorldPos::new(x, y, z), VoxelType::try_from_u32(voxel_type)?))
+            })
+            .collect::<Option<Vec<_>>>();
+        let edits = frame.edits.len();
+
+        let result = match writes {
+            Some(writes) => {
+                let mut transaction = EditTransaction::new();
+                for (world_pos, voxel_type) in writes {
+                    transaction.set_voxel(world_pos, voxel_type);
+                }
+
+                world.commit(transaction).map_err(|err| err.to_string())
+            }
+            None => Err("Voxel types missing from the block registry".to_string()),
+        };
+
+        if let Err(err) = result {
+            warn!("Replay frame {}: {err}", replay.next_frame);
+            replay.failed_edits += edits;
+        }
+    }
+
+    fn advance(
+        mut replay: ResMut<Replay>,
+        mut digests: ResMut<ChunkDigests>,
+        world: Res<World>,
+        mut time_update: ResMut<TimeUpdateStrategy>,
+        mut exit_events: EventWriter<AppExit>,
+    ) {
+        replay.next_frame += 1;
+
+        // The next frame runs with the delta it was recorded with
+        if let Some(frame) = replay.recording.frames.get(replay.next_frame) {
+            *time_update = TimeUpdateStrategy::ManualDuration(frame.delta());
+            return;
+        }
+        if replay.next_frame > replay.recording.frames.len() {
+            return;
+        }
+
+        digests.update(&world);
+        let digest = digests.digest().root;
+        let diverged = replay
+            .recording
+            .digest
+            .is_some_and(|recorded| recorded != digest);
+
+        info!(
+            "Replayed {} frames in {:.2}s",
+            replay.recording.frames.len(),
+            replay.started.elapsed().as_secs_f32()
+        );
+
+        if diverged || replay.failed_edits > 0 {
+            error!(
+                "Replay diverged: world digest {digest:016x}, recorded {:016x}, {} edits failed",
+                replay.recording.digest.unwrap_or_default(),
+                replay.failed_edits
+            );
+            exit_events.send(AppExit::error());
+        } else {
+            info!("Replay matched the recording, world digest {digest:016x}");
+            exit_events.send(AppExit::Success);
+        }
+    }
+}
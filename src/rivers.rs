@@ -0,0 +1,41 @@
+use bracket_noise::prelude::*;
+
+use crate::{
+    constants::{RIVER_DEPTH, RIVER_FREQUENCY, RIVER_WIDTH},
+    world_generator,
+};
+
+// Flow field of river channels, sampled in world space so that rivers line up across chunks
+pub struct RiverMap {
+    noise: FastNoise,
+}
+
+impl Default for RiverMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RiverMap {
+    pub fn new() -> Self {
+        let mut noise = FastNoise::seeded(world_generator::seed() + 2);
+        noise.set_noise_type(NoiseType::SimplexFractal);
+        noise.set_frequency(RIVER_FREQUENCY);
+        noise.set_fractal_octaves(3);
+
+        Self { noise }
+    }
+
+    // How far the terrain is lowered at this column, rivers follow the zero crossings of the noise
+    pub fn carve_depth(&self, x: i32, z: i32) -> f32 {
+        let distance = self.noise.get_noise(x as f32, z as f32).abs();
+
+        if distance >= RIVER_WIDTH {
+            return 0.;
+        }
+
+        // Smooth the banks so that the channel is deepest in the middle
+        let t = 1. - distance / RIVER_WIDTH;
+        RIVER_DEPTH * t * t * (3. - 2. * t)
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    block_registry::BlockRegistry,
+    byte_codec::{write_varint, ByteReader},
+    constants::{MAX_SCHEMATIC_SIZE, SCHEMATIC_DIRECTORY, SCHEMATIC_FORMAT_MAGIC},
+    positions::{ChunkPos, WorldPos},
+    voxel::VoxelType,
+    voxel_grid::VoxelGrid,
+    world::World,
+    world_edit::{EditError, EditTransaction},
+};
+
+// WorldEdit-style structures: a box of voxels copied from the world, saved to a file and placed
+// elsewhere, turned and mirrored. Voxels placed in chunks which aren't loaded wait in the world's
+// pending edits until the chunks load
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schematic {
+    pub size: UVec3,
+    // The voxel which lands on the position the schematic is placed at, relative to the box's minimum
+    pub anchor: IVec3,
+    pub palette: Vec<VoxelType>,
+    // Palette indices, x fastest then y then z
+    pub voxels: Vec<u16>,
+}
+
+// How a schematic is turned when placed, mirroring is applied before the turns
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SchematicPlacement {
+    // Quarter turns clockwise when seen from above, around the anchor
+    pub quarter_turns: u8,
+    pub mirror_x: bool,
+    pub mirror_z: bool,
+    // Leave the world's voxels where the schematic has air, rather than clearing them
+    pub skip_air: bool,
+}
+
+impl SchematicPlacement {
+    // Where a position relative to the anchor ends up
+    pub fn apply(&self, offset: IVec3) -> IVec3 {
+        let mut offset = offset;
+        if self.mirror_x {
+            offset.x = -offset.x;
+        }
+        if self.mirror_z {
+            offset.z = -offset.z;
+        }
+
+        for _ in 0..self.quarter_turns % 4 {
+            offset = IVec3::new(-offset.z, offset.y, offset.x);
+        }
+
+        offset
+    }
+}
+
+impl Schematic {
+    pub fn volume(&self) -> usize {
+        self.size.x as usize * self.size.y as usize * self.size.z as usize
+    }
+
+    // Voxels by their position in the box
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, VoxelType)> + '_ {
+        let size = self.size.as_ivec3();
+
+        self.voxels
+            .iter()
+            .enumerate()
+            .map(move |(index, &palette_index)| {
+                let index = index as i32;
+                let pos = IVec3::new(
+                    index % size.x,
+                    index / size.x % size.y,
+                    index / (size.x * size.y),
+                );
+
+                (pos, self.palette[palette_index as usize])
+            })
+    }
+
+    // Palette as block names, so schematics survive the registry's ids changing, then runs of
+    // palette indices, like the chunk format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![SCHEMATIC_FORMAT_MAGIC];
+
+        for component in self.size.to_array() {
+            write_varint(&mut bytes, component);
+        }
+        for component in self.anchor.to_array() {
+            bytes.extend_from_slice(&component.to_le_bytes());
+        }
+
+        write_varint(&mut bytes, self.palette.len() as u32);
+        for voxel_type in &self.palette {
+            let name = voxel_type.name();
+            write_varint(&mut bytes, name.len() as u32);
+            bytes.extend_from_slice(name.as_bytes());
+        }
+
+        let mut runs: Vec<(u32, u16)> = Vec::new();
+        for &palette_index in &self.voxels {
+            match runs.last_mut() {
+                Some((length, last)) if *last == palette_index => *length += 1,
+                _ => runs.push((1, palette_index)),
+            }
+        }
+        for (length, palette_index) in runs {
+            write_varint(&mut bytes, length);
+            write_varint(&mut bytes, palette_index as u32);
+        }
+
+        bytes
+    }
+
+    // Returns None for corrupted schematics, or ones with blocks the registry doesn't define
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.read_u8()? != SCHEMATIC_FORMAT_MAGIC {
+            return None;
+        }
+
+        let size = UVec3::new(
+            reader.read_varint()?,
+            reader.read_varint()?,
+            reader.read_varint()?,
+        );
+        if size.max_element() > MAX_SCHEMATIC_SIZE {
+            return None;
+        }
+        let anchor = IVec3::new(
+            reader.read_u32()? as i32,
+            reader.read_u32()? as i32,
+            reader.read_u32()? as i32,
+        );
+
+        let registry = BlockRegistry::global();
+        let palette = (0..reader.read_varint()?)
+            .map(|_| {
+                let len = reader.read_varint()? as usize;
+                let name = std::str::from_utf8(reader.read_bytes(len)?).ok()?;
+                registry.by_name(name)
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let volume = size.x as usize * size.y as usize * size.z as usize;
+        let mut voxels = Vec::with_capacity(volume);
+        while !reader.is_empty() {
+            let length = reader.read_varint()? as usize;
+            let palette_index = reader.read_varint()?;
+            if palette_index as usize >= palette.len() || voxels.len() + length > volume {
+                return None;
+            }
+
+            voxels.resize(voxels.len() + length, palette_index as u16);
+        }
+
+        (voxels.len() == volume).then_some(Self {
+            size,
+            anchor,
+            palette,
+            voxels,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Schematic is corrupted or has undefined blocks",
+            )
+        })
+    }
+}
+
+// Voxels outside the box are air
+impl VoxelGrid for Schematic {
+    fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    fn voxel_at(&self, pos: IVec3) -> VoxelType {
+        let size = self.size.as_ivec3();
+        if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(size).any() {
+            return VoxelType::AIR;
+        }
+
+        let index = pos.x + (pos.y + pos.z * size.y) * size.x;
+        self.palette[self.voxels[index as usize] as usize]
+    }
+}
+
+pub fn schematic_path(name: &str) -> PathBuf {
+    PathBuf::from(SCHEMATIC_DIRECTORY).join(format!("{name}.schematic"))
+}
+
+impl World {
+    // Copy the voxels in the box between two corners (inclusive) into a schematic, anchored at the
+    // given position, fails if any of the box's chunks aren't loaded
+    pub fn copy_region(
+        &self,
+        corner: WorldPos,
+        opposite_corner: WorldPos,
+        anchor: WorldPos,
+    ) -> Result<Schematic, EditError> {
+        let (a, b) = (
+            IVec3::from(corner.to_tuple()),
+            IVec3::from(opposite_corner.to_tuple()),
+        );
+        let (min, max) = (a.min(b), a.max(b));
+        let size = (max - min + 1).as_uvec3();
+
+        let mut palette = Vec::new();
+        let mut voxels = Vec::with_capacity(size.x as usize * size.y as usize * size.z as usize);
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let world_pos = WorldPos::new(x, y, z);
+                    let Some(voxel) = self.get_voxel(world_pos) else {
+                        return Err(EditError::ChunkNotLoaded(
+                            WorldPos::to_voxel_pos(world_pos).1,
+                        ));
+                    };
+
+                    let palette_index = match palette.iter().position(|&t| t == voxel.voxel_type) {
+                        Some(palette_index) => palette_index,
+                        None => {
+                            palette.push(voxel.voxel_type);
+                            palette.len() - 1
+                        }
+                    };
+                    voxels.push(palette_index as u16);
+                }
+            }
+        }
+
+        Ok(Schematic {
+            size,
+            anchor: IVec3::from(anchor.to_tuple()) - min,
+            palette,
+            voxels,
+        })
+    }
+
+    // Place a schematic with its anchor at the position, returns the voxels changed straight away
+    // Writes to loaded chunks are one undo entry, the rest are applied as their chunks load
+    pub fn place_schematic(
+        &mut self,
+        schematic: &Schematic,
+        world_pos: WorldPos,
+        placement: SchematicPlacement,
+    ) -> Result<usize, EditError> {
+        let registry = BlockRegistry::global();
+        if let Some(&voxel_type) = schematic
+            .palette
+            .iter()
+            .find(|&&voxel_type| registry.get(voxel_type).is_none())
+        {
+            return Err(EditError::UndefinedBlock(voxel_type));
+        }
+
+        let origin = IVec3::from(world_pos.to_tuple());
+        let mut transaction = EditTransaction::new();
+        let mut pending: HashMap<ChunkPos, Vec<(WorldPos, VoxelType)>> = HashMap::new();
+
+        for (pos, voxel_type) in schematic.iter() {
+            if placement.skip_air && voxel_type == VoxelType::AIR {
+                continue;
+            }
+
+            let target = origin + placement.apply(pos - schematic.anchor);
+            let target = WorldPos::new(target.x, target.y, target.z);
+            let (_, chunk_pos) = WorldPos::to_voxel_pos(target);
+
+            if self.chunks.contains_key(&chunk_pos) {
+                transaction.set_voxel(target, voxel_type);
+            } else {
+                pending
+                    .entry(chunk_pos)
+                    .or_default()
+                    .push((target, voxel_type));
+            }
+        }
+
+        for (chunk_pos, writes) in pending {
+            self.pending_edits
+                .entry(chunk_pos)
+                .or_default()
+                .extend(writes);
+        }
+
+        self.commit(transaction)
+    }
+
+    // Apply the pending edits of chunks which have loaded, these aren't undoable
+    pub fn apply_pending_edits(mut world: ResMut<World>) {
+        if world.pending_edits.is_empty() {
+            return;
+        }
+
+        let World {
+            pending_edits,
+            chunks,
+            ..
+        } = world.as_mut();
+
+        let loaded = pending_edits
+            .keys()
+            .copied()
+            .filter(|chunk_pos| chunks.contains_key(chunk_pos))
+            .collect::<Vec<_>>();
+        let writes = loaded
+            .into_iter()
+            .filter_map(|chunk_pos| pending_edits.remove(&chunk_pos))
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if !writes.is_empty() {
+            world.apply_writes(writes);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use bevy::{
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        experimental::taa::{
+            TemporalAntiAliasBundle, TemporalAntiAliasPlugin, TemporalAntiAliasSettings,
+        },
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+        fxaa::Fxaa,
+        prepass::{MotionVectorPrepass, ViewPrepassTextures},
+    },
+    ecs::query::QueryItem,
+    pbr::{
+        ScreenSpaceAmbientOcclusionBundle, ScreenSpaceAmbientOcclusionQualityLevel,
+        ScreenSpaceAmbientOcclusionSettings,
+    },
+    prelude::*,
+    render::{
+        camera::{RenderTarget, TemporalJitter},
+        extract_component::{
+            ComponentUniforms, DynamicUniformIndex, ExtractComponentPlugin, UniformComponentPlugin,
+        },
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            binding_types::{
+                sampler, texture_2d, texture_2d_multisampled, texture_depth_2d,
+                texture_depth_2d_multisampled, uniform_buffer,
+            },
+            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FragmentState, MultisampleState, Operations,
+            PipelineCache, PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
+            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
+            TextureFormat, TextureSampleType,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::BevyDefault,
+        view::ViewTarget,
+        RenderApp,
+    },
+};
+
+use crate::constants::OUTLINE_SHADER;
+
+// Screen space effects for the voxel terrain: Bevy's SSAO, a depth/normal edge outline and the
+// anti-aliasing selected by the AntiAliasing resource
+pub struct ScreenEffectsPlugin;
+
+impl Plugin for ScreenEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<VoxelOutline>()
+            .register_type::<AntiAliasing>()
+            .init_resource::<AntiAliasing>()
+            .add_plugins((
+                ExtractComponentPlugin::<VoxelOutline>::default(),
+                UniformComponentPlugin::<VoxelOutline>::default(),
+                TemporalAntiAliasPlugin,
+            ))
+            .add_systems(Update, AntiAliasing::apply);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<OutlineNode>>(Core3d, OutlineLabel)
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::Tonemapping,
+                    OutlineLabel,
+                    Node3d::EndMainPassPostProcessing,
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<OutlinePipeline>();
+    }
+}
+
+// SSAO settings which suit the blocky terrain, they are suspended while MSAA is selected
+pub fn voxel_ssao_bundle() -> ScreenSpaceAmbientOcclusionBundle {
+    ScreenSpaceAmbientOcclusionBundle {
+        settings: ScreenSpaceAmbientOcclusionSettings {
+            quality_level: ScreenSpaceAmbientOcclusionQualityLevel::Medium,
+        },
+        ..default()
+    }
+}
+
+// How the edges of the window cameras are smoothed
+#[derive(Resource, Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub enum AntiAliasing {
+    #[default]
+    Off,
+    // Post process blur of the edges found in the final image, cheap but softens textures
+    Fxaa,
+    // 4 samples per pixel. Bevy's SSAO can't read multisampled prepass textures, so it is
+    // suspended on the cameras until another option is selected
+    Msaa4,
+    // Jitters the camera and blends with earlier frames reprojected by the prepass motion vectors
+    Taa,
+}
+
+// SSAO settings taken off a camera while MSAA is selected
+#[derive(Component)]
+struct SuspendedSsao(ScreenSpaceAmbientOcclusionSettings);
+
+type AntiAliasedCamera = (
+    Entity,
+    &'static Camera,
+    Has<Fxaa>,
+    Has<TemporalAntiAliasSettings>,
+    Option<&'static ScreenSpaceAmbientOcclusionSettings>,
+    Option<&'static SuspendedSsao>,
+);
+
+impl AntiAliasing {
+    pub const ALL: &'static [Self] = &[Self::Off, Self::Fxaa, Self::Msaa4, Self::Taa];
+
+    pub fn msaa(self) -> Msaa {
+        match self {
+            Self::Msaa4 => Msaa::Sample4,
+            _ => Msaa::Off,
+        }
+    }
+
+    // Runs when the option changes or a camera is spawned, so cameras spawned later (e.g. by the
+    // spectator) get the same anti-aliasing. Thumbnail cameras render into images and are left alone
+    fn apply(
+        mut commands: Commands,
+        anti_aliasing: Res<AntiAliasing>,
+        mut msaa: ResMut<Msaa>,
+        cameras: Query<AntiAliasedCamera, With<Camera3d>>,
+        added: Query<(), Added<Camera3d>>,
+    ) {
+        if !anti_aliasing.is_changed() && added.is_empty() {
+            return;
+        }
+
+        // Msaa is global, so thumbnails are multisampled too
+        msaa.set_if_neq(anti_aliasing.msaa());
+
+        for (entity, camera, has_fxaa, has_taa, ssao, suspended_ssao) in cameras.iter() {
+            if !matches!(camera.target, RenderTarget::Window(_)) {
+                continue;
+            }
+            let mut entity = commands.entity(entity);
+
+            match (*anti_aliasing == Self::Fxaa, has_fxaa) {
+                (true, false) => {
+                    entity.insert(Fxaa::default());
+                }
+                (false, true) => {
+                    entity.remove::<Fxaa>();
+                }
+                _ => {}
+            }
+
+            // The depth prepass is kept, SSAO and the outline read it
+            match (*anti_aliasing == Self::Taa, has_taa) {
+                (true, false) => {
+                    entity.insert(TemporalAntiAliasBundle::default());
+                }
+                (false, true) => {
+                    entity.remove::<(
+                        TemporalAntiAliasSettings,
+                        TemporalJitter,
+                        MotionVectorPrepass,
+                    )>();
+                }
+                _ => {}
+            }
+
+            match (*anti_aliasing == Self::Msaa4, ssao, suspended_ssao) {
+                (true, Some(ssao), _) => {
+                    entity
+                        .remove::<ScreenSpaceAmbientOcclusionSettings>()
+                        .insert(SuspendedSsao(ssao.clone()));
+                }
+                (false, _, Some(SuspendedSsao(ssao))) => {
+                    entity.remove::<SuspendedSsao>().insert(ssao.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub use outline_settings::VoxelOutline;
+
+// The ShaderType derive generates per-field checks which newer compilers report as unused
+#[allow(dead_code)]
+mod outline_settings {
+    use bevy::{
+        prelude::*,
+        render::{extract_component::ExtractComponent, render_resource::ShaderType},
+    };
+
+    // Draws lines where the depth or the normal changes sharply, add to a camera with a depth and normal prepass
+    #[derive(Component, ExtractComponent, ShaderType, Reflect, Debug, Copy, Clone)]
+    #[reflect(Component)]
+    pub struct VoxelOutline {
+        // The alpha is how strongly the outline covers the scene
+        pub colour: LinearRgba,
+        // Distance in pixels that neighbouring samples are taken at
+        pub thickness: f32,
+        // Relative change in depth which counts as an edge
+        pub depth_threshold: f32,
+        // Change in normal (1 - cos angle) which counts as an edge
+        pub normal_threshold: f32,
+    }
+
+    impl Default for VoxelOutline {
+        fn default() -> Self {
+            Self {
+                colour: LinearRgba::new(0.02, 0.02, 0.03, 0.6),
+                thickness: 1.,
+                depth_threshold: 0.05,
+                normal_threshold: 0.5,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct OutlineLabel;
+
+#[derive(Default)]
+struct OutlineNode;
+
+impl ViewNode for OutlineNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static ViewPrepassTextures,
+        &'static DynamicUniformIndex<VoxelOutline>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, prepass_textures, settings_index): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        // Nothing to outline without both prepass textures
+        let (Some(depth), Some(normal)) = (&prepass_textures.depth, &prepass_textures.normal)
+        else {
+            return Ok(());
+        };
+
+        let outline_pipeline = world.resource::<OutlinePipeline>();
+        let variant = if depth.texture.texture.sample_count() > 1 {
+            &outline_pipeline.multisampled
+        } else {
+            &outline_pipeline.single_sampled
+        };
+
+        let Some(pipeline) = world
+            .resource::<PipelineCache>()
+            .get_render_pipeline(variant.pipeline_id)
+        else {
+            return Ok(());
+        };
+
+        let Some(settings_binding) = world
+            .resource::<ComponentUniforms<VoxelOutline>>()
+            .uniforms()
+            .binding()
+        else {
+            return Ok(());
+        };
+
+        let post_process = view_target.post_process_write();
+
+        let bind_group = render_context.render_device().create_bind_group(
+            "voxel_outline_bind_group",
+            &variant.layout,
+            &BindGroupEntries::sequential((
+                post_process.source,
+                &outline_pipeline.sampler,
+                &depth.texture.default_view,
+                &normal.texture.default_view,
+                settings_binding,
+            )),
+        );
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("voxel_outline_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[settings_index.index()]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+struct OutlinePipeline {
+    sampler: Sampler,
+    single_sampled: OutlineVariant,
+    // Reads the prepass textures of cameras drawn with MSAA
+    multisampled: OutlineVariant,
+}
+
+struct OutlineVariant {
+    layout: BindGroupLayout,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl OutlineVariant {
+    fn queue(world: &mut World, multisampled: bool) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let (depth_texture, normal_texture) = if multisampled {
+            (
+                texture_depth_2d_multisampled(),
+                texture_2d_multisampled(TextureSampleType::Float { filterable: false }),
+            )
+        } else {
+            (
+                texture_depth_2d(),
+                texture_2d(TextureSampleType::Float { filterable: false }),
+            )
+        };
+
+        let layout = render_device.create_bind_group_layout(
+            "voxel_outline_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                    depth_texture,
+                    normal_texture,
+                    uniform_buffer::<VoxelOutline>(true),
+                ),
+            ),
+        );
+
+        let shader = world.resource::<AssetServer>().load(OUTLINE_SHADER);
+        let shader_defs = if multisampled {
+            vec!["MULTISAMPLED".into()]
+        } else {
+            vec![]
+        };
+
+        let pipeline_id =
+            world
+                .resource_mut::<PipelineCache>()
+                .queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("voxel_outline_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    vertex: fullscreen_shader_vertex_state(),
+                    fragment: Some(FragmentState {
+                        shader,
+                        shader_defs,
+                        entry_point: "fragment".into(),
+                        // The camera isn't HDR, so after tonemapping the view target uses the default format
+                        targets: vec![Some(ColorTargetState {
+                            format: TextureFormat::bevy_default(),
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    // Drawn into the resolved post process texture, so it isn't multisampled itself
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                });
+
+        Self {
+            layout,
+            pipeline_id,
+        }
+    }
+}
+
+impl FromWorld for OutlinePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let sampler = world
+            .resource::<RenderDevice>()
+            .create_sampler(&SamplerDescriptor::default());
+
+        Self {
+            sampler,
+            single_sampled: OutlineVariant::queue(world, false),
+            multisampled: OutlineVariant::queue(world, true),
+        }
+    }
+}
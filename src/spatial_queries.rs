@@ -0,0 +1,220 @@
+use bevy::prelude::Entity;
+
+use crate::{
+    chunk::Chunk,
+    constants::{CHUNK_HEIGHT, CHUNK_SIZE},
+    positions::{ChunkPos, VoxelPos, WorldPos},
+    voxel::{Voxel, VoxelType},
+    world::World,
+};
+
+// Spatial queries over the loaded chunks, chunks which are entirely air are skipped wholesale
+impl World {
+    pub fn get_voxel(&self, world_pos: WorldPos) -> Option<&Voxel> {
+        let (voxel_pos, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        self.chunks.get(&chunk_pos).map(|chunk| &chunk[voxel_pos])
+    }
+
+    // Every chunk with loaded data, in no particular order
+    pub fn iter_loaded_chunks(&self) -> impl Iterator<Item = (ChunkPos, &Chunk)> + '_ {
+        self.chunks
+            .iter()
+            .map(|(&chunk_pos, chunk)| (chunk_pos, chunk.as_ref()))
+    }
+
+    // Every chunk which has been meshed, along with the entity its section meshes are parented to
+    pub fn iter_meshed_chunks(&self) -> impl Iterator<Item = (ChunkPos, Entity)> + '_ {
+        self.chunk_entities
+            .iter()
+            .map(|(&chunk_pos, &entity)| (chunk_pos, entity))
+    }
+
+    // Mesh entity of every non-empty section, along with its chunk
+    pub fn iter_section_entities(&self) -> impl Iterator<Item = (ChunkPos, Entity)> + '_ {
+        self.section_entities
+            .iter()
+            .flat_map(|(&chunk_pos, sections)| {
+                sections
+                    .iter()
+                    .flatten()
+                    .map(move |&entity| (chunk_pos, entity))
+            })
+    }
+
+    pub fn is_loaded(&self, chunk_pos: ChunkPos) -> bool {
+        self.chunks.contains_key(&chunk_pos)
+    }
+
+    // The entity a meshed chunk's section meshes are parented to
+    pub fn entity_at(&self, chunk_pos: ChunkPos) -> Option<Entity> {
+        self.chunk_entities.get(&chunk_pos).copied()
+    }
+
+    // The chunk a chunk entity belongs to, None for any other entity
+    pub fn chunk_of(&self, entity: Entity) -> Option<ChunkPos> {
+        self.entity_chunks.get(&entity).copied()
+    }
+
+    // Call f on every loaded voxel within the radius of the center, voxels in unloaded chunks are skipped
+    pub fn visit_voxels_in_radius(
+        &self,
+        center: WorldPos,
+        radius: u32,
+        mut f: impl FnMut(WorldPos, &Voxel),
+    ) {
+        let radius = radius as i32;
+        let min = center + WorldPos::new(-radius, -radius, -radius);
+        let max = center + WorldPos::new(radius, radius, radius);
+
+        for chunk_pos in chunks_in_box(min, max) {
+            let Some(chunk) = self.chunks.get(&chunk_pos) else {
+                continue;
+            };
+
+            for voxel_pos in voxels_in_chunk_box(chunk_pos, min, max) {
+                let world_pos = WorldPos::from_voxel_pos(voxel_pos, chunk_pos);
+                let (dx, dy, dz) = (
+                    world_pos.x - center.x,
+                    world_pos.y - center.y,
+                    world_pos.z - center.z,
+                );
+
+                if dx * dx + dy * dy + dz * dz <= radius * radius {
+                    f(world_pos, &chunk[voxel_pos]);
+                }
+            }
+        }
+    }
+
+    // Whether every voxel in the (inclusive) box is non-solid, unloaded chunks are never free
+    pub fn is_box_free(&self, min: WorldPos, max: WorldPos) -> bool {
+        let (min, max) = order_box(min, max);
+
+        chunks_in_box(min, max).all(|chunk_pos| {
+            let Some(chunk) = self.chunks.get(&chunk_pos) else {
+                return false;
+            };
+
+            chunk.is_all_air()
+                || voxels_in_chunk_box(chunk_pos, min, max)
+                    .all(|voxel_pos| !chunk[voxel_pos].voxel_type.is_solid())
+        })
+    }
+
+    // All solid voxels in the (inclusive) box, only loaded chunks are searched
+    pub fn solid_voxels_in_box(
+        &self,
+        min: WorldPos,
+        max: WorldPos,
+    ) -> impl Iterator<Item = (WorldPos, VoxelType)> + '_ {
+        let (min, max) = order_box(min, max);
+
+        chunks_in_box(min, max)
+            .filter_map(|chunk_pos| {
+                self.chunks
+                    .get(&chunk_pos)
+                    .filter(|chunk| !chunk.is_all_air())
+                    .map(|chunk| (chunk_pos, chunk))
+            })
+            .flat_map(move |(chunk_pos, chunk)| {
+                voxels_in_chunk_box(chunk_pos, min, max).filter_map(move |voxel_pos| {
+                    let voxel_type = chunk[voxel_pos].voxel_type;
+
+                    voxel_type
+                        .is_solid()
+                        .then(|| (WorldPos::from_voxel_pos(voxel_pos, chunk_pos), voxel_type))
+                })
+            })
+    }
+
+    // Whether no opaque voxel is above the position, using the column heightmaps
+    // Searching stops at the first unloaded chunk, which is assumed to let the sky through
+    pub fn sky_exposure(&self, world_pos: WorldPos) -> bool {
+        let (voxel_pos, mut chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        // Only voxels strictly above the position block it in its own chunk
+        let mut min_y = voxel_pos.y + 1;
+        while let Some(chunk) = self.chunks.get(&chunk_pos) {
+            if chunk
+                .column_height(voxel_pos.x, voxel_pos.z)
+                .is_some_and(|height| height >= min_y)
+            {
+                return false;
+            }
+
+            chunk_pos.y += 1;
+            min_y = 0;
+        }
+
+        true
+    }
+
+    // First solid voxel strictly below the position, stops searching at the first unloaded chunk
+    pub fn nearest_solid_below(&self, world_pos: WorldPos) -> Option<WorldPos> {
+        let mut y = world_pos.y - 1;
+
+        loop {
+            let (voxel_pos, chunk_pos) =
+                WorldPos::to_voxel_pos(WorldPos::new(world_pos.x, y, world_pos.z));
+            let chunk = self.chunks.get(&chunk_pos)?;
+
+            if !chunk.is_all_air() {
+                for local_y in (0..=voxel_pos.y).rev() {
+                    let sample_pos = VoxelPos::new(voxel_pos.x, local_y, voxel_pos.z);
+
+                    if chunk[sample_pos].voxel_type.is_solid() {
+                        return Some(WorldPos::from_voxel_pos(sample_pos, chunk_pos));
+                    }
+                }
+            }
+
+            // Continue from the top of the chunk below
+            y = chunk_pos.y * CHUNK_HEIGHT as i32 - 1;
+        }
+    }
+}
+
+fn order_box(a: WorldPos, b: WorldPos) -> (WorldPos, WorldPos) {
+    (
+        WorldPos::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+        WorldPos::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+    )
+}
+
+// Chunk positions which overlap the (inclusive) box
+fn chunks_in_box(min: WorldPos, max: WorldPos) -> impl Iterator<Item = ChunkPos> {
+    let (_, min_chunk) = WorldPos::to_voxel_pos(min);
+    let (_, max_chunk) = WorldPos::to_voxel_pos(max);
+
+    (min_chunk.z..=max_chunk.z).flat_map(move |z| {
+        (min_chunk.y..=max_chunk.y)
+            .flat_map(move |y| (min_chunk.x..=max_chunk.x).map(move |x| ChunkPos::new(x, y, z)))
+    })
+}
+
+// Voxel positions of a chunk which lie inside the (inclusive) box
+fn voxels_in_chunk_box(
+    chunk_pos: ChunkPos,
+    min: WorldPos,
+    max: WorldPos,
+) -> impl Iterator<Item = VoxelPos> {
+    let origin = WorldPos::from_voxel_pos(VoxelPos::new(0, 0, 0), chunk_pos);
+    let local_range = |min: i32, max: i32, origin: i32, size: usize| {
+        let start = (min - origin).clamp(0, size as i32 - 1) as usize;
+        let end = (max - origin).clamp(0, size as i32 - 1) as usize;
+
+        start..=end
+    };
+
+    let x_range = local_range(min.x, max.x, origin.x, CHUNK_SIZE);
+    let y_range = local_range(min.y, max.y, origin.y, CHUNK_HEIGHT);
+    let z_range = local_range(min.z, max.z, origin.z, CHUNK_SIZE);
+
+    z_range.flat_map(move |z| {
+        let x_range = x_range.clone();
+        y_range
+            .clone()
+            .flat_map(move |y| x_range.clone().map(move |x| VoxelPos::new(x, y, z)))
+    })
+}
@@ -0,0 +1,286 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    chunk_loading::ChunkLoader,
+    constants::{
+        CHUNK_SIZE, MAX_SPAWNS_PER_CHUNK, SIMULATION_DISTANCE, SPAWN_CHANCE,
+        SPAWN_SAMPLES_PER_CHUNK, SPAWN_TICK_SECS,
+    },
+    positions::{ChunkPos, VoxelPos, VoxelScale, WorldPos},
+    world::World,
+};
+
+// Hooks for games to spawn mobs and items: chunks near a loader are simulated, and each spawn tick
+// a random surface position is offered in simulated chunks which are under their spawn cap
+// Entities marked with SpawnedIn are counted against their chunk and despawned when it leaves range
+pub struct SpawningPlugin;
+
+impl Plugin for SpawningPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpawnRules>()
+            .register_type::<SpawnRules>()
+            .init_resource::<SimulatedChunks>()
+            .add_event::<ChunkEnteredSimulation>()
+            .add_event::<ChunkLeftSimulation>()
+            .add_event::<SpawnTick>()
+            .add_systems(
+                Update,
+                (
+                    SpawnedIn::follow,
+                    SimulatedChunks::update,
+                    SimulatedChunks::despawn_left,
+                    SimulatedChunks::random_tick,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct SpawnRules {
+    // Nothing is simulated while disabled, so SpawnedIn entities are despawned
+    pub enabled: bool,
+    // Chunks within this many chunks of a loader are simulated, if they are loaded
+    pub simulation_distance: u32,
+    // Surface positions sent with each chunk entering simulation
+    pub samples_per_chunk: usize,
+    // Spawn ticks stop in a chunk once this many SpawnedIn entities are in it
+    pub max_per_chunk: usize,
+    pub tick_secs: f32,
+    // Chance of a spawn tick in each simulated chunk below its cap, each tick
+    pub spawn_chance: f32,
+}
+
+impl Default for SpawnRules {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            simulation_distance: SIMULATION_DISTANCE,
+            samples_per_chunk: SPAWN_SAMPLES_PER_CHUNK,
+            max_per_chunk: MAX_SPAWNS_PER_CHUNK,
+            tick_secs: SPAWN_TICK_SECS,
+            spawn_chance: SPAWN_CHANCE,
+        }
+    }
+}
+
+// Sent when a loaded chunk comes within the simulation distance of a loader
+#[derive(Event, Debug, Clone)]
+pub struct ChunkEnteredSimulation {
+    pub chunk_pos: ChunkPos,
+    // Evenly spread positions an entity can stand at, from the chunk's spawn heights
+    pub surface: Vec<WorldPos>,
+}
+
+// Sent when a simulated chunk leaves the simulation distance or is unloaded, after its SpawnedIn
+// entities have been despawned
+#[derive(Event, Debug, Copy, Clone)]
+pub struct ChunkLeftSimulation {
+    pub chunk_pos: ChunkPos,
+}
+
+// A chance to spawn something in a simulated chunk which is below its cap
+#[derive(Event, Debug, Copy, Clone)]
+pub struct SpawnTick {
+    pub chunk_pos: ChunkPos,
+    // A random position an entity can stand at
+    pub spawn_pos: WorldPos,
+    // SpawnedIn entities already in the chunk
+    pub spawned: usize,
+}
+
+// Counts the entity against the chunk it is in, so it is capped and despawned with that chunk
+// Entities with a transform follow the chunk they move into
+#[derive(Component, Debug, Copy, Clone)]
+pub struct SpawnedIn(pub ChunkPos);
+
+impl SpawnedIn {
+    fn follow(
+        mut spawned: Query<(&mut SpawnedIn, &GlobalTransform), Changed<GlobalTransform>>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        for (mut spawned_in, g_transform) in spawned.iter_mut() {
+            let world_pos = voxel_scale.to_world_pos(g_transform.translation());
+            let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+            if spawned_in.0 != chunk_pos {
+                spawned_in.0 = chunk_pos;
+            }
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct SimulatedChunks {
+    pub chunks: HashSet<ChunkPos>,
+    // Chunks which left simulation this frame, despawned before ChunkLeftSimulation is sent
+    left: Vec<ChunkPos>,
+    tick_timer: f32,
+    tick: u64,
+}
+
+impl SimulatedChunks {
+    pub fn contains(&self, chunk_pos: ChunkPos) -> bool {
+        self.chunks.contains(&chunk_pos)
+    }
+
+    fn update(
+        mut simulated: ResMut<SimulatedChunks>,
+        rules: Res<SpawnRules>,
+        world: Res<World>,
+        loaders: Query<&ChunkLoader>,
+        mut entered_events: EventWriter<ChunkEnteredSimulation>,
+    ) {
+        let mut chunks = HashSet::new();
+
+        if rules.enabled {
+            let r = rules.simulation_distance as i32;
+
+            for loader in loaders.iter() {
+                for x in -r..=r {
+                    for y in -r..=r {
+                        for z in -r..=r {
+                            let chunk_pos = loader.prev_chunk_pos + ChunkPos::new(x, y, z);
+
+                            // Spawning needs the chunk's surface
+                            if world.chunk_meta(chunk_pos).is_some() {
+                                chunks.insert(chunk_pos);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for &chunk_pos in chunks.difference(&simulated.chunks) {
+            let Some(meta) = world.chunk_meta(chunk_pos) else {
+                continue;
+            };
+
+            // Spread the samples evenly over the surface columns
+            let surface_count = meta.spawn_heights.iter().flatten().count();
+            let step = surface_count
+                .div_ceil(rules.samples_per_chunk.max(1))
+                .max(1);
+
+            entered_events.send(ChunkEnteredSimulation {
+                chunk_pos,
+                surface: surface_positions(chunk_pos, &meta.spawn_heights)
+                    .step_by(step)
+                    .collect(),
+            });
+        }
+
+        simulated.left = simulated.chunks.difference(&chunks).copied().collect();
+        simulated.chunks = chunks;
+    }
+
+    fn despawn_left(
+        mut commands: Commands,
+        mut simulated: ResMut<SimulatedChunks>,
+        spawned: Query<(Entity, &SpawnedIn)>,
+        mut left_events: EventWriter<ChunkLeftSimulation>,
+    ) {
+        // Also catches entities which wandered out of range on their own
+        for (entity, spawned_in) in spawned.iter() {
+            if !simulated.chunks.contains(&spawned_in.0) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        left_events.send_batch(
+            simulated
+                .left
+                .drain(..)
+                .map(|chunk_pos| ChunkLeftSimulation { chunk_pos }),
+        );
+    }
+
+    fn random_tick(
+        mut simulated: ResMut<SimulatedChunks>,
+        rules: Res<SpawnRules>,
+        world: Res<World>,
+        spawned: Query<&SpawnedIn>,
+        time: Res<Time>,
+        mut tick_events: EventWriter<SpawnTick>,
+    ) {
+        simulated.tick_timer += time.delta_seconds();
+        if !rules.enabled || simulated.tick_timer < rules.tick_secs {
+            return;
+        }
+        simulated.tick_timer = 0.;
+        simulated.tick += 1;
+
+        let mut counts = HashMap::<ChunkPos, usize>::new();
+        for spawned_in in spawned.iter() {
+            *counts.entry(spawned_in.0).or_default() += 1;
+        }
+
+        for &chunk_pos in simulated.chunks.iter() {
+            let spawned = counts.get(&chunk_pos).copied().unwrap_or_default();
+            if spawned >= rules.max_per_chunk {
+                continue;
+            }
+
+            // Deterministic per tick and chunk, the low bits decide the chance and the high bits the column
+            let hash = random_hash(simulated.tick, chunk_pos);
+            if (hash as u32 as f32 / u32::MAX as f32) >= rules.spawn_chance {
+                continue;
+            }
+
+            let Some(meta) = world.chunk_meta(chunk_pos) else {
+                continue;
+            };
+            let surface_count = meta.spawn_heights.iter().flatten().count();
+            if surface_count == 0 {
+                continue;
+            }
+
+            if let Some(spawn_pos) = surface_positions(chunk_pos, &meta.spawn_heights)
+                .nth((hash >> 32) as usize % surface_count)
+            {
+                tick_events.send(SpawnTick {
+                    chunk_pos,
+                    spawn_pos,
+                    spawned,
+                });
+            }
+        }
+    }
+}
+
+fn random_hash(tick: u64, chunk_pos: ChunkPos) -> u64 {
+    let mut bytes = [0; 20];
+    bytes[..8].copy_from_slice(&tick.to_le_bytes());
+    for (i, value) in [chunk_pos.x, chunk_pos.y, chunk_pos.z]
+        .into_iter()
+        .enumerate()
+    {
+        bytes[8 + i * 4..12 + i * 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    xxh3_64(&bytes)
+}
+
+// Positions above every column with a surface, in column order
+fn surface_positions(
+    chunk_pos: ChunkPos,
+    spawn_heights: &[Option<i32>],
+) -> impl Iterator<Item = WorldPos> + '_ {
+    spawn_heights
+        .iter()
+        .enumerate()
+        .filter_map(move |(column, height)| {
+            let column_pos = WorldPos::from_voxel_pos(
+                VoxelPos::new(column % CHUNK_SIZE, 0, column / CHUNK_SIZE),
+                chunk_pos,
+            );
+
+            height.map(|y| WorldPos::new(column_pos.x, y, column_pos.z))
+        })
+}
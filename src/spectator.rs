@@ -0,0 +1,124 @@
+use bevy::{ecs::world::World as EcsWorld, prelude::*};
+
+use crate::{
+    chunk_loading::{self, ChunkLoader},
+    constants::{CHUNK_EXTENT, SPECTATOR_KEY},
+    positions::VoxelScale,
+};
+
+// Debug mode which pins chunk loading where the camera is, so the camera can fly out to look at the
+// edge of the loaded region, and the LOD and culling around the loader, from outside
+// The loader component is moved onto its own entity while pinned and back onto the camera after
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Spectator>()
+            .register_type::<Spectator>()
+            .add_systems(
+                Update,
+                (
+                    Spectator::toggle,
+                    PinnedLoader::draw_region.run_if(Spectator::is_pinned),
+                ),
+            );
+    }
+}
+
+#[derive(Resource, Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct Spectator {
+    pub pinned: bool,
+}
+
+// The entity holding the chunk loader while the camera flies freely
+#[derive(Component, Debug)]
+pub struct PinnedLoader;
+
+impl Spectator {
+    pub fn is_pinned(spectator: Res<Spectator>) -> bool {
+        spectator.pinned
+    }
+
+    fn toggle(
+        mut commands: Commands,
+        mut spectator: ResMut<Spectator>,
+        keys: Res<ButtonInput<KeyCode>>,
+        cameras: Query<(Entity, &Transform, Has<ChunkLoader>), With<Camera3d>>,
+        pinned: Query<Entity, With<PinnedLoader>>,
+    ) {
+        if !keys.just_pressed(SPECTATOR_KEY) {
+            return;
+        }
+        let Ok((camera, transform, has_loader)) = cameras.get_single() else {
+            return;
+        };
+
+        if has_loader {
+            let translation = transform.translation;
+
+            commands.add(move |ecs: &mut EcsWorld| {
+                let Some(loader) = ecs.entity_mut(camera).take::<ChunkLoader>() else {
+                    return;
+                };
+
+                ecs.spawn((
+                    loader,
+                    PinnedLoader,
+                    SpatialBundle::from_transform(Transform::from_translation(translation)),
+                    Name::new("Pinned chunk loader"),
+                ));
+            });
+
+            spectator.pinned = true;
+            info!("Chunk loader pinned");
+        } else if let Ok(pinned) = pinned.get_single() {
+            // The loader catches up with the camera like after any other move
+            commands.add(move |ecs: &mut EcsWorld| {
+                let Some(loader) = ecs.entity_mut(pinned).take::<ChunkLoader>() else {
+                    return;
+                };
+
+                ecs.entity_mut(camera).insert(loader);
+                ecs.despawn(pinned);
+            });
+
+            spectator.pinned = false;
+            info!("Chunk loader following the camera");
+        }
+    }
+}
+
+impl PinnedLoader {
+    // Outline the cube of chunks meshed around the pinned loader
+    fn draw_region(
+        mut gizmos: Gizmos,
+        loaders: Query<&ChunkLoader, With<PinnedLoader>>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        let chunk_extent = CHUNK_EXTENT.as_vec3() * voxel_scale.0;
+
+        for loader in loaders.iter() {
+            let centre = voxel_scale.chunk_translation(loader.prev_chunk_pos) + chunk_extent / 2.;
+            let vertical_distance = chunk_loading::vertical_distance(loader.load_distance);
+            let size = UVec3::new(
+                loader.load_distance * 2 + 1,
+                vertical_distance * 2 + 1,
+                loader.load_distance * 2 + 1,
+            )
+            .as_vec3()
+                * chunk_extent;
+
+            gizmos.cuboid(
+                Transform::from_translation(centre).with_scale(size),
+                Color::srgb(1., 0.8, 0.2),
+            );
+            gizmos.sphere(
+                centre,
+                Quat::IDENTITY,
+                chunk_extent.x / 4.,
+                Color::srgb(1., 0.8, 0.2),
+            );
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    chunk::Chunk,
+    positions::{ChunkPos, WorldPos},
+    voxel::VoxelType,
+    world_generator::{seed, PreviewLayer, WorldGenerator},
+};
+
+// Synthetic worlds built to hit the worst cases of the meshers and the renderer, so profiling runs
+// can target them. Every voxel depends only on its position (and the seed), never on noise settings
+
+// Worst-case checkerboard
+
+#[derive(Debug, Copy, Clone)]
+pub struct CheckerboardParams {
+    // The checkerboard fills the heights from bottom up to (not including) top
+    pub bottom: i32,
+    pub top: i32,
+}
+
+impl Default for CheckerboardParams {
+    fn default() -> Self {
+        Self { bottom: 0, top: 32 }
+    }
+}
+
+// A 3D checkerboard, no two blocks share a face so every block has all six faces and nothing merges
+pub struct CheckerboardGenerator {
+    params: CheckerboardParams,
+}
+
+impl CheckerboardGenerator {
+    pub fn new(params: CheckerboardParams) -> Self {
+        Self { params }
+    }
+}
+
+impl WorldGenerator for CheckerboardGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        Chunk::from_fn(chunk_pos, |world_pos| {
+            let in_band = (self.params.bottom..self.params.top).contains(&world_pos.y);
+
+            if in_band && (world_pos.x + world_pos.y + world_pos.z).rem_euclid(2) == 0 {
+                VoxelType::BLOCK
+            } else {
+                VoxelType::AIR
+            }
+        })
+    }
+
+    fn preview(&self, layer: PreviewLayer, columns: &[IVec2]) -> Option<Vec<f32>> {
+        (layer == PreviewLayer::Height).then(|| vec![self.params.top as f32; columns.len()])
+    }
+}
+
+// Sine valleys
+
+#[derive(Debug, Copy, Clone)]
+pub struct SineValleysParams {
+    // Height which the surface rises and falls around
+    pub base_height: f32,
+    // Distance from the base height to the tops of the hills
+    pub amplitude: f32,
+    // Distance in voxels between neighbouring hills
+    pub wavelength: f32,
+}
+
+impl Default for SineValleysParams {
+    fn default() -> Self {
+        Self {
+            base_height: 0.,
+            amplitude: 24.,
+            wavelength: 48.,
+        }
+    }
+}
+
+// Rolling hills on two crossed sine waves, a staircase of single steps on every slope gives ambient
+// occlusion a change of value on nearly every face
+pub struct SineValleysGenerator {
+    params: SineValleysParams,
+}
+
+impl SineValleysGenerator {
+    pub fn new(params: SineValleysParams) -> Self {
+        Self { params }
+    }
+
+    fn height_at(&self, x: i32, z: i32) -> f32 {
+        let frequency = TAU / self.params.wavelength;
+
+        self.params.base_height
+            + self.params.amplitude * (x as f32 * frequency).sin() * (z as f32 * frequency).cos()
+    }
+}
+
+impl WorldGenerator for SineValleysGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        Chunk::from_density_fn(chunk_pos, |world_pos| {
+            self.height_at(world_pos.x, world_pos.z) - world_pos.y as f32
+        })
+    }
+
+    fn preview(&self, layer: PreviewLayer, columns: &[IVec2]) -> Option<Vec<f32>> {
+        (layer == PreviewLayer::Height).then(|| {
+            columns
+                .iter()
+                .map(|column| self.height_at(column.x, column.y))
+                .collect()
+        })
+    }
+}
+
+// Floating dust
+
+#[derive(Debug, Copy, Clone)]
+pub struct FloatingDustParams {
+    // The dust fills the heights from bottom up to (not including) top
+    pub bottom: i32,
+    pub top: i32,
+    // Chance of each possible speck being a block
+    pub chance: f32,
+}
+
+impl Default for FloatingDustParams {
+    fn default() -> Self {
+        Self {
+            bottom: -32,
+            top: 64,
+            chance: 0.05,
+        }
+    }
+}
+
+// Sparse specks of single blocks hanging in the air, which are each a mesh of their own six faces
+// Specks only sit on even coordinates, so none of them touch
+pub struct FloatingDustGenerator {
+    params: FloatingDustParams,
+}
+
+impl FloatingDustGenerator {
+    pub fn new(params: FloatingDustParams) -> Self {
+        Self { params }
+    }
+
+    fn is_speck(&self, world_pos: WorldPos) -> bool {
+        let mut bytes = [0; 20];
+        bytes[..8].copy_from_slice(&seed().to_le_bytes());
+        bytes[8..12].copy_from_slice(&world_pos.x.to_le_bytes());
+        bytes[12..16].copy_from_slice(&world_pos.y.to_le_bytes());
+        bytes[16..].copy_from_slice(&world_pos.z.to_le_bytes());
+
+        (xxh3_64(&bytes) as u32 as f32 / u32::MAX as f32) < self.params.chance
+    }
+}
+
+impl WorldGenerator for FloatingDustGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        Chunk::from_fn(chunk_pos, |world_pos| {
+            let in_band = (self.params.bottom..self.params.top).contains(&world_pos.y);
+            let on_lattice = [world_pos.x, world_pos.y, world_pos.z]
+                .iter()
+                .all(|coord| coord.rem_euclid(2) == 0);
+
+            if in_band && on_lattice && self.is_speck(world_pos) {
+                VoxelType::BLOCK
+            } else {
+                VoxelType::AIR
+            }
+        })
+    }
+}
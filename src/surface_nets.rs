@@ -0,0 +1,261 @@
+use bevy::{
+    log::info_span,
+    math::{IVec3, Vec3},
+};
+
+use crate::{
+    chunk_from_middle::ChunksFromMiddle,
+    chunk_mesh::{ChunkMesh, SectionMeshes},
+    constants::{SECTIONS_PER_CHUNK, SECTION_SIZE},
+    lod::Lod,
+    mesh_pool,
+    positions::VoxelPos,
+    vertex::SmoothVertex,
+    voxel::VoxelType,
+};
+
+// Offsets of the corners of a cell, corner i has x from bit 0, y from bit 1 and z from bit 2
+const CORNERS: [IVec3; 8] = [
+    IVec3::new(0, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(0, 1, 0),
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(0, 1, 1),
+    IVec3::new(1, 1, 1),
+];
+
+// Pairs of corners joined by the edges of a cell
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+// Densities and voxel types of a section and the voxels around it, sampled at the centres of the
+// cubes of voxels each sample stands for, a single voxel at full detail
+struct Samples {
+    // Position of the first sample relative to the chunk, one sample below the section's minimum
+    min: IVec3,
+    // Voxels between samples along each axis
+    jump: i32,
+    // Samples along each axis, the section's and one either side of them
+    size: usize,
+    densities: Vec<f32>,
+    voxel_types: Vec<VoxelType>,
+}
+
+impl Samples {
+    fn new(grid: &ChunksFromMiddle, section_min: IVec3, jump: i32) -> Self {
+        let min = section_min - jump;
+        let size = SECTION_SIZE / jump as usize + 2;
+
+        let mut densities = Vec::with_capacity(size * size * size);
+        let mut voxel_types = Vec::with_capacity(size * size * size);
+
+        for z in 0..size as i32 {
+            for y in 0..size as i32 {
+                for x in 0..size as i32 {
+                    let pos = min + IVec3::new(x, y, z) * jump;
+
+                    densities.push(if jump == 1 {
+                        grid.get_density(pos)
+                    } else {
+                        grid.get_downsampled_density(pos, jump)
+                    });
+                    voxel_types.push(grid.get_voxel(pos).voxel_type);
+                }
+            }
+        }
+
+        Self {
+            min,
+            jump,
+            size,
+            densities,
+            voxel_types,
+        }
+    }
+
+    fn index(&self, pos: IVec3) -> usize {
+        pos.x as usize + pos.y as usize * self.size + pos.z as usize * self.size * self.size
+    }
+
+    fn density(&self, pos: IVec3) -> f32 {
+        self.densities[self.index(pos)]
+    }
+
+    fn is_inside(&self, pos: IVec3) -> bool {
+        self.density(pos) > 0.
+    }
+
+    // Vertex of the cell with its lowest corner at the sample, None if the surface doesn't cross it
+    // The vertex is the mean of the points where the surface crosses the cell's edges
+    fn cell_vertex(&self, cell: IVec3) -> Option<SmoothVertex> {
+        let densities = CORNERS.map(|corner| self.density(cell + corner));
+
+        let inside = densities.iter().filter(|&&density| density > 0.).count();
+        if inside == 0 || inside == CORNERS.len() {
+            return None;
+        }
+
+        let mut crossing_sum = Vec3::ZERO;
+        let mut crossings = 0;
+        for (start, end) in EDGES {
+            let (start_density, end_density) = (densities[start], densities[end]);
+            if (start_density > 0.) == (end_density > 0.) {
+                continue;
+            }
+
+            let t = start_density / (start_density - end_density);
+            crossing_sum += CORNERS[start].as_vec3().lerp(CORNERS[end].as_vec3(), t);
+            crossings += 1;
+        }
+
+        // Density rises into the terrain, so the normal points down its gradient
+        let gradient = CORNERS
+            .iter()
+            .zip(densities)
+            .fold(Vec3::ZERO, |gradient, (corner, density)| {
+                gradient + (corner.as_vec3() * 2. - 1.) * density
+            });
+        let normal = (-gradient).try_normalize().unwrap_or(Vec3::Y);
+
+        // Terrain is coloured by its opaque voxels over water
+        let solid_corners = CORNERS
+            .iter()
+            .map(|&corner| self.voxel_types[self.index(cell + corner)])
+            .filter(VoxelType::is_solid);
+        let voxel_type = solid_corners
+            .clone()
+            .find(VoxelType::is_opaque)
+            .or_else(|| solid_corners.clone().next())
+            .unwrap_or(VoxelType::BLOCK);
+
+        // Samples are at the centres of their cubes
+        let jump = self.jump as f32;
+        Some(SmoothVertex {
+            pos: self.min.as_vec3()
+                + jump / 2.
+                + (cell.as_vec3() + crossing_sum / crossings as f32) * jump,
+            normal,
+            voxel_type,
+        })
+    }
+}
+
+// Surface nets mesh of the section starting at the position, relative to the chunk
+// A section owns the faces crossing the edges from each of its samples to the samples above them,
+// so neighbouring sections and chunks at the same LOD meet without gaps or overlaps
+fn build_mesh(grid: &ChunksFromMiddle, section_min: IVec3, lod: Lod) -> Option<ChunkMesh> {
+    // Sections have at least one sample along each axis
+    let jump = lod.jump_index().min(SECTION_SIZE) as i32;
+    let samples = Samples::new(grid, section_min, jump);
+    let cells = samples.size - 1;
+
+    let first_inside = samples.densities[0] > 0.;
+    if samples
+        .densities
+        .iter()
+        .all(|&density| (density > 0.) == first_inside)
+    {
+        return None;
+    }
+
+    let mut mesh = mesh_pool::take();
+
+    let mut cell_indices = vec![None; cells * cells * cells];
+    for z in 0..cells as i32 {
+        for y in 0..cells as i32 {
+            for x in 0..cells as i32 {
+                let Some(vertex) = samples.cell_vertex(IVec3::new(x, y, z)) else {
+                    continue;
+                };
+
+                cell_indices[x as usize + y as usize * cells + z as usize * cells * cells] =
+                    Some(mesh.smooth_vertices.len() as u32);
+                mesh.smooth_vertices.push(vertex);
+            }
+        }
+    }
+    let cell_index = |cell: IVec3| {
+        cell_indices[cell.x as usize + cell.y as usize * cells + cell.z as usize * cells * cells]
+    };
+
+    let section_samples = cells as i32 - 1;
+    for z in 1..=section_samples {
+        for y in 1..=section_samples {
+            for x in 1..=section_samples {
+                let sample = IVec3::new(x, y, z);
+                let inside = samples.is_inside(sample);
+
+                for axis in 0..3 {
+                    if samples.is_inside(sample + IVec3::AXES[axis]) == inside {
+                        continue;
+                    }
+
+                    // The four cells around the edge, counter-clockwise seen from the end of the axis
+                    let (b, c) = (IVec3::AXES[(axis + 1) % 3], IVec3::AXES[(axis + 2) % 3]);
+                    let cells = [sample - b - c, sample - c, sample, sample - b];
+                    let Some(mut quad) = cells
+                        .into_iter()
+                        .map(cell_index)
+                        .collect::<Option<Vec<_>>>()
+                    else {
+                        continue;
+                    };
+
+                    // Faces point out of the terrain
+                    if !inside {
+                        quad.reverse();
+                    }
+
+                    mesh.indices
+                        .extend([quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+                }
+            }
+        }
+    }
+
+    if mesh.indices.is_empty() {
+        mesh_pool::recycle(mesh);
+        return None;
+    }
+
+    Some(mesh)
+}
+
+// Build the smooth meshes of the sections set in the section mask, in the chunk's voxel positions
+// Lower LODs sample the mean density of each cube of voxels, for smooth far terrain
+pub fn build_section_meshes(
+    chunks_from_middle: &ChunksFromMiddle,
+    lod: Lod,
+    sections: u64,
+) -> SectionMeshes {
+    let _span = info_span!("surface_nets_build_section_meshes", sections).entered();
+
+    let section_indices = (0..SECTIONS_PER_CHUNK).filter(|section| sections & (1 << section) != 0);
+
+    // Densities are positive exactly where voxels are solid, so uniform voxels have no surface
+    if chunks_from_middle.are_all_voxels_same() {
+        return section_indices.map(|section| (section, None)).collect();
+    }
+
+    section_indices
+        .map(|section| {
+            let section_min = VoxelPos::from_section_index(section).to_ivec3();
+
+            (section, build_mesh(chunks_from_middle, section_min, lod))
+        })
+        .collect()
+}
@@ -0,0 +1,73 @@
+use std::sync::OnceLock;
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    tasks::{TaskPool, TaskPoolBuilder},
+};
+
+use crate::{
+    constants::{GENERATION_THREADS, MESHING_THREADS},
+    world::World,
+};
+
+// Generation and meshing run on their own pools so that a burst of one can't starve the other
+// Saving has a thread of its own, see chunk_saver, and pathfinding uses the AsyncComputeTaskPool
+static GENERATION_POOL: OnceLock<TaskPool> = OnceLock::new();
+static MESHING_POOL: OnceLock<TaskPool> = OnceLock::new();
+
+// Running tasks as a percentage of each pool's threads, above 100 tasks are waiting for a thread
+pub const GENERATION_SATURATION: DiagnosticPath =
+    DiagnosticPath::const_new("chunk_pools/generation_saturation");
+pub const MESHING_SATURATION: DiagnosticPath =
+    DiagnosticPath::const_new("chunk_pools/meshing_saturation");
+
+// The pools are created once per process, so only the first plugin's thread counts are used
+pub struct ChunkTaskPoolsPlugin {
+    pub generation_threads: usize,
+    pub meshing_threads: usize,
+}
+
+impl Default for ChunkTaskPoolsPlugin {
+    fn default() -> Self {
+        Self {
+            generation_threads: GENERATION_THREADS,
+            meshing_threads: MESHING_THREADS,
+        }
+    }
+}
+
+impl Plugin for ChunkTaskPoolsPlugin {
+    fn build(&self, app: &mut App) {
+        GENERATION_POOL.get_or_init(|| build_pool("Chunk Generation", self.generation_threads));
+        MESHING_POOL.get_or_init(|| build_pool("Chunk Meshing", self.meshing_threads));
+
+        app.register_diagnostic(Diagnostic::new(GENERATION_SATURATION).with_suffix("%"))
+            .register_diagnostic(Diagnostic::new(MESHING_SATURATION).with_suffix("%"))
+            .add_systems(Update, measure_saturation);
+    }
+}
+
+fn build_pool(name: &str, threads: usize) -> TaskPool {
+    TaskPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .thread_name(name.to_string())
+        .build()
+}
+
+pub fn generation_pool() -> &'static TaskPool {
+    GENERATION_POOL.get_or_init(|| build_pool("Chunk Generation", GENERATION_THREADS))
+}
+
+pub fn meshing_pool() -> &'static TaskPool {
+    MESHING_POOL.get_or_init(|| build_pool("Chunk Meshing", MESHING_THREADS))
+}
+
+fn measure_saturation(mut diagnostics: Diagnostics, world: Res<World>) {
+    diagnostics.add_measurement(&GENERATION_SATURATION, || {
+        world.data_tasks.len() as f64 * 100. / generation_pool().thread_num() as f64
+    });
+    diagnostics.add_measurement(&MESHING_SATURATION, || {
+        world.mesh_tasks.len() as f64 * 100. / meshing_pool().thread_num() as f64
+    });
+}
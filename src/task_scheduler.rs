@@ -0,0 +1,173 @@
+use bevy::prelude::*;
+
+use crate::{
+    background_throttle::MeshingPace,
+    constants::{
+        CHUNK_TASK_BUDGET, GENERATION_TASK_WEIGHT, IO_TASK_WEIGHT, JOIN_TIME_BUDGET_SECS,
+        MESHING_TASK_WEIGHT,
+    },
+    world::{PipelineMode, World},
+};
+
+// Shares one budget of in-flight chunk tasks between the pipeline's stages, in proportion to their
+// weights, so a burst of finished data can't starve meshing and a burst of remeshes can't starve
+// generation. Stages with less work than their share hand the rest to the others
+pub struct TaskSchedulerPlugin;
+
+impl Plugin for TaskSchedulerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TaskScheduler>()
+            .register_type::<TaskScheduler>()
+            .add_systems(
+                PostUpdate,
+                TaskScheduler::allocate
+                    .before(World::start_data_tasks)
+                    .before(World::start_mesh_tasks),
+            );
+    }
+}
+
+#[derive(Reflect, Debug, Copy, Clone, PartialEq)]
+pub struct TaskWeights {
+    pub generation: f32,
+    pub meshing: f32,
+    // Saving doesn't go through the scheduler yet, so IO never has any work to claim its share
+    pub io: f32,
+}
+
+#[derive(Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TaskSlots {
+    pub generation: usize,
+    pub meshing: usize,
+    pub io: usize,
+}
+
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct TaskScheduler {
+    // In-flight tasks across every stage
+    pub budget: usize,
+    pub weights: TaskWeights,
+    // A stage whose last join took longer than this gets a smaller share, until its joins catch up
+    pub join_budget_secs: f32,
+    // Each stage's share of the budget this frame, including its in-flight tasks
+    pub shares: TaskSlots,
+    // New tasks each stage may start this frame
+    pub slots: TaskSlots,
+}
+
+impl Default for TaskScheduler {
+    fn default() -> Self {
+        Self {
+            budget: CHUNK_TASK_BUDGET,
+            weights: TaskWeights {
+                generation: GENERATION_TASK_WEIGHT,
+                meshing: MESHING_TASK_WEIGHT,
+                io: IO_TASK_WEIGHT,
+            },
+            join_budget_secs: JOIN_TIME_BUDGET_SECS,
+            shares: TaskSlots::default(),
+            slots: TaskSlots::default(),
+        }
+    }
+}
+
+impl TaskScheduler {
+    fn allocate(
+        mut scheduler: ResMut<TaskScheduler>,
+        world: Res<World>,
+        pace: Res<MeshingPace>,
+        pipeline_mode: Res<PipelineMode>,
+    ) {
+        // Parked meshes count against meshing, so they can't pile up while spawning catches up
+        let parked_meshes = world.parked_meshes.iter().map(Vec::len).sum::<usize>();
+        let in_flight = TaskSlots {
+            generation: world.data_tasks.len() + world.generation_stages.len(),
+            meshing: world.mesh_tasks.len() + parked_meshes,
+            io: 0,
+        };
+
+        // Work each stage could have in flight: what is running plus what is waiting to start
+        let demand = TaskSlots {
+            generation: in_flight.generation
+                + world.load_data_queue.len()
+                + world.generation_stages.decorate_queue.len(),
+            meshing: (in_flight.meshing + world.load_mesh_queue.len()).min(pace.max_mesh_tasks),
+            io: 0,
+        };
+
+        // Back off stages whose joins are taking too long on the main thread
+        // Join times are wall clock, so deterministic runs keep the fixed shares of the weights
+        let backpressure = |join_secs: f32| match *pipeline_mode {
+            PipelineMode::Async => (scheduler.join_budget_secs / join_secs).min(1.),
+            PipelineMode::Deterministic => 1.,
+        };
+        let weights = [
+            scheduler.weights.generation * backpressure(world.data_join_time.as_secs_f32()),
+            scheduler.weights.meshing * backpressure(world.mesh_join_time.as_secs_f32()),
+            scheduler.weights.io,
+        ];
+
+        // A resume burst may run more mesh tasks than the usual budget
+        let budget = scheduler.budget.max(pace.max_mesh_tasks);
+        let [generation, meshing, io] = proportional_shares(
+            budget,
+            weights,
+            [demand.generation, demand.meshing, demand.io],
+        );
+        scheduler.shares = TaskSlots {
+            generation,
+            meshing,
+            io,
+        };
+
+        scheduler.slots = TaskSlots {
+            generation: generation.saturating_sub(in_flight.generation),
+            meshing: meshing.saturating_sub(in_flight.meshing),
+            io: io.saturating_sub(in_flight.io),
+        };
+    }
+}
+
+// Split the budget in proportion to the weights, without giving any stage more than its demand
+// Whatever a stage can't use is split again between the stages which still want more
+fn proportional_shares<const N: usize>(
+    budget: usize,
+    weights: [f32; N],
+    demands: [usize; N],
+) -> [usize; N] {
+    let mut shares = [0; N];
+    let mut remaining = budget;
+
+    loop {
+        let wanting = (0..N)
+            .filter(|&stage| weights[stage] > 0. && shares[stage] < demands[stage])
+            .collect::<Vec<_>>();
+        let total_weight = wanting.iter().map(|&stage| weights[stage]).sum::<f32>();
+
+        if remaining == 0 || wanting.is_empty() {
+            return shares;
+        }
+
+        let mut given = 0;
+        for &stage in &wanting {
+            let share = (remaining as f32 * weights[stage] / total_weight) as usize;
+            let share = share.min(demands[stage] - shares[stage]);
+
+            shares[stage] += share;
+            given += share;
+        }
+
+        // Rounding down left less than one task each, give one to the heaviest stage
+        if given == 0 {
+            let heaviest = wanting
+                .into_iter()
+                .max_by(|&a, &b| weights[a].total_cmp(&weights[b]))
+                .unwrap_or_default();
+            shares[heaviest] += 1;
+            given = 1;
+        }
+
+        remaining -= given;
+    }
+}
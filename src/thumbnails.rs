@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    render::{
+        camera::{RenderTarget, ScalingMode},
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        view::RenderLayers,
+    },
+};
+
+use crate::{
+    chunk::Chunk,
+    chunk_mesh::ChunkMesh,
+    constants::{
+        CHUNK_EXTENT, MAX_THUMBNAILS_IN_FLIGHT, THUMBNAIL_ORIGIN, THUMBNAIL_RENDER_FRAMES,
+        THUMBNAIL_RENDER_LAYER, THUMBNAIL_SIZE, THUMBNAIL_VIEW_DIRECTION, VOXEL_GRID_MAX_SIZE,
+    },
+    greedy_mesher,
+    lod::Lod,
+    mesh_pool,
+    positions::VoxelPos,
+    rendering::{ChunkMaterial, GlobalChunkMaterial},
+    schematic::Schematic,
+    vertex::MAX_VERTEX_POSITION,
+    voxel::VoxelType,
+    voxel_grid::VoxelGrid,
+    world::{voxel_mesh, MeshAttributes},
+};
+
+// Renders chunks and schematics into images from a fixed orbit camera, for UI thumbnails such as
+// saved world previews or block menu icons. Grids are meshed straight away, then each is drawn by a
+// camera of its own on a render layer of its own for a few frames
+pub struct ThumbnailsPlugin;
+
+impl Plugin for ThumbnailsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Thumbnails>()
+            .add_systems(PostUpdate, Thumbnails::render);
+    }
+}
+
+struct QueuedThumbnail {
+    image: Handle<Image>,
+    mesh: ChunkMesh,
+    // Extent of the grid in voxels, the mesh is centred on it
+    size: Vec3,
+}
+
+struct RenderingThumbnail {
+    slot: usize,
+    entities: [Entity; 2],
+    frames_left: u32,
+}
+
+#[derive(Resource)]
+pub struct Thumbnails {
+    // Width and height in pixels of the images made by later requests
+    pub size: u32,
+    queue: VecDeque<QueuedThumbnail>,
+    rendering: Vec<RenderingThumbnail>,
+}
+
+impl Default for Thumbnails {
+    fn default() -> Self {
+        Self {
+            size: THUMBNAIL_SIZE,
+            queue: VecDeque::new(),
+            rendering: Vec::new(),
+        }
+    }
+}
+
+impl Thumbnails {
+    // Mesh the grid and return the image it will be drawn into, which is transparent until then
+    // Grids too large to mesh are downsampled, None if the grid has nothing to show or is still too large
+    pub fn request(
+        &mut self,
+        grid: &impl VoxelGrid,
+        images: &mut Assets<Image>,
+    ) -> Option<Handle<Image>> {
+        let size = grid.size();
+        let lod = [Lod::L32, Lod::L16, Lod::L8, Lod::L4]
+            .into_iter()
+            .find(|lod| {
+                let jump = lod.jump_index() as u32;
+                let cells = size.max_element().div_ceil(jump);
+
+                cells <= VOXEL_GRID_MAX_SIZE as u32 && cells * jump <= MAX_VERTEX_POSITION as u32
+            })?;
+
+        let mut mesh = greedy_mesher::build_downsampled_grid_mesh(grid, lod, true)?;
+        // Thumbnails don't belong to a biome, so they use the middle of the tint ramp
+        mesh.biome_tints = vec![0.5; mesh.vertices.len()];
+
+        let mut image = Image::new_fill(
+            Extent3d {
+                width: self.size,
+                height: self.size,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::Bgra8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        image.texture_descriptor.usage |= TextureUsages::RENDER_ATTACHMENT;
+        let image = images.add(image);
+
+        self.queue.push_back(QueuedThumbnail {
+            image: image.clone(),
+            mesh,
+            size: size.as_vec3(),
+        });
+
+        Some(image)
+    }
+
+    // The chunk on its own, with every side showing
+    pub fn request_chunk(
+        &mut self,
+        chunk: &Chunk,
+        images: &mut Assets<Image>,
+    ) -> Option<Handle<Image>> {
+        self.request(&IsolatedChunk(chunk), images)
+    }
+
+    pub fn request_schematic(
+        &mut self,
+        schematic: &Schematic,
+        images: &mut Assets<Image>,
+    ) -> Option<Handle<Image>> {
+        self.request(schematic, images)
+    }
+
+    // Thumbnails waiting for a render layer or being drawn
+    pub fn pending(&self) -> usize {
+        self.queue.len() + self.rendering.len()
+    }
+
+    fn render(
+        mut commands: Commands,
+        mut thumbnails: ResMut<Thumbnails>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        g_chunk_material: Res<GlobalChunkMaterial>,
+        mesh_attributes: Res<MeshAttributes>,
+    ) {
+        // The images keep the last frame drawn into them after the cameras are gone
+        thumbnails.rendering.retain_mut(|rendering| {
+            rendering.frames_left = rendering.frames_left.saturating_sub(1);
+            if rendering.frames_left > 0 {
+                return true;
+            }
+
+            for entity in rendering.entities {
+                commands.entity(entity).despawn_recursive();
+            }
+            false
+        });
+
+        while let Some(slot) = (0..MAX_THUMBNAILS_IN_FLIGHT)
+            .find(|&slot| thumbnails.rendering.iter().all(|r| r.slot != slot))
+        {
+            let Some(queued) = thumbnails.queue.pop_front() else {
+                break;
+            };
+            let layer = RenderLayers::layer(THUMBNAIL_RENDER_LAYER + slot);
+
+            let mesh_entity = commands
+                .spawn((
+                    MaterialMeshBundle::<ChunkMaterial> {
+                        mesh: meshes.add(voxel_mesh(&queued.mesh, *mesh_attributes)),
+                        material: g_chunk_material.0.clone(),
+                        transform: Transform::from_translation(THUMBNAIL_ORIGIN - queued.size / 2.),
+                        ..default()
+                    },
+                    layer.clone(),
+                    Name::new("Thumbnail mesh"),
+                ))
+                .id();
+            mesh_pool::recycle(queued.mesh);
+
+            // Orthographic, framing the sphere around the grid from any side
+            let radius = queued.size.length() / 2.;
+            let camera_entity = commands
+                .spawn((
+                    Camera3dBundle {
+                        camera: Camera {
+                            target: RenderTarget::Image(queued.image),
+                            // Drawn before the main camera, cameras sharing an order are ambiguous
+                            order: -1 - slot as isize,
+                            clear_color: ClearColorConfig::Custom(Color::NONE),
+                            ..default()
+                        },
+                        projection: Projection::Orthographic(OrthographicProjection {
+                            scaling_mode: ScalingMode::Fixed {
+                                width: radius * 2.,
+                                height: radius * 2.,
+                            },
+                            far: radius * 4.,
+                            ..default()
+                        }),
+                        transform: Transform::from_translation(
+                            THUMBNAIL_ORIGIN + THUMBNAIL_VIEW_DIRECTION.normalize() * radius * 2.,
+                        )
+                        .looking_at(THUMBNAIL_ORIGIN, Vec3::Y),
+                        ..default()
+                    },
+                    layer,
+                    Name::new("Thumbnail camera"),
+                ))
+                .id();
+
+            thumbnails.rendering.push(RenderingThumbnail {
+                slot,
+                entities: [mesh_entity, camera_entity],
+                frames_left: THUMBNAIL_RENDER_FRAMES,
+            });
+        }
+    }
+}
+
+// A chunk with air around it
+struct IsolatedChunk<'a>(&'a Chunk);
+
+impl VoxelGrid for IsolatedChunk<'_> {
+    fn size(&self) -> UVec3 {
+        CHUNK_EXTENT.as_uvec3()
+    }
+
+    fn voxel_at(&self, pos: IVec3) -> VoxelType {
+        if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(CHUNK_EXTENT).any() {
+            return VoxelType::AIR;
+        }
+
+        self.0[VoxelPos::from_ivec3(pos)].voxel_type
+    }
+}
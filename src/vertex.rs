@@ -1,4 +1,10 @@
-use crate::{positions::VoxelPos, voxel::VoxelType};
+use bevy::math::Vec3;
+
+use crate::{
+    constants::{CHUNK_HEIGHT, CHUNK_SIZE},
+    positions::VoxelPos,
+    voxel::VoxelType,
+};
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
@@ -6,15 +12,52 @@ pub struct Vertex {
     pub ao: u32,
     pub normal: usize, // Index of the normal
     pub voxel_type: VoxelType,
+    // How far below the sky the face is, from 0 (open sky) to MAX_SKY_DARKNESS
+    pub sky_darkness: u32,
+    // How much of the sun the hills toward it block, from 0 (in sunlight) to MAX_SUN_SHADOW
+    pub sun_shadow: u32,
+    // Block texture layer of the face, so the shader doesn't look it up per voxel type
+    pub texture: u32,
 }
 
-#[derive(Copy, Clone)]
-pub struct VertexU32(u32);
+// A vertex packed into two u32s for the chunk shader
+// First: position allocated 27 bits, 9 bits per component, normal allocated 3 bits and AO 2 bits
+// Second: voxel type allocated 12 bits, sky darkness 4 bits, sun shadow 4 bits and texture layer 12 bits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PackedVertex([u32; 2]);
+
+// Positions run from 0 to this inclusive, enough for a 256 voxel tall chunk
+pub const MAX_VERTEX_POSITION: usize = (1 << 9) - 1;
+
+// Voxel type ids up to this fit in a packed vertex, the block registry rejects larger ones
+pub const MAX_VERTEX_VOXEL_TYPE: u32 = (1 << 12) - 1;
+
+pub const MAX_SKY_DARKNESS: u32 = (1 << 4) - 1;
 
-impl VertexU32 {
+pub const MAX_SUN_SHADOW: u32 = (1 << 4) - 1;
+
+pub const MAX_TEXTURE_LAYER: u32 = (1 << 12) - 1;
+
+impl PackedVertex {
     pub fn new(pos: VoxelPos, ao: u32, normal_index: usize, voxel_type: VoxelType) -> Self {
         Vertex::new(pos, ao, normal_index, voxel_type).into()
     }
+
+    pub fn with_sky_darkness(self, sky_darkness: u32) -> Self {
+        Vertex {
+            sky_darkness,
+            ..self.into()
+        }
+        .into()
+    }
+
+    pub fn with_sun_shadow(self, sun_shadow: u32) -> Self {
+        Vertex {
+            sun_shadow,
+            ..self.into()
+        }
+        .into()
+    }
 }
 
 impl Vertex {
@@ -24,62 +67,173 @@ impl Vertex {
             ao,
             normal: normal_index,
             voxel_type,
+            sky_darkness: 0,
+            sun_shadow: 0,
+            texture: voxel_type.texture_layer(normal_index),
         }
     }
 
-    pub fn from_u32(vertex: VertexU32) -> Self {
-        let pos_mask = 0b111111u32; // 6 1s to mask each position component
-        let three_bits_mask = 0b111u32; // 3 1s to mask ao and normal
-        let eight_bits_mask = 0b11111111u32; // 8 1s to mask voxel type
+    pub fn from_packed(vertex: PackedVertex) -> Self {
+        let [first, second] = vertex.0;
+        let pos_mask = 0b111111111u32; // 9 1s to mask each position component
 
         let pos = VoxelPos {
-            x: (vertex.0 & pos_mask) as usize,
-            y: ((vertex.0 & (pos_mask << 12u32)) >> 12u32) as usize,
-            z: ((vertex.0 & (pos_mask << 6u32)) >> 6u32) as usize,
+            x: (first & pos_mask) as usize,
+            y: ((first >> 9u32) & pos_mask) as usize,
+            z: ((first >> 18u32) & pos_mask) as usize,
         };
 
-        let ao = (vertex.0 & (three_bits_mask << 18u32)) >> 18u32;
-        let normal = ((vertex.0 & (three_bits_mask << 21u32)) >> 21u32) as usize;
+        let normal = ((first >> 27u32) & 0b111) as usize;
+        let ao = first >> 30u32;
 
-        let voxel_type = ((vertex.0 & (eight_bits_mask << 24u32)) >> 24u32).into();
+        let voxel_type = (second & 0xfff).into();
+        let sky_darkness = (second >> 12u32) & 0b1111;
+        let sun_shadow = (second >> 16u32) & 0b1111;
+        let texture = second >> 20u32;
 
         Self {
             pos,
             normal,
             ao,
             voxel_type,
+            sky_darkness,
+            sun_shadow,
+            texture,
         }
     }
 
-    pub fn to_u32(&self) -> VertexU32 {
-        // Pos allocated 18 bits, 6 bits per component
-        // Normal allocated 3 bits
-        // Block type allocated 11 bits
-        VertexU32(
+    pub fn to_packed(self) -> PackedVertex {
+        // Out of range fields would wrap into their neighbours
+        debug_assert!(
+            self.pos.x.max(self.pos.y).max(self.pos.z) <= MAX_VERTEX_POSITION,
+            "Vertex position {:?} doesn't fit in 9 bits",
+            self.pos
+        );
+        debug_assert!(self.ao < 4, "Vertex AO {} doesn't fit in 2 bits", self.ao);
+        debug_assert!(
+            self.normal < 6,
+            "Vertex normal {} isn't a face",
+            self.normal
+        );
+
+        let voxel_type = u32::from(self.voxel_type);
+        debug_assert!(
+            voxel_type <= MAX_VERTEX_VOXEL_TYPE,
+            "Voxel type {voxel_type} doesn't fit in 12 bits"
+        );
+        debug_assert!(
+            self.sky_darkness <= MAX_SKY_DARKNESS,
+            "Sky darkness {} doesn't fit in 4 bits",
+            self.sky_darkness
+        );
+        debug_assert!(
+            self.sun_shadow <= MAX_SUN_SHADOW,
+            "Sun shadow {} doesn't fit in 4 bits",
+            self.sun_shadow
+        );
+        debug_assert!(
+            self.texture <= MAX_TEXTURE_LAYER,
+            "Texture layer {} doesn't fit in 12 bits",
+            self.texture
+        );
+
+        PackedVertex([
             self.pos.x as u32
-                | (self.pos.y as u32) << 6u32
-                | (self.pos.z as u32) << 12u32
-                | self.ao << 18u32
-                | (self.normal as u32) << 21u32
-                | (self.voxel_type as u32) << 24u32,
+                | (self.pos.y as u32) << 9u32
+                | (self.pos.z as u32) << 18u32
+                | (self.normal as u32) << 27u32
+                | self.ao << 30u32,
+            voxel_type
+                | self.sky_darkness << 12u32
+                | self.sun_shadow << 16u32
+                | self.texture << 20u32,
+        ])
+    }
+}
+
+// A vertex of a smooth mesh, which lies between voxels so isn't packed, see surface_nets
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SmoothVertex {
+    // Relative to the chunk, in voxels
+    pub pos: Vec3,
+    pub normal: Vec3,
+    pub voxel_type: VoxelType,
+}
+
+// A whole quad of a far chunk mesh packed into a u32, without AO or biome tint
+// Origin allocated 18 bits, 6 bits per component
+// Width and height minus one allocated 5 bits each, along the axes in FACE_AXES
+// Normal allocated 3 bits, and 1 bit for whether the face is water
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FaceU32(u32);
+
+// Whether a chunk's faces fit the far face fields, there are no bits to spare for a taller chunk's
+// y, so tall chunks keep their packed vertices at far distances
+pub const FAR_FACES_FIT: bool = CHUNK_SIZE <= 1 << 5 && CHUNK_HEIGHT <= 1 << 5;
+
+// Axes (x = 0, y = 1, z = 2) the width and height of a face run along for each normal, chosen so
+// that the corners wind counter-clockwise when seen from the front, must match the chunk shader
+pub const FACE_AXES: [(usize, usize); 6] = [(2, 1), (1, 2), (0, 1), (1, 0), (2, 0), (0, 2)];
+
+impl FaceU32 {
+    // Pack the four vertices of a quad
+    pub fn from_quad(quad: &[PackedVertex]) -> Self {
+        let vertices = quad
+            .iter()
+            .map(|&vertex| Vertex::from(vertex).pos.to_tuple());
+        let (min, max) = vertices.fold(([usize::MAX; 3], [0; 3]), |(min, max), (x, y, z)| {
+            (
+                [min[0].min(x), min[1].min(y), min[2].min(z)],
+                [max[0].max(x), max[1].max(y), max[2].max(z)],
+            )
+        });
+
+        let first = Vertex::from(quad[0]);
+        let (width_axis, height_axis) = FACE_AXES[first.normal];
+        let width = max[width_axis] - min[width_axis];
+        let height = max[height_axis] - min[height_axis];
+
+        debug_assert!(
+            min.iter().all(|&component| component < 1 << 6),
+            "Far face origin {min:?} doesn't fit in 6 bits"
+        );
+        debug_assert!(
+            (1..=1 << 5).contains(&width) && (1..=1 << 5).contains(&height),
+            "Far face size {width}x{height} doesn't fit in 5 bits"
+        );
+
+        FaceU32(
+            min[0] as u32
+                | (min[1] as u32) << 6u32
+                | (min[2] as u32) << 12u32
+                | (width as u32 - 1) << 18u32
+                | (height as u32 - 1) << 23u32
+                | (first.normal as u32) << 28u32
+                | ((first.voxel_type == VoxelType::WATER) as u32) << 31u32,
         )
     }
 }
 
-impl From<Vertex> for VertexU32 {
+impl From<FaceU32> for u32 {
+    fn from(face: FaceU32) -> Self {
+        face.0
+    }
+}
+
+impl From<Vertex> for PackedVertex {
     fn from(vertex: Vertex) -> Self {
-        vertex.to_u32()
+        vertex.to_packed()
     }
 }
 
-impl From<VertexU32> for Vertex {
-    fn from(vertex: VertexU32) -> Self {
-        Self::from_u32(vertex)
+impl From<PackedVertex> for Vertex {
+    fn from(vertex: PackedVertex) -> Self {
+        Self::from_packed(vertex)
     }
 }
 
-impl From<VertexU32> for u32 {
-    fn from(vertex: VertexU32) -> Self {
+impl From<PackedVertex> for [u32; 2] {
+    fn from(vertex: PackedVertex) -> Self {
         vertex.0
     }
 }
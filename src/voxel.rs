@@ -1,16 +1,80 @@
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-pub enum VoxelType {
-    Air,
-    Block,
-}
+use bevy::reflect::Reflect;
+
+use crate::block_registry::BlockRegistry;
+
+// Id of a block in the block registry, which defines everything else about it
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Reflect)]
+pub struct VoxelType(u16);
 
 impl VoxelType {
+    // Blocks the engine itself refers to, every registry defines them with these ids
+    pub const AIR: Self = Self(0);
+    pub const BLOCK: Self = Self(1);
+    pub const WATER: Self = Self(2);
+
+    pub const fn from_id(id: u16) -> Self {
+        Self(id)
+    }
+
+    pub fn id(&self) -> u16 {
+        self.0
+    }
+
+    pub fn name(&self) -> &'static str {
+        BlockRegistry::global()
+            .get(*self)
+            .map_or("unknown", |definition| definition.name.as_str())
+    }
+
     pub fn is_solid(&self) -> bool {
-        !matches!(self, VoxelType::Air)
+        *self != Self::AIR
+            && BlockRegistry::global()
+                .get(*self)
+                .is_some_and(|definition| definition.solid)
+    }
+
+    // Voxels which block sky light, light passes through transparent voxels
+    pub fn is_opaque(&self) -> bool {
+        self.is_solid() && !self.is_transparent()
+    }
+
+    // Voxels which should be drawn by a transparent pass
+    pub fn is_transparent(&self) -> bool {
+        BlockRegistry::global()
+            .get(*self)
+            .is_some_and(|definition| definition.transparent)
+    }
+
+    // Texture array layer of the face with the normal index (see Direction), from the block registry
+    pub fn texture_layer(&self, normal_index: usize) -> u32 {
+        BlockRegistry::global()
+            .get(*self)
+            .map_or(0, |definition| match normal_index {
+                4 => definition.textures.top,
+                5 => definition.textures.bottom,
+                _ => definition.textures.side,
+            })
+    }
+
+    // Seconds the voxel takes to break, negative for voxels which can't be broken
+    pub fn hardness(&self) -> f32 {
+        BlockRegistry::global()
+            .get(*self)
+            .map_or(-1., |definition| definition.hardness)
+    }
+
+    // Only ids defined in the block registry are voxel types
+    pub fn try_from_u32(voxel_type: u32) -> Option<Self> {
+        let voxel_type = Self(u16::try_from(voxel_type).ok()?);
+
+        BlockRegistry::global()
+            .get(voxel_type)
+            .is_some()
+            .then_some(voxel_type)
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Reflect)]
 pub struct Voxel {
     pub voxel_type: VoxelType,
 }
@@ -24,26 +88,21 @@ impl Voxel {
 impl Default for Voxel {
     fn default() -> Self {
         Self {
-            voxel_type: VoxelType::Air,
+            voxel_type: VoxelType::AIR,
         }
     }
 }
 
 impl From<VoxelType> for u32 {
     fn from(voxel_type: VoxelType) -> Self {
-        match voxel_type {
-            VoxelType::Air => 0,
-            VoxelType::Block => 1,
-        }
+        voxel_type.0 as u32
     }
 }
 
 impl From<u32> for VoxelType {
     fn from(voxel_type: u32) -> Self {
-        match voxel_type {
-            0 => VoxelType::Air,
-            1 => VoxelType::Block,
-            _ => panic!("Voxel type: {voxel_type} not recognised, so can't convert to VoxelType"),
-        }
+        VoxelType::try_from_u32(voxel_type).unwrap_or_else(|| {
+            panic!("Voxel type: {voxel_type} not recognised, so can't convert to VoxelType")
+        })
     }
 }
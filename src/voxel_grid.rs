@@ -0,0 +1,74 @@
+use bevy::math::{IVec3, UVec3};
+
+use crate::{lod::Lod, voxel::VoxelType};
+
+// A box of voxels which the meshers can build a mesh of, chunks and voxel objects are both grids
+// The meshers also read the voxels one step outside the box, to cull faces against and sample AO
+// Neither side can be larger than VOXEL_GRID_MAX_SIZE, so that a column fits in a u64 with padding
+pub trait VoxelGrid: Sync {
+    fn size(&self) -> UVec3;
+
+    // Positions range from -1 to size (inclusive) along each axis
+    fn voxel_at(&self, pos: IVec3) -> VoxelType;
+}
+
+// A grid at a lower level of detail, where each voxel stands for a cube of the jump size
+// A voxel is solid if at least half of its cube is, and takes the most common solid type in it
+pub struct Downsampled<'a, G: VoxelGrid> {
+    grid: &'a G,
+    jump: i32,
+}
+
+impl<'a, G: VoxelGrid> Downsampled<'a, G> {
+    pub fn new(grid: &'a G, lod: Lod) -> Self {
+        Self {
+            grid,
+            jump: lod.jump_index() as i32,
+        }
+    }
+}
+
+impl<G: VoxelGrid> VoxelGrid for Downsampled<'_, G> {
+    fn size(&self) -> UVec3 {
+        (self.grid.size().as_ivec3() + self.jump - 1).as_uvec3() / self.jump as u32
+    }
+
+    fn voxel_at(&self, pos: IVec3) -> VoxelType {
+        // The cube is clamped to the positions the grid can read, so the padding is a single layer
+        let min = (pos * self.jump).max(IVec3::NEG_ONE);
+        let max = ((pos + 1) * self.jump).min(self.grid.size().as_ivec3() + 1);
+
+        let mut samples = 0;
+        let mut solid_counts: Vec<(VoxelType, usize)> = Vec::new();
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    samples += 1;
+
+                    let voxel_type = self.grid.voxel_at(IVec3::new(x, y, z));
+                    if !voxel_type.is_solid() {
+                        continue;
+                    }
+
+                    match solid_counts
+                        .iter_mut()
+                        .find(|(solid, _)| *solid == voxel_type)
+                    {
+                        Some((_, count)) => *count += 1,
+                        None => solid_counts.push((voxel_type, 1)),
+                    }
+                }
+            }
+        }
+
+        let solid = solid_counts.iter().map(|(_, count)| count).sum::<usize>();
+        if solid * 2 < samples {
+            return VoxelType::AIR;
+        }
+
+        solid_counts
+            .into_iter()
+            .max_by_key(|&(_, count)| count)
+            .map_or(VoxelType::AIR, |(voxel_type, _)| voxel_type)
+    }
+}
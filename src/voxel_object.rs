@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+
+use crate::{
+    chunk_mesh::ChunkMesh,
+    constants::VOXEL_GRID_MAX_SIZE,
+    greedy_mesher, mesh_pool,
+    positions::{VoxelScale, WorldPos},
+    rendering::{ChunkMaterial, GlobalChunkMaterial},
+    voxel::VoxelType,
+    voxel_grid::VoxelGrid,
+    world::{voxel_mesh, MeshAttributes, World},
+    world_edit::{EditError, EditTransaction},
+};
+
+// Voxels cut out of the world into their own movable entity, for doors, vehicles or platforms
+pub struct VoxelObjectPlugin;
+
+impl Plugin for VoxelObjectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, VoxelObject::remesh);
+    }
+}
+
+// A small grid of voxels with its own mesh, positioned by the entity's transform
+// Spawn with spawn_voxel_object, the mesh is rebuilt whenever the grid changes
+#[derive(Component, Clone, Debug)]
+pub struct VoxelObject {
+    size: UVec3,
+    // In index order, x + (y + z * size.y) * size.x
+    voxels: Vec<VoxelType>,
+}
+
+impl VoxelObject {
+    // An empty grid, no axis can be larger than VOXEL_GRID_MAX_SIZE
+    pub fn new(size: UVec3) -> Self {
+        assert!(
+            size.cmple(UVec3::splat(VOXEL_GRID_MAX_SIZE as u32)).all(),
+            "Voxel object is too large: {size}"
+        );
+
+        Self {
+            size,
+            voxels: vec![VoxelType::AIR; (size.x * size.y * size.z) as usize],
+        }
+    }
+
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    fn index(&self, pos: UVec3) -> Option<usize> {
+        pos.cmplt(self.size)
+            .all()
+            .then(|| (pos.x + (pos.y + pos.z * self.size.y) * self.size.x) as usize)
+    }
+
+    pub fn get(&self, pos: UVec3) -> Option<VoxelType> {
+        self.index(pos).map(|index| self.voxels[index])
+    }
+
+    // Returns false if the position is outside the grid
+    pub fn set(&mut self, pos: UVec3, voxel_type: VoxelType) -> bool {
+        let Some(index) = self.index(pos) else {
+            return false;
+        };
+
+        self.voxels[index] = voxel_type;
+        true
+    }
+
+    pub fn is_all_air(&self) -> bool {
+        self.voxels.iter().all(|voxel_type| !voxel_type.is_solid())
+    }
+
+    pub fn build_mesh(&self) -> Option<ChunkMesh> {
+        let mut mesh = greedy_mesher::build_grid_mesh(self, true)?;
+
+        // Objects don't belong to a biome, so they use the middle of the tint ramp
+        mesh.biome_tints = vec![0.5; mesh.vertices.len()];
+
+        Some(mesh)
+    }
+
+    // Transform which places the grid's lowest corner at a world position
+    pub fn transform_at(origin: WorldPos, voxel_scale: &VoxelScale) -> Transform {
+        let (x, y, z) = origin.to_tuple();
+
+        Transform::from_translation(IVec3::new(x, y, z).as_vec3() * voxel_scale.0)
+            .with_scale(Vec3::splat(voxel_scale.0))
+    }
+
+    // World position of the grid's lowest corner, rounded to the nearest voxel
+    // Rotation is ignored, so objects should be turned back to axis alignment before welding
+    pub fn grid_origin(transform: &Transform, voxel_scale: &VoxelScale) -> WorldPos {
+        let origin = (transform.translation / voxel_scale.0).round().as_ivec3();
+
+        WorldPos::new(origin.x, origin.y, origin.z)
+    }
+
+    fn remesh(
+        mut objects: Query<(&VoxelObject, &mut Handle<Mesh>), Changed<VoxelObject>>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mesh_attributes: Res<MeshAttributes>,
+    ) {
+        for (object, mut mesh_handle) in objects.iter_mut() {
+            *mesh_handle = object
+                .build_mesh()
+                .map(|mesh| {
+                    let handle = meshes.add(voxel_mesh(&mesh, *mesh_attributes));
+                    mesh_pool::recycle(mesh);
+                    handle
+                })
+                .unwrap_or_default();
+        }
+    }
+}
+
+// Voxels outside the object are air
+impl VoxelGrid for VoxelObject {
+    fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    fn voxel_at(&self, pos: IVec3) -> VoxelType {
+        if pos.cmplt(IVec3::ZERO).any() {
+            return VoxelType::AIR;
+        }
+
+        self.get(pos.as_uvec3()).unwrap_or(VoxelType::AIR)
+    }
+}
+
+pub fn spawn_voxel_object(
+    commands: &mut Commands,
+    object: VoxelObject,
+    transform: Transform,
+    g_chunk_material: &GlobalChunkMaterial,
+) -> Entity {
+    commands
+        .spawn((
+            object,
+            MaterialMeshBundle::<ChunkMaterial> {
+                material: g_chunk_material.0.clone(),
+                transform,
+                ..default()
+            },
+        ))
+        .id()
+}
+
+impl World {
+    // Move the voxels of the (inclusive) box into a voxel object, leaving air behind as one undo entry
+    // None if the box is larger than a voxel object or isn't entirely loaded
+    pub fn cut_voxel_object(&mut self, a: WorldPos, b: WorldPos) -> Option<VoxelObject> {
+        let min = IVec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = IVec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        let size = (max - min + 1).as_uvec3();
+
+        if size.cmpgt(UVec3::splat(VOXEL_GRID_MAX_SIZE as u32)).any() {
+            return None;
+        }
+
+        let mut object = VoxelObject::new(size);
+        let mut transaction = EditTransaction::new();
+
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let offset = UVec3::new(x, y, z);
+                    let world_pos = min + offset.as_ivec3();
+                    let world_pos = WorldPos::new(world_pos.x, world_pos.y, world_pos.z);
+
+                    let voxel_type = self.get_voxel(world_pos)?.voxel_type;
+                    if voxel_type.is_solid() {
+                        object.set(offset, voxel_type);
+                        transaction.set_voxel(world_pos, VoxelType::AIR);
+                    }
+                }
+            }
+        }
+
+        self.commit(transaction).ok()?;
+
+        Some(object)
+    }
+
+    // Write the object's solid voxels back into the world with its lowest corner at the origin, as
+    // one undo entry. Air in the object leaves the world untouched, returns the number of voxels changed
+    // Nothing is welded if any of the object's solid voxels would be in a chunk which isn't loaded
+    pub fn weld_voxel_object(
+        &mut self,
+        object: &VoxelObject,
+        origin: WorldPos,
+    ) -> Result<usize, EditError> {
+        let mut transaction = EditTransaction::new();
+
+        for z in 0..object.size.z {
+            for y in 0..object.size.y {
+                for x in 0..object.size.x {
+                    let offset = UVec3::new(x, y, z);
+                    let Some(voxel_type) = object.get(offset).filter(VoxelType::is_solid) else {
+                        continue;
+                    };
+
+                    let world_pos = WorldPos::new(
+                        origin.x + x as i32,
+                        origin.y + y as i32,
+                        origin.z + z as i32,
+                    );
+                    transaction.set_voxel(world_pos, voxel_type);
+                }
+            }
+        }
+
+        self.commit(transaction)
+    }
+}
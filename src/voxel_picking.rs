@@ -0,0 +1,176 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    constants::PICK_DISTANCE,
+    positions::{VoxelScale, WorldPos},
+    world::World,
+};
+
+// Picking backend for the terrain: casts a ray from the cursor through the voxel data, so tools get
+// the exact voxel and face under the cursor whatever mesh (greedy, far or LOD) is drawn there
+pub struct VoxelPickingPlugin;
+
+impl Plugin for VoxelPickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelPicking>()
+            .add_event::<VoxelPicked>()
+            .add_systems(
+                Update,
+                (VoxelPicking::update_hovered, VoxelPicking::send_clicks).chain(),
+            );
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VoxelHit {
+    pub world_pos: WorldPos,
+    // Normal of the face the ray entered through, zero if the ray started inside the voxel
+    pub normal: IVec3,
+    // Distance along the ray in voxels
+    pub distance: f32,
+}
+
+impl VoxelHit {
+    // The empty voxel in front of the hit face, where a placed voxel would go
+    pub fn adjacent_pos(&self) -> WorldPos {
+        self.world_pos + WorldPos::new(self.normal.x, self.normal.y, self.normal.z)
+    }
+}
+
+// Sent when a mouse button is pressed over a voxel
+#[derive(Event, Debug, Copy, Clone)]
+pub struct VoxelPicked {
+    pub hit: VoxelHit,
+    pub button: MouseButton,
+    // Entity of the hit voxel's chunk, if it has a mesh
+    pub chunk_entity: Option<Entity>,
+}
+
+#[derive(Resource, Debug)]
+pub struct VoxelPicking {
+    pub enabled: bool,
+    pub max_distance: f32,
+    // The voxel under the cursor this frame
+    pub hovered: Option<VoxelHit>,
+}
+
+impl Default for VoxelPicking {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_distance: PICK_DISTANCE,
+            hovered: None,
+        }
+    }
+}
+
+impl VoxelPicking {
+    pub fn update_hovered(
+        mut picking: ResMut<VoxelPicking>,
+        world: Res<World>,
+        voxel_scale: Res<VoxelScale>,
+        windows: Query<&Window, With<PrimaryWindow>>,
+        cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    ) {
+        picking.hovered = None;
+        if !picking.enabled {
+            return;
+        }
+
+        let (Ok(window), Ok((camera, camera_transform))) =
+            (windows.get_single(), cameras.get_single())
+        else {
+            return;
+        };
+
+        // A grabbed cursor has no position, so pick from the centre of the screen like a crosshair
+        let cursor = window
+            .cursor_position()
+            .unwrap_or(Vec2::new(window.width(), window.height()) / 2.);
+        let Some(ray) = camera.viewport_to_world(camera_transform, cursor) else {
+            return;
+        };
+
+        // Voxel space is world space divided by the voxel size
+        picking.hovered = world.raycast(
+            ray.origin / voxel_scale.0,
+            *ray.direction,
+            picking.max_distance,
+        );
+    }
+
+    fn send_clicks(
+        picking: Res<VoxelPicking>,
+        world: Res<World>,
+        mouse_buttons: Res<ButtonInput<MouseButton>>,
+        mut picked_events: EventWriter<VoxelPicked>,
+    ) {
+        let Some(hit) = picking.hovered else {
+            return;
+        };
+
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(hit.world_pos);
+        let chunk_entity = world.entity_at(chunk_pos);
+
+        for &button in mouse_buttons.get_just_pressed() {
+            picked_events.send(VoxelPicked {
+                hit,
+                button,
+                chunk_entity,
+            });
+        }
+    }
+}
+
+impl World {
+    // First solid voxel along the ray (in voxel units), stepping voxel by voxel (Amanatides & Woo)
+    // Stops at the first unloaded chunk
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<VoxelHit> {
+        let direction = direction.try_normalize()?;
+
+        let mut voxel = origin.floor().as_ivec3();
+        let step = direction.signum().as_ivec3();
+
+        // Distance along the ray to the next voxel boundary on each axis, and between boundaries
+        let delta = direction.recip().abs();
+        let next_boundary = voxel.as_vec3() + step.max(IVec3::ZERO).as_vec3();
+        let mut t_max = Vec3::select(
+            direction.cmpeq(Vec3::ZERO),
+            Vec3::INFINITY,
+            (next_boundary - origin) / direction,
+        );
+
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.;
+
+        while distance <= max_distance {
+            let world_pos = WorldPos::new(voxel.x, voxel.y, voxel.z);
+
+            if self.get_voxel(world_pos)?.voxel_type.is_solid() {
+                return Some(VoxelHit {
+                    world_pos,
+                    normal,
+                    distance,
+                });
+            }
+
+            // Step across the nearest boundary
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+                0
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+
+            distance = t_max[axis];
+            t_max[axis] += delta[axis];
+            voxel[axis] += step[axis];
+
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+
+        None
+    }
+}
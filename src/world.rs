@@ -1,21 +1,54 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bevy::{
+    diagnostic::{Diagnostic, RegisterDiagnostic},
     prelude::*,
     render::{mesh::Indices, primitives::Aabb, render_asset::RenderAssetUsages},
-    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+    tasks::{block_on, futures_lite::future, Task},
+    utils::tracing::field,
 };
 
 use crate::{
+    background_throttle::MeshingPace,
+    biome::BiomeMap,
+    cave_culling::FaceConnectivity,
     chunk::Chunk,
+    chunk_cache::{ChunkCache, CHUNK_CACHE_HITS, CHUNK_CACHE_MISSES},
     chunk_from_middle::ChunksFromMiddle,
     chunk_loading::ChunkLoader,
-    chunk_mesh::ChunkMesh,
-    constants::{ATTRIBUTE_VOXEL, CHUNK_SIZE, MAX_DATA_TASKS, MAX_MESH_TASKS},
-    greedy_mesher,
+    chunk_mesh::{ChunkMesh, SectionMeshes},
+    chunk_meta::ChunkMeta,
+    chunk_queue::ChunkQueue,
+    chunk_saver,
+    constants::{
+        ALL_SECTIONS, ATTRIBUTE_BIOME_TINT, ATTRIBUTE_FAR_FACE, ATTRIBUTE_SMOOTH_VOXEL,
+        ATTRIBUTE_VOXEL, MAX_MESH_SPAWNS_PER_FRAME, MESH_JOIN_BUDGET_SECS, NORMALS_ARRAY,
+        SECTIONS_PER_CHUNK, SECTION_SIZE, TIGHT_MESH_AABBS,
+    },
+    culled_mesher,
+    decals::ChunkDecals,
+    generation_stages::GenerationStages,
+    greedy_mesher::{self, AoMerging},
     lod::Lod,
-    positions::ChunkPos,
-    rendering::GlobalChunkMaterial,
+    mesh_override::ChunkMeshOverride,
+    mesh_pool,
+    mesh_quality::{MeshQuality, MeshQualityPolicy},
+    persistence,
+    pipeline_metrics::{self, PipelineStage},
+    pipeline_stepping::PipelineStepping,
+    positions::{ChunkPos, VoxelPos, VoxelScale, WorldPos},
+    rendering::{ChunkMaterial, GlobalChunkMaterial, GlobalFarChunkMaterial},
+    surface_nets, task_pools,
+    task_scheduler::TaskScheduler,
+    vertex::{FaceU32, Vertex, FAR_FACES_FIT},
+    voxel::VoxelType,
+    world_border::WorldBorder,
+    world_edit::VoxelChanged,
+    world_generator::WorldGen,
 };
 
 pub struct WorldPlugin;
@@ -23,65 +56,493 @@ pub struct WorldPlugin;
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(World::default())
+            .init_resource::<PipelineMode>()
+            .init_resource::<MeshAttributes>()
+            .init_resource::<JoinLimits>()
+            .init_resource::<WorldGen>()
+            .init_resource::<WorldCounters>()
+            .init_resource::<VoxelScale>()
+            .register_type::<WorldCounters>()
+            .register_type::<VoxelScale>()
+            .register_type::<MeshAttributes>()
+            .register_type::<JoinLimits>()
+            .register_type::<ChunkPos>()
+            .register_type::<ChunkEntity>()
+            .register_type::<VoxelType>()
+            .add_event::<VoxelChanged>()
+            .register_diagnostic(Diagnostic::new(CHUNK_CACHE_HITS))
+            .register_diagnostic(Diagnostic::new(CHUNK_CACHE_MISSES))
             .add_systems(
                 Update,
                 (
-                    (World::join_data, World::join_mesh),
-                    (World::unload_data, World::unload_mesh),
+                    (
+                        (
+                            (World::join_data, World::apply_pending_edits).chain(),
+                            World::join_mesh.run_if(MeshingPace::is_running),
+                        ),
+                        (World::unload_data, World::unload_mesh),
+                    )
+                        .chain()
+                        .run_if(PipelineStepping::is_running),
+                    World::update_counters,
+                    World::update_mesh_qualities,
+                    ChunkCache::measure,
+                    World::apply_voxel_scale,
                 )
                     .chain(),
             )
             .add_systems(
                 PostUpdate,
-                (World::start_data_tasks, World::start_mesh_tasks),
+                (
+                    World::start_data_tasks,
+                    World::start_mesh_tasks.run_if(MeshingPace::is_running),
+                )
+                    .run_if(PipelineStepping::is_running),
+            )
+            // Every command spawning or despawning chunk entities has been applied by then, and
+            // every edit of the frame has been made
+            .add_systems(
+                Last,
+                (World::validate_chunk_entities, World::send_voxel_changes),
             );
     }
 }
 
+// Marks the parent entity of a chunk's meshes, with the chunk it belongs to
+#[derive(Component, Reflect, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct ChunkEntity(pub ChunkPos);
+
+// Vertex attributes chunk meshes are built with, the packed voxel attribute is always written
+// Standard also writes Mesh::ATTRIBUTE_POSITION (normals are always written for the prepass), so
+// default materials, gizmos, raycasting and exporters can read the meshes, at the cost of memory
+#[derive(Resource, Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[reflect(Resource)]
+pub enum MeshAttributes {
+    #[default]
+    Packed,
+    Standard,
+}
+
+// Which mesher builds a world's chunk meshes
+#[derive(Reflect, Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MeshingMode {
+    // Voxels are drawn as cubes, meshed by quality
+    #[default]
+    Blocky,
+    // Surface nets over the chunks' densities, far chunks are meshed from downsampled densities
+    Smooth,
+}
+
+// How many finished meshes join_mesh turns into entities each frame, the rest wait for later frames
+#[derive(Resource, Reflect, Debug, Copy, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct JoinLimits {
+    pub max_spawns_per_frame: usize,
+    // Spawning stops for the frame once joining has taken this long
+    pub max_join_secs: f32,
+}
+
+impl Default for JoinLimits {
+    fn default() -> Self {
+        Self {
+            max_spawns_per_frame: MAX_MESH_SPAWNS_PER_FRAME,
+            max_join_secs: MESH_JOIN_BUDGET_SECS,
+        }
+    }
+}
+
+// How chunk tasks are resolved, deterministic mode blocks on every task when joining so that
+// chunks finish in system order (useful for reproducible worlds in tests)
+#[derive(Resource, Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PipelineMode {
+    #[default]
+    Async,
+    Deterministic,
+}
+
+// Snapshot of the World's queue and task sizes, so they can be viewed in the inspector
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+pub struct WorldCounters {
+    pub loaded_chunks: usize,
+    pub chunk_entities: usize,
+    pub load_data_queue: usize,
+    pub load_mesh_queue: usize,
+    pub unload_data_queue: usize,
+    pub unload_mesh_queue: usize,
+    pub data_tasks: usize,
+    pub mesh_tasks: usize,
+    pub parked_meshes: usize,
+}
+
 #[derive(Resource, Default)]
 pub struct World {
-    pub chunks: HashMap<ChunkPos, Arc<Chunk>>,
-    pub load_data_queue: Vec<ChunkPos>,
-    pub load_mesh_queue: Vec<ChunkPos>,
-    pub unload_data_queue: Vec<ChunkPos>,
-    pub unload_mesh_queue: Vec<ChunkPos>,
-    pub data_tasks: HashMap<ChunkPos, Option<Task<Chunk>>>,
-    pub mesh_tasks: Vec<(ChunkPos, Option<Task<Option<ChunkMesh>>>)>,
-    pub chunk_entities: HashMap<ChunkPos, Entity>,
+    pub(crate) chunks: HashMap<ChunkPos, Arc<Chunk>>,
+    // Generation metadata of each loaded chunk
+    pub(crate) chunk_metas: HashMap<ChunkPos, Arc<ChunkMeta>>,
+    pub(crate) load_data_queue: ChunkQueue,
+    pub(crate) load_mesh_queue: ChunkQueue,
+    pub(crate) unload_data_queue: ChunkQueue,
+    pub(crate) unload_mesh_queue: ChunkQueue,
+    pub(crate) data_tasks: HashMap<ChunkPos, Option<Task<(Chunk, ChunkMeta)>>>,
+    pub(crate) mesh_tasks: Vec<MeshTask>,
+    // Bumped when a chunk's meshes are invalidated, so results of tasks started before are dropped
+    pub(crate) mesh_versions: HashMap<ChunkPos, u64>,
+    pub(crate) next_mesh_version: u64,
+    // Parent entity of each chunk, holding the chunk transform
+    pub(crate) chunk_entities: HashMap<ChunkPos, Entity>,
+    // The reverse of chunk_entities, see World::chunk_of
+    pub(crate) entity_chunks: HashMap<Entity, ChunkPos>,
+    // Mesh entity of each non-empty section, children of the chunk entity
+    pub(crate) section_entities: HashMap<ChunkPos, [Option<Entity>; SECTIONS_PER_CHUNK]>,
+    // Which faces of each meshed chunk are connected through non-opaque voxels, for cave culling
+    pub(crate) chunk_connectivity: HashMap<ChunkPos, FaceConnectivity>,
+    // Content hash of the mesh shown by each section entity
+    pub(crate) section_hashes: HashMap<ChunkPos, [Option<u64>; SECTIONS_PER_CHUNK]>,
+    // Quality each meshed chunk was last fully meshed at, chunks without an entry are full quality
+    pub(crate) mesh_qualities: HashMap<ChunkPos, MeshQuality>,
+    // Qualities chunks were upgraded to while the pipeline was idle, see LodRefinement
+    pub(crate) refined_qualities: HashMap<ChunkPos, MeshQuality>,
+    // Sections to rebuild for chunks in the load mesh queue, chunks without an entry rebuild every section
+    pub(crate) remesh_sections: HashMap<ChunkPos, u64>,
+    // Remeshes from edit transactions, each batch is started together and shown in the same frame
+    pub(crate) remesh_batches: Vec<HashMap<ChunkPos, u64>>,
+    pub(crate) next_batch_id: u64,
+    // Finished meshes of batches which are waiting for the rest of their batch
+    pub(crate) finished_batches: HashMap<u64, Vec<FinishedMesh>>,
+    // Finished meshes waiting for their entities to be spawned, in groups which are spawned together
+    pub(crate) parked_meshes: VecDeque<Vec<FinishedMesh>>,
+    // Writes reverting each committed edit transaction, most recent last
+    pub(crate) edit_history: VecDeque<Vec<(WorldPos, VoxelType)>>,
+    // Recently unloaded chunks, checked before loading or generating a chunk
+    pub(crate) chunk_cache: ChunkCache,
+    // Chunks edited since they were last saved
+    pub(crate) dirty_chunks: HashSet<ChunkPos>,
+    // Set when the app is exiting, no new tasks are started
+    pub(crate) shutting_down: bool,
+    // How long the last join of each stage took, the task scheduler backs off stages whose joins run long
+    pub(crate) data_join_time: Duration,
+    pub(crate) mesh_join_time: Duration,
+    // Chunks waiting on their neighbours' bases before they can be decorated
+    pub(crate) generation_stages: GenerationStages,
+    // Voxel writes since the replay recorder last took them, None while nothing is recording
+    pub(crate) recorded_writes: Option<Vec<(WorldPos, VoxelType)>>,
+    pub(crate) meshing_mode: MeshingMode,
+    // Chunks pinned to a mesh quality or mesher, see ChunkMeshOverride
+    pub(crate) mesh_overrides: HashMap<ChunkPos, ChunkMeshOverride>,
+    // How the greedy mesher merges faces with different AO, for full quality chunks
+    pub(crate) ao_merging: AoMerging,
+    // Bumped whenever a chunk is loaded, unloaded or replaced, see WorldReader
+    pub(crate) chunk_generation: u64,
+    // Overlays on voxel faces, see World::add_decal
+    pub(crate) decals: ChunkDecals,
+    // Writes to chunks which weren't loaded, applied when they load, see World::place_schematic
+    pub(crate) pending_edits: HashMap<ChunkPos, Vec<(WorldPos, VoxelType)>>,
+    // Voxels changed this frame, sent as VoxelChanged events at the end of the frame
+    pub(crate) voxel_changes: HashMap<ChunkPos, Vec<(VoxelPos, VoxelType, VoxelType)>>,
+}
+
+pub struct MeshTask {
+    pub chunk_pos: ChunkPos,
+    pub task: Option<Task<(SectionMeshes, FaceConnectivity)>>,
+    // Id of the remesh batch the task belongs to
+    pub batch: Option<u64>,
+    pub sections: u64,
+    // The chunk's mesh version when the task was started
+    pub version: u64,
+}
+
+pub struct FinishedMesh {
+    pub chunk_pos: ChunkPos,
+    // The mesh version of the task which built it
+    pub version: u64,
+    pub section_meshes: SectionMeshes,
 }
 
 impl World {
+    // Mark the chunk's in-flight mesh tasks as stale, their results are dropped and remeshed when joined
+    pub fn invalidate_meshes(&mut self, chunk_pos: ChunkPos) {
+        self.next_mesh_version += 1;
+        self.mesh_versions.insert(chunk_pos, self.next_mesh_version);
+    }
+
+    // Switch mesher and remesh every meshed chunk with it
+    pub fn set_meshing_mode(&mut self, meshing_mode: MeshingMode) {
+        if self.meshing_mode == meshing_mode {
+            return;
+        }
+        self.meshing_mode = meshing_mode;
+
+        let meshed = self.chunk_entities.keys().copied().collect::<Vec<_>>();
+        for chunk_pos in meshed {
+            self.invalidate_meshes(chunk_pos);
+            self.queue_remesh(chunk_pos);
+        }
+    }
+
+    // Remesh the full quality chunks, the only ones meshed with AO, with the new merging
+    pub fn set_ao_merging(&mut self, ao_merging: AoMerging) {
+        if self.ao_merging == ao_merging {
+            return;
+        }
+        self.ao_merging = ao_merging;
+
+        let full = self
+            .chunk_entities
+            .keys()
+            .copied()
+            .filter(|&chunk_pos| self.mesh_quality(chunk_pos) == MeshQuality::Full)
+            .collect::<Vec<_>>();
+        for chunk_pos in full {
+            self.invalidate_meshes(chunk_pos);
+            self.queue_remesh(chunk_pos);
+        }
+    }
+
+    // Replace the overrides, remeshing every chunk whose override was added, changed or removed
+    pub fn set_mesh_overrides(&mut self, mesh_overrides: HashMap<ChunkPos, ChunkMeshOverride>) {
+        let changed = mesh_overrides
+            .iter()
+            .filter(|(chunk_pos, mesh_override)| {
+                self.mesh_overrides.get(chunk_pos) != Some(mesh_override)
+            })
+            .map(|(&chunk_pos, _)| chunk_pos)
+            .chain(
+                self.mesh_overrides
+                    .keys()
+                    .filter(|chunk_pos| !mesh_overrides.contains_key(chunk_pos))
+                    .copied(),
+            )
+            .filter(|chunk_pos| self.chunk_entities.contains_key(chunk_pos))
+            .collect::<Vec<_>>();
+        self.mesh_overrides = mesh_overrides;
+
+        for chunk_pos in changed {
+            // Upgrades made before the override would be kept otherwise
+            self.refined_qualities.remove(&chunk_pos);
+            self.invalidate_meshes(chunk_pos);
+            self.queue_remesh(chunk_pos);
+        }
+    }
+
+    // Quality the chunk should be meshed at, from its override or else the loader's policy
+    pub fn expected_quality(
+        &self,
+        chunk_pos: ChunkPos,
+        policy: &MeshQualityPolicy,
+        loader_pos: ChunkPos,
+    ) -> MeshQuality {
+        expected_quality(
+            &self.mesh_overrides,
+            &self.refined_qualities,
+            chunk_pos,
+            policy,
+            loader_pos,
+        )
+    }
+
+    pub fn is_quality_overridden(&self, chunk_pos: ChunkPos) -> bool {
+        self.mesh_overrides
+            .get(&chunk_pos)
+            .is_some_and(|mesh_override| mesh_override.quality.is_some())
+    }
+
+    pub fn mesh_quality(&self, chunk_pos: ChunkPos) -> MeshQuality {
+        self.mesh_qualities
+            .get(&chunk_pos)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Chunks queued to load and to unload their data, in that order
+    pub fn data_queues(&self) -> (&ChunkQueue, &ChunkQueue) {
+        (&self.load_data_queue, &self.unload_data_queue)
+    }
+
+    // Chunks queued to load and to unload their meshes, in that order
+    pub fn mesh_queues(&self) -> (&ChunkQueue, &ChunkQueue) {
+        (&self.load_mesh_queue, &self.unload_mesh_queue)
+    }
+
+    // Stop starting tasks and wait for the in-flight ones, returns false if the timeout was reached
+    pub fn drain_tasks(&mut self, timeout: Duration) -> bool {
+        self.shutting_down = true;
+
+        let deadline = Instant::now() + timeout;
+        // Chunks still waiting on their neighbours won't be decorated, so only finished chunks are saved
+        self.generation_stages = GenerationStages::default();
+
+        let World {
+            chunks,
+            chunk_metas,
+            data_tasks,
+            mesh_tasks,
+            ..
+        } = self;
+
+        loop {
+            data_tasks.retain(|chunk_pos, task_option| {
+                let Some(task) = task_option.as_mut() else {
+                    return false;
+                };
+
+                let Some((chunk, meta)) = block_on(future::poll_once(task)) else {
+                    return true;
+                };
+
+                chunks.insert(*chunk_pos, Arc::new(chunk));
+                chunk_metas.insert(*chunk_pos, Arc::new(meta));
+                false
+            });
+
+            // Meshes won't be shown any more, so only wait for them to finish
+            mesh_tasks.retain_mut(|mesh_task| {
+                mesh_task
+                    .task
+                    .as_mut()
+                    .is_some_and(|task| block_on(future::poll_once(task)).is_none())
+            });
+
+            if data_tasks.is_empty() && mesh_tasks.is_empty() {
+                return true;
+            }
+
+            if Instant::now() >= deadline {
+                warn!(
+                    "Shutting down with {} data tasks and {} mesh tasks still running",
+                    data_tasks.len(),
+                    mesh_tasks.len()
+                );
+                return false;
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // Remesh chunks whose mesh quality changed, checked when the loader moves to another chunk or its
+    // quality policy changes
+    pub fn update_mesh_qualities(
+        mut world: ResMut<World>,
+        loaders: Query<(&GlobalTransform, &ChunkLoader)>,
+        voxel_scale: Res<VoxelScale>,
+        mut last_checked: Local<Option<(ChunkPos, MeshQualityPolicy)>>,
+    ) {
+        // The loader may be moving between entities, see Spectator
+        let Ok((loader_transform, loader)) = loaders.get_single() else {
+            return;
+        };
+        let loader_pos = voxel_scale.loader_chunk_pos(loader_transform.translation());
+        if *last_checked == Some((loader_pos, loader.mesh_quality)) {
+            return;
+        }
+        *last_checked = Some((loader_pos, loader.mesh_quality));
+
+        let crossed = world
+            .chunk_entities
+            .keys()
+            .filter(|&&chunk_pos| {
+                world.expected_quality(chunk_pos, &loader.mesh_quality, loader_pos)
+                    != world.mesh_quality(chunk_pos)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        for chunk_pos in crossed {
+            world.queue_remesh(chunk_pos);
+        }
+    }
+
+    pub fn update_counters(world: Res<World>, mut counters: ResMut<WorldCounters>) {
+        *counters = WorldCounters {
+            loaded_chunks: world.chunks.len(),
+            chunk_entities: world.chunk_entities.len(),
+            load_data_queue: world.load_data_queue.len(),
+            load_mesh_queue: world.load_mesh_queue.len(),
+            unload_data_queue: world.unload_data_queue.len(),
+            unload_mesh_queue: world.unload_mesh_queue.len(),
+            data_tasks: world.data_tasks.len(),
+            mesh_tasks: world.mesh_tasks.len(),
+            parked_meshes: world.parked_meshes.iter().map(Vec::len).sum(),
+        };
+    }
+
     // Start data building tasks for the chunks in range
     pub fn start_data_tasks(
         mut world: ResMut<World>,
         loaders: Query<&GlobalTransform, With<ChunkLoader>>,
+        world_gen: Res<WorldGen>,
+        voxel_scale: Res<VoxelScale>,
+        border: Res<WorldBorder>,
+        scheduler: Res<TaskScheduler>,
     ) {
-        let task_pool = AsyncComputeTaskPool::get();
+        if world.shutting_down {
+            return;
+        }
+
+        let task_pool = task_pools::generation_pool();
+        // The loader may be moving between entities, see Spectator
+        let Ok(loader_transform) = loaders.get_single() else {
+            return;
+        };
+        let loader_pos = voxel_scale.loader_chunk_pos(loader_transform.translation());
+
+        // Chunks waiting on decoration are nearer to loading, so they take the slots first
+        world
+            .generation_stages
+            .decorate_queue
+            .sort_by_distance(loader_pos);
+        let decorating = world.start_decorations(&world_gen, &border, scheduler.slots.generation);
+        let slots = scheduler.slots.generation.saturating_sub(decorating);
+
+        let decorates = world_gen.0.decorates();
+        let load_data_queue = &mut world.load_data_queue;
+        load_data_queue.sort_by_distance(loader_pos);
+        let chunk_positions = load_data_queue.drain_front(slots);
 
         let World {
-            load_data_queue,
+            chunks,
+            chunk_metas,
             data_tasks,
+            chunk_cache,
+            chunk_generation,
             ..
         } = world.as_mut();
 
-        let g_loader = loaders.single();
-        let loader_pos =
-            ChunkPos::from_vec3(g_loader.translation() - Vec3::splat(CHUNK_SIZE as f32 / 2.)) / 32;
+        let mut staged = Vec::new();
+        for chunk_pos in chunk_positions {
+            // Cached chunks are ready straight away, without a task
+            if let Some((chunk, meta)) = chunk_cache.take(chunk_pos) {
+                chunks.insert(chunk_pos, chunk);
+                chunk_metas.insert(chunk_pos, meta);
+                *chunk_generation += 1;
+                continue;
+            }
 
-        load_data_queue.sort_by(|lhs, rhs| {
-            lhs.distance_squared(loader_pos)
-                .cmp(&rhs.distance_squared(loader_pos))
-        });
+            // Chunks past the border are never generated
+            if !border.contains_chunk(chunk_pos) {
+                let task = task_pool.spawn(async { (Chunk::new(), ChunkMeta::default()) });
+                data_tasks.insert(chunk_pos, Some(task));
+                continue;
+            }
 
-        let tasks_left = (MAX_DATA_TASKS as i32 - data_tasks.len() as i32)
-            .min(load_data_queue.len() as i32)
-            .max(0) as usize;
+            // Decorated chunks are finished by the generation stages
+            if decorates {
+                staged.push(chunk_pos);
+                continue;
+            }
 
-        for chunk_pos in load_data_queue.drain(0..tasks_left) {
-            let task = task_pool.spawn(async move { Chunk::new_from_noise(chunk_pos) });
+            let generator = Arc::clone(&world_gen.0);
+            let task = task_pool
+                .spawn(async move { persistence::load_or_generate(chunk_pos, generator.as_ref()) });
 
             data_tasks.insert(chunk_pos, Some(task));
         }
+
+        for chunk_pos in staged {
+            world.start_generation(chunk_pos, &world_gen);
+        }
     }
 
     // Destroy chunk data
@@ -89,51 +550,173 @@ impl World {
         let World {
             unload_data_queue,
             chunks,
+            chunk_metas,
+            dirty_chunks,
+            chunk_cache,
+            mesh_versions,
+            chunk_generation,
+            decals,
             ..
         } = world.as_mut();
 
-        for chunk_pos in unload_data_queue.drain(..) {
-            chunks.remove(&chunk_pos);
+        for chunk_pos in unload_data_queue.drain_all() {
+            mesh_versions.remove(&chunk_pos);
+            decals.remove_chunk(chunk_pos);
+
+            let (Some(chunk), Some(meta)) =
+                (chunks.remove(&chunk_pos), chunk_metas.remove(&chunk_pos))
+            else {
+                continue;
+            };
+            *chunk_generation += 1;
+
+            // Don't lose edits to chunks which haven't been autosaved yet
+            if dirty_chunks.remove(&chunk_pos) {
+                chunk_saver::queue_save(chunk_pos, Arc::clone(&chunk), Arc::clone(&meta));
+            }
+
+            chunk_cache.insert(chunk_pos, chunk, meta);
         }
     }
 
     pub fn start_mesh_tasks(
         mut world: ResMut<World>,
-        loaders: Query<&GlobalTransform, With<ChunkLoader>>,
+        loaders: Query<(&GlobalTransform, &ChunkLoader)>,
+        g_chunk_material: Res<GlobalChunkMaterial>,
+        chunk_materials: Res<Assets<ChunkMaterial>>,
+        voxel_scale: Res<VoxelScale>,
+        scheduler: Res<TaskScheduler>,
+        border: Res<WorldBorder>,
     ) {
-        let task_pool = AsyncComputeTaskPool::get();
+        if world.shutting_down {
+            return;
+        }
+
+        // Skip sampling AO in the mesher when the material doesn't display it
+        let ao_enabled = chunk_materials
+            .get(&g_chunk_material.0)
+            .is_none_or(ChunkMaterial::is_ao_enabled);
+        let ao = ao_enabled.then_some(world.ao_merging);
 
         let World {
             chunks,
             load_mesh_queue,
             mesh_tasks,
+            remesh_sections,
+            remesh_batches,
+            next_batch_id,
+            mesh_qualities,
+            refined_qualities,
+            mesh_versions,
+            meshing_mode,
+            mesh_overrides,
             ..
         } = world.as_mut();
+        let meshing_mode_of = |chunk_pos| {
+            mesh_overrides
+                .get(&chunk_pos)
+                .and_then(|mesh_override| mesh_override.meshing_mode)
+                .unwrap_or(*meshing_mode)
+        };
 
-        let loader_g = loaders.single();
-        let loader_pos =
-            ChunkPos::from_vec3(loader_g.translation() - Vec3::splat(CHUNK_SIZE as f32 / 2.)) / 32;
+        // Batches ignore the task limit, so that every chunk in a batch is started in the same frame
+        for batch in remesh_batches.drain(..) {
+            let batch_id = *next_batch_id;
+            *next_batch_id += 1;
 
-        load_mesh_queue.sort_by(|lhs, rhs| {
-            lhs.distance_squared(loader_pos)
-                .cmp(&rhs.distance_squared(loader_pos))
-        });
+            for (chunk_pos, sections) in batch {
+                let quality = mesh_qualities.get(&chunk_pos).copied().unwrap_or_default();
 
-        let tasks_left = (MAX_MESH_TASKS as i32 - mesh_tasks.len() as i32)
-            .min(load_mesh_queue.len() as i32)
-            .max(0) as usize;
-        for chunk_pos in load_mesh_queue.drain(0..tasks_left) {
-            let Some(chunks_from_middle) = ChunksFromMiddle::try_new(chunks, chunk_pos) else {
+                if let Some(task) = spawn_mesh_task(
+                    chunks,
+                    chunk_pos,
+                    sections,
+                    ao,
+                    quality,
+                    meshing_mode_of(chunk_pos),
+                    &border,
+                ) {
+                    mesh_tasks.push(MeshTask {
+                        chunk_pos,
+                        task: Some(task),
+                        batch: Some(batch_id),
+                        sections,
+                        version: mesh_versions.get(&chunk_pos).copied().unwrap_or_default(),
+                    });
+                }
+            }
+        }
+
+        // The loader may be moving between entities, see Spectator
+        let Ok((loader_transform, loader)) = loaders.get_single() else {
+            return;
+        };
+        let loader_pos = voxel_scale.loader_chunk_pos(loader_transform.translation());
+
+        load_mesh_queue.sort_by_distance(loader_pos);
+
+        for chunk_pos in load_mesh_queue.drain_front(scheduler.slots.meshing) {
+            let sections = remesh_sections.remove(&chunk_pos).unwrap_or(ALL_SECTIONS);
+            let version = mesh_versions.get(&chunk_pos).copied().unwrap_or_default();
+
+            // Skip remeshes which an in-flight task is already building from the same voxels
+            if mesh_tasks.iter().any(|mesh_task| {
+                mesh_task.chunk_pos == chunk_pos
+                    && mesh_task.version == version
+                    && mesh_task.sections & sections == sections
+            }) {
                 continue;
-            };
+            }
 
-            let task = task_pool
-                // .spawn(async move { culled_mesher::build_chunk_mesh(&chunks_from_middle) });
-                .spawn(
-                    async move { greedy_mesher::build_chunk_mesh(&chunks_from_middle, Lod::L32) },
-                );
+            // Full remeshes pick the quality by distance, partial ones match the other sections
+            if sections == ALL_SECTIONS {
+                match expected_quality(
+                    mesh_overrides,
+                    refined_qualities,
+                    chunk_pos,
+                    &loader.mesh_quality,
+                    loader_pos,
+                ) {
+                    MeshQuality::Full => mesh_qualities.remove(&chunk_pos),
+                    quality => mesh_qualities.insert(chunk_pos, quality),
+                };
+            }
+            let quality = mesh_qualities.get(&chunk_pos).copied().unwrap_or_default();
+
+            if let Some(task) = spawn_mesh_task(
+                chunks,
+                chunk_pos,
+                sections,
+                ao,
+                quality,
+                meshing_mode_of(chunk_pos),
+                &border,
+            ) {
+                mesh_tasks.push(MeshTask {
+                    chunk_pos,
+                    task: Some(task),
+                    batch: None,
+                    sections,
+                    version,
+                });
+            }
+        }
+    }
+
+    // Move and resize the existing chunk entities when the voxel scale changes
+    pub fn apply_voxel_scale(
+        world: Res<World>,
+        voxel_scale: Res<VoxelScale>,
+        mut transforms: Query<&mut Transform>,
+    ) {
+        if !voxel_scale.is_changed() {
+            return;
+        }
 
-            mesh_tasks.push((chunk_pos, Some(task)));
+        for (chunk_pos, entity) in world.chunk_entities.iter() {
+            if let Ok(mut transform) = transforms.get_mut(*entity) {
+                *transform = chunk_transform(*chunk_pos, &voxel_scale);
+            }
         }
     }
 
@@ -142,27 +725,121 @@ impl World {
         let World {
             unload_mesh_queue,
             chunk_entities,
+            entity_chunks,
+            section_entities,
+            section_hashes,
+            remesh_sections,
+            mesh_qualities,
+            refined_qualities,
+            chunk_connectivity,
+            mesh_tasks,
+            finished_batches,
+            parked_meshes,
             ..
         } = world.as_mut();
 
-        let mut retry = Vec::new();
+        let unloaded = unload_mesh_queue.drain_all();
+
+        // Mesh tasks and finished or parked meshes of unloaded chunks would spawn entities nothing
+        // despawns, so they are dropped (which cancels the tasks)
+        let unloaded_set = unloaded.iter().collect::<HashSet<_>>();
+        mesh_tasks.retain(|mesh_task| !unloaded_set.contains(&mesh_task.chunk_pos));
+        for batch_meshes in finished_batches.values_mut() {
+            batch_meshes.retain(|finished_mesh| !unloaded_set.contains(&finished_mesh.chunk_pos));
+        }
+        for group in parked_meshes.iter_mut() {
+            group.retain(|finished_mesh| !unloaded_set.contains(&finished_mesh.chunk_pos));
+        }
+        parked_meshes.retain(|group| !group.is_empty());
+
+        for chunk_pos in unloaded {
+            section_entities.remove(&chunk_pos);
+            chunk_connectivity.remove(&chunk_pos);
+            section_hashes.remove(&chunk_pos);
+            mesh_qualities.remove(&chunk_pos);
+            refined_qualities.remove(&chunk_pos);
+            remesh_sections.remove(&chunk_pos);
 
-        for chunk_pos in unload_mesh_queue.drain(..) {
             let Some(chunk_id) = chunk_entities.remove(&chunk_pos) else {
                 continue;
             };
-            if let Some(mut entity_commands) = commands.get_entity(chunk_id) {
-                entity_commands.despawn();
+            entity_chunks.remove(&chunk_id);
+            if let Some(entity_commands) = commands.get_entity(chunk_id) {
+                // Also despawns the section meshes
+                entity_commands.despawn_recursive();
             };
         }
+    }
+
+    // Repair chunk_entities when it disagrees with the live chunk entities, e.g. after something
+    // else despawned one. Chunks which lost their entity are remeshed, entities without a chunk are despawned
+    pub fn validate_chunk_entities(
+        mut commands: Commands,
+        mut world: ResMut<World>,
+        chunk_entities: Query<(Entity, &ChunkEntity)>,
+    ) {
+        let lost = world
+            .chunk_entities
+            .iter()
+            .filter(|(_, &entity)| !chunk_entities.contains(entity))
+            .map(|(&chunk_pos, &entity)| (chunk_pos, entity))
+            .collect::<Vec<_>>();
+
+        for (chunk_pos, entity) in lost {
+            warn!("Chunk entity {entity:?} of {chunk_pos:?} was despawned, remeshing the chunk");
+
+            world.chunk_entities.remove(&chunk_pos);
+            world.entity_chunks.remove(&entity);
+            world.section_entities.remove(&chunk_pos);
+            world.section_hashes.remove(&chunk_pos);
+            world.queue_remesh(chunk_pos);
+        }
+
+        for (entity, &ChunkEntity(chunk_pos)) in chunk_entities.iter() {
+            if world.entity_at(chunk_pos) == Some(entity) {
+                continue;
+            }
+
+            // The component was changed, the World's record is the chunk the meshes were built for
+            if let Some(mapped_pos) = world
+                .chunk_of(entity)
+                .filter(|&mapped_pos| world.entity_at(mapped_pos) == Some(entity))
+            {
+                warn!("Chunk entity {entity:?} was marked {chunk_pos:?}, restoring {mapped_pos:?}");
+                commands.entity(entity).insert(ChunkEntity(mapped_pos));
+                continue;
+            }
+
+            warn!(
+                "Chunk entity {entity:?} of {chunk_pos:?} isn't known to the World, despawning it"
+            );
+            world.entity_chunks.remove(&entity);
+            commands.entity(entity).despawn_recursive();
+        }
 
-        unload_mesh_queue.append(&mut retry);
+        // Entries left behind by entities which aren't in chunk_entities any more
+        let World {
+            chunk_entities,
+            entity_chunks,
+            ..
+        } = world.as_mut();
+        if entity_chunks.len() != chunk_entities.len() {
+            entity_chunks.retain(|entity, chunk_pos| chunk_entities.get(chunk_pos) == Some(entity));
+        }
     }
 
     // Join the chunk threads
-    pub fn join_data(mut world: ResMut<World>) {
+    pub fn join_data(mut world: ResMut<World>, pipeline_mode: Res<PipelineMode>) {
+        let span = info_span!("join_data", joined = field::Empty, pending = field::Empty);
+        let _guard = span.enter();
+        let started = Instant::now();
+
         let World {
-            chunks, data_tasks, ..
+            chunks,
+            chunk_metas,
+            data_tasks,
+            chunk_generation,
+            ..
         } = world.as_mut();
 
         for (chunk_pos, task_option) in data_tasks.iter_mut() {
@@ -171,16 +848,29 @@ impl World {
                 continue;
             };
 
-            let Some(chunk) = block_on(future::poll_once(&mut task)) else {
+            let data = match *pipeline_mode {
+                PipelineMode::Async => block_on(future::poll_once(&mut task)),
+                PipelineMode::Deterministic => Some(block_on(&mut task)),
+            };
+
+            let Some((chunk, meta)) = data else {
                 // Failed to poll, keep task alive
                 *task_option = Some(task);
                 continue;
             };
 
             chunks.insert(*chunk_pos, Arc::new(chunk));
+            chunk_metas.insert(*chunk_pos, Arc::new(meta));
+            *chunk_generation += 1;
         }
 
+        let pending_before = data_tasks.len();
         data_tasks.retain(|_chunk_pos, task_option| task_option.is_some());
+
+        span.record("joined", pending_before - data_tasks.len());
+        span.record("pending", data_tasks.len());
+
+        world.data_join_time = started.elapsed();
     }
 
     // Join the mesh threads
@@ -189,100 +879,464 @@ impl World {
         mut commands: Commands,
         mut meshes: ResMut<Assets<Mesh>>,
         // mut materials: ResMut<Assets<StandardMaterial>>,
-        g_chunk_material: Res<GlobalChunkMaterial>,
+        (g_chunk_material, g_far_chunk_material): (
+            Res<GlobalChunkMaterial>,
+            Res<GlobalFarChunkMaterial>,
+        ),
+        (pipeline_mode, join_limits): (Res<PipelineMode>, Res<JoinLimits>),
+        voxel_scale: Res<VoxelScale>,
+        mesh_attributes: Res<MeshAttributes>,
     ) {
+        let span = info_span!(
+            "join_mesh",
+            joined = field::Empty,
+            pending = field::Empty,
+            parked = field::Empty
+        );
+        let _guard = span.enter();
+        let started = Instant::now();
+
         let World {
             mesh_tasks,
             chunk_entities,
+            entity_chunks,
+            section_entities,
+            section_hashes,
+            finished_batches,
+            parked_meshes,
+            mesh_versions,
+            chunk_connectivity,
+            unload_mesh_queue,
             ..
         } = world.as_mut();
 
-        for (chunk_pos, task_option) in mesh_tasks.iter_mut() {
-            let Some(mut task) = task_option.take() else {
+        let mut stale = Vec::new();
+        for mesh_task in mesh_tasks.iter_mut() {
+            let Some(mut task) = mesh_task.task.take() else {
                 warn!("Someone modified a task");
                 continue;
             };
 
-            let Some(chunk_mesh) = block_on(future::poll_once(&mut task)) else {
+            let result = match *pipeline_mode {
+                PipelineMode::Async => block_on(future::poll_once(&mut task)),
+                PipelineMode::Deterministic => Some(block_on(&mut task)),
+            };
+
+            let Some((section_meshes, connectivity)) = result else {
                 // Failed to poll, keep task alive
-                *task_option = Some(task);
+                mesh_task.task = Some(task);
                 continue;
             };
 
-            let Some(mesh) = chunk_mesh else {
+            // Unloaded later this frame, the entity would be spawned only to be despawned
+            if unload_mesh_queue.contains(&mesh_task.chunk_pos) {
                 continue;
+            }
+
+            // The chunk was edited after the task started, so its meshes would overwrite newer ones
+            let version = mesh_versions.get(&mesh_task.chunk_pos).copied();
+            if version.is_some_and(|version| version > mesh_task.version) {
+                stale.push((mesh_task.chunk_pos, mesh_task.sections));
+                continue;
+            }
+
+            let chunk_pos = mesh_task.chunk_pos;
+            chunk_connectivity.insert(chunk_pos, connectivity);
+
+            let finished_mesh = FinishedMesh {
+                chunk_pos,
+                version: mesh_task.version,
+                section_meshes,
             };
+            match mesh_task.batch {
+                Some(batch_id) => finished_batches
+                    .entry(batch_id)
+                    .or_default()
+                    .push(finished_mesh),
+                None => parked_meshes.push_back(vec![finished_mesh]),
+            }
+        }
 
-            // let vertices = mesh
-            //     .vertices
-            //     .iter()
-            //     .map(|vertex| {
-            //         [
-            //             vertex.pos.x as f32,
-            //             vertex.pos.y as f32,
-            //             vertex.pos.z as f32,
-            //         ]
-            //     })
-            //     .collect::<Vec<[f32; 3]>>();
-
-            // let normals = mesh
-            //     .vertices
-            //     .iter()
-            //     .map(|vertex| NORMALS_ARRAY[vertex.normal])
-            //     .collect::<Vec<[f32; 3]>>();
-
-            let bevy_mesh = Mesh::new(
-                bevy::render::mesh::PrimitiveTopology::TriangleList,
-                RenderAssetUsages::RENDER_WORLD,
-            )
-            .with_inserted_attribute(
-                ATTRIBUTE_VOXEL,
-                mesh.vertices
-                    .iter()
-                    .cloned()
-                    .map(|v| v.into())
-                    .collect::<Vec<u32>>(),
-            )
-            // .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vertices)
-            // .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
-            .with_inserted_indices(Indices::U32(mesh.indices.clone()));
+        let pending_before = mesh_tasks.len();
+        mesh_tasks.retain(|mesh_task| mesh_task.task.is_some());
 
-            let mesh_handle = meshes.add(bevy_mesh);
+        span.record("joined", pending_before - mesh_tasks.len());
+        span.record("pending", mesh_tasks.len());
 
-            if let Some(entity) = chunk_entities.get(chunk_pos) {
-                // Remove any chunks at this position
-                commands.entity(*entity).despawn();
+        // Only show a batch once every one of its meshes has finished
+        finished_batches.retain(|batch_id, batch_meshes| {
+            let batch_pending = mesh_tasks
+                .iter()
+                .any(|mesh_task| mesh_task.batch == Some(*batch_id));
+            if !batch_pending {
+                parked_meshes.push_back(std::mem::take(batch_meshes));
             }
 
-            // let hue = ((chunk_pos.x.unsigned_abs() as usize * CHUNK_SIZE
-            //     + chunk_pos.y.unsigned_abs() as usize)
-            //     * CHUNK_SIZE
-            //     + chunk_pos.z.unsigned_abs() as usize) as f32
-            //     * (360. / (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as f32);
-
-            let chunk_entity = commands
-                .spawn((
-                    Aabb::from_min_max(Vec3::ZERO, Vec3::splat(CHUNK_SIZE as f32)),
-                    MaterialMeshBundle {
-                        transform: Transform::from_xyz(
-                            (chunk_pos.x * CHUNK_SIZE as i32) as f32,
-                            (chunk_pos.y * CHUNK_SIZE as i32) as f32,
-                            (chunk_pos.z * CHUNK_SIZE as i32) as f32,
-                        ),
-                        mesh: mesh_handle,
-                        material: g_chunk_material.0.clone(),
-                        // material: materials.add(StandardMaterial {
-                        //     base_color: Color::hsv(hue, 1., 1.),
-                        //     ..default()
-                        // }),
-                        ..default()
-                    },
-                ))
-                .id();
-
-            chunk_entities.insert(*chunk_pos, chunk_entity);
+            batch_pending
+        });
+
+        // Spawning is capped per frame so a burst of finished tasks doesn't hitch, the rest stay
+        // parked until later frames. A batch is always spawned whole, and one group is spawned every
+        // frame so the queue keeps moving
+        let mut spawned = 0;
+        while let Some(group) = parked_meshes.front() {
+            let over_budget = spawned + group.len() > join_limits.max_spawns_per_frame
+                || started.elapsed().as_secs_f32() > join_limits.max_join_secs;
+            if spawned > 0 && over_budget {
+                break;
+            }
+
+            let Some(group) = parked_meshes.pop_front() else {
+                break;
+            };
+            spawned += group.len();
+
+            for FinishedMesh {
+                chunk_pos,
+                version,
+                section_meshes,
+            } in group
+            {
+                if unload_mesh_queue.contains(&chunk_pos) {
+                    continue;
+                }
+
+                // Edited while parked, rebuilt like the results of stale tasks
+                if mesh_versions
+                    .get(&chunk_pos)
+                    .is_some_and(|&current| current > version)
+                {
+                    let sections = section_meshes
+                        .iter()
+                        .fold(0, |sections, (section, _)| sections | 1 << section);
+                    stale.push((chunk_pos, sections));
+                    continue;
+                }
+
+                // Don't create an entity for chunks which have never had anything to show
+                if !chunk_entities.contains_key(&chunk_pos)
+                    && section_meshes.iter().all(|(_, mesh)| mesh.is_none())
+                {
+                    continue;
+                }
+
+                let chunk_entity = *chunk_entities.entry(chunk_pos).or_insert_with(|| {
+                    let entity = commands
+                        .spawn((
+                            ChunkEntity(chunk_pos),
+                            SpatialBundle::from_transform(chunk_transform(chunk_pos, &voxel_scale)),
+                        ))
+                        .id();
+                    entity_chunks.insert(entity, chunk_pos);
+
+                    entity
+                });
+                // Arrays of more than 32 sections don't implement Default
+                let sections = section_entities
+                    .entry(chunk_pos)
+                    .or_insert([None; SECTIONS_PER_CHUNK]);
+                let hashes = section_hashes
+                    .entry(chunk_pos)
+                    .or_insert([None; SECTIONS_PER_CHUNK]);
+
+                for (section, mesh) in section_meshes {
+                    // Edits which don't change the visible geometry (e.g. interior voxels) keep the old mesh
+                    let hash = mesh.as_ref().map(ChunkMesh::content_hash);
+                    if sections[section].is_some() && hash == hashes[section] {
+                        if let Some(mesh) = mesh {
+                            mesh_pool::recycle(mesh);
+                        }
+                        continue;
+                    }
+                    hashes[section] = hash;
+
+                    if let Some(entity) = sections[section].take() {
+                        // Remove the old mesh of this section
+                        commands.entity(entity).despawn_recursive();
+                    }
+
+                    let Some(mesh) = mesh else {
+                        continue;
+                    };
+
+                    // Vertices are relative to the chunk, so the section is culled by its own
+                    // bounds, or by the section's when the mesh task didn't measure them
+                    let aabb = mesh.aabb.unwrap_or_else(|| {
+                        let section_min =
+                            VoxelPos::from_section_index(section).to_ivec3().as_vec3();
+                        Aabb::from_min_max(section_min, section_min + SECTION_SIZE as f32)
+                    });
+
+                    if !mesh.far_faces.is_empty() {
+                        let section_entity = commands
+                            .spawn((
+                                aabb,
+                                MaterialMeshBundle {
+                                    mesh: meshes.add(far_faces_mesh(&mesh.far_faces)),
+                                    material: g_far_chunk_material.0.clone(),
+                                    ..default()
+                                },
+                            ))
+                            .set_parent(chunk_entity)
+                            .id();
+
+                        sections[section] = Some(section_entity);
+                        mesh_pool::recycle(mesh);
+                        continue;
+                    }
+
+                    // The buffers are copied into the Bevy mesh, so the mesh tasks can reuse them
+                    let mesh_handle = meshes.add(voxel_mesh(&mesh, *mesh_attributes));
+                    mesh_pool::recycle(mesh);
+
+                    let section_entity = commands
+                        .spawn((
+                            aabb,
+                            MaterialMeshBundle {
+                                mesh: mesh_handle,
+                                material: g_chunk_material.0.clone(),
+                                // material: materials.add(StandardMaterial {
+                                //     base_color: Color::hsv(hue, 1., 1.),
+                                //     ..default()
+                                // }),
+                                ..default()
+                            },
+                        ))
+                        .set_parent(chunk_entity)
+                        .id();
+
+                    sections[section] = Some(section_entity);
+                }
+            }
+        }
+
+        span.record("parked", parked_meshes.len());
+
+        // Stale results are rebuilt from the current voxels, unless the chunk's mesh was unloaded
+        for (chunk_pos, sections) in stale {
+            let unloading = world.unload_mesh_queue.contains(&chunk_pos);
+            if !unloading
+                && (world.chunk_entities.contains_key(&chunk_pos) || sections == ALL_SECTIONS)
+            {
+                world.queue_section_remesh(chunk_pos, sections);
+            }
+        }
+
+        world.mesh_join_time = started.elapsed();
+    }
+}
+
+// Bevy mesh of the packed vertices, drawn with the chunk material
+pub fn voxel_mesh(mesh: &ChunkMesh, attributes: MeshAttributes) -> Mesh {
+    if mesh.is_smooth() {
+        return smooth_mesh(mesh);
+    }
+
+    // Only read by the prepass pipeline, which requires a normal attribute for its normal prepass
+    let normals = mesh
+        .vertices
+        .iter()
+        .map(|&vertex| NORMALS_ARRAY[Vertex::from(vertex).normal])
+        .collect::<Vec<[f32; 3]>>();
+
+    let mut bevy_mesh = Mesh::new(
+        bevy::render::mesh::PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(
+        ATTRIBUTE_VOXEL,
+        mesh.vertices
+            .iter()
+            .cloned()
+            .map(|v| v.into())
+            .collect::<Vec<[u32; 2]>>(),
+    )
+    .with_inserted_attribute(ATTRIBUTE_BIOME_TINT, mesh.biome_tints.clone())
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+
+    if attributes == MeshAttributes::Standard {
+        let positions = mesh
+            .vertices
+            .iter()
+            .map(|&vertex| Vertex::from(vertex).pos.to_ivec3().as_vec3().to_array())
+            .collect::<Vec<[f32; 3]>>();
+
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    }
+
+    bevy_mesh.with_inserted_indices(compact_indices(&mesh.indices, mesh.vertices.len()))
+}
+
+// Smooth vertices can't be packed, so they use the standard position and normal attributes
+fn smooth_mesh(mesh: &ChunkMesh) -> Mesh {
+    let (positions, normals): (Vec<[f32; 3]>, Vec<[f32; 3]>) = mesh
+        .smooth_vertices
+        .iter()
+        .map(|vertex| (vertex.pos.to_array(), vertex.normal.to_array()))
+        .unzip();
+
+    Mesh::new(
+        bevy::render::mesh::PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_attribute(
+        ATTRIBUTE_SMOOTH_VOXEL,
+        mesh.smooth_vertices
+            .iter()
+            .map(|vertex| u32::from(vertex.voxel_type))
+            .collect::<Vec<u32>>(),
+    )
+    .with_inserted_attribute(ATTRIBUTE_BIOME_TINT, mesh.biome_tints.clone())
+    .with_inserted_indices(compact_indices(&mesh.indices, mesh.smooth_vertices.len()))
+}
+
+// Most section meshes have few enough vertices for u16 indices, which halves their index buffers
+pub fn compact_indices(indices: &[u32], vertex_count: usize) -> Indices {
+    if vertex_count <= u16::MAX as usize + 1 {
+        Indices::U16(indices.iter().map(|&index| index as u16).collect())
+    } else {
+        Indices::U32(indices.to_vec())
+    }
+}
+
+// Far meshes have no index buffer, each face is repeated for the six vertices of its two triangles
+fn far_faces_mesh(faces: &[FaceU32]) -> Mesh {
+    Mesh::new(
+        bevy::render::mesh::PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(
+        ATTRIBUTE_FAR_FACE,
+        faces
+            .iter()
+            .flat_map(|&face| [u32::from(face); 6])
+            .collect::<Vec<u32>>(),
+    )
+}
+
+// Chunk meshes are built in voxel units, so they are scaled to the voxel size
+pub(crate) fn chunk_transform(chunk_pos: ChunkPos, voxel_scale: &VoxelScale) -> Transform {
+    Transform::from_translation(voxel_scale.chunk_translation(chunk_pos))
+        .with_scale(Vec3::splat(voxel_scale.0))
+}
+
+// Quality from the chunk's override, or else the policy's at its distance with any refinement
+fn expected_quality(
+    mesh_overrides: &HashMap<ChunkPos, ChunkMeshOverride>,
+    refined_qualities: &HashMap<ChunkPos, MeshQuality>,
+    chunk_pos: ChunkPos,
+    policy: &MeshQualityPolicy,
+    loader_pos: ChunkPos,
+) -> MeshQuality {
+    match mesh_overrides
+        .get(&chunk_pos)
+        .and_then(|mesh_override| mesh_override.quality)
+    {
+        Some(quality) => quality,
+        None => {
+            let refined = refined_qualities.get(&chunk_pos).copied();
+            policy.refined_quality(chunk_pos, loader_pos, refined)
         }
+    }
+}
+
+// Start meshing the sections of a chunk, returns None if a neighbouring chunk isn't loaded
+fn spawn_mesh_task(
+    chunks: &HashMap<ChunkPos, Arc<Chunk>>,
+    chunk_pos: ChunkPos,
+    sections: u64,
+    ao: Option<AoMerging>,
+    quality: MeshQuality,
+    meshing_mode: MeshingMode,
+    border: &WorldBorder,
+) -> Option<Task<(SectionMeshes, FaceConnectivity)>> {
+    let chunks_from_middle = ChunksFromMiddle::try_new_bounded(chunks, chunk_pos, border)?;
 
-        mesh_tasks.retain(|(_chunk_pos, option_task)| option_task.is_some());
+    let task = task_pools::meshing_pool().spawn(async move {
+        let started = Instant::now();
+        let mut section_meshes = match (meshing_mode, quality) {
+            (MeshingMode::Smooth, MeshQuality::Far(lod)) => {
+                surface_nets::build_section_meshes(&chunks_from_middle, lod, sections)
+            }
+            (MeshingMode::Smooth, _) => {
+                surface_nets::build_section_meshes(&chunks_from_middle, Lod::L32, sections)
+            }
+            (MeshingMode::Blocky, MeshQuality::Full) => {
+                greedy_mesher::build_section_meshes(&chunks_from_middle, Lod::L32, ao, sections)
+            }
+            (MeshingMode::Blocky, MeshQuality::Culled) => {
+                culled_mesher::build_section_meshes(&chunks_from_middle, sections)
+            }
+            // Far faces don't store AO
+            (MeshingMode::Blocky, MeshQuality::Far(lod)) => {
+                greedy_mesher::build_section_meshes(&chunks_from_middle, lod, None, sections)
+            }
+        };
+        let vertices = section_meshes
+            .iter()
+            .filter_map(|(_, mesh)| mesh.as_ref())
+            .map(ChunkMesh::vertex_count)
+            .sum();
+
+        let biome_map = BiomeMap::new();
+        for mesh in section_meshes
+            .iter_mut()
+            .filter_map(|(_, mesh)| mesh.as_mut())
+        {
+            mesh.aabb = mesh.compute_aabb(TIGHT_MESH_AABBS);
+
+            if quality.is_far() && FAR_FACES_FIT && !mesh.is_smooth() {
+                let far = mesh.to_far();
+                mesh_pool::recycle(std::mem::replace(mesh, far));
+            } else {
+                biome_map.apply_tints(mesh, chunk_pos);
+            }
+            mesh.track_memory();
+        }
+
+        let connectivity = FaceConnectivity::from_chunk(chunks_from_middle.get_middle_chunk());
+        pipeline_metrics::record(
+            PipelineStage::Meshing,
+            chunk_pos,
+            started.elapsed(),
+            vertices,
+        );
+
+        (section_meshes, connectivity)
+    });
+
+    Some(task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_are_u16_while_every_vertex_fits() {
+        // The largest index is one less than the vertex count, so u16 reaches 65,536 vertices
+        for (vertex_count, is_u16) in [
+            (65_534, true),
+            (65_535, true),
+            (65_536, true),
+            (65_537, false),
+            (65_538, false),
+        ] {
+            let indices = [0, vertex_count as u32 - 1];
+            let compacted = compact_indices(&indices, vertex_count);
+
+            assert_eq!(
+                matches!(compacted, Indices::U16(_)),
+                is_u16,
+                "{vertex_count} vertices"
+            );
+            assert_eq!(
+                compacted.iter().collect::<Vec<_>>(),
+                [0, vertex_count - 1],
+                "{vertex_count} vertices"
+            );
+        }
     }
 }
@@ -0,0 +1,211 @@
+use bevy::{math::BVec3, prelude::*, transform::TransformSystem};
+
+use crate::{
+    chunk_loading::ChunkLoader,
+    constants::{
+        ADJACENT_CHUNK_DIRECTIONS, CHUNK_HEIGHT, CHUNK_SIZE, WORLD_BORDER_CHUNKS,
+        WORLD_BORDER_WALL_HEIGHT, WORLD_HEIGHT_BOUNDS,
+    },
+    positions::{ChunkPos, VoxelScale},
+    world::World,
+};
+
+pub struct WorldBorderPlugin;
+
+impl Plugin for WorldBorderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldBorder>()
+            .register_type::<WorldBorder>()
+            .add_systems(
+                Update,
+                (
+                    BorderWall::rebuild.run_if(
+                        resource_changed::<WorldBorder>.or_else(resource_changed::<VoxelScale>),
+                    ),
+                    BorderWall::follow_loader,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                WorldBorder::remesh_edges.run_if(resource_changed::<WorldBorder>),
+            )
+            .add_systems(
+                PostUpdate,
+                WorldBorder::confine_loaders.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+// What the meshers see past the edge of the world, in place of the air chunks loaded there
+#[derive(Reflect, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BoundaryPolicy {
+    // The terrain's cross-section is drawn, a wall of faces along the border
+    #[default]
+    Air,
+    // Faces against the border are culled, the terrain looks cut open from outside
+    Solid,
+    // The chunks at the edge are reflected across it, so the surface carries on without walls or holes
+    Mirror,
+}
+
+// Square border around the origin, optionally with a floor and ceiling, chunks past it are never
+// generated and loaders can't cross it sideways
+#[derive(Resource, Reflect, Debug, Copy, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct WorldBorder {
+    // Chunks from the origin to the border along x and z, None for an endless world
+    pub half_size: Option<u32>,
+    // Lowest and highest chunk y inside the world, None for no floor or ceiling
+    pub height_bounds: Option<(i32, i32)>,
+    pub boundary: BoundaryPolicy,
+}
+
+impl Default for WorldBorder {
+    fn default() -> Self {
+        Self {
+            half_size: WORLD_BORDER_CHUNKS,
+            height_bounds: WORLD_HEIGHT_BOUNDS,
+            boundary: BoundaryPolicy::default(),
+        }
+    }
+}
+
+impl WorldBorder {
+    pub fn contains_chunk(&self, chunk_pos: ChunkPos) -> bool {
+        !self.outside_axes(chunk_pos, 0).any()
+    }
+
+    // Chunks just outside the border are loaded as air, so that the chunks at the edge can be meshed
+    pub fn should_load_chunk(&self, chunk_pos: ChunkPos) -> bool {
+        !self.outside_axes(chunk_pos, 1).any()
+    }
+
+    // The axes along which the chunk is past the border, by more than the margin (in chunks)
+    pub fn outside_axes(&self, chunk_pos: ChunkPos, margin: i32) -> BVec3 {
+        let (x, z) = self.half_size.map_or((false, false), |half_size| {
+            let range = -(half_size as i32) - margin..half_size as i32 + margin;
+
+            (!range.contains(&chunk_pos.x), !range.contains(&chunk_pos.z))
+        });
+        let y = self.height_bounds.is_some_and(|(min_y, max_y)| {
+            !(min_y - margin..=max_y + margin).contains(&chunk_pos.y)
+        });
+
+        BVec3::new(x, y, z)
+    }
+
+    // Chunks at the edge are meshed with their neighbours past it, so they change with the border
+    fn remesh_edges(border: Res<WorldBorder>, mut world: ResMut<World>) {
+        let edges = world
+            .chunk_entities
+            .keys()
+            .copied()
+            .filter(|&chunk_pos| {
+                border.contains_chunk(chunk_pos)
+                    && ADJACENT_CHUNK_DIRECTIONS
+                        .iter()
+                        .any(|&offset| !border.contains_chunk(chunk_pos + offset))
+            })
+            .collect::<Vec<_>>();
+
+        for chunk_pos in edges {
+            world.queue_remesh(chunk_pos);
+        }
+    }
+
+    // Distance from the origin to the border in world units
+    pub fn half_extent(&self, voxel_scale: &VoxelScale) -> Option<f32> {
+        self.half_size
+            .map(|half_size| (half_size as usize * CHUNK_SIZE) as f32 * voxel_scale.0)
+    }
+
+    fn confine_loaders(
+        mut loaders: Query<&mut Transform, With<ChunkLoader>>,
+        border: Res<WorldBorder>,
+        voxel_scale: Res<VoxelScale>,
+    ) {
+        let Some(half_extent) = border.half_extent(&voxel_scale) else {
+            return;
+        };
+
+        for mut transform in loaders.iter_mut() {
+            let translation = transform.translation;
+            let x = translation.x.clamp(-half_extent, half_extent);
+            let z = translation.z.clamp(-half_extent, half_extent);
+
+            // Avoid triggering change detection when the loader is inside
+            if x != translation.x || z != translation.z {
+                transform.translation.x = x;
+                transform.translation.z = z;
+            }
+        }
+    }
+}
+
+// Translucent wall along one side of the world border
+#[derive(Component, Debug)]
+pub struct BorderWall;
+
+impl BorderWall {
+    fn rebuild(
+        mut commands: Commands,
+        walls: Query<Entity, With<BorderWall>>,
+        border: Res<WorldBorder>,
+        voxel_scale: Res<VoxelScale>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut materials: ResMut<Assets<StandardMaterial>>,
+    ) {
+        for entity in walls.iter() {
+            commands.entity(entity).despawn();
+        }
+
+        let Some(half_extent) = border.half_extent(&voxel_scale) else {
+            return;
+        };
+
+        let height = (WORLD_BORDER_WALL_HEIGHT * CHUNK_HEIGHT) as f32 * voxel_scale.0;
+        let mesh = meshes.add(Rectangle::new(half_extent * 2., height));
+        let material = materials.add(StandardMaterial {
+            base_color: Color::srgba(0.4, 0.6, 1.0, 0.2),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            double_sided: true,
+            cull_mode: None,
+            ..default()
+        });
+
+        // The rectangle faces +Z, so each wall is rotated to face the origin
+        for (offset, angle) in [
+            (Vec3::Z, std::f32::consts::PI),
+            (Vec3::NEG_Z, 0.),
+            (Vec3::X, -std::f32::consts::FRAC_PI_2),
+            (Vec3::NEG_X, std::f32::consts::FRAC_PI_2),
+        ] {
+            commands.spawn((
+                BorderWall,
+                PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(offset * half_extent)
+                        .with_rotation(Quat::from_rotation_y(angle)),
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    // Walls are only as tall as the loaded area, so they follow the loader's height
+    fn follow_loader(
+        mut walls: Query<&mut Transform, With<BorderWall>>,
+        loaders: Query<&GlobalTransform, (With<ChunkLoader>, Without<BorderWall>)>,
+    ) {
+        let Ok(loader_transform) = loaders.get_single() else {
+            return;
+        };
+
+        for mut transform in walls.iter_mut() {
+            transform.translation.y = loader_transform.translation().y;
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use bevy::{
+    ecs::{
+        event::{Event, EventWriter},
+        system::ResMut,
+    },
+    math::IVec3,
+};
+use bracket_noise::prelude::*;
+
+use crate::{
+    block_registry::BlockRegistry,
+    constants::{ADJACENT_CHUNK_DIRECTIONS, ALL_SECTIONS, MAX_UNDO_ENTRIES},
+    positions::{ChunkPos, VoxelPos, WorldPos},
+    voxel::VoxelType,
+    world::World,
+    world_generator,
+};
+
+// Why an edit was refused, refused edits don't change any voxels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EditError {
+    // Edits to chunks which aren't loaded couldn't be meshed or saved
+    ChunkNotLoaded(ChunkPos),
+    // A position relative to a chunk which is outside of it, see World::set_voxel_wrapping
+    OutOfChunk(IVec3),
+    // The voxel type has no definition in the block registry
+    UndefinedBlock(VoxelType),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChunkNotLoaded(chunk_pos) => write!(f, "Chunk {chunk_pos:?} isn't loaded"),
+            Self::OutOfChunk(voxel_pos) => {
+                write!(f, "Voxel position {voxel_pos} is outside the chunk")
+            }
+            Self::UndefinedBlock(voxel_type) => {
+                write!(f, "Voxel type {} isn't a defined block", voxel_type.id())
+            }
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+// The voxels of a chunk which changed during a frame, sent once at the end of the frame so
+// systems which follow the voxels (lighting, fluids, networking) can update without rescanning
+// Changes are (position, old type, new type) in the order they were made, including undos
+#[derive(Event, Debug, Clone)]
+pub struct VoxelChanged {
+    pub chunk_pos: ChunkPos,
+    pub changes: Vec<(VoxelPos, VoxelType, VoxelType)>,
+}
+
+// Voxel writes which are applied together, remeshed in the same frame and undone as a single entry
+#[derive(Default, Debug, Clone)]
+pub struct EditTransaction {
+    writes: Vec<(WorldPos, VoxelType)>,
+}
+
+impl EditTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Later writes to the same position replace earlier ones
+    pub fn set_voxel(&mut self, world_pos: WorldPos, voxel_type: VoxelType) -> &mut Self {
+        self.writes.push((world_pos, voxel_type));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
+
+// Edits to the loaded voxels, edited chunks are marked dirty and remeshed
+impl World {
+    // Returns the previous voxel type
+    pub fn set_voxel(
+        &mut self,
+        world_pos: WorldPos,
+        voxel_type: VoxelType,
+    ) -> Result<VoxelType, EditError> {
+        self.check_write(world_pos, voxel_type)?;
+        let previous_type = self
+            .get_voxel(world_pos)
+            .map_or(VoxelType::AIR, |voxel| voxel.voxel_type);
+
+        let mut transaction = EditTransaction::new();
+        transaction.set_voxel(world_pos, voxel_type);
+        self.commit(transaction)?;
+
+        Ok(previous_type)
+    }
+
+    // Set a voxel by its position in a chunk, the position must be inside the chunk
+    pub fn set_voxel_in_chunk(
+        &mut self,
+        chunk_pos: ChunkPos,
+        voxel_pos: IVec3,
+        voxel_type: VoxelType,
+    ) -> Result<VoxelType, EditError> {
+        let in_chunk = VoxelPos::try_from_ivec3(voxel_pos).filter(VoxelPos::is_in_chunk);
+        let Some(voxel_pos) = in_chunk else {
+            return Err(EditError::OutOfChunk(voxel_pos));
+        };
+
+        self.set_voxel(WorldPos::from_voxel_pos(voxel_pos, chunk_pos), voxel_type)
+    }
+
+    // Set a voxel by its position relative to a chunk, positions outside it wrap into the neighbours
+    pub fn set_voxel_wrapping(
+        &mut self,
+        chunk_pos: ChunkPos,
+        voxel_pos: IVec3,
+        voxel_type: VoxelType,
+    ) -> Result<VoxelType, EditError> {
+        let chunk_origin = WorldPos::from_voxel_pos(VoxelPos::new(0, 0, 0), chunk_pos);
+
+        self.set_voxel(
+            chunk_origin + WorldPos::new(voxel_pos.x, voxel_pos.y, voxel_pos.z),
+            voxel_type,
+        )
+    }
+
+    // Apply the writes of a transaction as one undo entry, returns the number of voxels changed
+    // Every write is checked first, so nothing is changed if any of them would fail
+    pub fn commit(&mut self, transaction: EditTransaction) -> Result<usize, EditError> {
+        for &(world_pos, voxel_type) in &transaction.writes {
+            self.check_write(world_pos, voxel_type)?;
+        }
+
+        let undo_writes = self.apply_writes(transaction.writes);
+        let changed = undo_writes.len();
+
+        if !undo_writes.is_empty() {
+            if self.edit_history.len() >= MAX_UNDO_ENTRIES {
+                self.edit_history.pop_front();
+            }
+            self.edit_history.push_back(undo_writes);
+        }
+
+        Ok(changed)
+    }
+
+    fn check_write(&self, world_pos: WorldPos, voxel_type: VoxelType) -> Result<(), EditError> {
+        if BlockRegistry::global().get(voxel_type).is_none() {
+            return Err(EditError::UndefinedBlock(voxel_type));
+        }
+
+        let (_, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+        if !self.chunks.contains_key(&chunk_pos) {
+            return Err(EditError::ChunkNotLoaded(chunk_pos));
+        }
+
+        Ok(())
+    }
+
+    // Revert the most recent transaction, returns false if there is nothing to undo
+    // Writes to chunks which have been unloaded since are skipped
+    pub fn undo(&mut self) -> bool {
+        let Some(undo_writes) = self.edit_history.pop_back() else {
+            return false;
+        };
+
+        self.apply_writes(undo_writes);
+        true
+    }
+
+    // Write the voxels then schedule the remeshing of every affected section as one batch
+    // Returns the writes which would revert this, in the order they should be applied
+    pub(crate) fn apply_writes(
+        &mut self,
+        writes: Vec<(WorldPos, VoxelType)>,
+    ) -> Vec<(WorldPos, VoxelType)> {
+        let mut undo_writes = Vec::new();
+        let mut remesh_sections = HashMap::new();
+
+        for (world_pos, voxel_type) in writes {
+            let Some(previous_type) = self.write_voxel(world_pos, voxel_type) else {
+                continue;
+            };
+            if previous_type == voxel_type {
+                continue;
+            }
+
+            add_affected_sections(&mut remesh_sections, world_pos);
+            undo_writes.push((world_pos, previous_type));
+            if let Some(recorded_writes) = &mut self.recorded_writes {
+                recorded_writes.push((world_pos, voxel_type));
+            }
+        }
+
+        // In-flight meshes of every affected chunk were built from the old voxels
+        for &chunk_pos in remesh_sections.keys() {
+            self.invalidate_meshes(chunk_pos);
+        }
+
+        // Chunks with unloaded neighbours will be meshed when they load
+        remesh_sections.retain(|&chunk_pos, _| self.neighbours_loaded(chunk_pos));
+        if !remesh_sections.is_empty() {
+            self.remesh_batches.push(remesh_sections);
+        }
+
+        undo_writes.reverse();
+        undo_writes
+    }
+
+    // Remove the voxels within a noise-perturbed sphere, returning the solid voxels which were removed
+    // The removal is a single transaction, so it is remeshed in one frame and can be undone
+    // Voxels in chunks which aren't loaded are left alone
+    pub fn explode(
+        &mut self,
+        center: WorldPos,
+        radius: f32,
+    ) -> Result<Vec<(WorldPos, VoxelType)>, EditError> {
+        let mut noise = FastNoise::seeded(world_generator::seed() + 3);
+        noise.set_noise_type(NoiseType::Simplex);
+        noise.set_frequency(0.2);
+
+        let extent = (radius * 1.25).ceil() as i32;
+        let mut removed = Vec::new();
+        let mut transaction = EditTransaction::new();
+
+        for z in -extent..=extent {
+            for y in -extent..=extent {
+                for x in -extent..=extent {
+                    let world_pos = center + WorldPos::new(x, y, z);
+
+                    // Roughen the edge of the sphere
+                    let perturbed_radius = radius
+                        * (1.
+                            + 0.25
+                                * noise.get_noise3d(
+                                    world_pos.x as f32,
+                                    world_pos.y as f32,
+                                    world_pos.z as f32,
+                                ));
+                    if ((x * x + y * y + z * z) as f32) > perturbed_radius * perturbed_radius {
+                        continue;
+                    }
+
+                    let Some(voxel_type) = self
+                        .get_voxel(world_pos)
+                        .map(|voxel| voxel.voxel_type)
+                        .filter(VoxelType::is_solid)
+                    else {
+                        continue;
+                    };
+
+                    transaction.set_voxel(world_pos, VoxelType::AIR);
+                    removed.push((world_pos, voxel_type));
+                }
+            }
+        }
+
+        self.commit(transaction)?;
+
+        Ok(removed)
+    }
+
+    // Queue a chunk to be remeshed, if it and all of its neighbours are loaded
+    pub fn queue_remesh(&mut self, chunk_pos: ChunkPos) -> bool {
+        self.queue_section_remesh(chunk_pos, ALL_SECTIONS)
+    }
+
+    // Queue the sections in the mask to be remeshed, merging with any remesh already queued for the chunk
+    pub fn queue_section_remesh(&mut self, chunk_pos: ChunkPos, sections: u64) -> bool {
+        if !self.neighbours_loaded(chunk_pos) {
+            return false;
+        }
+
+        if self.load_mesh_queue.contains(&chunk_pos) {
+            // Without an entry every section is already being remeshed
+            if let Some(queued_sections) = self.remesh_sections.get_mut(&chunk_pos) {
+                *queued_sections |= sections;
+            }
+
+            return false;
+        }
+
+        if sections != ALL_SECTIONS {
+            self.remesh_sections.insert(chunk_pos, sections);
+        }
+        self.load_mesh_queue.push(chunk_pos)
+    }
+
+    fn neighbours_loaded(&self, chunk_pos: ChunkPos) -> bool {
+        ADJACENT_CHUNK_DIRECTIONS
+            .iter()
+            .all(|&offset| self.chunks.contains_key(&(chunk_pos + offset)))
+    }
+
+    // Send the changes made this frame, one event per changed chunk
+    pub fn send_voxel_changes(
+        mut world: ResMut<World>,
+        mut voxel_changed_events: EventWriter<VoxelChanged>,
+    ) {
+        if world.voxel_changes.is_empty() {
+            return;
+        }
+
+        voxel_changed_events.send_batch(
+            world
+                .voxel_changes
+                .drain()
+                .map(|(chunk_pos, changes)| VoxelChanged { chunk_pos, changes }),
+        );
+    }
+
+    // Change a voxel and mark its chunk dirty, without remeshing, returns the previous voxel type
+    fn write_voxel(&mut self, world_pos: WorldPos, voxel_type: VoxelType) -> Option<VoxelType> {
+        let (voxel_pos, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        let chunk = self.chunks.get_mut(&chunk_pos)?;
+        let previous_type = chunk[voxel_pos].voxel_type;
+        if previous_type == voxel_type {
+            return Some(previous_type);
+        }
+
+        // Chunks may still be shared with mesh tasks, so copy on write
+        Arc::make_mut(chunk).set_voxel(voxel_pos, voxel_type);
+        self.dirty_chunks.insert(chunk_pos);
+        self.chunk_generation += 1;
+        self.decals.remove_voxel(world_pos);
+        self.voxel_changes.entry(chunk_pos).or_default().push((
+            voxel_pos,
+            previous_type,
+            voxel_type,
+        ));
+
+        Some(previous_type)
+    }
+}
+
+// The sections whose meshes sample the edited voxel, including those in neighbouring chunks
+fn add_affected_sections(remesh_sections: &mut HashMap<ChunkPos, u64>, world_pos: WorldPos) {
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let (voxel_pos, chunk_pos) =
+                    WorldPos::to_voxel_pos(world_pos + WorldPos::new(x, y, z));
+
+                *remesh_sections.entry(chunk_pos).or_default() |= 1 << voxel_pos.section_index();
+            }
+        }
+    }
+}
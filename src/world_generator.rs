@@ -0,0 +1,482 @@
+use std::sync::{Arc, OnceLock};
+
+use bevy::prelude::*;
+use bracket_noise::prelude::*;
+use xxhash_rust::xxh3::xxh3_64;
+
+#[cfg(feature = "profiling")]
+use crate::stress_generators::{
+    CheckerboardGenerator, CheckerboardParams, FloatingDustGenerator, FloatingDustParams,
+    SineValleysGenerator, SineValleysParams,
+};
+#[cfg(feature = "anvil")]
+use crate::{
+    anvil::AnvilGenerator,
+    constants::{ANVIL_REGION_DIRECTORY, ANVIL_Y_OFFSET},
+};
+use crate::{
+    chunk::{terrain_noise, Chunk},
+    chunk_from_middle::ChunksFromMiddle,
+    chunk_meta::ChunkMeta,
+    constants::{
+        CHUNK_HEIGHT, CHUNK_SIZE, CHUNK_VOLUME, NOISE_HEIGHT_SCALE, NOISE_SEED, RIVER_DEPTH,
+        TREE_CANOPY_RADIUS, TREE_CHANCE, TREE_MIN_GROUND_NORMAL_Y, TREE_TRUNK_MAX, TREE_TRUNK_MIN,
+        WATER_LEVEL,
+    },
+    positions::{ChunkPos, VoxelPos, WorldPos},
+    rivers::RiverMap,
+    voxel::VoxelType,
+};
+
+static SEED: OnceLock<u64> = OnceLock::new();
+
+// Seed the generators' noise is built from, NOISE_SEED unless the launch options set another
+pub fn seed() -> u64 {
+    *SEED.get_or_init(|| NOISE_SEED)
+}
+
+// Only takes effect before anything has read the seed, returns false if it was too late
+pub fn set_seed(seed: u64) -> bool {
+    SEED.set(seed).is_ok()
+}
+
+// Builds the voxel data for a chunk, implementations must be deterministic across chunks
+// Chunks are generated in stages: generate and replace_surface build a chunk's base on its own,
+// then decorate finishes it once the bases of all of its neighbours are ready
+pub trait WorldGenerator: Send + Sync {
+    // The density pass, which decides the shape of the terrain
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk;
+
+    // Replace voxels of the generated shape, e.g. filling lakes or covering the ground
+    fn replace_surface(&self, _chunk_pos: ChunkPos, _chunk: &mut Chunk) {}
+
+    // Generators which decorate have to wait for the neighbouring bases, the rest skip the stage
+    fn decorates(&self) -> bool {
+        false
+    }
+
+    // Add features which can cross chunk borders, reading the bases of the chunk and its neighbours
+    // Only the chunk is written to, so every chunk a feature overlaps has to place its own part of it
+    fn decorate(&self, _chunk_pos: ChunkPos, _bases: &ChunksFromMiddle, _chunk: &mut Chunk) {}
+
+    // The chunk before decoration, which neighbouring chunks decorate from
+    fn generate_base(&self, chunk_pos: ChunkPos) -> Chunk {
+        let mut chunk = self.generate(chunk_pos);
+        self.replace_surface(chunk_pos, &mut chunk);
+
+        chunk
+    }
+
+    // Metadata stored alongside the generated chunk, generators which place structures should record them here
+    fn generate_meta(&self, chunk_pos: ChunkPos, chunk: &Chunk) -> ChunkMeta {
+        ChunkMeta::from_chunk(chunk_pos, chunk)
+    }
+
+    // A value for each column (x, z) for the generation preview, None if the generator doesn't have
+    // the layer. Heights are in voxels, the other layers in the range 0..=1
+    fn preview(&self, _layer: PreviewLayer, _columns: &[IVec2]) -> Option<Vec<f32>> {
+        None
+    }
+}
+
+// Layers of the world which the generation preview draws from above
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PreviewLayer {
+    // Height of the surface
+    Height,
+    // How deep rivers carve into the terrain
+    Rivers,
+    // The biome map, which every generator shares
+    Biomes,
+}
+
+impl PreviewLayer {
+    pub const ALL: [PreviewLayer; 3] = [Self::Height, Self::Rivers, Self::Biomes];
+}
+
+// The generator which chunk data tasks are started with
+#[derive(Resource, Clone)]
+pub struct WorldGen(pub Arc<dyn WorldGenerator>);
+
+impl Default for WorldGen {
+    fn default() -> Self {
+        Self::from_preset(GeneratorPreset::default())
+    }
+}
+
+impl WorldGen {
+    pub fn from_preset(preset: GeneratorPreset) -> Self {
+        match preset {
+            GeneratorPreset::Noise => Self(Arc::new(NoiseGenerator)),
+            GeneratorPreset::FloatingIslands => Self(Arc::new(FloatingIslandsGenerator::new(
+                FloatingIslandsParams::default(),
+            ))),
+            GeneratorPreset::AmplifiedMountains => Self(Arc::new(AmplifiedGenerator::new(
+                AmplifiedParams::default(),
+            ))),
+            GeneratorPreset::FlatGrid => {
+                Self(Arc::new(FlatGridGenerator::new(FlatGridParams::default())))
+            }
+            #[cfg(feature = "anvil")]
+            GeneratorPreset::Anvil => Self(Arc::new(AnvilGenerator::new(
+                ANVIL_REGION_DIRECTORY,
+                ANVIL_Y_OFFSET,
+            ))),
+            #[cfg(feature = "profiling")]
+            GeneratorPreset::Checkerboard => Self(Arc::new(CheckerboardGenerator::new(
+                CheckerboardParams::default(),
+            ))),
+            #[cfg(feature = "profiling")]
+            GeneratorPreset::SineValleys => Self(Arc::new(SineValleysGenerator::new(
+                SineValleysParams::default(),
+            ))),
+            #[cfg(feature = "profiling")]
+            GeneratorPreset::FloatingDust => Self(Arc::new(FloatingDustGenerator::new(
+                FloatingDustParams::default(),
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum GeneratorPreset {
+    #[default]
+    Noise,
+    FloatingIslands,
+    AmplifiedMountains,
+    FlatGrid,
+    // A Minecraft world's region files, read-only
+    #[cfg(feature = "anvil")]
+    Anvil,
+    // Synthetic worlds for stress testing, see stress_generators
+    #[cfg(feature = "profiling")]
+    Checkerboard,
+    #[cfg(feature = "profiling")]
+    SineValleys,
+    #[cfg(feature = "profiling")]
+    FloatingDust,
+}
+
+impl GeneratorPreset {
+    pub const ALL: &'static [GeneratorPreset] = &[
+        Self::Noise,
+        Self::FloatingIslands,
+        Self::AmplifiedMountains,
+        Self::FlatGrid,
+        #[cfg(feature = "anvil")]
+        Self::Anvil,
+        #[cfg(feature = "profiling")]
+        Self::Checkerboard,
+        #[cfg(feature = "profiling")]
+        Self::SineValleys,
+        #[cfg(feature = "profiling")]
+        Self::FloatingDust,
+    ];
+}
+
+// Default terrain, with rivers, lakes and trees
+pub struct NoiseGenerator;
+
+impl WorldGenerator for NoiseGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        Chunk::new_from_noise(chunk_pos)
+    }
+
+    fn preview(&self, layer: PreviewLayer, columns: &[IVec2]) -> Option<Vec<f32>> {
+        let river_map = RiverMap::new();
+
+        match layer {
+            PreviewLayer::Height => {
+                let noise = terrain_noise();
+
+                // The terrain is 3D, so the surface is found by sampling the noise at the height of the
+                // previous guess a few times, which is close wherever the terrain isn't overhanging
+                Some(
+                    columns
+                        .iter()
+                        .map(|column| {
+                            let carve = river_map.carve_depth(column.x, column.y);
+                            (0..3).fold(0., |height, _| {
+                                noise.get_noise3d(column.x as f32, height, column.y as f32)
+                                    * NOISE_HEIGHT_SCALE
+                                    - carve
+                            })
+                        })
+                        .collect(),
+                )
+            }
+            PreviewLayer::Rivers => Some(
+                columns
+                    .iter()
+                    .map(|column| river_map.carve_depth(column.x, column.y) / RIVER_DEPTH)
+                    .collect(),
+            ),
+            PreviewLayer::Biomes => None,
+        }
+    }
+
+    // Fill rivers and lakes up to the water level
+    fn replace_surface(&self, chunk_pos: ChunkPos, chunk: &mut Chunk) {
+        let chunk_min_y = chunk_pos.y * CHUNK_HEIGHT as i32;
+        if chunk_min_y >= WATER_LEVEL {
+            return;
+        }
+
+        for index in 0..CHUNK_VOLUME {
+            let voxel_pos = VoxelPos::from_index(index);
+            if chunk[voxel_pos].voxel_type == VoxelType::AIR
+                && chunk_min_y + (voxel_pos.y as i32) < WATER_LEVEL
+            {
+                chunk.set_voxel(voxel_pos, VoxelType::WATER);
+            }
+        }
+    }
+
+    fn decorates(&self) -> bool {
+        true
+    }
+
+    // Trees grow from ground with air above it, wherever the column's hash allows one
+    // The ground is read from the bases, so trees don't grow on the trees of neighbouring chunks
+    fn decorate(&self, chunk_pos: ChunkPos, bases: &ChunksFromMiddle, chunk: &mut Chunk) {
+        let size = CHUNK_SIZE as i32;
+        let height = CHUNK_HEIGHT as i32;
+        let reach = TREE_CANOPY_RADIUS;
+        let chunk_origin = WorldPos::from_voxel_pos(VoxelPos::new(0, 0, 0), chunk_pos);
+
+        for z in -reach..size + reach {
+            for x in -reach..size + reach {
+                let hash = tree_hash(chunk_origin.x + x, chunk_origin.z + z);
+                if (hash as u32 as f32 / u32::MAX as f32) >= TREE_CHANCE {
+                    continue;
+                }
+                let trunk =
+                    TREE_TRUNK_MIN + (hash >> 32) as i32 % (TREE_TRUNK_MAX - TREE_TRUNK_MIN + 1);
+
+                // Only ground low enough for the tree to reach into this chunk
+                for y in -(trunk + reach + 1)..height {
+                    let is_ground = bases.get_voxel(IVec3::new(x, y, z)).voxel_type
+                        == VoxelType::BLOCK
+                        && bases.get_voxel(IVec3::new(x, y + 1, z)).voxel_type == VoxelType::AIR;
+
+                    if !is_ground || chunk_origin.y + y < WATER_LEVEL {
+                        continue;
+                    }
+
+                    // Trees don't grow out of cliffs
+                    let normal = bases.surface_normal(IVec3::new(x, y, z));
+                    if normal.y >= TREE_MIN_GROUND_NORMAL_Y {
+                        place_tree(chunk, IVec3::new(x, y + 1, z), trunk);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn tree_hash(x: i32, z: i32) -> u64 {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&seed().to_le_bytes());
+    bytes[8..12].copy_from_slice(&x.to_le_bytes());
+    bytes[12..].copy_from_slice(&z.to_le_bytes());
+
+    xxh3_64(&bytes)
+}
+
+// Write the part of a tree rooted at the position (relative to the chunk) which is inside the chunk
+// Trees only grow into air
+fn place_tree(chunk: &mut Chunk, root: IVec3, trunk: i32) {
+    let reach = TREE_CANOPY_RADIUS;
+    let canopy_centre = root + IVec3::Y * trunk;
+
+    let trunk_voxels = (0..trunk).map(|y| root + IVec3::Y * y);
+    let canopy_voxels = (-reach..=reach).flat_map(|z| {
+        (-reach..=reach).flat_map(move |y| {
+            (-reach..=reach)
+                .map(move |x| IVec3::new(x, y, z))
+                .filter(move |offset| offset.length_squared() <= reach * reach)
+                .map(move |offset| canopy_centre + offset)
+        })
+    });
+
+    for pos in trunk_voxels.chain(canopy_voxels) {
+        let Some(voxel_pos) = VoxelPos::try_from_ivec3(pos).filter(VoxelPos::is_in_chunk) else {
+            continue;
+        };
+
+        if chunk[voxel_pos].voxel_type == VoxelType::AIR {
+            chunk.set_voxel(voxel_pos, VoxelType::BLOCK);
+        }
+    }
+}
+
+// Floating islands
+
+#[derive(Debug, Copy, Clone)]
+pub struct FloatingIslandsParams {
+    pub frequency: f32,
+    // Height which the islands are centred around
+    pub centre_height: f32,
+    // Distance from the centre height at which the density has fallen to nothing
+    pub falloff_height: f32,
+    // Density needed for a voxel to be solid
+    pub threshold: f32,
+}
+
+impl Default for FloatingIslandsParams {
+    fn default() -> Self {
+        Self {
+            frequency: 0.02,
+            centre_height: 32.,
+            falloff_height: 48.,
+            threshold: 0.1,
+        }
+    }
+}
+
+pub struct FloatingIslandsGenerator {
+    params: FloatingIslandsParams,
+    noise: FastNoise,
+}
+
+impl FloatingIslandsGenerator {
+    pub fn new(params: FloatingIslandsParams) -> Self {
+        let mut noise = FastNoise::seeded(seed());
+        noise.set_noise_type(NoiseType::SimplexFractal);
+        noise.set_frequency(params.frequency);
+        noise.set_fractal_octaves(4);
+
+        Self { params, noise }
+    }
+}
+
+impl WorldGenerator for FloatingIslandsGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        Chunk::from_density_fn(chunk_pos, |world_pos| {
+            // Density falls off vertically, so islands only form around the centre height
+            let falloff = ((world_pos.y as f32 - self.params.centre_height)
+                / self.params.falloff_height)
+                .powi(2);
+            let density =
+                self.noise
+                    .get_noise3d(world_pos.x as f32, world_pos.y as f32, world_pos.z as f32)
+                    - falloff;
+
+            // The noise changes by about its frequency per voxel, which scales it to voxels
+            (density - self.params.threshold) / self.params.frequency
+        })
+    }
+}
+
+// Amplified mountains
+
+#[derive(Debug, Copy, Clone)]
+pub struct AmplifiedParams {
+    pub frequency: f32,
+    pub height_scale: f32,
+    pub octaves: i32,
+}
+
+impl Default for AmplifiedParams {
+    fn default() -> Self {
+        Self {
+            frequency: 0.006,
+            height_scale: 256.,
+            octaves: 5,
+        }
+    }
+}
+
+pub struct AmplifiedGenerator {
+    params: AmplifiedParams,
+    noise: FastNoise,
+}
+
+impl AmplifiedGenerator {
+    pub fn new(params: AmplifiedParams) -> Self {
+        let mut noise = FastNoise::seeded(seed());
+        noise.set_noise_type(NoiseType::SimplexFractal);
+        noise.set_fractal_type(FractalType::RigidMulti);
+        noise.set_frequency(params.frequency);
+        noise.set_fractal_octaves(params.octaves);
+
+        Self { params, noise }
+    }
+}
+
+impl AmplifiedGenerator {
+    fn height_at(&self, x: i32, z: i32) -> f32 {
+        // Ridged noise squared gives sharp peaks with wide valleys
+        let ridge = (self.noise.get_noise(x as f32, z as f32) * 0.5 + 0.5).clamp(0., 1.);
+
+        ridge * ridge * self.params.height_scale - self.params.height_scale * 0.25
+    }
+}
+
+impl WorldGenerator for AmplifiedGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        Chunk::from_density_fn(chunk_pos, |world_pos| {
+            self.height_at(world_pos.x, world_pos.z) - world_pos.y as f32
+        })
+    }
+
+    fn preview(&self, layer: PreviewLayer, columns: &[IVec2]) -> Option<Vec<f32>> {
+        (layer == PreviewLayer::Height).then(|| {
+            columns
+                .iter()
+                .map(|column| self.height_at(column.x, column.y))
+                .collect()
+        })
+    }
+}
+
+// Flat grid world
+
+#[derive(Debug, Copy, Clone)]
+pub struct FlatGridParams {
+    // Voxels below this height are solid
+    pub ground_level: i32,
+    // Distance between the raised grid lines on top of the ground
+    pub grid_spacing: i32,
+}
+
+impl Default for FlatGridParams {
+    fn default() -> Self {
+        Self {
+            ground_level: 0,
+            grid_spacing: 16,
+        }
+    }
+}
+
+pub struct FlatGridGenerator {
+    params: FlatGridParams,
+}
+
+impl FlatGridGenerator {
+    pub fn new(params: FlatGridParams) -> Self {
+        Self { params }
+    }
+}
+
+impl WorldGenerator for FlatGridGenerator {
+    fn generate(&self, chunk_pos: ChunkPos) -> Chunk {
+        Chunk::from_fn(chunk_pos, |world_pos| {
+            let on_grid_line = world_pos.x.rem_euclid(self.params.grid_spacing) == 0
+                || world_pos.z.rem_euclid(self.params.grid_spacing) == 0;
+
+            if world_pos.y < self.params.ground_level
+                || (world_pos.y == self.params.ground_level && on_grid_line)
+            {
+                VoxelType::BLOCK
+            } else {
+                VoxelType::AIR
+            }
+        })
+    }
+
+    fn preview(&self, layer: PreviewLayer, columns: &[IVec2]) -> Option<Vec<f32>> {
+        (layer == PreviewLayer::Height)
+            .then(|| vec![self.params.ground_level as f32; columns.len()])
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy::prelude::*;
+
+use crate::{
+    chunk::Chunk,
+    positions::{ChunkPos, WorldPos},
+    voxel::Voxel,
+    world::World,
+};
+
+// Keeps the WorldReader resource up to date at the end of every frame
+pub struct WorldReaderPlugin;
+
+impl Plugin for WorldReaderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WorldReader>()
+            .add_systems(Last, WorldReader::refresh);
+    }
+}
+
+// Read-only handle to the loaded chunks as of one moment, which background tasks (pathfinding, AI,
+// analytics) can hold and send between threads while the World keeps loading, unloading and editing
+// Clones share one map of the chunks, which share their voxels with the World
+//
+// Consistency guarantees:
+// - A reader never changes: edits copy chunks on write, so the World never writes to a chunk a
+//   reader holds, and unloaded chunks stay readable until every reader holding them is dropped
+// - A reader is taken between systems, so it sees every voxel of an edit transaction or none of them
+// - Readers with the same generation see the same chunks, and a reader is stale once the World's
+//   chunk generation has moved past its own
+// - Nothing is promised about chunks the reader doesn't have: they may be unloaded, still loading
+//   or outside the loaders' range
+#[derive(Resource, Clone, Default)]
+pub struct WorldReader {
+    generation: u64,
+    chunks: Arc<HashMap<ChunkPos, Arc<Chunk>>>,
+}
+
+impl WorldReader {
+    pub fn new(world: &World) -> Self {
+        Self {
+            generation: world.chunk_generation,
+            chunks: Arc::new(world.chunks.clone()),
+        }
+    }
+
+    // The World's chunk generation when the reader was taken
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn is_current(&self, world: &World) -> bool {
+        self.generation == world.chunk_generation
+    }
+
+    // Take the World's chunks again if they changed since the reader was taken
+    pub fn update(&mut self, world: &World) {
+        if !self.is_current(world) {
+            *self = Self::new(world);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn contains_chunk(&self, chunk_pos: ChunkPos) -> bool {
+        self.chunks.contains_key(&chunk_pos)
+    }
+
+    pub fn get_chunk(&self, chunk_pos: ChunkPos) -> Option<&Arc<Chunk>> {
+        self.chunks.get(&chunk_pos)
+    }
+
+    pub fn get_voxel(&self, world_pos: WorldPos) -> Option<&Voxel> {
+        let (voxel_pos, chunk_pos) = WorldPos::to_voxel_pos(world_pos);
+
+        self.chunks.get(&chunk_pos).map(|chunk| &chunk[voxel_pos])
+    }
+
+    // Every chunk the reader holds, in no particular order
+    pub fn iter_chunks(&self) -> impl Iterator<Item = (ChunkPos, &Chunk)> + '_ {
+        self.chunks
+            .iter()
+            .map(|(&chunk_pos, chunk)| (chunk_pos, chunk.as_ref()))
+    }
+
+    fn refresh(mut reader: ResMut<WorldReader>, world: Res<World>) {
+        // Avoid triggering change detection while the chunks are unchanged
+        if !reader.is_current(&world) {
+            reader.update(&world);
+        }
+    }
+}
+
+impl World {
+    pub fn reader(&self) -> WorldReader {
+        WorldReader::new(self)
+    }
+}
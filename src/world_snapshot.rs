@@ -0,0 +1,164 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Weak},
+};
+
+use crate::{
+    chunk::Chunk,
+    chunk_meta::ChunkMeta,
+    positions::{ChunkPos, WorldPos},
+    voxel::VoxelType,
+    world::World,
+};
+
+// A copy of the loaded voxels, for editor play mode round trips and rollback experiments
+// Chunks are kept in the compact chunk format, and a snapshot taken from a previous one shares the
+// bytes of every chunk which hasn't changed since, so a history of snapshots only grows by the deltas
+#[derive(Clone, Default)]
+pub struct WorldSnapshot {
+    chunks: HashMap<ChunkPos, SnapshotChunk>,
+    edit_history: VecDeque<Vec<(WorldPos, VoxelType)>>,
+}
+
+#[derive(Clone)]
+struct SnapshotChunk {
+    bytes: Arc<[u8]>,
+    meta: Arc<ChunkMeta>,
+    // The chunk the bytes were written from, edits copy on write so a changed chunk never matches it
+    source: Weak<Chunk>,
+}
+
+impl SnapshotChunk {
+    fn is_from(&self, chunk: &Arc<Chunk>) -> bool {
+        std::ptr::eq(self.source.as_ptr(), Arc::as_ptr(chunk))
+    }
+}
+
+impl WorldSnapshot {
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn contains(&self, chunk_pos: ChunkPos) -> bool {
+        self.chunks.contains_key(&chunk_pos)
+    }
+
+    // Bytes held by the snapshot which aren't shared with the previous one
+    pub fn delta_bytes(&self, previous: &WorldSnapshot) -> usize {
+        self.chunks
+            .iter()
+            .filter(|(chunk_pos, chunk)| {
+                previous
+                    .chunks
+                    .get(chunk_pos)
+                    .is_none_or(|previous_chunk| !Arc::ptr_eq(&previous_chunk.bytes, &chunk.bytes))
+            })
+            .map(|(_, chunk)| chunk.bytes.len())
+            .sum()
+    }
+}
+
+impl World {
+    pub fn snapshot(&self) -> WorldSnapshot {
+        self.snapshot_from(&WorldSnapshot::default())
+    }
+
+    // Only chunks which changed since the previous snapshot are written again
+    pub fn snapshot_from(&self, previous: &WorldSnapshot) -> WorldSnapshot {
+        let chunks = self
+            .chunks
+            .iter()
+            .filter_map(|(&chunk_pos, chunk)| {
+                let meta = self.chunk_metas.get(&chunk_pos)?;
+
+                let snapshot_chunk = match previous.chunks.get(&chunk_pos) {
+                    Some(previous_chunk) if previous_chunk.is_from(chunk) => previous_chunk.clone(),
+                    _ => SnapshotChunk {
+                        bytes: chunk.to_bytes().into(),
+                        meta: Arc::clone(meta),
+                        source: Arc::downgrade(chunk),
+                    },
+                };
+
+                Some((chunk_pos, snapshot_chunk))
+            })
+            .collect();
+
+        WorldSnapshot {
+            chunks,
+            edit_history: self.edit_history.clone(),
+        }
+    }
+
+    // Put the voxels back as they were in the snapshot, returns the number of chunks which changed
+    // Changed chunks are marked dirty and remeshed along with their neighbours, in-flight tasks for
+    // them are dropped, and the undo history is restored with them
+    // Snapshot chunks which aren't loaded or loading any more go to the chunk cache, to be used if
+    // they load again, and chunks loaded since the snapshot are left as they are
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) -> usize {
+        let mut changed = HashSet::new();
+
+        for (&chunk_pos, snapshot_chunk) in &snapshot.chunks {
+            if let Some(chunk) = self.chunks.get(&chunk_pos) {
+                if snapshot_chunk.is_from(chunk) || *snapshot_chunk.bytes == chunk.to_bytes() {
+                    continue;
+                }
+            }
+
+            let Some(chunk) = Chunk::from_bytes(&snapshot_chunk.bytes) else {
+                continue;
+            };
+            let (chunk, meta) = (Arc::new(chunk), Arc::clone(&snapshot_chunk.meta));
+
+            let is_loading = self.data_tasks.contains_key(&chunk_pos)
+                || self.load_data_queue.contains(&chunk_pos)
+                || self.is_generating(chunk_pos);
+            if !self.chunks.contains_key(&chunk_pos) && !is_loading {
+                self.chunk_cache.insert(chunk_pos, chunk, meta);
+                continue;
+            }
+
+            // Loading tasks would overwrite the restored voxels
+            self.data_tasks.remove(&chunk_pos);
+            self.load_data_queue.remove(&chunk_pos);
+            self.cancel_generation(chunk_pos);
+
+            self.chunks.insert(chunk_pos, chunk);
+            self.chunk_metas.insert(chunk_pos, meta);
+            self.dirty_chunks.insert(chunk_pos);
+            self.chunk_generation += 1;
+            // The restored voxels may not have the faces the decals were on
+            self.decals.remove_chunk(chunk_pos);
+            changed.insert(chunk_pos);
+        }
+
+        // Meshes sample their neighbours' voxels, so those are rebuilt as well
+        let remesh = changed
+            .iter()
+            .flat_map(|&chunk_pos| {
+                (-1..=1).flat_map(move |z| {
+                    (-1..=1).flat_map(move |y| {
+                        (-1..=1).map(move |x| chunk_pos + ChunkPos::new(x, y, z))
+                    })
+                })
+            })
+            .collect::<HashSet<_>>();
+
+        for chunk_pos in remesh {
+            self.invalidate_meshes(chunk_pos);
+
+            // Only chunks which are shown, the loader meshes the rest when they come into range
+            if self.chunk_entities.contains_key(&chunk_pos) {
+                self.queue_remesh(chunk_pos);
+            }
+        }
+
+        self.edit_history = snapshot.edit_history.clone();
+
+        changed.len()
+    }
+}
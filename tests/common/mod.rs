@@ -0,0 +1,115 @@
+// Headless app running the chunk pipeline without a window or renderer, shared by the integration tests
+#![allow(dead_code)]
+
+use std::sync::Once;
+
+use bevy::{input::InputPlugin, prelude::*, window::ExitCondition};
+use cube_world::{
+    background_throttle::BackgroundThrottlePlugin,
+    block_registry::BlockRegistryPlugin,
+    chunk_loading::{ChunkLoader, ChunkLoaderPlugin},
+    generation_stages::GenerationStagesPlugin,
+    persistence,
+    pipeline_stepping::PipelineSteppingPlugin,
+    rendering::{ChunkMaterial, FarChunkMaterial, GlobalChunkMaterial, GlobalFarChunkMaterial},
+    task_pools::ChunkTaskPoolsPlugin,
+    task_scheduler::TaskSchedulerPlugin,
+    world::{PipelineMode, World, WorldCounters, WorldPlugin},
+    world_border::WorldBorderPlugin,
+};
+
+// Saves go to a directory of the test binary's own, so chunks are generated rather than loaded
+// from the saves of a previous run
+fn use_test_save_directory() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let directory =
+            std::env::temp_dir().join(format!("cube_world_test_{}", std::process::id()));
+        persistence::set_save_directory(directory);
+    });
+}
+
+pub fn pipeline_app(load_distance: u32, pipeline_mode: PipelineMode) -> App {
+    use_test_save_directory();
+
+    let mut app = App::new();
+    // Input and window events are read by the debug hotkeys and the background throttle, there is no
+    // window to send them
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        InputPlugin,
+        WindowPlugin {
+            primary_window: None,
+            exit_condition: ExitCondition::DontExit,
+            close_when_requested: false,
+        },
+    ));
+
+    app.init_asset::<Mesh>()
+        .init_asset::<ChunkMaterial>()
+        .init_asset::<FarChunkMaterial>()
+        .init_asset::<StandardMaterial>()
+        .add_plugins(BlockRegistryPlugin)
+        .add_plugins((
+            ChunkLoaderPlugin,
+            ChunkTaskPoolsPlugin::default(),
+            WorldPlugin,
+            BackgroundThrottlePlugin,
+            WorldBorderPlugin,
+            PipelineSteppingPlugin,
+            TaskSchedulerPlugin,
+            GenerationStagesPlugin,
+        ))
+        .insert_resource(pipeline_mode)
+        // Meshes are spawned with the default handles, nothing draws them
+        .insert_resource(GlobalChunkMaterial(Handle::default()))
+        .insert_resource(GlobalFarChunkMaterial(Handle::default()));
+
+    // Unloads aren't delayed, so a drained pipeline has nothing left waiting on a timer
+    let mut loader = ChunkLoader::new(load_distance);
+    loader.unload_delay = 0.;
+    app.world_mut().spawn((loader, TransformBundle::default()));
+
+    app
+}
+
+// Run frames until nothing is queued or in flight, returns the number of frames run
+pub fn run_until_drained(app: &mut App, max_frames: u32) -> u32 {
+    for frame in 1..=max_frames {
+        app.update();
+
+        if frame > 1 && is_drained(app) {
+            return frame;
+        }
+    }
+
+    panic!("The chunk pipeline didn't drain within {max_frames} frames");
+}
+
+pub fn is_drained(app: &App) -> bool {
+    let world = app.world().resource::<World>();
+    let counters = app.world().resource::<WorldCounters>();
+    let loaders_idle = app
+        .world()
+        .iter_entities()
+        .filter_map(|entity| entity.get::<ChunkLoader>())
+        .all(|loader| {
+            loader.data_load_queue.is_empty()
+                && loader.mesh_load_queue.is_empty()
+                && loader.data_unload_queue.is_empty()
+                && loader.mesh_unload_queue.is_empty()
+                && loader.pending_data_unloads.is_empty()
+                && loader.pending_mesh_unloads.is_empty()
+        });
+
+    loaders_idle
+        && counters.load_data_queue == 0
+        && counters.load_mesh_queue == 0
+        && counters.unload_data_queue == 0
+        && counters.unload_mesh_queue == 0
+        && counters.data_tasks == 0
+        && counters.mesh_tasks == 0
+        && counters.parked_meshes == 0
+        && world.generation_stages().is_empty()
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use cube_world::{
+    chunk_digest::ChunkDigests,
+    chunk_loading::ChunkLoader,
+    positions::ChunkPos,
+    world::{PipelineMode, World, WorldCounters},
+};
+
+use common::{pipeline_app, run_until_drained};
+
+const LOAD_DISTANCE: u32 = 1;
+const MAX_FRAMES: u32 = 600;
+
+// Loads a small world around the origin in deterministic mode, returning the chunk counts and the
+// world's digest once drained
+fn load_world() -> (WorldCounters, u64) {
+    let mut app = pipeline_app(LOAD_DISTANCE, PipelineMode::Deterministic);
+    run_until_drained(&mut app, MAX_FRAMES);
+
+    let world = app.world().resource::<World>();
+    let mut digests = ChunkDigests::default();
+    digests.update(world);
+
+    let counters = app.world().resource::<WorldCounters>();
+    (
+        WorldCounters {
+            loaded_chunks: counters.loaded_chunks,
+            chunk_entities: counters.chunk_entities,
+            ..Default::default()
+        },
+        digests.digest().root,
+    )
+}
+
+// Chunks within a few chunks of the origin which the loader keeps, by its data and mesh bounds
+fn chunks_in_range(loader: &ChunkLoader) -> (usize, usize) {
+    let reach = LOAD_DISTANCE as i32 + 2;
+    let mut data = 0;
+    let mut meshes = 0;
+    for x in -reach..=reach {
+        for y in -reach..=reach {
+            for z in -reach..=reach {
+                let chunk_pos = ChunkPos::new(x, y, z);
+                data += loader.keeps_data(chunk_pos) as usize;
+                meshes += loader.keeps_mesh(chunk_pos) as usize;
+            }
+        }
+    }
+
+    (data, meshes)
+}
+
+#[test]
+fn loads_every_chunk_in_range() {
+    let mut app = pipeline_app(LOAD_DISTANCE, PipelineMode::Deterministic);
+    run_until_drained(&mut app, MAX_FRAMES);
+
+    let loader = app.world_mut().query::<&ChunkLoader>().single(app.world());
+    let (data_in_range, meshes_in_range) = chunks_in_range(loader);
+    let world = app.world().resource::<World>();
+    let counters = app.world().resource::<WorldCounters>();
+
+    // Every chunk in range has data, and chunks which have a mesh are in mesh range
+    // Chunks without any faces (all air or all solid) aren't given a mesh
+    assert_eq!(counters.loaded_chunks, data_in_range);
+    assert!(counters.chunk_entities > 0);
+    assert!(counters.chunk_entities <= meshes_in_range);
+    for (chunk_pos, _) in world.iter_meshed_chunks() {
+        assert!(world.is_loaded(chunk_pos));
+        assert!(loader.keeps_mesh(chunk_pos));
+    }
+}
+
+#[test]
+fn loads_the_same_world_every_run() {
+    // Frame counts aren't compared, loading is paced by time even in deterministic mode
+    let (first_counters, first_digest) = load_world();
+    let (second_counters, second_digest) = load_world();
+
+    assert_eq!(first_counters.loaded_chunks, second_counters.loaded_chunks);
+    assert_eq!(
+        first_counters.chunk_entities,
+        second_counters.chunk_entities
+    );
+    assert_eq!(first_digest, second_digest);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use bevy::prelude::*;
+use cube_world::{
+    chunk_loading::{AnchoredChunks, ChunkLoader},
+    constants::CHUNK_SIZE,
+    world::{PipelineMode, World},
+};
+
+use common::{pipeline_app, run_until_drained};
+
+const LOAD_DISTANCE: u32 = 1;
+// Legs of the walk and their length in chunks
+const LEGS: usize = 6;
+const LEG_LENGTH: f32 = 3.;
+// Voxels the loader moves each frame, fast enough that chunks are unloaded while still loading
+const STEP: f32 = 12.;
+const SETTLE_FRAMES: u32 = 600;
+
+// Zig-zag outwards so that chunks are loaded, unloaded and loaded again in every direction
+fn waypoints() -> Vec<Vec3> {
+    let leg = LEG_LENGTH * CHUNK_SIZE as f32;
+
+    (0..LEGS)
+        .map(|i| {
+            let i = i as f32;
+            Vec3::new(leg * i, (i * 0.7).sin() * leg * 0.25, leg * (i % 2.))
+        })
+        .collect()
+}
+
+// Invariants of the pipeline which hold on every frame, while chunks are loading and unloading
+fn check_invariants(app: &App, frame: u32) {
+    let world = app.world().resource::<World>();
+
+    let (load_data_queue, unload_data_queue) = world.data_queues();
+    for chunk_pos in load_data_queue.iter() {
+        assert!(
+            !unload_data_queue.contains(chunk_pos),
+            "Frame {frame}: {chunk_pos:?} is queued to load and unload its data"
+        );
+    }
+    let (load_mesh_queue, unload_mesh_queue) = world.mesh_queues();
+    for chunk_pos in load_mesh_queue.iter() {
+        assert!(
+            !unload_mesh_queue.contains(chunk_pos),
+            "Frame {frame}: {chunk_pos:?} is queued to load and unload its mesh"
+        );
+    }
+
+    for (chunk_pos, entity) in world.iter_meshed_chunks() {
+        assert!(
+            world.is_loaded(chunk_pos),
+            "Frame {frame}: {chunk_pos:?} has a mesh entity but no data"
+        );
+        assert!(
+            app.world().get_entity(entity).is_some(),
+            "Frame {frame}: {chunk_pos:?} has a despawned chunk entity"
+        );
+    }
+
+    // Section entities are despawned along with their chunk entity
+    for (chunk_pos, entity) in world.iter_section_entities() {
+        assert!(
+            world.entity_at(chunk_pos).is_some(),
+            "Frame {frame}: {chunk_pos:?} has sections but no chunk entity"
+        );
+        assert!(
+            app.world().get_entity(entity).is_some(),
+            "Frame {frame}: {chunk_pos:?} has a despawned section entity"
+        );
+    }
+}
+
+// Walks a loader through the world checking the pipeline's invariants every frame, then checks that
+// it drains and leaves no chunk entities out of range
+// Deterministic mode joins every task on the frame after it starts, so the walk takes the same
+// number of frames however fast the machine is
+#[test]
+fn walking_loader_keeps_the_pipeline_consistent() {
+    let mut app = pipeline_app(LOAD_DISTANCE, PipelineMode::Deterministic);
+    let loader = app
+        .world_mut()
+        .query_filtered::<Entity, With<ChunkLoader>>()
+        .single(app.world());
+
+    let mut frame = 0;
+    for target in waypoints() {
+        loop {
+            let mut transform = app.world_mut().get_mut::<Transform>(loader).unwrap();
+            let to_target = target - transform.translation;
+            let arrived = to_target.length() <= STEP;
+            transform.translation = if arrived {
+                target
+            } else {
+                transform.translation + to_target.normalize() * STEP
+            };
+
+            app.update();
+            frame += 1;
+            check_invariants(&app, frame);
+
+            if arrived {
+                break;
+            }
+        }
+    }
+
+    frame += run_until_drained(&mut app, SETTLE_FRAMES);
+    check_invariants(&app, frame);
+
+    // Meshes which joined after their chunk was unloaded would leave entities nothing despawns, once
+    // drained every chunk entity should be in range
+    let world = app.world().resource::<World>();
+    let loader = app.world().get::<ChunkLoader>(loader).unwrap();
+    let anchored = app.world().resource::<AnchoredChunks>();
+    assert!(world.iter_meshed_chunks().next().is_some());
+    for (chunk_pos, _) in world.iter_meshed_chunks() {
+        assert!(
+            loader.keeps_mesh(chunk_pos) || anchored.meshes.contains(&chunk_pos),
+            "{chunk_pos:?} has a mesh entity out of range of every loader"
+        );
+    }
+}